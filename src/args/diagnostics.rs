@@ -0,0 +1,225 @@
+//! Count warnings and errors for a summary at the end of the build
+//!
+//! CountingDrain sits in front of every other drain, so counts are up to date as soon as a message is logged
+//! ```
+//! use slog::{o, warn, Drain};
+//!
+//! let diagnostics = Arc::new(Diagnostics::default());
+//! let drain = CountingDrain::new(slog::Discard, diagnostics.clone());
+//! let log = slog::Logger::root(drain.fuse(), o!("page" => "index.page"));
+//!
+//! warn!(log, "Undefined variable x");
+//! warn!(log, "Undefined variable x");
+//! assert_eq!(diagnostics.summary(), vec!["Undefined variable x: 2 occurrences across 1 pages"]);
+//! ```
+
+/* IMPORTS */
+use slog::{Drain, Key, Level, Logger, Never, OwnedKVList, Record, Serializer, KV};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/* DIAGNOSTICS */
+/// Occurrences of a single message
+#[derive(Default)]
+struct Occurrences {
+    /// Number of times it was logged
+    count: usize,
+    /// Pages it was logged for
+    pages: BTreeSet<Box<str>>,
+}
+
+/// Warnings and errors logged so far, grouped by message
+#[derive(Default)]
+pub struct Diagnostics {
+    messages: Mutex<BTreeMap<(Level, Box<str>), Occurrences>>,
+    /// Whether something happened that should fail the build, even though it carried on
+    failed: AtomicBool,
+}
+
+impl Diagnostics {
+    /// Record a message logged at level, for page if it belongs to one
+    fn add(&self, level: Level, msg: Box<str>, page: Option<Box<str>>) {
+        let mut messages = self.messages.lock().unwrap();
+        let o = messages.entry((level, msg)).or_default();
+        o.count += 1;
+        match page {
+            Some(p) => {
+                o.pages.insert(p);
+            }
+            None => (),
+        }
+    }
+
+    /// Make the build fail once it is done
+    pub fn fail(&self) {
+        self.failed.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the build should fail
+    pub fn failed(&self) -> bool {
+        return self.failed.load(Ordering::Relaxed);
+    }
+
+    /// Number of warnings and errors logged so far
+    pub fn count(&self) -> usize {
+        return self
+            .messages
+            .lock()
+            .unwrap()
+            .values()
+            .map(|o| o.count)
+            .sum();
+    }
+
+    /// Number of warnings and of errors logged so far, critical messages count as errors
+    pub fn counts(&self) -> (usize, usize) {
+        let (mut warnings, mut errors) = (0, 0);
+        for ((level, _), o) in self.messages.lock().unwrap().iter() {
+            match level {
+                Level::Warning => warnings += o.count,
+                _ => errors += o.count,
+            }
+        }
+        return (warnings, errors);
+    }
+
+    /// One line for every message that was logged more than once, most frequent first
+    pub fn summary(&self) -> Vec<String> {
+        let messages = self.messages.lock().unwrap();
+        let mut repeated: Vec<_> = messages.iter().filter(|(_, o)| o.count > 1).collect();
+        repeated.sort_by_key(|(_, o)| Reverse(o.count));
+        return repeated
+            .iter()
+            .map(|((_, msg), o)| match o.pages.len() {
+                0 => format!("{msg}: {n} occurrences", n = o.count),
+                p => format!("{msg}: {n} occurrences across {p} pages", n = o.count),
+            })
+            .collect();
+    }
+}
+
+/// Finds the page a record was logged for
+struct PageSerializer(Option<Box<str>>);
+
+impl Serializer for PageSerializer {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        if key == "page" {
+            self.0 = Some(val.to_string().into());
+        }
+        return Ok(());
+    }
+}
+
+/// Slog Drain that counts warnings and errors into Diagnostics before passing records on
+pub struct CountingDrain<D: Drain> {
+    /// Drain records are passed on to
+    drain: D,
+    /// Where messages are counted
+    diagnostics: Arc<Diagnostics>,
+}
+
+impl<D: Drain> CountingDrain<D> {
+    /// Create a new CountingDrain from given arguments
+    pub fn new(drain: D, diagnostics: Arc<Diagnostics>) -> CountingDrain<D> {
+        return CountingDrain {
+            drain: drain,
+            diagnostics: diagnostics,
+        };
+    }
+}
+
+impl<D: Drain> Drain for CountingDrain<D> {
+    type Ok = D::Ok;
+    type Err = D::Err;
+
+    /// Count record if it is a warning or worse
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        if record.level().is_at_least(Level::Warning) {
+            let mut page = PageSerializer(None);
+            let _ = values.serialize(record, &mut page);
+            self.diagnostics
+                .add(record.level(), record.msg().to_string().into(), page.0);
+        }
+        return self.drain.log(record, values);
+    }
+}
+
+/// Errors caught by a CatchingDrain, in the order they were logged
+pub type Caught = Arc<Mutex<Vec<Box<str>>>>;
+
+/// Slog Drain that keeps errors instead of logging them, passing every other record on to a logger
+pub struct CatchingDrain {
+    /// Logger records that are not errors go to
+    logger: Logger,
+    /// Where errors are kept
+    caught: Caught,
+}
+
+impl CatchingDrain {
+    /// Create a new CatchingDrain from given arguments
+    pub fn new(logger: Logger, caught: Caught) -> CatchingDrain {
+        return CatchingDrain {
+            logger: logger,
+            caught: caught,
+        };
+    }
+}
+
+impl Drain for CatchingDrain {
+    type Ok = ();
+    type Err = Never;
+
+    /// Keep record if it is an error or worse, log it otherwise
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+        if record.level().is_at_least(Level::Error) {
+            self.caught
+                .lock()
+                .unwrap()
+                .push(record.msg().to_string().into());
+            return Ok(());
+        }
+        return Drain::log(&self.logger, record, values);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::o;
+
+    /// Ensure repeated messages are grouped and counted per page
+    #[test]
+    fn test_summary() {
+        let diagnostics = Arc::new(Diagnostics::default());
+        let drain = CountingDrain::new(slog::Discard, diagnostics.clone());
+        let log = slog::Logger::root(drain, o!());
+        let a = log.new(o!("page" => "a.page"));
+        let b = log.new(o!("page" => "b.page"));
+        for _ in 0..3 {
+            slog::warn!(a, "Undefined variable {}", "x");
+        }
+        slog::warn!(b, "Undefined variable {}", "x");
+        slog::error!(log, "Unknown tag");
+        slog::error!(log, "Unknown tag");
+        slog::warn!(log, "Once");
+        slog::info!(log, "Not a warning");
+        slog::info!(log, "Not a warning");
+
+        assert_eq!(
+            diagnostics.summary(),
+            vec![
+                "Undefined variable x: 4 occurrences across 2 pages",
+                "Unknown tag: 2 occurrences",
+            ]
+        );
+        assert_eq!(diagnostics.count(), 7);
+        assert_eq!(diagnostics.counts(), (5, 2));
+    }
+}
@@ -0,0 +1,177 @@
+//! File Drain for slog
+//!
+//! Writes plain, uncolored records with a timestamp into a log file, at its own verbosity level
+//! ```
+//! use slog::{o, info, Drain, Level};
+//!
+//! let file = filedrain::open(Path::new("build.log"), LogMode::Rotate).unwrap();
+//! let drain = slog::Duplicate::new(
+//!     ProgressDrain::new(prog.clone(), Level::Warning, LogFormat::Text),
+//!     FileDrain::new(Some(file), Level::Debug, LogFormat::Text),
+//! );
+//! let log = slog::Logger::root(drain.ignore_res(), o!());
+//!
+//! info!(log, "only written to build.log");
+//! ```
+
+/* IMPORTS */
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use slog::{Drain, Level, Never, OwnedKVList, Record};
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    result::Result,
+    sync::Mutex,
+};
+
+/* LOCAL IMPORTS */
+use super::logformat::{self, LogFormat};
+
+/* LOG FILE */
+/// Verbosity of the log file
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl From<LogLevel> for Level {
+    fn from(l: LogLevel) -> Level {
+        return match l {
+            LogLevel::Error => Level::Error,
+            LogLevel::Warning => Level::Warning,
+            LogLevel::Info => Level::Info,
+            LogLevel::Debug => Level::Debug,
+        };
+    }
+}
+
+/// What to do with the log of a previous build
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogMode {
+    /// Overwrite it
+    Truncate,
+    /// Keep writing at its end
+    Append,
+    /// Move it to <FILE>.1, replacing the one before it
+    Rotate,
+}
+
+/// Open the log file at path for writing
+pub fn open(path: &Path, mode: LogMode) -> io::Result<File> {
+    if mode == LogMode::Rotate && path.exists() {
+        let mut old = OsString::from(path.as_os_str());
+        old.push(".1");
+        fs::rename(path, old)?;
+    }
+    return OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(mode == LogMode::Append)
+        .truncate(mode != LogMode::Append)
+        .open(path);
+}
+
+/* FILEDRAIN */
+/// Slog Drain that writes into a file, or does nothing if there is no file
+pub struct FileDrain {
+    /// File to write to
+    file: Option<Mutex<File>>,
+    /// Verbosity level to log at
+    level: Level,
+    /// Format of written records
+    format: LogFormat,
+}
+
+impl FileDrain {
+    /// Create a new FileDrain from given arguments
+    pub fn new(file: Option<File>, level: Level, format: LogFormat) -> FileDrain {
+        return FileDrain {
+            file: file.map(Mutex::new),
+            level: level,
+            format: format,
+        };
+    }
+}
+
+impl Drain for FileDrain {
+    type Ok = ();
+    type Err = Never;
+
+    /// Append one line per record to the file
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        let file = match &self.file {
+            Some(f) if self.level >= record.level() => f,
+            _ => return Ok(()),
+        };
+        let line = match self.format {
+            LogFormat::Text => format!(
+                "{time} [{level}] {message}{values} {file}:{line}\n",
+                time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                level = record.level(),
+                message = record.msg(),
+                values = logformat::text_values(record, values),
+                file = record.file(),
+                line = record.line(),
+            ),
+            LogFormat::Json => logformat::json(record, values) + "\n",
+        };
+        // a failing log file should never stop the build
+        let _ = file.lock().unwrap().write_all(line.as_bytes());
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::o;
+    use std::fs;
+
+    /// Ensure records are written to the file at its own verbosity
+    #[test]
+    fn test_log() {
+        let path = "/tmp/ssgen_test_filedrain.log";
+        let drain = FileDrain::new(
+            Some(File::create(path).unwrap()),
+            Level::Info,
+            LogFormat::Text,
+        );
+        let log = slog::Logger::root(drain, o!());
+        slog::error!(log, "Error test");
+        slog::info!(log, "Info test");
+        slog::debug!(log, "Debug test");
+
+        let written = fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].contains(" [ERRO] Error test src/"), true);
+        assert_eq!(lines[1].contains(" [INFO] Info test src/"), true);
+
+        // previous logs
+        fs::write(path, "old\n").unwrap();
+        open(Path::new(path), LogMode::Append)
+            .unwrap()
+            .write_all(b"new\n")
+            .unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "old\nnew\n");
+        open(Path::new(path), LogMode::Rotate).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "");
+        assert_eq!(
+            fs::read_to_string(format!("{path}.1")).unwrap(),
+            "old\nnew\n"
+        );
+        open(Path::new(path), LogMode::Truncate).unwrap();
+        fs::remove_file(path).unwrap();
+        fs::remove_file(format!("{path}.1")).unwrap();
+
+        // no file
+        let log = slog::Logger::root(FileDrain::new(None, Level::Trace, LogFormat::Json), o!());
+        slog::error!(log, "Nowhere");
+    }
+}
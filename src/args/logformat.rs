@@ -0,0 +1,118 @@
+//! Machine-readable log records
+//!
+//! Turns a slog record into a single line JSON object, for log aggregators that cannot read the colored text format.
+//! Key-value pairs attached to the record or its logger become fields of the object
+//! ```
+//! use slog::{o, warn};
+//!
+//! // {"timestamp":"...","level":"warning","msg":"Unknown tag","file":"src/parser/mod.rs","line":1,"page":"index.page"}
+//! let log = log.new(o!("page" => "index.page"));
+//! warn!(log, "Unknown tag");
+//! ```
+
+/* IMPORTS */
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use slog::{Key, OwnedKVList, Record, Serializer, KV};
+use std::fmt;
+
+/* LOGFORMAT */
+/// Format of log messages
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// Human readable, colored when written to a terminal
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Collects key-value pairs of a record into a JSON object
+struct JsonSerializer(Map<String, Value>);
+
+impl Serializer for JsonSerializer {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.0
+            .insert(key.to_string(), Value::String(val.to_string()));
+        return Ok(());
+    }
+}
+
+/// Collects key-value pairs of a record into key=value text
+struct TextSerializer(String);
+
+impl Serializer for TextSerializer {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.0 += &format!(" {key}={val}");
+        return Ok(());
+    }
+}
+
+/// Format the key-value pairs of the logger as text, each preceded by a space
+///
+/// Those of record are left out, since named arguments of the message already show up in it
+pub fn text_values(record: &Record, values: &OwnedKVList) -> String {
+    let mut ser = TextSerializer(String::new());
+    let _ = values.serialize(record, &mut ser);
+    return ser.0;
+}
+
+/// Format record and the values of its logger as a JSON object
+pub fn json(record: &Record, values: &OwnedKVList) -> String {
+    let mut ser = JsonSerializer(Map::new());
+    ser.0.insert(
+        "timestamp".into(),
+        Utc::now()
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+            .into(),
+    );
+    ser.0.insert(
+        "level".into(),
+        record.level().as_str().to_lowercase().into(),
+    );
+    ser.0.insert("msg".into(), record.msg().to_string().into());
+    ser.0.insert("file".into(), record.file().into());
+    ser.0.insert("line".into(), record.line().into());
+    // serializing into a map cannot fail
+    let _ = values.serialize(record, &mut ser);
+    let _ = record.kv().serialize(record, &mut ser);
+    return Value::Object(ser.0).to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{o, Drain, Level, Never};
+    use std::sync::{Arc, Mutex};
+
+    /// Drain that keeps every formatted record
+    struct TestDrain(Arc<Mutex<Vec<String>>>);
+
+    impl Drain for TestDrain {
+        type Ok = ();
+        type Err = Never;
+
+        fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+            self.0.lock().unwrap().push(json(record, values));
+            self.0.lock().unwrap().push(text_values(record, values));
+            return Ok(());
+        }
+    }
+
+    /// Ensure records and their key-value pairs become JSON objects
+    #[test]
+    fn test_json() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let log = slog::Logger::root(TestDrain(lines.clone()), o!("page" => "index.page"));
+        slog::warn!(log, "Unknown tag \"{}\"", "!FOO"; "tag" => "!FOO");
+
+        let v: Value = serde_json::from_str(&lines.lock().unwrap()[0]).unwrap();
+        assert_eq!(v["level"], Level::Warning.as_str().to_lowercase());
+        assert_eq!(v["msg"], "Unknown tag \"!FOO\"");
+        assert_eq!(v["file"], "src/args/logformat.rs");
+        assert_eq!(v["page"], "index.page");
+        assert_eq!(v["tag"], "!FOO");
+        assert_eq!(v["timestamp"].is_string(), true);
+        assert_eq!(lines.lock().unwrap()[1], " page=index.page");
+    }
+}
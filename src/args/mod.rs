@@ -12,19 +12,50 @@
 //! ```
 
 /* IMPORTS */
-use clap::Parser;
-use indicatif::MultiProgress;
+use clap::{Parser, Subcommand};
+use clap_complete::Shell;
+use indicatif::{MultiProgress, ProgressDrawTarget};
 use slog::{o, Drain, Level, Logger};
 use slog_async::{Async, OverflowStrategy};
 use std::{
-    fs,
+    collections::BTreeMap,
+    env, fs,
+    io::{self, IsTerminal},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{atomic::AtomicBool, Arc},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 /* LOCAL IMPORTS */
+use crate::{
+    build::Streams,
+    buildinfo::Stamp,
+    cdn::CdnProvider,
+    commands::Template,
+    favicons::FaviconCache,
+    fetch::FetchCache,
+    git::{self, GitHistory},
+    hooks::{AssetHook, BuildHook, HtmlFilter},
+    parser::IncludeCache,
+    paths::{self, Symlinks},
+    redirects::Redirect,
+    robots::Robots,
+    server::{Headers, ServerConfig},
+    transforms::{ExternalLinks, Transform},
+    var::UndefinedVars,
+    Timings,
+};
+mod diagnostics;
+pub use diagnostics::Caught;
+use diagnostics::{CatchingDrain, CountingDrain, Diagnostics};
+mod filedrain;
+use filedrain::{FileDrain, LogLevel, LogMode};
+mod logformat;
+use logformat::LogFormat;
 mod progressdrain;
 use progressdrain::ProgressDrain;
+mod writes;
+use writes::Writes;
 
 /* MACROS */
 /// Wrapper for slog error!() macro to fix indicatif progress bar duplication
@@ -58,6 +89,7 @@ macro_rules! debug {
 /// ```
 /// let o: Options = Args::parse().build_options();
 /// ```
+#[derive(Clone)]
 pub struct Options {
     /// Output directory for generated HTML
     pub output: PathBuf,
@@ -68,25 +100,236 @@ pub struct Options {
     /// Global logger
     pub logger: Box<Logger>,
 
+    /// Logger tagged with the page being built but nothing narrower, which for_source starts from
+    pub page_logger: Box<Logger>,
+
     /// Global progress bar
     pub progress: Arc<MultiProgress>,
 
+    /// Print a status line every now and then instead of drawing progress bars
+    pub status_lines: bool,
+
+    /// Only log errors, then print statistics of the build at the end
+    pub summary: bool,
+
+    /// Warnings and errors logged so far, for the summary at the end of the build
+    pub diagnostics: Arc<Diagnostics>,
+
+    /// Files written into the output directory so far, see Writes
+    pub writes: Arc<Writes>,
+
+    /// Included files parsed so far, shared by every page
+    pub include_cache: Arc<IncludeCache>,
+
+    /// Files streamed into pages so far, see build::streamed
+    pub streams: Arc<Streams>,
+
+    /// Remote resources fetched so far, shared by every page
+    pub fetch_cache: Arc<FetchCache>,
+
+    /// Favicon sets generated so far, shared by every page
+    pub favicon_cache: Arc<FaviconCache>,
+
+    /// History of the git repository of the input directory, shared by every page
+    pub git_history: Arc<GitHistory>,
+
+    /// Time spent in directives and included files, only recorded with --profile-templates
+    pub timings: Option<Arc<Timings>>,
+
     /// Is shell directivr enabled
     pub allow_shell: bool,
+
+    /// Only rebuild pages whose sources changed since the last build
+    pub incremental: bool,
+
+    /// Let directives that splice in content (such as !IF) define variables in the enclosing node
+    pub legacy_scoping: bool,
+
+    /// Render pages without writing or copying anything into the output directory
+    pub dry_run: bool,
+
+    /// Report templates that build but are probably wrong
+    pub lint: bool,
+
+    /// Fail the build on anything it would otherwise only warn about
+    pub strict: bool,
+
+    /// Are network requests allowed
+    pub allow_network: bool,
+
+    /// Directory fetched responses are stored in
+    pub fetch_cache_dir: PathBuf,
+
+    /// Seconds to wait for a fetch before giving up
+    pub fetch_timeout: u64,
+
+    /// Seconds a stored response is used without fetching it again
+    pub fetch_ttl: u64,
+
+    /// Only use stored responses instead of fetching
+    pub offline: bool,
+
+    /// URL the output directory is served from
+    pub base_url: Option<Box<str>>,
+
+    /// Name of the site, from META.yaml
+    pub title: Box<str>,
+
+    /// Language of the site, from META.yaml
+    pub language: Box<str>,
+
+    /// Profile chosen with --profile, which selects settings from META.yaml
+    pub profile: Option<Box<str>>,
+
+    /// Commit checked out in the input directory, if it is part of a git repository
+    pub commit: Option<Box<str>>,
+
+    /// Where the build is stamped, see buildinfo
+    pub stamp: Vec<Stamp>,
+
+    /// Variables defined by the settings in META.yaml, before it is parsed
+    pub site_vars: BTreeMap<Box<str>, Box<str>>,
+
+    /// CDN to purge changed pages from after building
+    pub purge_cdn: Option<CdnProvider>,
+
+    /// Format of the configuration to write for the server, if any
+    pub server_config: Option<ServerConfig>,
+
+    /// Redirects declared in the settings in META.yaml, for the server configuration
+    pub redirects: Vec<Redirect>,
+
+    /// Headers declared in the settings in META.yaml, for the server configuration
+    pub headers: Vec<Headers>,
+
+    /// Commands that compile matching files as they are copied, declared in the settings in META.yaml
+    pub asset_hooks: Vec<AssetHook>,
+
+    /// Commands run before anything is parsed, declared in the settings in META.yaml
+    pub pre_build: Vec<BuildHook>,
+
+    /// Commands run once everything is written, declared in the settings in META.yaml
+    pub post_build: Vec<BuildHook>,
+
+    /// Command every page is passed through before it is written, declared in the settings in META.yaml
+    pub html_filter: Option<Arc<HtmlFilter>>,
+
+    /// Commands that render !DIAGRAM sources to SVG by diagram language, declared in the settings in META.yaml
+    pub diagram_renderers: BTreeMap<Box<str>, Vec<Box<str>>>,
+
+    /// Members of the web app manifest, declared in the settings in META.yaml
+    pub webmanifest: Option<serde_json::Map<String, serde_json::Value>>,
+
+    /// Globs of the files the service worker precaches, declared in the settings in META.yaml.
+    /// No service worker is written without any
+    pub service_worker: Vec<Box<str>>,
+
+    /// Rules of the robots.txt to write, declared in the settings in META.yaml
+    pub robots: Option<Robots>,
+
+    /// Give headings without an id one generated from their text
+    pub heading_ids: bool,
+
+    /// Passes over the text of every page, in the order they run
+    pub transforms: Vec<Transform>,
+
+    /// What links to other sites get with --transform external-links, declared in the settings in META.yaml
+    pub external_links: ExternalLinks,
+
+    /// Make two builds of the same input byte-identical
+    pub reproducible: bool,
+
+    /// Remove files the previous build wrote to the output directory that this build did not produce
+    pub clean: bool,
+
+    /// Remove everything from the output directory before building
+    pub clean_all: bool,
+
+    /// Write compressed copies of text files next to them
+    pub precompress: bool,
+
+    /// Stop right away on Ctrl-C instead of finishing the pages being built
+    pub fail_fast: bool,
+
+    /// Set once Ctrl-C is pressed, after which no more pages are started
+    pub interrupted: Arc<AtomicBool>,
+
+    /// Unix time the build is treated as happening at, taken from SOURCE_DATE_EPOCH if it is set
+    pub build_time: i64,
+
+    /// What to do with symbolic links in the input directory
+    pub symlinks: Symlinks,
+
+    /// Directories outside of the input directory that can be included from with @name/
+    pub template_dirs: Vec<(Box<str>, PathBuf)>,
+
+    /// What using an undefined variable turns into
+    pub undefined_vars: UndefinedVars,
+
+    /// Text undefined variables turn into with UndefinedVars::Placeholder
+    pub undefined_placeholder: Box<str>,
+
+    /// Maximum number of loop iterations in a single page, 0 for no limit
+    pub max_iterations: usize,
+
+    /// Maximum number of included files in a single page, 0 for no limit
+    pub max_includes: usize,
+
+    /// Bytes of memory the pages being built at the same time are estimated to use at most, 0 for no limit
+    pub max_memory: u64,
+}
+
+impl Options {
+    /// Copy of these options whose logger tags every record with the page being built
+    pub fn for_page(&self, page: &str) -> Options {
+        let logger = Box::new(self.page_logger.new(o!("page" => page.to_string())));
+        return Options {
+            logger: logger.clone(),
+            page_logger: logger,
+            ..self.clone()
+        };
+    }
+
+    /// Copy of these options whose logger also says where in the source the record comes from
+    pub fn for_source(&self, source: &str) -> Options {
+        return Options {
+            logger: Box::new(self.page_logger.new(o!("source" => source.to_string()))),
+            ..self.clone()
+        };
+    }
+
+    /// Copy of these options whose errors are caught instead of logged, along with where they end up
+    ///
+    /// Anything that only fails the build, like an undefined variable with --undefined-vars error,
+    /// fails the diagnostics of the copy instead
+    pub fn catching(&self) -> (Options, Caught) {
+        let caught = Caught::default();
+        let drain = CatchingDrain::new((*self.page_logger).clone(), caught.clone());
+        let logger = Box::new(Logger::root(drain, o!()));
+        let options = Options {
+            logger: logger.clone(),
+            page_logger: logger,
+            diagnostics: Arc::new(Diagnostics::default()),
+            ..self.clone()
+        };
+        return (options, caught);
+    }
 }
 
 /* ARGS */
 /// Command-line arugments
+///
+/// --input is only optional for subcommands, which check for it themselves if they need it
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Args {
     /// Output directory for generated HTML
     #[arg(short, long, value_name = "FILE", default_value = "./")]
     output: Box<Path>,
 
     /// Input directory for page files
-    #[arg(short, long, value_name = "FILE")]
-    input: Box<Path>,
+    #[arg(short, long, value_name = "FILE", required = true)]
+    input: Option<Box<Path>>,
 
     /// Show verbose messages
     #[arg(short, long)]
@@ -104,9 +347,211 @@ pub struct Args {
     #[arg(short, long)]
     silent: bool,
 
+    /// Only show errors, then print statistics of the build once it is done
+    #[arg(long, conflicts_with_all = ["verbose", "debug", "silent"])]
+    summary: bool,
+
+    /// Print the directives and included files that took the longest once done
+    #[arg(long)]
+    profile_templates: bool,
+
+    /// Do not color output, also set by the NO_COLOR environment variable
+    #[arg(long)]
+    no_color: bool,
+
     /// Explicitly allow shell directives
     #[arg(short, long)]
     enable_shell: bool,
+
+    /// Only rebuild pages whose sources changed since the last build
+    #[arg(long)]
+    incremental: bool,
+
+    /// Use the old variable scoping, where !DEF inside an !IF is visible outside of it
+    #[arg(long)]
+    legacy_scoping: bool,
+
+    /// Explicitly allow network requests
+    #[arg(long)]
+    allow_network: bool,
+
+    /// Directory responses of !FETCH are stored in, used when a request fails, and diagrams rendered by !DIAGRAM
+    #[arg(long, value_name = "DIR", default_value = ".ssgen-cache")]
+    fetch_cache: PathBuf,
+
+    /// Seconds to wait for a !FETCH request before giving up
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    fetch_timeout: u64,
+
+    /// Seconds a stored !FETCH response is used before fetching it again
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    fetch_ttl: u64,
+
+    /// Never fetch, use only responses stored in the fetch cache
+    #[arg(long)]
+    offline: bool,
+
+    /// URL the output directory is served from, i.e. https://example.com
+    #[arg(long, value_name = "URL")]
+    base_url: Option<Box<str>>,
+
+    /// Use the settings of this profile from the !SITE block of META.yaml, i.e. production
+    #[arg(long, value_name = "NAME")]
+    profile: Option<Box<str>>,
+
+    /// Purge pages that changed since the last build from a CDN, requires --allow-network and --base-url
+    #[arg(long, value_name = "PROVIDER")]
+    purge_cdn: Option<CdnProvider>,
+
+    /// Write redirects and headers as configuration for a server, overrides server in !SITE
+    #[arg(long, value_name = "FORMAT")]
+    server_config: Option<ServerConfig>,
+
+    /// Give headings without an id one generated from their text, i.e. <h2 id="getting-started">
+    #[arg(long)]
+    heading_ids: bool,
+
+    /// Stamp the build into every page or build-info.json, to check what is deployed, can be given more than once
+    #[arg(long, value_name = "WHERE", value_enum, value_delimiter = ',')]
+    stamp: Vec<Stamp>,
+
+    /// Run a transform over every page, can be given more than once
+    #[arg(long, value_name = "NAME", value_enum, value_delimiter = ',')]
+    transform: Vec<Transform>,
+
+    /// Make two builds of the same input byte-identical, dates come from SOURCE_DATE_EPOCH
+    #[arg(long)]
+    reproducible: bool,
+
+    /// Remove files the previous build wrote to the output directory that this build did not produce
+    #[arg(long)]
+    clean: bool,
+
+    /// Remove everything from the output directory before building
+    #[arg(long)]
+    clean_all: bool,
+
+    /// Write .gz and .br copies of HTML, CSS, JS and SVG files next to them, for servers that serve precompressed files
+    #[arg(long)]
+    precompress: bool,
+
+    /// On Ctrl-C, stop right away and discard the files being written instead of finishing the pages being built
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Format of log messages, json writes one object per line to stderr and --log-file
+    #[arg(long, value_name = "FORMAT", default_value_t = LogFormat::Text, value_enum)]
+    log_format: LogFormat,
+
+    /// Directory of shared blocks that !INCLUDE @NAME/file.block can use, NAME defaults to the directory name
+    #[arg(long, value_name = "[NAME=]DIR")]
+    template_dir: Vec<Box<str>>,
+
+    /// What to do with symbolic links in the input directory, followed links never leave it
+    #[arg(long, value_name = "POLICY", default_value_t = Symlinks::Follow, value_enum)]
+    symlinks: Symlinks,
+
+    /// Also write log messages to a file
+    #[arg(long, value_name = "FILE")]
+    log_file: Option<Box<Path>>,
+
+    /// Verbosity of --log-file, independent of the terminal
+    #[arg(long, value_name = "LEVEL", default_value_t = LogLevel::Info, value_enum)]
+    log_file_level: LogLevel,
+
+    /// What to do with an existing --log-file
+    #[arg(long, value_name = "MODE", default_value_t = LogMode::Truncate, value_enum)]
+    log_file_mode: LogMode,
+
+    /// What using an undefined variable turns into, error also fails the build
+    #[arg(long, value_name = "MODE", default_value_t = UndefinedVars::Empty, value_enum)]
+    undefined_vars: UndefinedVars,
+
+    /// Fail on undefined variables, unknown directives, missing includes, invalid HTML and any other warning
+    #[arg(long)]
+    strict: bool,
+
+    /// Text undefined variables turn into with --undefined-vars placeholder
+    #[arg(long, value_name = "TEXT", default_value = "UNDEFINED")]
+    undefined_placeholder: Box<str>,
+
+    /// Maximum number of loop iterations in a single page, 0 for no limit
+    #[arg(long, value_name = "N", default_value_t = 10000)]
+    max_iterations: usize,
+
+    /// Maximum number of included files in a single page, 0 for no limit
+    #[arg(long, value_name = "N", default_value_t = 200)]
+    max_includes: usize,
+
+    /// Build fewer pages at once so they are estimated to use at most MB megabytes of memory, 0 for no limit
+    #[arg(long, value_name = "MB", default_value_t = 0)]
+    max_memory: u64,
+
+    /// Run a subcommand instead of building the site
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/* COMMANDS */
+/// Subcommands, running ssgen without one builds the site
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    /// List output pages that would change if the site was built now, without writing anything
+    Changed {
+        /// Manifest file of a previous build, or a git revision of the input directory
+        #[arg(long, value_name = "MANIFEST|REV")]
+        since: Box<str>,
+    },
+
+    /// Compare the output of two builds, listing the files that differ and the words that changed in pages
+    Diff {
+        /// Output directory of the older build
+        old: PathBuf,
+
+        /// Output directory of the newer build, the output directory by default
+        new: Option<PathBuf>,
+    },
+
+    /// Check every page for problems without writing anything, failing if there are any
+    Lint,
+
+    /// Upload the output directory to where the site is hosted, only sending what changed since the last deploy
+    Deploy {
+        /// rsync destination like user@host:/srv/www, or an S3 bucket like s3://bucket/prefix
+        #[arg(long, value_name = "DEST")]
+        target: Box<str>,
+
+        /// URL of S3-compatible storage to upload to instead of AWS
+        #[arg(long, value_name = "URL")]
+        endpoint: Option<Box<str>>,
+
+        /// Print the files that would be uploaded (U) and deleted (D) without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Compare pages under tests/ in the input directory against snapshots of their expected HTML
+    Test {
+        /// Write the snapshots of new and changed pages instead of failing
+        #[arg(long)]
+        update: bool,
+    },
+
+    /// Create a starter project in the input directory
+    Init {
+        /// Kind of project to create
+        #[arg(long, value_enum, default_value_t = Template::Minimal)]
+        template: Template,
+    },
+
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate completions for
+        shell: Shell,
+    },
+
+    /// Print a man page
+    Man,
 }
 
 impl Args {
@@ -115,59 +560,115 @@ impl Args {
     /// Registers variables that are derived from arguments and creates global objects like the logger
     /// Is also a sanity check for arguments that ensures basic functionality won't be interrupted
     /// Does the following:
-    /// - Set up logger
+    /// - Set up logger, and the log file if there is one
     /// - Canonicalise paths
     /// - Ensure input directory is not the same as output directory
     pub fn build_options(self) -> Options {
         // Set up logger
-
-        let prog = Arc::new(MultiProgress::new());
-        let drain = ProgressDrain::new(
-            prog.clone(),
-            if self.debug {
-                Level::Debug
-            } else if self.verbose {
-                Level::Info
-            } else if self.quiet {
-                Level::Error
-            } else if self.silent {
-                Level::Critical
-            } else {
-                Level::Warning
+        let (file, file_err) = match &self.log_file {
+            Some(p) => match filedrain::open(p, self.log_file_mode) {
+                Ok(f) => (Some(f), None),
+                Err(e) => (None, Some(e)),
             },
+            None => (None, None),
+        };
+
+        // logs and progress bars go to stderr, which is not a terminal in CI or when redirected to a file
+        let terminal = io::stderr().is_terminal();
+        // NO_COLOR and CLICOLOR_FORCE are handled by colored itself
+        if self.no_color || (!terminal && env::var_os("CLICOLOR_FORCE").is_none()) {
+            colored::control::set_override(false);
+        }
+        let prog = Arc::new(match terminal && !self.summary {
+            true => MultiProgress::new(),
+            false => MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        });
+        let level = if self.debug {
+            Level::Debug
+        } else if self.verbose {
+            Level::Info
+        } else if self.quiet || self.summary {
+            Level::Error
+        } else if self.silent {
+            Level::Critical
+        } else {
+            Level::Warning
+        };
+        let drain = ProgressDrain::new(prog.clone(), level, self.log_format);
+        let drain = slog::Duplicate::new(
+            drain,
+            FileDrain::new(file, self.log_file_level.into(), self.log_format),
         );
-        let drain = Async::new(drain)
+        let drain = Async::new(drain.ignore_res())
             .overflow_strategy(OverflowStrategy::Block)
             .chan_size(1024)
-            .build()
-            .fuse();
+            .build();
+        let diagnostics = Arc::new(Diagnostics::default());
+        let drain = CountingDrain::new(drain, diagnostics.clone()).fuse();
         let log = slog::Logger::root(drain, o!());
 
         slog::debug!(log, "Logger built!");
 
         let mut exit = false;
 
+        // logging to a file was asked for, so not being able to is fatal
+        match (&self.log_file, file_err) {
+            (Some(path), Some(e)) => {
+                slog::error!(
+                    log,
+                    "Error opening log file '{path}' '{e}'",
+                    path = path.display(),
+                );
+                exit = true;
+            }
+            _ => (),
+        }
+
+        // subcommands do not require --input, but everything that builds Options does apart from diff,
+        // which only compares output directories
+        let input_arg: Box<Path> = match &self.input {
+            Some(i) => i.clone(),
+            None if matches!(self.command, Some(Command::Diff { .. })) => Path::new("./").into(),
+            None => {
+                slog::error!(log, "An input directory is required, set it with --input");
+                exit = true;
+                Path::new("").into()
+            }
+        };
+
+        // init creates the input directory
+        if matches!(self.command, Some(Command::Init { .. })) {
+            match fs::create_dir_all(&input_arg) {
+                Ok(()) => (),
+                Err(e) => slog::error!(
+                    log,
+                    "Error creating input directory '{path}' '{e}'",
+                    path = &input_arg.display(),
+                ),
+            }
+        }
+
         // canonicalise paths
         slog::debug!(log, "Canonicalising paths...");
-        let input = match fs::canonicalize(&self.input) {
+        let input = match paths::canonicalize(&input_arg) {
             Ok(p) => p,
             Err(e) => {
                 slog::error!(
                     log,
                     "Error canonicalizing input path '{path}' '{e}'",
-                    path = &self.input.display(),
+                    path = &input_arg.display(),
                 );
                 exit = true;
-                self.input.to_path_buf()
+                input_arg.to_path_buf()
             }
         };
-        let output = match fs::canonicalize(&self.output) {
+        let output = match paths::canonicalize(&self.output) {
             Ok(p) => p,
             Err(e) => {
                 slog::error!(
                     log,
                     "Error canonicalizing output path '{path}' '{e}'",
-                    path = &self.input.display(),
+                    path = &self.output.display(),
                 );
 
                 exit = true;
@@ -175,16 +676,85 @@ impl Args {
             }
         };
 
+        // template directories, by the name they are included with
+        let mut template_dirs = Vec::<(Box<str>, PathBuf)>::new();
+        for t in &self.template_dir {
+            let (name, dir) = match t.split_once('=') {
+                Some((n, d)) => (n.into(), Path::new(d)),
+                None => (
+                    Path::new(&t[..])
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into())
+                        .unwrap_or_default(),
+                    Path::new(&t[..]),
+                ),
+            };
+            match paths::canonicalize(dir) {
+                Ok(d) if template_dirs.iter().all(|(n, _)| n != &name) => {
+                    template_dirs.push((name, d))
+                }
+                Ok(_) => {
+                    slog::error!(log, "Template directory @{name} is defined twice!");
+                    exit = true;
+                }
+                Err(e) => {
+                    slog::error!(
+                        log,
+                        "Error canonicalizing template directory '{path}' '{e}'",
+                        path = dir.display(),
+                    );
+                    exit = true;
+                }
+            }
+        }
+
         // give important info
         if self.enable_shell {
             slog::info!(log, "Shell directivr enabled! Tread carefully...");
         }
 
         // sanity check
-        if output == input {
+        if output == input && self.input.is_some() {
             slog::error!(log, "Output directory is the same as Input directory!");
             exit = true;
         }
+        if (self.clean || self.clean_all) && input.starts_with(&output) {
+            slog::error!(
+                log,
+                "Refusing to clean the output directory because it contains the input directory!"
+            );
+            exit = true;
+        }
+        if self.purge_cdn.is_some() && !self.allow_network {
+            slog::error!(
+                log,
+                "Purging a CDN requires network requests to be enabled with --allow-network!"
+            );
+            exit = true;
+        }
+
+        // time of the build, see https://reproducible-builds.org/specs/source-date-epoch/
+        let build_time = match std::env::var("SOURCE_DATE_EPOCH").map(|v| v.parse::<i64>()) {
+            Ok(Ok(t)) => t,
+            Ok(Err(e)) => {
+                slog::error!(log, "SOURCE_DATE_EPOCH is not a valid unix time '{e}'");
+                exit = true;
+                0
+            }
+            Err(_) if self.reproducible => {
+                slog::warn!(
+                    log,
+                    "SOURCE_DATE_EPOCH is not set, reproducible builds use the unix epoch as the build time"
+                );
+                0
+            }
+            // snapshots would go stale every second otherwise
+            Err(_) if matches!(self.command, Some(Command::Test { .. })) => 0,
+            Err(_) => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0),
+        };
 
         if exit {
             slog::error!(
@@ -198,13 +768,84 @@ impl Args {
             panic!("Sanity check fail panic");
         }
 
+        // builds are stamped with it, and templates can link to it
+        let commit = git::head_commit(&input);
+
         // done
         return Options {
             input: input,
             output: output,
-            logger: Box::new(log),
+            logger: Box::new(log.clone()),
+            page_logger: Box::new(log),
             progress: prog,
+            status_lines: !terminal
+                && self.log_format == LogFormat::Text
+                && Level::Warning.is_at_least(level),
+            summary: self.summary,
+            diagnostics: diagnostics,
+            writes: Arc::new(Writes::default()),
+            include_cache: Arc::new(IncludeCache::default()),
+            streams: Arc::new(Streams::default()),
+            fetch_cache: Arc::new(FetchCache::default()),
+            favicon_cache: Arc::new(FaviconCache::default()),
+            git_history: Arc::new(GitHistory::default()),
+            timings: match self.profile_templates {
+                true => Some(Arc::new(Timings::default())),
+                false => None,
+            },
             allow_shell: self.enable_shell,
+            incremental: self.incremental,
+            legacy_scoping: self.legacy_scoping,
+            dry_run: matches!(
+                self.command,
+                Some(Command::Changed { .. }) | Some(Command::Lint) | Some(Command::Test { .. })
+            ),
+            lint: matches!(self.command, Some(Command::Lint)),
+            strict: self.strict,
+            allow_network: self.allow_network,
+            fetch_cache_dir: self.fetch_cache,
+            fetch_timeout: self.fetch_timeout,
+            fetch_ttl: self.fetch_ttl,
+            offline: self.offline,
+            base_url: self.base_url,
+            title: "".into(),
+            language: "".into(),
+            profile: self.profile,
+            commit: commit,
+            stamp: self.stamp,
+            site_vars: BTreeMap::new(),
+            purge_cdn: self.purge_cdn,
+            server_config: self.server_config,
+            redirects: Vec::new(),
+            headers: Vec::new(),
+            asset_hooks: Vec::new(),
+            pre_build: Vec::new(),
+            post_build: Vec::new(),
+            html_filter: None,
+            diagram_renderers: BTreeMap::new(),
+            webmanifest: None,
+            service_worker: Vec::new(),
+            robots: None,
+            heading_ids: self.heading_ids,
+            transforms: self.transform,
+            external_links: ExternalLinks::default(),
+            reproducible: self.reproducible,
+            clean: self.clean,
+            clean_all: self.clean_all,
+            precompress: self.precompress,
+            fail_fast: self.fail_fast,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            build_time: build_time,
+            symlinks: self.symlinks,
+            template_dirs: template_dirs,
+            undefined_vars: match self.strict {
+                true => UndefinedVars::Error,
+                false => self.undefined_vars,
+            },
+            undefined_placeholder: self.undefined_placeholder,
+            max_iterations: self.max_iterations,
+            max_includes: self.max_includes,
+            max_memory: self.max_memory * 1024 * 1024,
         };
     }
 }
@@ -224,6 +865,21 @@ mod tests {
         error!(o, "Test error");
     }
 
+    /// Ensure a page's options log with their own logger
+    #[test]
+    fn test_for_page() {
+        let o: Options = Args::parse_from(["", "--input", "./", "--output", "/tmp/", "--silent"])
+            .build_options();
+        let page = o.for_page("index.page");
+        assert_eq!(page.input, o.input);
+        assert_eq!(format!("{:?}", page.logger.list()), "(page)");
+        warn!(page, "Test warn");
+        let source = page
+            .for_source("index.page:1:1")
+            .for_source("index.page:2:1");
+        assert_eq!(format!("{:?}", source.logger.list()), "(source, page)");
+    }
+
     /// Ensure built options struct makes sense
     #[test]
     #[should_panic(expected = "Sanity check fail panic")]
@@ -246,4 +902,11 @@ mod tests {
         ])
         .build_options();
     }
+
+    /// Ensure diff works without an input directory, where the output directory defaults to the current one
+    #[test]
+    fn test_diff_without_input() {
+        let o: Options = Args::parse_from(["", "-s", "diff", "/tmp", "/tmp"]).build_options();
+        assert_eq!(o.output, paths::canonicalize(Path::new("./")).unwrap());
+    }
 }
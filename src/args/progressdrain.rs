@@ -8,7 +8,7 @@
 //! use std::sync::Arc;
 //!
 //! let prog = Arc::new(MultiProgress::new());
-//! let drain = ProgressDrain::new(prog.clone(), Level::Info);
+//! let drain = ProgressDrain::new(prog.clone(), Level::Info, LogFormat::Text);
 //! let drain = slog_async::Async::new(drain).build().fuse();
 //! let log = slog::Logger::root(drain, o!());
 //!
@@ -25,7 +25,14 @@
 use colored::Colorize;
 use indicatif::MultiProgress;
 use slog::{Drain, Level, Never, OwnedKVList, Record};
-use std::{result::Result, sync::Arc};
+use std::{
+    collections::HashSet,
+    result::Result,
+    sync::{Arc, Mutex},
+};
+
+/* LOCAL IMPORTS */
+use super::logformat::{self, LogFormat};
 
 /* PROGRESSDRAIN */
 /// Slog Drain with progressbar support using indicatif::MultiProgress
@@ -34,14 +41,20 @@ pub struct ProgressDrain {
     progress: Arc<MultiProgress>,
     /// Verbosity level to log at
     level: Level,
+    /// Format of printed records
+    format: LogFormat,
+    /// Warnings and errors printed so far
+    seen: Mutex<HashSet<String>>,
 }
 
 impl ProgressDrain {
     /// Create a new ProgressDrain from given arguments
-    pub fn new(prog: Arc<MultiProgress>, level: Level) -> ProgressDrain {
+    pub fn new(prog: Arc<MultiProgress>, level: Level, format: LogFormat) -> ProgressDrain {
         return ProgressDrain {
             progress: prog,
             level: level,
+            format: format,
+            seen: Mutex::new(HashSet::new()),
         };
     }
 }
@@ -50,14 +63,23 @@ impl Drain for ProgressDrain {
     type Ok = ();
     type Err = Never;
 
-    /// Log to stdout while not interrupting progressbar
-    fn log(&self, record: &Record, _: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+    /// Log to stderr while not interrupting progressbar
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
         if self.level < record.level() {
             return Ok(());
         };
+        if self.format == LogFormat::Json {
+            let s = logformat::json(record, values);
+            self.progress.suspend(|| eprintln!("{s}"));
+            return Ok(());
+        }
+        // repeated messages are counted in the summary at the end instead, unless asked to be verbose
+        if self.level < Level::Info && !self.seen.lock().unwrap().insert(record.msg().to_string()) {
+            return Ok(());
+        }
         let levelstr = format!("{}", record.level());
         let s = format!(
-            "[{level}] {message}",
+            "[{level}] {message}{values}",
             level = match record.level() {
                 Level::Error => levelstr.red(),
                 Level::Warning => levelstr.yellow(),
@@ -65,19 +87,17 @@ impl Drain for ProgressDrain {
                 Level::Debug => levelstr.green(),
                 _ => levelstr.into(),
             },
-            message = record.msg()
+            message = record.msg(),
+            values = logformat::text_values(record, values).dimmed(),
         );
 
         // debug build log formatting
         #[cfg(debug_assertions)]
         let s = s + format!(
-            " {location}",
-            location = format!(
-                "{file}:{line}:{column}",
-                file = record.file(),
-                line = record.line(),
-                column = record.column()
-            )
+            " {file}:{line}:{column}",
+            file = record.file(),
+            line = record.line(),
+            column = record.column()
         )
         .italic()
         .bold()
@@ -85,7 +105,8 @@ impl Drain for ProgressDrain {
         .to_string()
         .as_str();
 
-        self.progress.println(s).unwrap();
+        // println would do nothing while progress bars are hidden
+        self.progress.suspend(|| eprintln!("{s}"));
         return Ok(());
     }
 }
@@ -101,7 +122,7 @@ mod tests {
         use slog::{o, Level};
         use std::sync::Arc;
         let prog = Arc::new(MultiProgress::new());
-        let drain = ProgressDrain::new(prog.clone(), Level::Trace);
+        let drain = ProgressDrain::new(prog.clone(), Level::Trace, LogFormat::Text);
         let log = slog::Logger::root(drain, o!());
         slog::crit!(log, "Critical test");
         slog::error!(log, "Error test");
@@ -109,6 +130,10 @@ mod tests {
         slog::info!(log, "Info test");
         slog::debug!(log, "Debug test");
         slog::trace!(log, "Trace test");
+
+        let drain = ProgressDrain::new(prog.clone(), Level::Trace, LogFormat::Json);
+        let log = slog::Logger::root(drain, o!("page" => "test.page"));
+        slog::error!(log, "Error test");
     }
 
     /// Ensure progress bar works properly
@@ -118,7 +143,7 @@ mod tests {
         use slog::{o, Level};
         use std::sync::Arc;
         let prog = Arc::new(MultiProgress::new());
-        let drain = ProgressDrain::new(prog.clone(), Level::Critical);
+        let drain = ProgressDrain::new(prog.clone(), Level::Critical, LogFormat::Text);
         let log = slog::Logger::root(drain, o!());
 
         slog::info!(log, "log loop test");
@@ -0,0 +1,141 @@
+//! Write files into the output directory without ever leaving them half written
+//!
+//! Every file is written to a temporary file next to it first, then renamed into place.
+//! If the build is interrupted, the temporary files are removed and no more renames happen,
+//! so every output file is either the old one or the new one
+//! ```
+//! let writes = Writes::default();
+//! writes.write(Path::new("public/index.html"), b"<p>hi</p>")?;
+//! writes.roll_back(); // on Ctrl-C
+//! ```
+
+/* IMPORTS */
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/* WRITES */
+/// Suffix of temporary files, which clean_output removes if one is ever left behind
+const TMP_SUFFIX: &str = ".ssgen-tmp";
+
+/// Files being written into the output directory
+#[derive(Default)]
+pub struct Writes {
+    /// Temporary files that have not been renamed into place yet
+    pending: Mutex<BTreeSet<PathBuf>>,
+    /// Whether the build was interrupted, after which nothing is written
+    interrupted: AtomicBool,
+    /// Number of files written so far
+    written: AtomicUsize,
+}
+
+impl Writes {
+    /// Write data to path by way of a temporary file, creating any missing directories
+    pub fn write(&self, path: &Path, mut data: &[u8]) -> io::Result<()> {
+        return self.write_from(path, &mut data);
+    }
+
+    /// Write everything read from data to path by way of a temporary file, without holding all of it in memory
+    pub fn write_from(&self, path: &Path, data: &mut dyn Read) -> io::Result<()> {
+        let tmp = match path.file_name() {
+            Some(name) => {
+                let mut name = name.to_os_string();
+                name.push(TMP_SUFFIX);
+                path.with_file_name(format!(".{}", name.to_string_lossy()))
+            }
+            None => return Err(io::Error::other("not a file")),
+        };
+        self.start(&tmp)?;
+        let written = match path.parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+        .and_then(|()| fs::File::create(&tmp))
+        .and_then(|mut f| io::copy(data, &mut f));
+
+        // renaming while holding the lock means roll_back can never miss a file that is about to appear
+        let mut pending = self.pending.lock().unwrap();
+        pending.remove(&tmp);
+        let renamed = match self.interrupted.load(Ordering::SeqCst) {
+            true => Err(interrupted()),
+            false => written.and_then(|_| fs::rename(&tmp, path)),
+        };
+        match renamed {
+            Ok(()) => {
+                self.written.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                let _ = fs::remove_file(&tmp);
+            }
+        }
+        return renamed;
+    }
+
+    /// Record that tmp is about to be written, unless the build was interrupted
+    fn start(&self, tmp: &Path) -> io::Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        if self.interrupted.load(Ordering::SeqCst) {
+            return Err(interrupted());
+        }
+        pending.insert(tmp.into());
+        return Ok(());
+    }
+
+    /// Stop writing files and remove the temporary files of the ones being written
+    pub fn roll_back(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        self.interrupted.store(true, Ordering::SeqCst);
+        for tmp in std::mem::take(&mut *pending) {
+            let _ = fs::remove_file(tmp);
+        }
+    }
+
+    /// Number of files written so far
+    pub fn written(&self) -> usize {
+        return self.written.load(Ordering::Relaxed);
+    }
+}
+
+/// Error returned by writes that happen after roll_back
+fn interrupted() -> io::Error {
+    return io::Error::new(io::ErrorKind::Interrupted, "the build was interrupted");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure files are written without leaving temporary files, and nothing is written after roll_back
+    #[test]
+    fn test_write() {
+        let dir = Path::new("/tmp/ssgen_test_dest_dir_writes");
+        let writes = Writes::default();
+        writes.write(&dir.join("a/b.html"), b"<p>a</p>").unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("a/b.html")).unwrap(),
+            "<p>a</p>"
+        );
+        assert_eq!(fs::read_dir(dir.join("a")).unwrap().count(), 1);
+        assert_eq!(writes.written(), 1);
+
+        writes.roll_back();
+        let e = writes
+            .write(&dir.join("a/b.html"), b"<p>b</p>")
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(
+            fs::read_to_string(dir.join("a/b.html")).unwrap(),
+            "<p>a</p>"
+        );
+        assert_eq!(fs::read_dir(dir.join("a")).unwrap().count(), 1);
+        assert_eq!(writes.written(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1642 @@
+//! Steps for building a site from the input directory
+//!
+//! Each step is usable on its own so that subcommands can render pages without writing any output
+//! ```
+//! let pages = build::find_pages(&o);
+//! let meta = build::parse_meta(o.clone(), &pages);
+//! let (html, entry) = build::render_page(o.clone(), meta.clone(), &pages[0], None);
+//! let fragment = build::render_fragment(&o, "p: '{x}'", vars, Path::new("site/components"))?;
+//! ```
+
+/* IMPORTS */
+use indicatif::ProgressBar;
+use pathdiff::diff_paths;
+use regex::Regex;
+use serde_yaml::{Mapping, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    hash::{BuildHasher, Hasher, RandomState},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, OnceLock},
+};
+
+/* LOCAL IMPORTS */
+use crate::{
+    buildinfo, commands, debug, error, git, hooks, info, manifest, pagenode, parser::yaml, paths,
+    warn, Manifest, Options, PageEntry, PageNode, Parser, Var,
+};
+
+/* BUILD */
+/// Template every tag page is built from, see render_tag_page
+pub const TAG_TEMPLATE: &str = "TAG.yaml";
+
+/// Directory in the output directory tag pages are written to
+const TAG_DIR: &str = "tags";
+
+/// Stand-ins for page.word_count and page.reading_time, replaced once the page is rendered
+///
+/// Private use characters are left alone by every directive, even !UPPERCASE
+const WORD_COUNT: &str = "\u{E000}1\u{E000}";
+const READING_TIME: &str = "\u{E000}2\u{E000}";
+
+/// Starts and ends the stand-in of a file whose contents are copied into the page as it is written, see streamed
+const STREAMED: char = '\u{E001}';
+
+/// Reading speed page.reading_time is estimated with
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Length of excerpts taken from the text of a page
+const EXCERPT_WORDS: usize = 50;
+
+/// Result of parsing META.yaml, shared by every page
+#[derive(Clone, Default)]
+pub struct Meta {
+    /// Global variables defined in META.yaml
+    pub vars: HashMap<Box<str>, Var>,
+
+    /// META.yaml and every file it read, as manifest keys
+    pub dependencies: Vec<Box<str>>,
+
+    /// Every file META.yaml wrote into the output directory mapped to its source, as manifest keys
+    pub artifacts: BTreeMap<Box<str>, Box<str>>,
+
+    /// Rewrite rules applied to every page, see directives::rewrite
+    pub rewrites: Vec<(Regex, Box<str>)>,
+
+    /// Environment variables META.yaml read, with their values
+    pub env_vars: BTreeMap<Box<str>, Option<Box<str>>>,
+
+    /// Hash of vars, see site_hash
+    pub hash: Box<str>,
+
+    /// Excerpts of the pages, see excerpt_var
+    pub excerpts: Arc<Excerpts>,
+}
+
+/// Excerpts of the pages, shared by site.pages and page.excerpt of every page
+#[derive(Default)]
+pub struct Excerpts {
+    /// Variables excerpts are rendered with, set once META.yaml is parsed, see excerpt_vars
+    vars: OnceLock<HashMap<Box<str>, Var>>,
+
+    /// Excerpt of every page that has been asked for, so no page is rendered for it twice
+    made: Mutex<HashMap<PathBuf, Var>>,
+}
+
+/// Directory of the input directory holding the test pages of `ssgen test`, which are not part of the site
+pub const TESTS_DIR: &str = "tests";
+
+/// Find every page file in the input directory, except for the test pages in TESTS_DIR
+pub fn find_pages(o: &Options) -> Vec<PathBuf> {
+    let tests_dir = paths::join_slash(&o.input, TESTS_DIR);
+    return find_all_pages(o)
+        .into_iter()
+        .filter(|p| !p.starts_with(&tests_dir))
+        .collect();
+}
+
+/// Find every page file in the input directory, test pages included
+pub fn find_all_pages(o: &Options) -> Vec<PathBuf> {
+    info!(o, "Walking input directory");
+    let mut pages = Vec::<PathBuf>::new();
+    let walkspin = o.progress.add(ProgressBar::new_spinner());
+    for entry in paths::walk_files(&o.input, &o.input, o.symlinks) {
+        match entry {
+            // match any file that ends with .page (case insensitive)
+            Ok(path) if is_page(&path) => {
+                debug!(o, "Found file {}", path.display());
+                walkspin.tick();
+                pages.push(path);
+            }
+            Ok(_) => (),
+            Err(e) => error!(o, "Error finding file {}", e),
+        }
+    }
+    walkspin.finish();
+    return pages;
+}
+
+/// Whether path is a page file
+fn is_page(path: &Path) -> bool {
+    return match path.file_name() {
+        Some(name) => name.to_string_lossy().to_lowercase().ends_with(".page"),
+        None => false,
+    };
+}
+
+/// Parse the special "META.yaml" file, if it exists
+///
+/// The `site` variable is available while parsing, and META.yaml's variables are added to it as `site.meta`
+pub fn parse_meta(o: Arc<Options>, pages: &[PathBuf]) -> Meta {
+    let mut meta_file: PathBuf = o.input.clone();
+    meta_file.push("META.yaml");
+    let mut meta = Meta::default();
+    let mut site = site_var(&o, pages, &meta.excerpts);
+    if !meta_file.exists() {
+        info!(o, "META.yaml not found! Creating empty root node");
+        meta.vars = o
+            .site_vars
+            .iter()
+            .map(|(k, v)| (k.clone(), Var::Str(v.clone())))
+            .collect();
+        meta.vars.insert("site".into(), site);
+        meta.hash = site_hash(&meta.vars);
+        let _ = meta.excerpts.vars.set(excerpt_vars(&meta.vars));
+        return meta;
+    }
+
+    info!(o, "META.yaml found! Parsing...");
+    let o = Arc::new(o.for_page(&manifest::input_key(&o, &meta_file)));
+    match fs::read_to_string(meta_file.clone()) {
+        Ok(s) => {
+            let mut vars: HashMap<Box<str>, Var> = o
+                .site_vars
+                .iter()
+                .map(|(k, v)| (k.clone(), Var::Str(v.clone())))
+                .collect();
+            vars.insert("site".into(), site.clone());
+            let mut parser = Parser::new_with_vars(o.clone(), vars);
+            parser.set_source(&manifest::input_key(&o, &meta_file));
+            parser.parse_yaml(s.as_str());
+            // every page depends on META.yaml and whatever it reads
+            meta.dependencies.push(manifest::input_key(&o, &meta_file));
+            parser
+                .get_dependencies()
+                .iter()
+                .for_each(|d| meta.dependencies.push(manifest::input_key(&o, d)));
+            let meta_key = manifest::input_key(&o, &meta_file);
+            parser.get_artifacts().iter().for_each(|(a, source)| {
+                meta.artifacts.insert(
+                    manifest::output_key(&o, a),
+                    match source {
+                        Some(s) => manifest::input_key(&o, s),
+                        None => meta_key.clone(),
+                    },
+                );
+            });
+            meta.rewrites = parser.get_rewrites();
+            meta.env_vars = parser.get_env_vars();
+            meta.vars = PageNode::consume_into_vars(Parser::consume_into_root_node(parser));
+        }
+        Err(e) => {
+            panic!("Unable to read META.yaml despite file existing, please ensure permissions are correct: {e}");
+        }
+    }
+
+    // expose META.yaml's variables through site.meta, sorted so the order is stable
+    let mut meta_vars: Vec<(Box<str>, Var)> = meta
+        .vars
+        .iter()
+        .filter(|(k, v)| &k[..] != "site" && !matches!(v, Var::Component(_)))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    meta_vars.sort_by(|a, b| a.0.cmp(&b.0));
+    match &mut site {
+        Var::Map(m) => m.push(("meta".into(), Var::Map(meta_vars))),
+        _ => (),
+    }
+    meta.vars.insert("site".into(), site);
+    meta.hash = site_hash(&meta.vars);
+    let _ = meta.excerpts.vars.set(excerpt_vars(&meta.vars));
+    return meta;
+}
+
+/// Copy of vars to render excerpts with, see excerpt_var
+///
+/// site.pages and site.tags are left out, since they hold the excerpts themselves
+fn excerpt_vars(vars: &HashMap<Box<str>, Var>) -> HashMap<Box<str>, Var> {
+    return vars
+        .iter()
+        .map(|(k, v)| match (&k[..], v) {
+            ("site", Var::Map(m)) => (
+                k.clone(),
+                Var::Map(
+                    m.iter()
+                        .filter(|(k, _)| !matches!(&k[..], "pages" | "tags"))
+                        .cloned()
+                        .collect(),
+                ),
+            ),
+            _ => (k.clone(), v.clone()),
+        })
+        .collect();
+}
+
+/// Hash of the variables every page sees, leaving out site.build which is different for every build
+///
+/// When it changes, like when a page is added to site.pages, any page could render differently
+pub fn site_hash(vars: &HashMap<Box<str>, Var>) -> Box<str> {
+    let mut names: Vec<&Box<str>> = vars.keys().collect();
+    names.sort();
+    let mut text = String::new();
+    for name in names {
+        let var = match (&name[..], &vars[name]) {
+            ("site", Var::Map(m)) => Var::Map(
+                m.iter()
+                    .filter(|(k, _)| &k[..] != "build")
+                    .cloned()
+                    .collect(),
+            ),
+            (_, v) => v.clone(),
+        };
+        text += &format!("{name:?}=");
+        var_text(&var, &mut text);
+        text.push('\n');
+    }
+    return hash(text.as_bytes());
+}
+
+/// Write var to out in a form that tells apart every two different variables, for site_hash
+fn var_text(var: &Var, out: &mut String) {
+    match var {
+        Var::Str(s) => *out += &format!("s{s:?}"),
+        Var::Lazy(s) => *out += &format!("l{s:?}"),
+        Var::List(l) => {
+            out.push('[');
+            for v in l {
+                var_text(v, out);
+                out.push(',');
+            }
+            out.push(']');
+        }
+        Var::Map(m) => {
+            out.push('{');
+            for (k, v) in m {
+                *out += &format!("{k:?}:");
+                var_text(v, out);
+                out.push(',');
+            }
+            out.push('}');
+        }
+        Var::Component(c) => {
+            *out += &format!(
+                "c{:?}{:?}{:?}",
+                c.params,
+                c.dir,
+                serde_yaml::to_string(&c.body).unwrap_or_default()
+            )
+        }
+        Var::Deferred(d) => *out += &format!("d{:?}", d.key),
+    }
+}
+
+/// Build the `site` variable, which describes the whole site to templates
+///
+/// Contains:
+/// - `site.base_url`: URL the site is served from, if set with --base-url or in !SITE
+/// - `site.title` and `site.language`: as set in !SITE, see settings::apply
+/// - `site.profile`: profile chosen with --profile, if any
+/// - `site.pages`: every page, each with a `source`, `output`, `url`, `title` and `excerpt`, see excerpt_var,
+///   along with its metadata, see page_metadata
+/// - `site.tags`: every tag given in the `_tags` of a page, see tags_var
+/// - `site.build`: the `version` of ssgen, the unix `time` of the build, see Options::build_time,
+///   and the git `commit` of the input directory if there is one
+pub fn site_var(o: &Options, pages: &[PathBuf], excerpts: &Arc<Excerpts>) -> Var {
+    let base_url = o.base_url.as_deref().unwrap_or("").trim_end_matches('/');
+    let mut sorted = pages.to_vec();
+    sorted.sort();
+    let pages = sorted
+        .iter()
+        .map(|p| {
+            let output = manifest::output_key(o, &output_path(o, p));
+            let stem = p.file_stem().unwrap_or_default().to_string_lossy();
+            let mut entry: Vec<(Box<str>, Var)> = vec![
+                ("source".into(), Var::Str(manifest::input_key(o, p))),
+                (
+                    "url".into(),
+                    Var::Str(format!("{base_url}/{output}").into()),
+                ),
+                ("output".into(), Var::Str(output)),
+                ("title".into(), Var::Str(stem.into())),
+                ("excerpt".into(), excerpt_var(o, excerpts, p)),
+            ];
+            for (k, v) in page_metadata(p) {
+                match entry.iter_mut().find(|(name, _)| *name == k) {
+                    Some((name, old)) if &name[..] == "title" => *old = v,
+                    Some(_) => (),
+                    None => entry.push((k, v)),
+                }
+            }
+            Var::Map(entry)
+        })
+        .collect::<Vec<Var>>();
+    let tags = tags_var(o, &pages);
+    return Var::Map(vec![
+        ("base_url".into(), Var::Str(base_url.into())),
+        ("title".into(), Var::Str(o.title.clone())),
+        ("language".into(), Var::Str(o.language.clone())),
+        (
+            "profile".into(),
+            Var::Str(o.profile.clone().unwrap_or_default()),
+        ),
+        ("pages".into(), Var::List(pages)),
+        ("tags".into(), tags),
+        (
+            "build".into(),
+            Var::Map(vec![
+                ("version".into(), Var::Str(env!("CARGO_PKG_VERSION").into())),
+                ("time".into(), Var::Str(o.build_time.to_string().into())),
+                (
+                    "commit".into(),
+                    Var::Str(o.commit.clone().unwrap_or_default()),
+                ),
+            ]),
+        ),
+    ]);
+}
+
+/// Every tag of the pages in site.pages, sorted by name
+///
+/// Each tag has a `name`, `slug`, `count` and the `pages` that have it.
+/// If there is a TAG.yaml to build a page for each tag from, its `url` and `output` are there too, see render_tag_page
+pub fn tags_var(o: &Options, pages: &[Var]) -> Var {
+    let mut tags = BTreeMap::<Box<str>, Vec<Var>>::new();
+    for page in pages {
+        for name in page_tags(page) {
+            tags.entry(name).or_default().push(page.clone());
+        }
+    }
+    let template = paths::join_slash(&o.input, TAG_TEMPLATE).exists();
+    let base_url = o.base_url.as_deref().unwrap_or("").trim_end_matches('/');
+    return Var::List(
+        tags.into_iter()
+            .map(|(name, pages)| {
+                let slug: Box<str> = pagenode::slugify(&name).into();
+                let mut tag = vec![
+                    ("name".into(), Var::Str(name)),
+                    ("slug".into(), Var::Str(slug.clone())),
+                    ("count".into(), Var::Str(pages.len().to_string().into())),
+                ];
+                if template {
+                    let output = format!("{TAG_DIR}/{slug}.html");
+                    tag.push((
+                        "url".into(),
+                        Var::Str(format!("{base_url}/{output}").into()),
+                    ));
+                    tag.push(("output".into(), Var::Str(output.into())));
+                }
+                tag.push(("pages".into(), Var::List(pages)));
+                Var::Map(tag)
+            })
+            .collect(),
+    );
+}
+
+/// Tags of an entry in site.pages, which can be a list or a single tag
+pub fn page_tags(page: &Var) -> Vec<Box<str>> {
+    return match page.get_path(".tags") {
+        Some(Var::List(l)) => l
+            .iter()
+            .filter_map(|t| match t {
+                Var::Str(t) => Some(t.clone()),
+                _ => None,
+            })
+            .collect(),
+        Some(Var::Str(s)) if s.len() != 0 => vec![s.clone()],
+        _ => Vec::new(),
+    };
+}
+
+/// Metadata given at the top level of a page, such as `_title: Home`, without the leading underscore
+///
+/// Values are taken as written, without variables or directives, since every page reads the metadata of every other page.
+/// Pages that cannot be read or parsed have none, building them reports why
+pub fn page_metadata(page: &Path) -> Vec<(Box<str>, Var)> {
+    let docs = match fs::read_to_string(page).map(|s| yaml::parse(&s)) {
+        Ok(Ok(d)) => d,
+        _ => return Vec::new(),
+    };
+    let mut metadata: Vec<(Box<str>, Var)> = Vec::new();
+    for doc in docs.values.iter() {
+        let maps: Vec<&Mapping> = match doc {
+            Value::Mapping(m) => vec![m],
+            Value::Sequence(s) => s.iter().filter_map(|v| v.as_mapping()).collect(),
+            _ => Vec::new(),
+        };
+        for (k, v) in maps.into_iter().flatten() {
+            match k.as_str().and_then(|k| k.strip_prefix('_')) {
+                Some(k) => metadata.push((k.into(), Var::from_value(v))),
+                None => (),
+            }
+        }
+    }
+    return metadata;
+}
+
+/// First paragraph of a rendered page
+static PARAGRAPH: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<p(?:\s[^>]*)?>(.*?)</p>").unwrap());
+
+/// Elements of a rendered page whose text is not shown
+static HIDDEN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)<head\b.*?</head>|<script\b.*?</script>|<style\b.*?</style>").unwrap()
+});
+
+/// Tags of a rendered page
+static MARKUP: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// page.excerpt of page, its `_excerpt` metadata if it has one or else its rendered text, see rendered_excerpt
+///
+/// Rendering waits until the excerpt is used, and excerpts used before META.yaml is parsed are empty
+fn excerpt_var(o: &Options, excerpts: &Arc<Excerpts>, page: &Path) -> Var {
+    match excerpts.made.lock().unwrap().get(page) {
+        Some(v) => return v.clone(),
+        None => (),
+    }
+    let excerpt = match page_metadata(page)
+        .into_iter()
+        .find(|(k, _)| &k[..] == "excerpt")
+    {
+        Some((_, v)) => v,
+        None => {
+            // the excerpt only changes with the page, or with META.yaml which is hashed anyway
+            let key = format!("excerpt {}", fs::read(page).map_or("".into(), |b| hash(&b)));
+            let (o, excerpts, page) = (o.clone(), Arc::downgrade(excerpts), page.to_path_buf());
+            Var::deferred(&key, move || {
+                match excerpts.upgrade().as_ref().and_then(|e| e.vars.get()) {
+                    Some(vars) => Var::Str(rendered_excerpt(&o, vars.clone(), &page).into()),
+                    None => Var::Str("".into()),
+                }
+            })
+        }
+    };
+    excerpts
+        .made
+        .lock()
+        .unwrap()
+        .insert(page.to_path_buf(), excerpt.clone());
+    return excerpt;
+}
+
+/// Text of the first paragraph of page rendered with vars, or of the whole page if it has none, see excerpt_of
+///
+/// Nothing is written, and problems are left for building the page itself to report
+fn rendered_excerpt(o: &Options, vars: HashMap<Box<str>, Var>, page: &Path) -> String {
+    let output = output_path(o, page);
+    let (o, _) = o.for_page(&manifest::input_key(o, page)).catching();
+    let o = Arc::new(Options { dry_run: true, ..o });
+    let mut parser = page_parser(&o, vars, Var::Str("".into()), page, &output, None);
+    parser.apply_transforms();
+    let html = parser.to_string();
+    let text: String = stand_ins(&o, &html).into_iter().map(|(t, _)| t).collect();
+    return excerpt_of(&text.replace(WORD_COUNT, "").replace(READING_TIME, ""));
+}
+
+/// Text of the first paragraph in html, or of all of html if it has none, cut off after EXCERPT_WORDS words
+fn excerpt_of(html: &str) -> String {
+    let text = match PARAGRAPH.captures(html) {
+        Some(c) => MARKUP.replace_all(&c[1], "").into_owned(),
+        None => MARKUP
+            .replace_all(&HIDDEN.replace_all(html, " "), " ")
+            .into_owned(),
+    };
+    let words: Vec<&str> = text.split_whitespace().collect();
+    return match words.len() > EXCERPT_WORDS {
+        true => format!("{}…", words[..EXCERPT_WORDS].join(" ")),
+        false => words.join(" "),
+    };
+}
+
+/// Render a single page into a complete HTML document without writing it
+///
+/// Returns the document along with the manifest entry describing it.
+/// The page can use its own `{page.word_count}` and `{page.reading_time}` in minutes, which are filled in once it is rendered,
+/// its `{page.excerpt}` like listings of it can, see excerpt_var, its `{page.source}` in the input directory
+/// and its `{page.output}` in the output directory
+pub fn render_page(
+    o: Arc<Options>,
+    meta: Meta,
+    page: &Path,
+    progressbar: Option<Arc<ProgressBar>>,
+) -> (String, PageEntry) {
+    let output = output_path(&o, page);
+    return render(o, meta, page, &output, progressbar);
+}
+
+/// Another version of a page, built from the same source with different variables
+pub struct Variant {
+    /// Name of the variant, which is part of the name of its output file
+    pub name: Box<str>,
+    /// Variables the variant defines
+    pub vars: Vec<(Box<str>, Var)>,
+}
+
+/// Variants of a page besides the regular one
+///
+/// A page declares them in its metadata, either as a list of names or as a mapping of names to variables
+/// ```YAML
+/// _variants: [print]
+/// _variants: {print: {layout: print}, amp: {layout: amp, scripts: ''}}
+/// ```
+pub fn variants(o: &Options, page: &Path) -> Vec<Variant> {
+    let declared = match page_metadata(page)
+        .into_iter()
+        .find(|(k, _)| &k[..] == "variants")
+    {
+        Some((_, v)) => v,
+        None => return Vec::new(),
+    };
+    return match declared {
+        Var::List(l) => l
+            .into_iter()
+            .filter_map(|v| match v {
+                Var::Str(name) => Some(Variant {
+                    name: name,
+                    vars: Vec::new(),
+                }),
+                _ => None,
+            })
+            .collect(),
+        Var::Map(m) => m
+            .into_iter()
+            .map(|(name, vars)| Variant {
+                name: name,
+                vars: match vars {
+                    Var::Map(vars) => vars,
+                    _ => Vec::new(),
+                },
+            })
+            .collect(),
+        Var::Str(name) if name.len() != 0 => vec![Variant {
+            name: name,
+            vars: Vec::new(),
+        }],
+        _ => Vec::new(),
+    }
+    .into_iter()
+    .filter(|v| {
+        // the name ends up in a file name
+        let valid = pagenode::slugify(&v.name) == v.name[..];
+        if !valid {
+            warn!(
+                o,
+                "Variant {} of {} must only contain lowercase letters, digits and -",
+                v.name,
+                manifest::input_key(o, page)
+            );
+        }
+        valid
+    })
+    .collect();
+}
+
+/// Manifest key of a variant of the page with the key page
+pub fn variant_key(page: &str, variant: &str) -> Box<str> {
+    return format!("{page}#{variant}").into();
+}
+
+/// Render a variant of a page into a complete HTML document without writing it
+///
+/// The variant is written next to the regular page with its name before the extension, like `index.print.html`.
+/// It has `{variant}` set to its name, which is empty for the regular page, and the variables it declares, see variants
+pub fn render_variant(
+    o: Arc<Options>,
+    mut meta: Meta,
+    page: &Path,
+    variant: &Variant,
+) -> (String, PageEntry) {
+    let mut output = output_path(&o, page);
+    output.set_extension(format!("{}.html", variant.name));
+    meta.vars
+        .insert("variant".into(), Var::Str(variant.name.clone()));
+    meta.vars.extend(variant.vars.iter().cloned());
+    return render(o, meta, page, &output, None);
+}
+
+/// Render the page of a single tag in site.tags from TAG.yaml, which has it as the `tag` variable
+///
+/// Returns the document along with the manifest entry describing it.
+/// Tag pages depend on every page, so they are built again by every build
+pub fn render_tag_page(o: Arc<Options>, mut meta: Meta, tag: &Var) -> (String, PageEntry) {
+    let template = paths::join_slash(&o.input, TAG_TEMPLATE);
+    let output = match tag.get_path(".output") {
+        Some(Var::Str(out)) => paths::join_slash(&o.output, out),
+        _ => output_path(&o, &template),
+    };
+    meta.vars.insert("tag".into(), tag.clone());
+    return render(o, meta, &template, &output, None);
+}
+
+/// Tags in site.tags that get a page, which is all of them if there is a TAG.yaml
+pub fn tag_pages(meta: &Meta) -> Vec<Var> {
+    let tags = match meta.vars.get("site").and_then(|s| s.get_path(".tags")) {
+        Some(Var::List(tags)) => tags.clone(),
+        _ => return Vec::new(),
+    };
+    return tags
+        .into_iter()
+        .filter(|t| t.get_path(".output").is_some())
+        .collect();
+}
+
+/// Why page is not built, if its metadata says it is not
+///
+/// - `_skip: true` never builds the page
+/// - `_only_profiles: [production]` only builds it for the profiles listed, see --profile
+/// - `_only_if: NAME` only builds it if the variable NAME from META.yaml is set and not empty
+pub fn skip_reason(o: &Options, meta: &Meta, page: &Path) -> Option<String> {
+    for (k, v) in page_metadata(page) {
+        match (&k[..], v) {
+            ("skip", Var::Str(s)) if &s[..] == "true" => return Some("_skip is set".into()),
+            ("only_profiles", v) => {
+                let profiles: Vec<Box<str>> = match v {
+                    Var::List(l) => l
+                        .into_iter()
+                        .filter_map(|p| match p {
+                            Var::Str(p) => Some(p),
+                            _ => None,
+                        })
+                        .collect(),
+                    Var::Str(p) => vec![p],
+                    _ => Vec::new(),
+                };
+                if !profiles.iter().any(|p| Some(p) == o.profile.as_ref()) {
+                    return Some(format!(
+                        "it is only built for the profiles {}",
+                        profiles.join(", ")
+                    ));
+                }
+            }
+            ("only_if", Var::Str(name)) => {
+                let (root, path) = match name.find(['.', '[']) {
+                    Some(i) => name.split_at(i),
+                    None => (&name[..], ""),
+                };
+                let set = match meta.vars.get(root).and_then(|v| v.get_path(path)) {
+                    Some(Var::Str(s)) => s.len() != 0,
+                    Some(Var::List(l)) => l.len() != 0,
+                    Some(Var::Map(m)) => m.len() != 0,
+                    Some(_) => true,
+                    None => false,
+                };
+                if !set {
+                    return Some(format!("{name} is not set"));
+                }
+            }
+            _ => (),
+        }
+    }
+    return None;
+}
+
+/// Leave out the pages that are not built, see skip_reason
+///
+/// site.pages and site.tags are made again without them, so nothing links to a page that does not exist
+pub fn skip_pages(o: &Options, meta: &mut Meta, pages: &mut Vec<PathBuf>) {
+    let before = pages.len();
+    pages.retain(|page| match skip_reason(o, meta, page) {
+        Some(reason) => {
+            info!(o, "Skipping {}, {reason}", manifest::input_key(o, page));
+            false
+        }
+        None => true,
+    });
+    if pages.len() == before {
+        return;
+    }
+    let mut site = site_var(o, pages, &meta.excerpts);
+    let meta_vars = meta
+        .vars
+        .get("site")
+        .and_then(|s| s.get_path(".meta"))
+        .cloned();
+    match (&mut site, meta_vars) {
+        (Var::Map(m), Some(v)) => m.push(("meta".into(), v)),
+        _ => (),
+    }
+    meta.vars.insert("site".into(), site);
+    meta.hash = site_hash(&meta.vars);
+}
+
+/// Render page into a complete HTML document that will be written to output
+fn render(
+    o: Arc<Options>,
+    meta: Meta,
+    page: &Path,
+    output: &Path,
+    progressbar: Option<Arc<ProgressBar>>,
+) -> (String, PageEntry) {
+    // everything logged while building the page says which page it was
+    let o = Arc::new(o.for_page(&manifest::input_key(&o, page)));
+    let excerpt = excerpt_var(&o, &meta.excerpts, page);
+    let mut parser = page_parser(&o, meta.vars, excerpt, page, output, progressbar);
+    if o.heading_ids {
+        parser.add_heading_ids();
+    }
+    parser.apply_transforms();
+    let mut html = format!("<!DOCTYPE html>\n{}", parser);
+    let text: String = stand_ins(&o, &html).into_iter().map(|(t, _)| t).collect();
+    let words = pagenode::word_count(&text.replace(WORD_COUNT, "").replace(READING_TIME, ""));
+    html = html
+        .replace(WORD_COUNT, &words.to_string())
+        .replace(READING_TIME, &words.div_ceil(WORDS_PER_MINUTE).to_string());
+    // rewrite rules and the HTML filter have to see streamed files as well
+    if meta.rewrites.len() + parser.get_rewrites().len() != 0 || o.html_filter.is_some() {
+        html = unstreamed(&o, html);
+    }
+    for (pattern, replacement) in meta.rewrites.iter().chain(parser.get_rewrites().iter()) {
+        html = pattern.replace_all(&html, &replacement[..]).into_owned();
+    }
+    html = buildinfo::stamp(&o, html);
+    html = hooks::filter_html(&o, html);
+    // strict builds fail on invalid HTML, which lint only warns about for every page already
+    if o.strict && !o.lint {
+        let mut problems: Vec<Box<str>> = commands::duplicate_ids(&html)
+            .iter()
+            .map(|id| format!(r#"Duplicate id "{id}""#).into())
+            .collect();
+        problems.extend(commands::unbalanced_tags(&html));
+        for problem in problems {
+            error!(o, "{problem}");
+            o.diagnostics.fail();
+        }
+    }
+
+    // record what the page was built from
+    let mut dependencies = meta.dependencies;
+    parser
+        .get_dependencies()
+        .iter()
+        .for_each(|d| dependencies.push(manifest::input_key(&o, d)));
+    let mut env_vars = meta.env_vars;
+    env_vars.extend(parser.get_env_vars());
+    let mut artifacts = meta.artifacts;
+    parser.get_artifacts().iter().for_each(|(a, source)| {
+        artifacts.insert(
+            manifest::output_key(&o, a),
+            match source {
+                Some(s) => manifest::input_key(&o, s),
+                None => manifest::input_key(&o, page),
+            },
+        );
+    });
+    let entry = PageEntry {
+        dependencies: dependencies,
+        output: manifest::output_key(&o, output),
+        hash: match stand_ins(&o, &html).len() {
+            1 => hash(html.as_bytes()),
+            // the streamed files are part of the output, even though they are not part of html
+            _ => spliced(&o, &html)
+                .and_then(hash_reader)
+                .unwrap_or_else(|e| {
+                    error!(o, "Error reading streamed file | {e}");
+                    hash(html.as_bytes())
+                }),
+        },
+        artifacts: artifacts,
+        env: env_vars,
+        profile: o.profile.clone(),
+        site: meta.hash,
+    };
+    return (html, entry);
+}
+
+/// Parser holding page, which besides vars gets the `page`, `git` and `variant` variables
+fn page_parser(
+    o: &Arc<Options>,
+    mut vars: HashMap<Box<str>, Var>,
+    excerpt: Var,
+    page: &Path,
+    output: &Path,
+    progressbar: Option<Arc<ProgressBar>>,
+) -> Parser {
+    vars.entry("variant".into()).or_insert(Var::Str("".into()));
+    // the page is not rendered yet, so its statistics are placeholders until it is
+    vars.insert(
+        "page".into(),
+        Var::Map(vec![
+            ("source".into(), Var::Str(manifest::input_key(o, page))),
+            ("output".into(), Var::Str(manifest::output_key(o, output))),
+            ("word_count".into(), Var::Str(WORD_COUNT.into())),
+            ("reading_time".into(), Var::Str(READING_TIME.into())),
+            ("excerpt".into(), excerpt),
+        ]),
+    );
+    // last commit that changed the page, if the input directory is in a git repository
+    // the history is only read if the page uses it
+    let (git_o, git_page) = (o.clone(), page.to_path_buf());
+    vars.insert(
+        "git".into(),
+        Var::deferred(&manifest::input_key(o, page), move || {
+            git::page_var(&git_o, &git_page)
+        }),
+    );
+    let mut parser = Parser::new_with_vars(o.clone(), vars);
+    let mut root_file = page.to_path_buf();
+    root_file.pop();
+    parser.set_root_dir(root_file);
+    parser.set_source(&manifest::input_key(o, page));
+    parser.set_output(output.to_path_buf());
+    match progressbar {
+        Some(pb) => parser.add_progressbar(pb),
+        None => (),
+    }
+    // read input
+    info!(o, "Reading file {}", page.display());
+    match fs::read_to_string(page) {
+        Ok(yaml) => parser.parse_yaml(yaml.as_str()),
+        Err(e) => error!(o, "Error reading file {f} | {e}", f = page.display()),
+    }
+    return parser;
+}
+
+/// Render a piece of YAML into HTML without a DOCTYPE, as if it were a page in root
+///
+/// Includes are resolved against root and cannot leave it, and nothing is ever written or copied.
+/// Invalid YAML is returned as an error instead of panicking like a page would
+pub fn render_fragment(
+    o: &Options,
+    yaml: &str,
+    vars: HashMap<Box<str>, Var>,
+    root: &Path,
+) -> Result<String, Box<str>> {
+    let root = paths::canonicalize(root)
+        .map_err(|e| format!("Error canonicalizing root '{r}' '{e}'", r = root.display()))?;
+    let o = Arc::new(Options {
+        input: root.clone(),
+        dry_run: true,
+        ..o.clone()
+    });
+    let mut parser = Parser::new_with_vars(o.clone(), vars);
+    parser.set_root_dir(root);
+    parser.try_parse_yaml(yaml)?;
+    if o.heading_ids {
+        parser.add_heading_ids();
+    }
+    parser.apply_transforms();
+    let mut html = parser.to_string();
+    for (pattern, replacement) in parser.get_rewrites().iter() {
+        html = pattern.replace_all(&html, &replacement[..]).into_owned();
+    }
+    return Ok(html);
+}
+
+/// Write a rendered page to its output file, creating any missing directories
+///
+/// The page is renamed into place once it is complete, see Writes.
+/// An output file that already has the same contents is left alone, so its modification time only changes along with it
+pub fn write_page(o: &Options, out_f: &Path, html: &str) {
+    let old = fs::File::open(out_f).and_then(hash_reader);
+    let new = spliced(o, html).and_then(hash_reader);
+    if matches!((old, new), (Ok(old), Ok(new)) if old == new) {
+        debug!(o, "Unchanged file {}", out_f.display());
+        return;
+    }
+    info!(o, "Writing file {}", out_f.display());
+    match spliced(o, html).and_then(|mut r| o.writes.write_from(out_f, &mut r)) {
+        Ok(()) => (),
+        Err(e) => error!(o, "Error writing file {f} | {e}", f = out_f.display()),
+    }
+}
+
+/// Files streamed into pages during the build, see streamed
+///
+/// Pages only hold the index of a file here, behind a marker made up for every build,
+/// so text that happens to look like a stand-in cannot name a file of its own
+pub struct Streams {
+    marker: Box<str>,
+    files: Mutex<Vec<PathBuf>>,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        // RandomState is seeded randomly, which is all the marker needs
+        let nonce = RandomState::new().build_hasher().finish();
+        return Streams {
+            marker: format!("{STREAMED}{nonce:016x}").into(),
+            files: Mutex::new(Vec::new()),
+        };
+    }
+}
+
+/// Stand-in for the contents of file, which are copied into the page when it is written instead of being held in memory
+///
+/// Word counts do not see the contents, anything else reading the text has to get it through unstreamed
+pub fn streamed(o: &Options, file: &Path) -> String {
+    let mut files = o.streams.files.lock().unwrap();
+    files.push(file.to_path_buf());
+    return format!("{}{}{STREAMED}", o.streams.marker, files.len() - 1);
+}
+
+/// Text with the contents of streamed files in place of their stand-ins, held in memory after all
+///
+/// A file that cannot be read is left out
+pub fn unstreamed(o: &Options, text: String) -> String {
+    if stand_ins(o, &text).len() == 1 {
+        return text;
+    }
+    let mut out = String::with_capacity(text.len());
+    return match spliced(o, &text).and_then(|mut r| r.read_to_string(&mut out)) {
+        Ok(_) => out,
+        Err(e) => {
+            error!(o, "Error reading streamed file | {e}");
+            stand_ins(o, &text).into_iter().map(|(t, _)| t).collect()
+        }
+    };
+}
+
+/// The text of html between stand-ins, each with the file of the stand-in that follows it, the last one without
+///
+/// Anything that looks like a stand-in but is not one of this build's is left in the text
+fn stand_ins<'a>(o: &Options, html: &'a str) -> Vec<(&'a str, Option<PathBuf>)> {
+    let marker = &o.streams.marker[..];
+    let files = o.streams.files.lock().unwrap();
+    let mut parts = Vec::new();
+    let (mut start, mut search) = (0, 0);
+    while let Some(i) = html[search..].find(marker) {
+        let at = search + i + marker.len();
+        let index = html[at..].split_once(STREAMED).map(|(n, _)| n);
+        match index.and_then(|n| Some((n, files.get(n.parse::<usize>().ok()?)?))) {
+            Some((n, file)) => {
+                parts.push((&html[start..search + i], Some(file.clone())));
+                start = at + n.len() + STREAMED.len_utf8();
+                search = start;
+            }
+            None => search = at,
+        }
+    }
+    parts.push((&html[start..], None));
+    return parts;
+}
+
+/// Contents of a rendered page, reading streamed files in place of their stand-ins
+fn spliced<'a>(o: &Options, html: &'a str) -> io::Result<Box<dyn Read + 'a>> {
+    let mut reader: Box<dyn Read> = Box::new(io::empty());
+    for (text, file) in stand_ins(o, html) {
+        reader = Box::new(reader.chain(text.as_bytes()));
+        match file {
+            Some(f) => reader = Box::new(reader.chain(fs::File::open(f)?)),
+            None => (),
+        }
+    }
+    return Ok(reader);
+}
+
+/// Remove everything inside of the output directory, keeping the directory itself
+pub fn wipe_output(o: &Options) {
+    info!(o, "Removing everything in {}", o.output.display());
+    let entries = match fs::read_dir(&o.output) {
+        Ok(e) => e,
+        Err(e) => {
+            error!(o, "Unable to read {f} | {e}", f = o.output.display());
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        debug!(o, "Removing {}", path.display());
+        let removed = match entry.file_type() {
+            Ok(t) if t.is_dir() => fs::remove_dir_all(&path),
+            _ => fs::remove_file(&path),
+        };
+        match removed {
+            Ok(()) => (),
+            Err(e) => error!(o, "Unable to remove {f} | {e}", f = path.display()),
+        }
+    }
+}
+
+/// Remove every file the previous build wrote that this one did not, along with directories left empty
+///
+/// Only outputs listed in the old manifest are touched, anything else in the output directory was not written by ssgen
+pub fn clean_output(o: &Options, old: &Manifest, new: &Manifest) {
+    info!(o, "Removing files that this build no longer produces");
+    let keep = new.outputs();
+    for key in old.outputs().difference(&keep) {
+        // the manifest is read from disk, so its keys are not trusted to stay inside the output directory
+        if Path::new(&key[..])
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            warn!(
+                o,
+                "Not removing {key}, it is outside of the output directory"
+            );
+            continue;
+        }
+        let path = paths::join_slash(&o.output, key);
+        match fs::symlink_metadata(&path) {
+            Ok(m) if !m.is_dir() => (),
+            _ => continue,
+        }
+        info!(o, "Removing stale file {}", path.display());
+        match fs::remove_file(&path) {
+            Ok(()) => (),
+            Err(e) => {
+                error!(o, "Unable to remove {f} | {e}", f = path.display());
+                continue;
+            }
+        }
+        // directories that are not empty fail to be removed, which is where this stops
+        let mut dir = path.parent();
+        while let Some(d) = dir {
+            if d == o.output || !d.starts_with(&o.output) || fs::remove_dir(d).is_err() {
+                break;
+            }
+            debug!(o, "Removed empty directory {}", d.display());
+            dir = d.parent();
+        }
+    }
+}
+
+/// Get the path of the HTML file a page in the input directory gets written to
+pub fn output_path(o: &Options, page: &Path) -> PathBuf {
+    let mut out_f = o.output.clone();
+    out_f.push(diff_paths(page, o.input.clone()).unwrap());
+    out_f.set_extension("html");
+    return out_f;
+}
+
+/// Get the hex encoded SHA-256 hash of some data
+pub fn hash(data: &[u8]) -> Box<str> {
+    return hex(&Sha256::digest(data));
+}
+
+/// Get the hex encoded SHA-256 hash of everything in r, without holding all of it in memory
+pub fn hash_reader(mut r: impl Read) -> io::Result<Box<str>> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut r, &mut hasher)?;
+    return Ok(hex(&hasher.finalize()));
+}
+
+/// Hex encoding of a hash
+fn hex(digest: &[u8]) -> Box<str> {
+    return digest
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>()
+        .into();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{settings, var::UndefinedVars, Args};
+    use clap::Parser;
+
+    /// Ensure a page can be rendered on its own using variables from META.yaml
+    #[test]
+    fn test_render_page() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_build/sub").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_build").unwrap();
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                "/tmp/ssgen_test_source_dir_build",
+                "-o",
+                "/tmp/ssgen_test_dest_dir_build",
+                "-s",
+            ])
+            .build_options(),
+        );
+        fs::write(
+            "/tmp/ssgen_test_source_dir_build/META.yaml",
+            "!DEF [x, meta]",
+        )
+        .unwrap();
+        fs::write("/tmp/ssgen_test_source_dir_build/sub/a.page", "p: '{x}'").unwrap();
+
+        let pages = find_pages(&o);
+        assert_eq!(pages.len(), 1);
+        let meta = parse_meta(o.clone(), &pages);
+        let (html, entry) = render_page(o.clone(), meta, &pages[0], None);
+        assert_eq!(html, "<!DOCTYPE html>\n<p>meta</p>");
+        assert_eq!(&entry.output[..], "sub/a.html");
+        assert_eq!(entry.dependencies, vec!["META.yaml".into()]);
+        assert_eq!(entry.hash, hash(html.as_bytes()));
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_build").unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_build").unwrap();
+    }
+
+    /// Ensure strict builds fail on what is otherwise a warning, and on missing includes and invalid HTML
+    #[test]
+    fn test_strict() {
+        let dir = "/tmp/ssgen_test_source_dir_strict";
+        fs::create_dir_all(dir).unwrap();
+        fs::write(format!("{dir}/a.page"), "[p: {_id: x}, p: {_id: x}]").unwrap();
+        fs::write(format!("{dir}/b.page"), "p: !FOREAHC x").unwrap();
+        fs::write(format!("{dir}/c.page"), "p: !INCLUDE missing.yaml").unwrap();
+        fs::write(format!("{dir}/d.page"), "div: !INCLUDE_RAW open.html").unwrap();
+        fs::write(format!("{dir}/open.html"), "<section>").unwrap();
+        let options = |strict: bool| {
+            let mut args = vec!["", "-i", dir, "-o", "/tmp/", "-s"];
+            if strict {
+                args.push("--strict");
+            }
+            Arc::new(Args::parse_from(args).build_options())
+        };
+        let render = |o: &Arc<Options>, page: &str| {
+            let meta = parse_meta(o.clone(), &[]);
+            render_page(o.clone(), meta, Path::new(&format!("{dir}/{page}")), None);
+        };
+
+        let o = options(false);
+        for page in ["a.page", "b.page", "d.page"] {
+            render(&o, page);
+        }
+        assert_eq!(o.diagnostics.counts(), (1, 0));
+        render(&o, "c.page");
+        assert_eq!(o.diagnostics.failed(), false);
+
+        // every check fails the build on its own
+        for page in ["a.page", "b.page", "c.page", "d.page"] {
+            let o = options(true);
+            assert_eq!(o.undefined_vars, UndefinedVars::Error);
+            render(&o, page);
+            assert_eq!(o.diagnostics.failed(), true);
+        }
+        let o = options(true);
+        render(&o, "a.page");
+        render(&o, "d.page");
+        assert_eq!(o.diagnostics.counts(), (0, 2));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Ensure fragments render without a DOCTYPE, with includes confined to their root
+    #[test]
+    fn test_render_fragment() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_fragment/components").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_fragment").unwrap();
+        let o = Args::parse_from([
+            "",
+            "-i",
+            "/tmp/ssgen_test_source_dir_fragment",
+            "-o",
+            "/tmp/ssgen_test_dest_dir_fragment",
+            "-s",
+        ])
+        .build_options();
+        fs::write(
+            "/tmp/ssgen_test_source_dir_fragment/components/greeting.block",
+            "p: Hello, {name}!",
+        )
+        .unwrap();
+        fs::write("/tmp/ssgen_test_source_dir_fragment/secret.txt", "secret").unwrap();
+        let root = Path::new("/tmp/ssgen_test_source_dir_fragment/components");
+        let vars = HashMap::from([("name".into(), Var::Str("World".into()))]);
+
+        let html = render_fragment(&o, "div: !INCLUDE greeting.block", vars.clone(), root);
+        assert_eq!(html.unwrap(), "<div><p>Hello, World!</p></div>");
+        let html = render_fragment(&o, "!INCLUDE_RAW ../secret.txt", vars.clone(), root);
+        assert_eq!(html.unwrap(), "");
+        assert_eq!(
+            render_fragment(&o, "p: [", vars.clone(), root).is_err(),
+            true
+        );
+        assert_eq!(
+            render_fragment(&o, "p: a", vars, Path::new("/tmp/ssgen_test_missing")).is_err(),
+            true
+        );
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_fragment").unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_fragment").unwrap();
+    }
+
+    /// Ensure rewrite rules from META.yaml and the page apply to the finished page, in order
+    #[test]
+    fn test_rewrite() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_rewrite").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_rewrite").unwrap();
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                "/tmp/ssgen_test_source_dir_rewrite",
+                "-o",
+                "/tmp/ssgen_test_dest_dir_rewrite",
+                "-s",
+            ])
+            .build_options(),
+        );
+        fs::write(
+            "/tmp/ssgen_test_source_dir_rewrite/META.yaml",
+            r#"[!DEF [v, 2], !REWRITE ['(\w+)\.css', '$1.css?v={v}'], !REWRITE ['staging\.', '']]"#,
+        )
+        .unwrap();
+        fs::write(
+            "/tmp/ssgen_test_source_dir_rewrite/a.page",
+            r#"[{link: {_href: /style.css}}, https://staging.example.com, !REWRITE ['\?v=2', '?v=3']]"#,
+        )
+        .unwrap();
+
+        let pages = find_pages(&o);
+        let meta = parse_meta(o.clone(), &pages);
+        let (html, entry) = render_page(o.clone(), meta, &pages[0], None);
+        assert_eq!(
+            html,
+            "<!DOCTYPE html>\n<link href=\"/style.css?v=3\"/>https://example.com"
+        );
+        assert_eq!(entry.hash, hash(html.as_bytes()));
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_rewrite").unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_rewrite").unwrap();
+    }
+
+    /// Ensure the site variable describes every page and META.yaml
+    #[test]
+    fn test_site_var() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_site/sub").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_site").unwrap();
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                "/tmp/ssgen_test_source_dir_site",
+                "-o",
+                "/tmp/ssgen_test_dest_dir_site",
+                "-s",
+                "--base-url",
+                "https://example.com/",
+            ])
+            .build_options(),
+        );
+        fs::write(
+            "/tmp/ssgen_test_source_dir_site/META.yaml",
+            "[!SITE {title: Example}, !DEF [title, '{site.base_url}']]",
+        )
+        .unwrap();
+        let o = Arc::new(settings::apply(&o));
+        fs::write("/tmp/ssgen_test_source_dir_site/a.page", "_title: Apple").unwrap();
+        fs::write(
+            "/tmp/ssgen_test_source_dir_site/sub/b.page",
+            "[!FOREACH_VAR [site.pages, p, '{p.title}>{p.url};'], '{site.meta.title}', ' {site.title}']",
+        )
+        .unwrap();
+        // test pages of `ssgen test` are not part of the site
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_site/tests").unwrap();
+        fs::write("/tmp/ssgen_test_source_dir_site/tests/t.page", "p: t").unwrap();
+
+        let pages = find_pages(&o);
+        assert_eq!(find_all_pages(&o).len(), pages.len() + 1);
+        let meta = parse_meta(o.clone(), &pages);
+        let b = pages.iter().find(|p| p.ends_with("b.page")).unwrap();
+        let (html, entry) = render_page(o.clone(), meta.clone(), b, None);
+        assert_eq!(
+            html,
+            "<!DOCTYPE html>\nApple>https://example.com/a.html;b>https://example.com/sub/b.html;https://example.com Example"
+        );
+        assert_eq!(entry.site, meta.hash);
+
+        // pages listing the site have to be built again when a page is added, but not for every build
+        let mut later = (*o).clone();
+        later.build_time += 60;
+        assert_eq!(parse_meta(Arc::new(later), &pages).hash, meta.hash);
+        fs::write("/tmp/ssgen_test_source_dir_site/c.page", "_title: Cherry").unwrap();
+        assert_ne!(parse_meta(o.clone(), &find_pages(&o)).hash, meta.hash);
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_site").unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_site").unwrap();
+    }
+
+    /// Ensure a page can show its own word count and reading time
+    #[test]
+    fn test_page_stats() {
+        let dir = "/tmp/ssgen_test_source_dir_stats";
+        fs::create_dir_all(dir).unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", dir, "-o", "/tmp/", "-s"]).build_options());
+        fs::write(
+            format!("{dir}/a.page"),
+            "[h1: !UPPERCASE '{page.word_count} words, {page.reading_time} min', p: one two three, script: var x = 1]",
+        )
+        .unwrap();
+        let (html, _) = render_page(
+            o.clone(),
+            Meta::default(),
+            Path::new(&format!("{dir}/a.page")),
+            None,
+        );
+        assert_eq!(
+            html,
+            "<!DOCTYPE html>\n<h1>5 WORDS, 1 MIN</h1><p>one two three</p><script>var x = 1</script>"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Ensure excerpts come from the first rendered paragraph without markup, unless a page has its own
+    #[test]
+    fn test_excerpt() {
+        assert_eq!(
+            excerpt_of("<head><title>T</title></head><h1>Title</h1><div><p class=\"x\">Hello <b>world</b>!</p><p>second</p></div>"),
+            "Hello world!"
+        );
+        assert_eq!(
+            excerpt_of(
+                "<head><title>T</title></head><h1>Title</h1><pre>some</pre>text<script>x</script>"
+            ),
+            "Title some text"
+        );
+        assert_eq!(
+            excerpt_of(&format!("<p>{}</p>", "word ".repeat(60))),
+            format!("{}…", ["word"; 50].join(" "))
+        );
+
+        let dir = "/tmp/ssgen_test_source_dir_excerpt";
+        fs::create_dir_all(dir).unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_excerpt").unwrap();
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                dir,
+                "-o",
+                "/tmp/ssgen_test_dest_dir_excerpt",
+                "-s",
+            ])
+            .build_options(),
+        );
+        fs::write(format!("{dir}/META.yaml"), "!DEF [name, World]").unwrap();
+        fs::write(format!("{dir}/part.yaml"), "b: '{x}'").unwrap();
+        fs::write(
+            format!("{dir}/a.page"),
+            "[!DEF [x, again], h1: '{site.title}', p: ['Hello {name}, ', !INCLUDE part.yaml], p: second]",
+        )
+        .unwrap();
+        fs::write(format!("{dir}/b.page"), "[_excerpt: Own, p: text]").unwrap();
+        fs::write(
+            format!("{dir}/c.page"),
+            "[!FOREACH_VAR [site.pages, p, '{p.excerpt};'], ' {page.excerpt}']",
+        )
+        .unwrap();
+        let pages = find_pages(&o);
+        let meta = parse_meta(o.clone(), &pages);
+        let c = pages.iter().find(|p| p.ends_with("c.page")).unwrap();
+        let (html, _) = render_page(o.clone(), meta, c, None);
+        assert_eq!(html, "<!DOCTYPE html>\nHello World, again;Own;; ");
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_excerpt").unwrap();
+    }
+
+    /// Ensure tags are collected from every page, and each gets a page from TAG.yaml
+    #[test]
+    fn test_tag_pages() {
+        let dir = "/tmp/ssgen_test_source_dir_tags";
+        fs::create_dir_all(dir).unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_tags").unwrap();
+        let o = Arc::new(
+            Args::parse_from(["", "-i", dir, "-o", "/tmp/ssgen_test_dest_dir_tags", "-s"])
+                .build_options(),
+        );
+        fs::write(format!("{dir}/a.page"), "[{_tags: [Rust Lang, web]}, p: a]").unwrap();
+        fs::write(format!("{dir}/b.page"), "_tags: web").unwrap();
+        fs::write(
+            format!("{dir}/c.page"),
+            "!FOREACH_VAR [site.tags, t, '{t.name}:{t.count}:{t.url};']",
+        )
+        .unwrap();
+
+        // without a template, tags are only listed
+        let pages = find_pages(&o);
+        let meta = parse_meta(o.clone(), &pages);
+        assert_eq!(tag_pages(&meta).len(), 0);
+
+        fs::write(
+            format!("{dir}/{TAG_TEMPLATE}"),
+            "[h1: '{tag.name}', !FOREACH_VAR [tag.pages, p, '{p.title};']]",
+        )
+        .unwrap();
+        let meta = parse_meta(o.clone(), &pages);
+        let c = pages.iter().find(|p| p.ends_with("c.page")).unwrap();
+        let (html, _) = render_page(o.clone(), meta.clone(), c, None);
+        assert_eq!(
+            html,
+            "<!DOCTYPE html>\nRust Lang:1:/tags/rust-lang.html;web:2:/tags/web.html;"
+        );
+        let tags = tag_pages(&meta);
+        assert_eq!(tags.len(), 2);
+        let (html, entry) = render_tag_page(o.clone(), meta, &tags[1]);
+        assert_eq!(html, "<!DOCTYPE html>\n<h1>web</h1>a;b;");
+        assert_eq!(entry.output, "tags/web.html".into());
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_tags").unwrap();
+    }
+
+    /// Ensure pages are skipped as their metadata says, and left out of site.pages
+    #[test]
+    fn test_skip_pages() {
+        let dir = "/tmp/ssgen_test_source_dir_skip";
+        fs::create_dir_all(dir).unwrap();
+        fs::write(format!("{dir}/META.yaml"), "!DEF [analytics, on]").unwrap();
+        fs::write(format!("{dir}/draft.page"), "[{_skip: true}, p: draft]").unwrap();
+        fs::write(format!("{dir}/prod.page"), "_only_profiles: [production]").unwrap();
+        fs::write(format!("{dir}/stats.page"), "_only_if: analytics").unwrap();
+        fs::write(format!("{dir}/beta.page"), "_only_if: site.meta.beta").unwrap();
+        fs::write(format!("{dir}/index.page"), "_skip: false").unwrap();
+        let args = ["", "-i", dir, "-o", "/tmp/", "-s"];
+        let built = |args: &[&str]| {
+            let o = Arc::new(Args::parse_from(args).build_options());
+            let mut pages = find_pages(&o);
+            let mut meta = parse_meta(o.clone(), &pages);
+            skip_pages(&o, &mut meta, &mut pages);
+            let mut built: Vec<Box<str>> =
+                pages.iter().map(|p| manifest::input_key(&o, p)).collect();
+            built.sort();
+            let site = &meta.vars["site"];
+            assert_eq!(
+                site.get_path(".pages")
+                    .map(|p| matches!(p, Var::List(l) if l.len() == built.len())),
+                Some(true)
+            );
+            assert_eq!(site.get_path(".meta.analytics").is_some(), true);
+            return built;
+        };
+        assert_eq!(built(&args), ["index.page".into(), "stats.page".into()]);
+        assert_eq!(
+            built(&[&args[..], &["--profile", "production"]].concat()),
+            ["index.page".into(), "prod.page".into(), "stats.page".into()]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Ensure variants are rendered with their own variables and output file, and invalid names are left out
+    #[test]
+    fn test_variants() {
+        let dir = "/tmp/ssgen_test_source_dir_variants";
+        fs::create_dir_all(dir).unwrap();
+        fs::write(
+            format!("{dir}/x.page"),
+            "[{_variants: {print: {layout: print}, Bad Name: {}}}, p: 'v={variant} l={layout}']",
+        )
+        .unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", dir, "-o", "/tmp/", "-s"]).build_options());
+        let pages = find_pages(&o);
+        let meta = parse_meta(o.clone(), &pages);
+        let page = Path::new(dir).join("x.page");
+        let variants = variants(&o, &page);
+        assert_eq!(variants.len(), 1);
+        assert_eq!(&variants[0].name[..], "print");
+
+        let (html, entry) = render_variant(o.clone(), meta.clone(), &page, &variants[0]);
+        assert_eq!(&entry.output[..], "x.print.html");
+        assert_eq!(html.contains("<p>v=print l=print</p>"), true);
+        let (html, entry) = render_page(o.clone(), meta, &page, None);
+        assert_eq!(&entry.output[..], "x.html");
+        assert_eq!(html.contains("<p>v= l="), true);
+        assert_eq!(&variant_key("x.page", "print")[..], "x.page#print");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Ensure only files the previous build produced and this one did not are removed from the output directory
+    #[test]
+    fn test_clean_output() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_clean").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_clean/kept").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_clean/stale/deeper").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_clean/.git").unwrap();
+        let o = Args::parse_from([
+            "",
+            "-i",
+            "/tmp/ssgen_test_source_dir_clean",
+            "-o",
+            "/tmp/ssgen_test_dest_dir_clean",
+            "-s",
+        ])
+        .build_options();
+        for f in [
+            "a.html",
+            "old.html",
+            "kept/copied.txt",
+            "stale/deeper/x.txt",
+            "notes.txt",
+            ".git/HEAD",
+            manifest::MANIFEST_FILE,
+        ] {
+            fs::write(format!("/tmp/ssgen_test_dest_dir_clean/{f}"), "").unwrap();
+        }
+        let entry = |output: &str, artifacts: &[&str]| PageEntry {
+            output: output.into(),
+            artifacts: artifacts
+                .iter()
+                .map(|a| (Box::<str>::from(*a), "copied.txt".into()))
+                .collect(),
+            ..Default::default()
+        };
+        let mut old = Manifest::default();
+        old.pages.insert(
+            "a.page".into(),
+            entry("a.html", &["kept/copied.txt", "stale/deeper/x.txt", "../x"]),
+        );
+        old.pages.insert("old.page".into(), entry("old.html", &[]));
+        let mut new = Manifest::default();
+        new.pages
+            .insert("a.page".into(), entry("a.html", &["kept/copied.txt"]));
+
+        clean_output(&o, &old, &new);
+        let exists = |f: &str| Path::new(&format!("/tmp/ssgen_test_dest_dir_clean/{f}")).exists();
+        assert_eq!(exists("a.html"), true);
+        assert_eq!(exists("kept/copied.txt"), true);
+        assert_eq!(exists(manifest::MANIFEST_FILE), true);
+        assert_eq!(exists("notes.txt"), true);
+        assert_eq!(exists(".git/HEAD"), true);
+        assert_eq!(exists("old.html"), false);
+        assert_eq!(exists("stale"), false);
+
+        wipe_output(&o);
+        assert_eq!(exists("a.html"), false);
+        assert_eq!(exists("kept"), false);
+        assert_eq!(exists(""), true);
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_clean").unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_clean").unwrap();
+    }
+
+    /// Ensure pages are only written when their contents change
+    #[test]
+    fn test_write_page() {
+        let dir = "/tmp/ssgen_test_dest_dir_write";
+        fs::create_dir_all(dir).unwrap();
+        let o = Args::parse_from(["", "-i", "./", "-o", dir, "-s"]).build_options();
+        let out_f = Path::new(dir).join("a/b.html");
+        write_page(&o, &out_f, "<p>a</p>");
+        assert_eq!(fs::read_to_string(&out_f).unwrap(), "<p>a</p>");
+
+        let old = std::time::SystemTime::UNIX_EPOCH;
+        let modified = || fs::metadata(&out_f).unwrap().modified().unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&out_f)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        write_page(&o, &out_f, "<p>a</p>");
+        assert_eq!(modified(), old);
+        write_page(&o, &out_f, "<p>b</p>");
+        assert_ne!(modified(), old);
+        assert_eq!(fs::read_to_string(&out_f).unwrap(), "<p>b</p>");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Ensure big raw includes are copied into the written page without being part of the rendered one
+    #[test]
+    fn test_streamed() {
+        let dir = "/tmp/ssgen_test_source_dir_streamed";
+        let dest = "/tmp/ssgen_test_dest_dir_streamed";
+        fs::create_dir_all(dir).unwrap();
+        fs::create_dir_all(dest).unwrap();
+        let dump = "word ".repeat(4 * 1024 * 1024);
+        fs::write(format!("{dir}/dump.txt"), &dump).unwrap();
+        fs::write(format!("{dir}/a.page"), "pre: !INCLUDE_RAW dump.txt").unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", dir, "-o", dest, "-s"]).build_options());
+        let page = Path::new(dir).join("a.page");
+        let (html, entry) = render_page(o.clone(), Meta::default(), &page, None);
+        assert_eq!(html.len() < 1000, true);
+        let expected = format!("<!DOCTYPE html>\n<pre>{dump}</pre>");
+        assert_eq!(entry.hash, hash(expected.as_bytes()));
+
+        let out_f = Path::new(dest).join("a.html");
+        write_page(&o, &out_f, &html);
+        assert_eq!(fs::read_to_string(&out_f).unwrap(), expected);
+
+        // directives reading the include get its contents, and so do rewrite rules
+        fs::write(
+            format!("{dir}/b.page"),
+            r#"
+- p: !UPPERCASE [!INCLUDE_RAW dump.txt]
+- !WRITE_FILE [/dump.txt, !INCLUDE_RAW dump.txt]
+- !REWRITE ['word </pre>', 'end</pre>']
+- pre: !INCLUDE_RAW dump.txt
+"#,
+        )
+        .unwrap();
+        let page = Path::new(dir).join("b.page");
+        let (html, _) = render_page(o.clone(), Meta::default(), &page, None);
+        assert_eq!(html.contains(STREAMED), false);
+        assert_eq!(html.starts_with("<!DOCTYPE html>\n<p>WORD WORD "), true);
+        assert_eq!(html.ends_with("word word end</pre>"), true);
+        assert_eq!(
+            fs::read_to_string(format!("{dest}/dump.txt")).unwrap(),
+            dump
+        );
+
+        // text cannot stream files by looking like a stand-in
+        fs::write(
+            format!("{dir}/c.page"),
+            format!("p: \"a\\uE001{dir}/dump.txt\\uE001b\\uE0010\\uE001\""),
+        )
+        .unwrap();
+        let page = Path::new(dir).join("c.page");
+        let (html, _) = render_page(o.clone(), Meta::default(), &page, None);
+        let expected = format!(
+            "<!DOCTYPE html>\n<p>a{STREAMED}{dir}/dump.txt{STREAMED}b{STREAMED}0{STREAMED}</p>"
+        );
+        assert_eq!(html, expected);
+        write_page(&o, &out_f, &html);
+        assert_eq!(fs::read_to_string(&out_f).unwrap(), expected);
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    /// Ensure hashes are hex encoded SHA-256
+    #[test]
+    fn test_hash() {
+        assert_eq!(
+            &hash(b"")[..],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! Stamps saying which build produced the output, to check what is actually deployed
+//!
+//! Every stamp holds the version of ssgen, the build time, the git commit of the input directory and the profile.
+//! Chosen with --stamp or stamp in !SITE:
+//! - `comment`: an HTML comment at the end of every page
+//! - `meta`: a generator and an `ssgen-build` meta element in the head of every page
+//! - `json`: build-info.json in the output directory
+//! ```
+//! let html = buildinfo::stamp(&o, html);
+//! buildinfo::write(&o, &mut manifest);
+//! ```
+
+/* IMPORTS */
+use chrono::{DateTime, SecondsFormat};
+use clap::ValueEnum;
+use serde_json::Value;
+
+/* LOCAL IMPORTS */
+use crate::{debug, error, paths, Manifest, Options};
+
+/* BUILD INFO */
+/// Name of the JSON stamp inside the output directory
+pub const BUILD_INFO_FILE: &str = "build-info.json";
+
+/// Where builds are stamped
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Stamp {
+    /// HTML comment at the end of every page
+    Comment,
+    /// Meta elements in the head of every page
+    Meta,
+    /// build-info.json in the output directory
+    Json,
+}
+
+/// Description of the build, as JSON
+pub fn info(o: &Options) -> Value {
+    let time = DateTime::from_timestamp(o.build_time, 0)
+        .map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default();
+    return serde_json::json!({
+        "generator": "SSGen",
+        "version": env!("CARGO_PKG_VERSION"),
+        "time": time,
+        "commit": o.commit.as_deref(),
+        "profile": o.profile.as_deref(),
+    });
+}
+
+/// Description of the build as one line of `key=value` pairs, leaving out what is unknown
+fn summary(o: &Options) -> String {
+    let info = info(o);
+    return ["version", "time", "commit", "profile"]
+        .iter()
+        .filter_map(|k| info[k].as_str().map(|v| format!("{k}={v}")))
+        .collect::<Vec<_>>()
+        .join("; ");
+}
+
+/// Add the page stamps chosen in o to html
+///
+/// Meta elements go at the end of the head, pages without one only get a comment if that is chosen
+pub fn stamp(o: &Options, mut html: String) -> String {
+    // attribute values and comments cannot hold every character, but none of these are in the summary
+    let summary = summary(o).replace(['"', '<', '>'], "").replace("--", "-");
+    if o.stamp.contains(&Stamp::Meta) {
+        match html.to_ascii_lowercase().find("</head>") {
+            Some(i) => html.insert_str(
+                i,
+                &format!(
+                    r#"<meta name="generator" content="SSGen {version}"/><meta name="ssgen-build" content="{summary}"/>"#,
+                    version = env!("CARGO_PKG_VERSION")
+                ),
+            ),
+            None => debug!(o, "Not stamping the page with meta elements, it has no head"),
+        }
+    }
+    if o.stamp.contains(&Stamp::Comment) {
+        html += &format!("\n<!-- SSGen build: {summary} -->\n");
+    }
+    return html;
+}
+
+/// Write build-info.json if it is chosen, recording it as an artifact of every page
+pub fn write(o: &Options, manifest: &mut Manifest) {
+    if !o.stamp.contains(&Stamp::Json) {
+        return;
+    }
+    let path = paths::join_slash(&o.output, BUILD_INFO_FILE);
+    debug!(o, "Writing {}", path.display());
+    let data = serde_json::to_string_pretty(&info(o)).unwrap() + "\n";
+    match o.writes.write(&path, data.as_bytes()) {
+        Ok(()) => (),
+        Err(e) => {
+            error!(o, "Error writing file {f} | {e}", f = path.display());
+            return;
+        }
+    }
+    for entry in manifest.pages.values_mut() {
+        entry
+            .artifacts
+            .insert(BUILD_INFO_FILE.into(), "META.yaml".into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+    use std::fs;
+
+    /// Ensure pages get the chosen stamps, and build-info.json describes the build
+    #[test]
+    fn test_stamp() {
+        let dest = "/tmp/ssgen_test_dest_dir_buildinfo";
+        fs::create_dir_all(dest).unwrap();
+        let mut o = Args::parse_from(["", "-i", "/tmp/", "-o", dest, "-s"]).build_options();
+        o.build_time = 86400;
+        o.commit = Some("abc123".into());
+        o.profile = Some("production".into());
+        let version = env!("CARGO_PKG_VERSION");
+        let summary = format!(
+            "version={version}; time=1970-01-02T00:00:00Z; commit=abc123; profile=production"
+        );
+
+        o.stamp = vec![Stamp::Meta, Stamp::Comment];
+        assert_eq!(
+            stamp(&o, "<html><HEAD><title>a</title></HEAD></html>".into()),
+            format!(
+                r#"<html><HEAD><title>a</title><meta name="generator" content="SSGen {version}"/><meta name="ssgen-build" content="{summary}"/></HEAD></html>
+<!-- SSGen build: {summary} -->
+"#
+            )
+        );
+        o.stamp = vec![Stamp::Meta];
+        assert_eq!(stamp(&o, "<p>a</p>".into()), "<p>a</p>");
+
+        o.commit = None;
+        o.stamp = vec![Stamp::Json];
+        write(&o, &mut Manifest::default());
+        let info: Value =
+            serde_json::from_str(&fs::read_to_string(format!("{dest}/{BUILD_INFO_FILE}")).unwrap())
+                .unwrap();
+        assert_eq!(info["commit"], Value::Null);
+        assert_eq!(info["time"], "1970-01-02T00:00:00Z");
+        assert_eq!(stamp(&o, "<p>a</p>".into()), "<p>a</p>");
+        fs::remove_dir_all(dest).unwrap();
+    }
+}
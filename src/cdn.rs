@@ -0,0 +1,227 @@
+//! Purge changed pages from a CDN cache after a build
+//!
+//! Only the URLs of pages that changed compared to the previous build's manifest are purged.
+//! Credentials are read from environment variables:
+//! - Cloudflare: `CLOUDFLARE_API_TOKEN` and `CLOUDFLARE_ZONE_ID`
+//! - Fastly: `FASTLY_API_TOKEN`
+//! - BunnyCDN: `BUNNY_API_KEY`
+//! ```
+//! let urls = cdn::changed_urls("https://example.com", &manifest.changes_since(&old_manifest));
+//! cdn::purge(&o, CdnProvider::Cloudflare, &urls);
+//! ```
+
+/* IMPORTS */
+use clap::ValueEnum;
+use std::env;
+
+/* LOCAL IMPORTS */
+use crate::{debug, error, info, manifest::Change, Options};
+
+/* CDN */
+/// Maximum number of URLs Cloudflare accepts in a single purge request
+const CLOUDFLARE_BATCH: usize = 30;
+
+/// Supported CDN providers
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum CdnProvider {
+    Cloudflare,
+    Fastly,
+    Bunny,
+}
+
+/// A single HTTP request to a purge API
+#[derive(Debug, PartialEq)]
+struct PurgeRequest {
+    /// Endpoint to send a POST request to
+    url: Box<str>,
+    /// Header containing the credentials
+    auth: (&'static str, Box<str>),
+    /// JSON body, if there is one
+    body: Option<serde_json::Value>,
+}
+
+/// Convert changed output files into the URLs they are served from
+///
+/// Pages named index.html are also purged by the URL of their directory
+pub fn changed_urls(base_url: &str, changes: &[(Change, Box<str>)]) -> Vec<Box<str>> {
+    let base = base_url.trim_end_matches('/');
+    let mut urls = Vec::<Box<str>>::new();
+    for (_, output) in changes {
+        urls.push(format!("{base}/{output}").into());
+        match output.strip_suffix("index.html") {
+            Some(dir) if dir.len() == 0 || dir.ends_with('/') => {
+                urls.push(format!("{base}/{dir}").into())
+            }
+            _ => (),
+        }
+    }
+    return urls;
+}
+
+/// Purge the given URLs from a CDN
+pub fn purge(o: &Options, provider: CdnProvider, urls: &[Box<str>]) {
+    if !o.allow_network {
+        error!(o, "Purging a CDN requires --allow-network");
+        return;
+    }
+    if urls.len() == 0 {
+        info!(o, "Nothing changed, skipping CDN purge");
+        return;
+    }
+    info!(o, "Purging {n} URLs from {provider:?}", n = urls.len());
+    let requests = match build_requests(provider, urls, |k| env::var(k).ok()) {
+        Ok(r) => r,
+        Err(e) => {
+            error!(o, "Unable to purge CDN | {e}");
+            return;
+        }
+    };
+    for r in requests {
+        debug!(o, "Sending purge request to {}", r.url);
+        let req = ureq::post(&r.url).set(r.auth.0, &r.auth.1);
+        let res = match r.body {
+            Some(b) => req.send_json(b),
+            None => req.call(),
+        };
+        match res {
+            Ok(_) => (),
+            Err(e) => error!(o, "CDN purge request failed | {e}"),
+        }
+    }
+}
+
+/// Build the API requests needed to purge urls, reading credentials through env
+fn build_requests(
+    provider: CdnProvider,
+    urls: &[Box<str>],
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<PurgeRequest>, Box<str>> {
+    let var = |k: &str| -> Result<Box<str>, Box<str>> {
+        return env(k)
+            .map(|v| v.into())
+            .ok_or(format!("Environment variable {k} is not set").into());
+    };
+    return Ok(match provider {
+        CdnProvider::Cloudflare => {
+            let token = var("CLOUDFLARE_API_TOKEN")?;
+            let zone = var("CLOUDFLARE_ZONE_ID")?;
+            urls.chunks(CLOUDFLARE_BATCH)
+                .map(|batch| PurgeRequest {
+                    url: format!("https://api.cloudflare.com/client/v4/zones/{zone}/purge_cache")
+                        .into(),
+                    auth: ("Authorization", format!("Bearer {token}").into()),
+                    body: Some(serde_json::json!({ "files": batch })),
+                })
+                .collect()
+        }
+        CdnProvider::Fastly => {
+            let token = var("FASTLY_API_TOKEN")?;
+            urls.iter()
+                .map(|u| PurgeRequest {
+                    url: format!(
+                        "https://api.fastly.com/purge/{}",
+                        u.split_once("://").map(|(_, rest)| rest).unwrap_or(u)
+                    )
+                    .into(),
+                    auth: ("Fastly-Key", token.clone()),
+                    body: None,
+                })
+                .collect()
+        }
+        CdnProvider::Bunny => {
+            let key = var("BUNNY_API_KEY")?;
+            urls.iter()
+                .map(|u| PurgeRequest {
+                    url: format!("https://api.bunny.net/purge?url={}", url_encode(u)).into(),
+                    auth: ("AccessKey", key.clone()),
+                    body: None,
+                })
+                .collect()
+        }
+    });
+}
+
+/// Percent-encode a string for use in a query parameter
+fn url_encode(s: &str) -> String {
+    return s
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure changed pages are turned into the right URLs
+    #[test]
+    fn test_changed_urls() {
+        let changes = vec![
+            (Change::Modified, "index.html".into()),
+            (Change::Added, "blog/index.html".into()),
+            (Change::Deleted, "blog/myindex.html".into()),
+        ];
+        assert_eq!(
+            changed_urls("https://example.com/", &changes),
+            vec![
+                "https://example.com/index.html".into(),
+                "https://example.com/".into(),
+                "https://example.com/blog/index.html".into(),
+                "https://example.com/blog/".into(),
+                "https://example.com/blog/myindex.html".into(),
+            ] as Vec<Box<str>>
+        );
+    }
+
+    /// Ensure requests for every provider are built correctly
+    #[test]
+    fn test_build_requests() {
+        let env = |k: &str| match k {
+            "CLOUDFLARE_API_TOKEN" | "FASTLY_API_TOKEN" | "BUNNY_API_KEY" => Some("t".to_string()),
+            "CLOUDFLARE_ZONE_ID" => Some("z".to_string()),
+            _ => None,
+        };
+        let urls: Vec<Box<str>> = (0..31)
+            .map(|i| format!("https://example.com/{i}.html").into())
+            .collect();
+
+        let cf = build_requests(CdnProvider::Cloudflare, &urls, env).unwrap();
+        assert_eq!(cf.len(), 2);
+        assert_eq!(
+            &cf[0].url[..],
+            "https://api.cloudflare.com/client/v4/zones/z/purge_cache"
+        );
+        assert_eq!(cf[0].auth, ("Authorization", "Bearer t".into()));
+        assert_eq!(
+            cf[1].body,
+            Some(serde_json::json!({ "files": ["https://example.com/30.html"] }))
+        );
+
+        let fastly = build_requests(CdnProvider::Fastly, &urls[..1], env).unwrap();
+        assert_eq!(
+            fastly,
+            vec![PurgeRequest {
+                url: "https://api.fastly.com/purge/example.com/0.html".into(),
+                auth: ("Fastly-Key", "t".into()),
+                body: None,
+            }]
+        );
+
+        let bunny = build_requests(CdnProvider::Bunny, &urls[..1], env).unwrap();
+        assert_eq!(
+            &bunny[0].url[..],
+            "https://api.bunny.net/purge?url=https%3A%2F%2Fexample.com%2F0.html"
+        );
+
+        // missing credentials
+        assert_eq!(
+            build_requests(CdnProvider::Cloudflare, &urls, |_| None).is_err(),
+            true
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! List the output pages that changed since a previous build
+//!
+//! Renders every page without writing anything, then compares the hashes against a manifest.
+//! The manifest can either be the file written by a previous build, or is created by rendering the input directory at a git revision.
+//! ```sh
+//! $ ssgen -i site/ -o public/ changed --since public/ssgen-manifest.json
+//! M index.html
+//! A blog/new-post.html
+//! D old.html
+//! ```
+
+/* IMPORTS */
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+    sync::Arc,
+    thread,
+    thread::JoinHandle,
+};
+
+/* LOCAL IMPORTS */
+use crate::{
+    build, debug, error, info,
+    manifest::{self, Change},
+    paths, Manifest, Options, PageEntry,
+};
+
+/* CHANGED */
+/// Print every output page that differs from the build described by since
+pub fn changed(o: Arc<Options>, since: &str) {
+    let old = if Path::new(since).is_file() {
+        info!(o, "Comparing against manifest {since}");
+        Manifest::load_from(&o, Path::new(since))
+    } else {
+        info!(o, "Comparing against git revision {since}");
+        match manifest_at_revision(o.clone(), since) {
+            Ok(m) => m,
+            Err(e) => {
+                error!(o, "Unable to build git revision {since} | {e}");
+                return;
+            }
+        }
+    };
+
+    let changes = render_manifest(o.clone()).changes_since(&old);
+    if changes.len() == 0 {
+        info!(o, "No pages changed");
+    }
+    for (change, output) in changes {
+        println!(
+            "{c} {output}",
+            c = match change {
+                Change::Added => "A",
+                Change::Modified => "M",
+                Change::Deleted => "D",
+            }
+        );
+    }
+}
+
+/// Render every page in the input directory into a manifest, without writing anything
+fn render_manifest(o: Arc<Options>) -> Manifest {
+    let mut pages = build::find_pages(&o);
+    let mut meta = build::parse_meta(o.clone(), &pages);
+    build::skip_pages(&o, &mut meta, &mut pages);
+    let mut handlers = Vec::<JoinHandle<Vec<(Box<str>, PageEntry)>>>::new();
+    for page in pages {
+        let thread_o = o.clone();
+        let thread_meta = meta.clone();
+        let key = manifest::input_key(&o, &page);
+        handlers.push(thread::spawn(move || {
+            let mut built = vec![(
+                key.clone(),
+                build::render_page(thread_o.clone(), thread_meta.clone(), &page, None).1,
+            )];
+            for variant in build::variants(&thread_o, &page) {
+                let entry =
+                    build::render_variant(thread_o.clone(), thread_meta.clone(), &page, &variant).1;
+                built.push((build::variant_key(&key, &variant.name), entry));
+            }
+            return built;
+        }));
+    }
+
+    let mut manifest = Manifest::default();
+    for t in handlers {
+        manifest.pages.extend(t.join().unwrap());
+    }
+    return manifest;
+}
+
+/// Render the input directory as it was at a git revision into a manifest
+///
+/// The input directory at that revision is exported into a temporary directory using `git archive`
+fn manifest_at_revision(o: Arc<Options>, rev: &str) -> Result<Manifest, Box<str>> {
+    // find where the input directory is inside of the repository
+    let git_rev_parse = |arg: &str| -> Result<String, Box<str>> {
+        let out = Command::new("git")
+            .arg("-C")
+            .arg(&o.input)
+            .args(["rev-parse", arg])
+            .output()
+            .map_err(|e| e.to_string())?;
+        if !out.status.success() {
+            return Err(String::from_utf8_lossy(&out.stderr).trim().into());
+        }
+        return Ok(String::from_utf8_lossy(&out.stdout).trim().to_string());
+    };
+    let toplevel = git_rev_parse("--show-toplevel")?;
+    let prefix = git_rev_parse("--show-prefix")?;
+
+    // export the revision
+    let mut tmp = std::env::temp_dir();
+    tmp.push(format!("ssgen-changed-{}", std::process::id()));
+    fs::create_dir_all(&tmp).map_err(|e| e.to_string())?;
+    debug!(o, "Exporting {rev}:{prefix} into {}", tmp.display());
+    // git archive refuses to run from inside an untracked working directory, so run it from the top
+    let archive = Command::new("git")
+        .arg("-C")
+        .arg(&toplevel)
+        .args([
+            "archive",
+            "--format=tar",
+            format!("{rev}:{prefix}").as_str(),
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let extracted = Command::new("tar")
+        .arg("-x")
+        .arg("-C")
+        .arg(&tmp)
+        .stdin(archive.stdout.unwrap())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| e.to_string())?;
+
+    let result = if extracted.success() {
+        let mut old_o = (*o).clone();
+        old_o.input = paths::canonicalize(&tmp).map_err(|e| e.to_string())?;
+        Ok(render_manifest(Arc::new(old_o)))
+    } else {
+        Err(format!("Revision {rev} does not exist").into())
+    };
+    match fs::remove_dir_all(&tmp) {
+        Ok(()) => (),
+        Err(e) => error!(o, "Unable to remove {f} | {e}", f = tmp.display()),
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+
+    /// Ensure the manifest of a freshly rendered site matches a previous build of the same site
+    #[test]
+    fn test_render_manifest() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_changed").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_changed").unwrap();
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                "/tmp/ssgen_test_source_dir_changed",
+                "-o",
+                "/tmp/ssgen_test_dest_dir_changed",
+                "-s",
+                "changed",
+                "--since",
+                "HEAD",
+            ])
+            .build_options(),
+        );
+        assert_eq!(o.dry_run, true);
+        fs::write("/tmp/ssgen_test_source_dir_changed/a.page", "p: a").unwrap();
+        fs::write("/tmp/ssgen_test_source_dir_changed/b.page", "p: b").unwrap();
+        fs::write("/tmp/ssgen_test_source_dir_changed/c.txt", "c").unwrap();
+        fs::write(
+            "/tmp/ssgen_test_source_dir_changed/META.yaml",
+            "!COPY c.txt",
+        )
+        .unwrap();
+
+        let old = render_manifest(o.clone());
+        assert_eq!(old.pages.len(), 2);
+        assert_eq!(render_manifest(o.clone()).changes_since(&old), vec![]);
+        // dry runs never copy files
+        assert_eq!(
+            Path::new("/tmp/ssgen_test_dest_dir_changed/c.txt").exists(),
+            false
+        );
+
+        fs::write("/tmp/ssgen_test_source_dir_changed/b.page", "p: c").unwrap();
+        assert_eq!(
+            render_manifest(o.clone()).changes_since(&old),
+            vec![(Change::Modified, "b.html".into())]
+        );
+
+        // not a git repository
+        assert_eq!(manifest_at_revision(o.clone(), "HEAD").is_err(), true);
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_changed").unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_changed").unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+//! Generate shell completions and a man page from the command-line arguments
+//!
+//! Both are written to stdout, and neither needs an input directory
+//! ```sh
+//! $ ssgen completions bash > /usr/share/bash-completion/completions/ssgen
+//! $ ssgen man > /usr/share/man/man1/ssgen.1
+//! ```
+
+/* IMPORTS */
+use clap::CommandFactory;
+use clap_complete::Shell;
+use std::io;
+
+/* LOCAL IMPORTS */
+use crate::Args;
+
+/* COMPLETIONS */
+/// Print a completion script for shell
+pub fn completions(shell: Shell) {
+    let mut cmd = Args::command();
+    let name = cmd.get_name().to_string();
+    clap_complete::generate(shell, &mut cmd, name, &mut io::stdout());
+}
+
+/// Print a man page in roff format
+pub fn man() {
+    match clap_mangen::Man::new(Args::command()).render(&mut io::stdout()) {
+        Ok(()) => (),
+        Err(e) => eprintln!("Error writing man page | {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure the argument definitions are valid, which completions and man pages rely on
+    #[test]
+    fn test_command() {
+        Args::command().debug_assert();
+    }
+}
@@ -0,0 +1,282 @@
+//! Upload the output directory to where the site is hosted
+//!
+//! The manifest of the build is uploaded along with the site, so the next deploy knows what is already there.
+//! Only files whose hash differs from that manifest are uploaded, and files the build no longer produces are deleted.
+//! Without a manifest at the target everything is uploaded and nothing is deleted.
+//! Targets:
+//! - rsync destinations like `user@host:/srv/www`, uploaded with rsync over ssh
+//! - S3 buckets like `s3://bucket/prefix`, uploaded with the AWS CLI, which also reads the credentials
+//! ```sh
+//! $ ssgen -o public/ deploy --target s3://example-site --endpoint https://s3.example.com
+//! [INFO] Uploading 3 files and deleting 2 files
+//! ```
+
+/* IMPORTS */
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/* LOCAL IMPORTS */
+use crate::{debug, error, info, manifest::MANIFEST_FILE, paths, warn, Manifest, Options};
+
+/* DEPLOY */
+/// Where the site is uploaded to
+#[derive(Debug, PartialEq)]
+enum Target {
+    /// rsync destination directory
+    Rsync(Box<str>),
+    /// S3 URL of the bucket and prefix, and the endpoint of S3-compatible storage
+    S3(Box<str>, Option<Box<str>>),
+}
+
+/// Upload the files that changed since the last deploy to target, and delete the ones that are gone
+///
+/// With dry_run, the changes are printed instead
+pub fn deploy(o: &Options, target: &str, endpoint: Option<&str>, dry_run: bool) {
+    let local = Manifest::load(o);
+    if local.files.len() == 0 {
+        error!(
+            o,
+            "Nothing to deploy, build the site into {} first",
+            o.output.display()
+        );
+        o.diagnostics.fail();
+        return;
+    }
+    let target = match target.starts_with("s3://") {
+        true => Target::S3(
+            target.trim_end_matches('/').into(),
+            endpoint.map(|e| e.into()),
+        ),
+        false => Target::Rsync(target.trim_end_matches('/').into()),
+    };
+    match (&target, endpoint) {
+        (Target::Rsync(_), Some(_)) => warn!(o, "--endpoint is only used for S3 targets"),
+        _ => (),
+    }
+
+    let remote = remote_manifest(o, &target);
+    let (upload, delete) = plan(&local, &remote);
+    if dry_run {
+        for file in upload.iter() {
+            println!("U {file}");
+        }
+        for file in delete.iter() {
+            println!("D {file}");
+        }
+        return;
+    }
+    info!(
+        o,
+        "Uploading {} files and deleting {} files",
+        upload.len(),
+        delete.len()
+    );
+
+    let done = match &target {
+        Target::Rsync(dest) => rsync(o, dest, &upload, &delete),
+        Target::S3(url, endpoint) => {
+            upload
+                .iter()
+                .map(|file| (file, paths::join_slash(&o.output, file)))
+                .all(|(file, path)| {
+                    aws(
+                        o,
+                        endpoint,
+                        &["cp", &path.to_string_lossy(), &format!("{url}/{file}")],
+                    )
+                })
+                && delete
+                    .iter()
+                    .all(|file| aws(o, endpoint, &["rm", &format!("{url}/{file}")]))
+        }
+    };
+    // the manifest goes last, so a deploy that failed halfway is redone completely next time
+    let done = done
+        && match &target {
+            Target::Rsync(dest) => run(
+                o,
+                Command::new("rsync")
+                    .arg("-q")
+                    .arg(Manifest::path(o))
+                    .arg(format!("{dest}/")),
+            ),
+            Target::S3(url, endpoint) => aws(
+                o,
+                endpoint,
+                &[
+                    "cp",
+                    &Manifest::path(o).to_string_lossy(),
+                    &format!("{url}/{MANIFEST_FILE}"),
+                ],
+            ),
+        };
+    if !done {
+        error!(o, "Deploy failed, the site at the target may be incomplete");
+        o.diagnostics.fail();
+    }
+}
+
+/// Output files of local to upload because they differ from remote, and those to delete because local no longer has them
+fn plan(local: &Manifest, remote: &Manifest) -> (Vec<Box<str>>, Vec<Box<str>>) {
+    let upload = local
+        .files
+        .iter()
+        .filter(|(file, entry)| remote.files.get(*file).map(|r| &r.hash) != Some(&entry.hash))
+        .map(|(file, _)| file.clone())
+        .collect();
+    let delete = remote
+        .files
+        .keys()
+        .filter(|file| !local.files.contains_key(*file))
+        .cloned()
+        .collect();
+    return (upload, delete);
+}
+
+/// Manifest of the last deploy to target, or an empty manifest if there is none
+fn remote_manifest(o: &Options, target: &Target) -> Manifest {
+    let mut tmp = std::env::temp_dir();
+    tmp.push(format!("ssgen-deploy-{}.json", std::process::id()));
+    let downloaded = match target {
+        Target::Rsync(dest) => run(
+            o,
+            Command::new("rsync")
+                .arg("-q")
+                .arg(format!("{dest}/{MANIFEST_FILE}"))
+                .arg(&tmp),
+        ),
+        Target::S3(url, endpoint) => aws(
+            o,
+            endpoint,
+            &[
+                "cp",
+                &format!("{url}/{MANIFEST_FILE}"),
+                &tmp.to_string_lossy(),
+            ],
+        ),
+    };
+    if !downloaded || !tmp.exists() {
+        warn!(o, "No manifest found at the target, uploading everything");
+        return Manifest::default();
+    }
+    let remote = Manifest::load_from(o, &tmp);
+    match fs::remove_file(&tmp) {
+        Ok(()) => (),
+        Err(e) => debug!(o, "Unable to remove {f} | {e}", f = tmp.display()),
+    }
+    return remote;
+}
+
+/// Send the files to upload to dest with rsync, deleting the files to delete there
+///
+/// Files that are listed but missing from the output directory are deleted by --delete-missing-args
+fn rsync(o: &Options, dest: &str, upload: &[Box<str>], delete: &[Box<str>]) -> bool {
+    if upload.len() == 0 && delete.len() == 0 {
+        return true;
+    }
+    let mut child = match Command::new("rsync")
+        .args(["-a", "--files-from=-", "--delete-missing-args"])
+        .arg(format!("{}/", o.output.display()))
+        .arg(format!("{dest}/"))
+        .stdin(Stdio::piped())
+        .spawn()
+    {
+        Ok(c) => c,
+        Err(e) => {
+            error!(o, "Unable to run rsync | {e}");
+            return false;
+        }
+    };
+    debug!(o, "Running rsync from {} to {dest}", o.output.display());
+    let list: String = upload
+        .iter()
+        .chain(delete.iter())
+        .map(|f| format!("{f}\n"))
+        .collect();
+    match child.stdin.take().map(|mut s| s.write_all(list.as_bytes())) {
+        Some(Ok(())) => (),
+        Some(Err(e)) => error!(o, "Unable to send the file list to rsync | {e}"),
+        None => (),
+    }
+    return match child.wait() {
+        Ok(s) if s.success() => true,
+        Ok(s) => {
+            error!(o, "rsync failed with {s}");
+            false
+        }
+        Err(e) => {
+            error!(o, "Unable to run rsync | {e}");
+            false
+        }
+    };
+}
+
+/// Run an `aws s3` command, against endpoint if there is one
+fn aws(o: &Options, endpoint: &Option<Box<str>>, args: &[&str]) -> bool {
+    let mut cmd = Command::new("aws");
+    cmd.arg("s3").args(args).arg("--only-show-errors");
+    match endpoint {
+        Some(e) => {
+            cmd.args(["--endpoint-url", e]);
+        }
+        None => (),
+    }
+    return run(o, &mut cmd);
+}
+
+/// Run a command, reporting why it failed if it did
+fn run(o: &Options, cmd: &mut Command) -> bool {
+    debug!(o, "Running {cmd:?}");
+    return match cmd.stdin(Stdio::null()).status() {
+        Ok(s) if s.success() => true,
+        Ok(s) => {
+            debug!(o, "{cmd:?} failed with {s}");
+            false
+        }
+        Err(e) => {
+            error!(
+                o,
+                "Unable to run {p} | {e}",
+                p = cmd.get_program().to_string_lossy().to_string()
+            );
+            false
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::FileEntry;
+
+    /// Ensure only changed and new files are uploaded, and only files that are gone are deleted
+    #[test]
+    fn test_plan() {
+        let manifest = |files: &[(&str, &str)]| {
+            let mut m = Manifest::default();
+            for (file, hash) in files {
+                m.files.insert(
+                    (*file).into(),
+                    FileEntry {
+                        source: "index.page".into(),
+                        hash: (*hash).into(),
+                        size: 0,
+                    },
+                );
+            }
+            return m;
+        };
+        let local = manifest(&[("index.html", "a"), ("new.html", "b"), ("same.css", "c")]);
+        let remote = manifest(&[("index.html", "x"), ("old.html", "y"), ("same.css", "c")]);
+        let (upload, delete) = plan(&local, &remote);
+        assert_eq!(upload, ["index.html".into(), "new.html".into()]);
+        assert_eq!(delete, ["old.html".into()]);
+
+        let (upload, delete) = plan(&local, &Manifest::default());
+        assert_eq!(upload.len(), 3);
+        assert_eq!(delete.len(), 0);
+    }
+}
@@ -0,0 +1,329 @@
+//! Compare two output directories, to review what a build changes before it is deployed
+//!
+//! Files are compared by content. Changed pages also get a word-level diff of their text, leaving markup aside,
+//! so a page that only changed in its markup says so instead of listing every attribute.
+//! The new directory is the output directory unless it is given, so a copy of the deployed site can be compared against a fresh build
+//! ```sh
+//! $ ssgen -i site/ -o public/ diff deployed/
+//! M index.html: +3 -1 words
+//!   …Welcome to [-my-]{+our new+} blog, where…
+//! M about.html: markup only
+//! A blog/new-post.html
+//! D old.html
+//! 2 modified, 1 added, 1 deleted
+//! ```
+
+/* IMPORTS */
+use std::{collections::BTreeSet, fs, path::Path, sync::Arc};
+
+/* LOCAL IMPORTS */
+use crate::{error, info, manifest::MANIFEST_FILE, paths, Options};
+
+/* DIFF */
+/// Words of unchanged text shown around every change
+const CONTEXT_WORDS: usize = 4;
+
+/// Changes shown for a page, the rest are only counted
+const MAX_HUNKS: usize = 5;
+
+/// Pages with more words than this between their first and last change are not diffed word by word
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Word of a diff, with what happened to it
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edit<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Print every file that differs between the output directories old and new, new being the output directory by default
+pub fn diff(o: Arc<Options>, old: &Path, new: Option<&Path>) {
+    let new = new.unwrap_or(&o.output);
+    let (old_files, new_files) = match (list_files(old), list_files(new)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            error!(o, "Unable to compare output directories | {e}");
+            return;
+        }
+    };
+
+    let (mut modified, mut added, mut deleted) = (0, 0, 0);
+    for key in old_files.union(&new_files) {
+        let old_file = paths::join_slash(old, key);
+        let new_file = paths::join_slash(new, key);
+        match (old_files.contains(key), new_files.contains(key)) {
+            (true, false) => {
+                deleted += 1;
+                println!("D {key}");
+            }
+            (false, true) => {
+                added += 1;
+                println!("A {key}");
+            }
+            _ => {
+                let (a, b) = match (fs::read(&old_file), fs::read(&new_file)) {
+                    (Ok(a), Ok(b)) => (a, b),
+                    (Err(e), _) | (_, Err(e)) => {
+                        error!(o, "Unable to read {key} | {e}");
+                        continue;
+                    }
+                };
+                if a == b {
+                    continue;
+                }
+                modified += 1;
+                if !key.ends_with(".html") {
+                    println!("M {key}");
+                    continue;
+                }
+                let (old_text, new_text) = (
+                    html_text(&String::from_utf8_lossy(&a)),
+                    html_text(&String::from_utf8_lossy(&b)),
+                );
+                let old_words: Vec<&str> = old_text.split_whitespace().collect();
+                let new_words: Vec<&str> = new_text.split_whitespace().collect();
+                let edits = diff_words(&old_words, &new_words);
+                let words_removed = edits
+                    .iter()
+                    .filter(|e| matches!(e, Edit::Removed(_)))
+                    .count();
+                let words_added = edits.iter().filter(|e| matches!(e, Edit::Added(_))).count();
+                if words_removed + words_added == 0 {
+                    println!("M {key}: markup only");
+                    continue;
+                }
+                println!("M {key}: +{words_added} -{words_removed} words");
+                let hunks = hunks(&edits);
+                for hunk in hunks.iter().take(MAX_HUNKS) {
+                    println!("  {hunk}");
+                }
+                if hunks.len() > MAX_HUNKS {
+                    println!("  … and {} more changes", hunks.len() - MAX_HUNKS);
+                }
+            }
+        }
+    }
+
+    if modified + added + deleted == 0 {
+        info!(o, "No files changed");
+    } else {
+        println!("{modified} modified, {added} added, {deleted} deleted");
+    }
+}
+
+/// Every file below dir relative to it, except the manifest which changes with every build
+fn list_files(dir: &Path) -> Result<BTreeSet<Box<str>>, Box<str>> {
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()).into());
+    }
+    let mut files = BTreeSet::new();
+    for file in paths::walk_files(dir, dir, paths::Symlinks::Follow) {
+        let file = file.map_err(|e| e.to_string())?;
+        let key = paths::to_slash(file.strip_prefix(dir).unwrap_or(&file));
+        if &key[..] != MANIFEST_FILE {
+            files.insert(key);
+        }
+    }
+    return Ok(files);
+}
+
+/// Text a reader sees in html, with tags, comments, scripts and styles replaced by spaces
+fn html_text(html: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+    let mut text = String::with_capacity(html.len());
+    let mut i = 0;
+    while i < html.len() {
+        let rest = &lower[i..];
+        if !rest.starts_with('<') {
+            let next = rest.find('<').map(|n| i + n).unwrap_or(html.len());
+            text += &html[i..next];
+            i = next;
+            continue;
+        }
+        // the end of what is skipped, comments and raw text elements can hold a `>`
+        let end = if rest.starts_with("<!--") {
+            rest.find("-->").map(|n| n + 3)
+        } else if rest.starts_with("<script") {
+            rest.find("</script>").map(|n| n + 9)
+        } else if rest.starts_with("<style") {
+            rest.find("</style>").map(|n| n + 8)
+        } else {
+            rest.find('>').map(|n| n + 1)
+        };
+        i += end.unwrap_or(rest.len());
+        text.push(' ');
+    }
+    return text;
+}
+
+/// Shortest list of edits turning old into new
+///
+/// Words both start or end with are kept out of the table, and when what is left is too large it is all replaced
+fn diff_words<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut edits: Vec<Edit> = old[..prefix].iter().map(|w| Edit::Same(w)).collect();
+    if (a.len() + 1) * (b.len() + 1) > MAX_DIFF_CELLS {
+        edits.extend(a.iter().map(|w| Edit::Removed(w)));
+        edits.extend(b.iter().map(|w| Edit::Added(w)));
+    } else {
+        // lcs[i][j] is the longest common subsequence of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lcs = vec![0usize; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = match a[i] == b[j] {
+                    true => lcs[(i + 1) * width + j + 1] + 1,
+                    false => lcs[(i + 1) * width + j].max(lcs[i * width + j + 1]),
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                edits.push(Edit::Same(a[i]));
+                i += 1;
+                j += 1;
+            } else if j == b.len()
+                || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+            {
+                edits.push(Edit::Removed(a[i]));
+                i += 1;
+            } else {
+                edits.push(Edit::Added(b[j]));
+                j += 1;
+            }
+        }
+    }
+    edits.extend(old[old.len() - suffix..].iter().map(|w| Edit::Same(w)));
+    return edits;
+}
+
+/// Every change in edits as a line in the style of wdiff, `[-removed-]{+added+}` with a few words around it
+///
+/// Changes closer together than their context are shown on the same line
+fn hunks(edits: &[Edit]) -> Vec<String> {
+    let changed: Vec<usize> = (0..edits.len())
+        .filter(|i| !matches!(edits[*i], Edit::Same(_)))
+        .collect();
+    // ranges of edits to show, merged when they touch
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for i in changed {
+        let start = i.saturating_sub(CONTEXT_WORDS);
+        let end = (i + 1 + CONTEXT_WORDS).min(edits.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let mut lines = Vec::new();
+    for (start, end) in ranges {
+        let mut line = String::new();
+        if start > 0 {
+            line += "…";
+        }
+        let mut words = Vec::<String>::new();
+        let mut k = start;
+        while k < end {
+            match edits[k] {
+                Edit::Same(w) => {
+                    words.push(w.into());
+                    k += 1;
+                }
+                _ => {
+                    // a run of changes, removed words first
+                    let mut removed = Vec::new();
+                    let mut added = Vec::new();
+                    while k < end {
+                        match edits[k] {
+                            Edit::Removed(w) => removed.push(w),
+                            Edit::Added(w) => added.push(w),
+                            Edit::Same(_) => break,
+                        }
+                        k += 1;
+                    }
+                    let mut change = String::new();
+                    if removed.len() != 0 {
+                        change += &format!("[-{}-]", removed.join(" "));
+                    }
+                    if added.len() != 0 {
+                        change += &format!("{{+{}+}}", added.join(" "));
+                    }
+                    words.push(change);
+                }
+            }
+        }
+        line += &words.join(" ");
+        if end < edits.len() {
+            line += "…";
+        }
+        lines.push(line);
+    }
+    return lines;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure only visible text is compared, and changes are shown with their context
+    #[test]
+    fn test_diff_words() {
+        assert_eq!(
+            html_text("<p class=\"a\">Hello <b>you</b></p><!-- <p>x</p> --><script>if (a > b) {}</script>!")
+                .split_whitespace()
+                .collect::<Vec<_>>(),
+            ["Hello", "you", "!"]
+        );
+
+        let old: Vec<&str> = "one two three four five six seven eight nine ten eleven twelve"
+            .split(' ')
+            .collect();
+        let new: Vec<&str> =
+            "one 2 three four five six seven eight nine ten eleven twelve thirteen"
+                .split(' ')
+                .collect();
+        let edits = diff_words(&old, &new);
+        assert_eq!(
+            edits[..3],
+            [Edit::Same("one"), Edit::Removed("two"), Edit::Added("2")]
+        );
+        assert_eq!(
+            hunks(&edits),
+            [
+                "one [-two-]{+2+} three four five six…",
+                "…nine ten eleven twelve {+thirteen+}",
+            ]
+        );
+        assert_eq!(diff_words(&old, &old).len(), old.len());
+        assert_eq!(hunks(&diff_words(&old, &old)), Vec::<String>::new());
+    }
+
+    /// Ensure files in subdirectories are listed, and the manifest is left out
+    #[test]
+    fn test_list_files() {
+        let dir = "/tmp/ssgen_test_dest_dir_diff";
+        fs::create_dir_all(format!("{dir}/blog")).unwrap();
+        fs::write(format!("{dir}/index.html"), "").unwrap();
+        fs::write(format!("{dir}/blog/a.html"), "").unwrap();
+        fs::write(format!("{dir}/{MANIFEST_FILE}"), "{}").unwrap();
+        assert_eq!(
+            list_files(Path::new(dir)).unwrap(),
+            BTreeSet::from(["blog/a.html".into(), "index.html".into()])
+        );
+        assert!(list_files(Path::new("/tmp/ssgen_test_dest_dir_diff_missing")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
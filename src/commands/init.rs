@@ -0,0 +1,232 @@
+//! Scaffold a new project in the input directory
+//!
+//! Existing files are never overwritten, so running it twice is harmless
+//! ```sh
+//! $ ssgen -i mysite init --template blog
+//! $ mkdir public && ssgen -i mysite -o public
+//! ```
+
+/* IMPORTS */
+use clap::ValueEnum;
+use std::fs;
+
+/* LOCAL IMPORTS */
+use crate::{error, info, warn, Options};
+
+/* INIT */
+/// Starter projects that can be created
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Template {
+    /// A single page
+    Minimal,
+    /// An index listing every page, and a first post
+    Blog,
+    /// Documentation pages sharing a navigation bar
+    Docs,
+}
+
+/// Write the files of template into the input directory
+pub fn init(o: &Options, template: Template) {
+    info!(o, "Creating {template:?} project in {}", o.input.display());
+    for (name, contents) in files(template) {
+        let path = o.input.join(name);
+        if path.exists() {
+            warn!(o, "Not overwriting existing file {}", path.display());
+            continue;
+        }
+        let mut dir = path.clone();
+        dir.pop();
+        match fs::create_dir_all(&dir).and_then(|()| fs::write(&path, contents)) {
+            Ok(()) => println!("created {name}"),
+            Err(e) => error!(o, "Error writing file {f} | {e}", f = path.display()),
+        }
+    }
+}
+
+/// Get the path and contents of every file in template
+fn files(template: Template) -> Vec<(&'static str, &'static str)> {
+    let mut files = vec![
+        ("META.yaml", META),
+        ("blocks/head.block", HEAD_BLOCK),
+        ("assets/style.css", STYLE),
+    ];
+    match template {
+        Template::Minimal => files.push(("index.page", MINIMAL_INDEX)),
+        Template::Blog => {
+            files.push(("index.page", BLOG_INDEX));
+            files.push(("posts/hello-world.page", BLOG_POST));
+        }
+        Template::Docs => {
+            files.push(("index.page", DOCS_INDEX));
+            files.push(("getting-started.page", DOCS_PAGE));
+            files.push(("blocks/nav.block", DOCS_NAV));
+        }
+    }
+    return files;
+}
+
+/* TEMPLATES */
+/// Global variables and files shared by every page, this is the place for site-wide settings
+const META: &str = r#"# Site-wide settings
+#
+# Variables defined here are available on every page, and directives here only run once
+- !DEF [SITE_TITLE, My Site]
+- !DEF [AUTHOR, Me]
+- !DEF [DESCRIPTION, A website built with SSGen]
+- !DEF [STYLESHEET, /assets/style.css]
+
+# Copy static files into the output directory
+- !COPY_DIR /assets
+"#;
+
+/// Shared <head> of every page, set TITLE before including it
+const HEAD_BLOCK: &str = r#"# Head shared by every page
+#
+# Variables:
+# TITLE - Title of the page
+head:
+  - meta:
+      _charset: UTF-8
+  - meta:
+      _name: viewport
+      _content: width=device-width, initial-scale=1
+  - title: '{TITLE} | {SITE_TITLE}'
+  - !SEO {title: '{TITLE}', description: '{DESCRIPTION}'}
+  - link:
+      _rel: stylesheet
+      _href: '{STYLESHEET}'
+"#;
+
+/// Stylesheet for every page
+const STYLE: &str = r#"body {
+  max-width: 60rem;
+  margin: 0 auto;
+  padding: 1rem;
+  font-family: sans-serif;
+  line-height: 1.5;
+}
+"#;
+
+const MINIMAL_INDEX: &str = r#"- !DEF [TITLE, Home]
+---
+html:
+  - !INCLUDE /blocks/head.block
+  - body:
+      - h1: '{SITE_TITLE}'
+      - p: Edit index.page to get started.
+"#;
+
+const BLOG_INDEX: &str = r#"- !DEF [TITLE, Home]
+- _title: Home
+---
+html:
+  - !INCLUDE /blocks/head.block
+  - body:
+      - h1: '{SITE_TITLE}'
+      - p: '{DESCRIPTION}'
+      - h2: Pages
+      - ul:
+          - !FOREACH_VAR [site.pages, post, li: '<a href="{post.url}">{post.title}</a>']
+"#;
+
+const BLOG_POST: &str = r#"- !DEF [TITLE, Hello World]
+# metadata other pages see in site.pages, set the date when writing a new post
+- _title: Hello World
+- _date: 2024-01-01
+---
+html:
+  - !INCLUDE /blocks/head.block
+  - body:
+      - p: '<a href="/index.html">Back</a>'
+      - article:
+          - h1: '{TITLE}'
+          - p: 'Written by {AUTHOR} on 2024-01-01'
+          - p: This is the first post, copy this file to write another.
+"#;
+
+const DOCS_NAV: &str = r#"# Navigation shared by every documentation page
+nav:
+  ul:
+    - li: '<a href="/index.html">Introduction</a>'
+    - li: '<a href="/getting-started.html">Getting started</a>'
+"#;
+
+const DOCS_INDEX: &str = r#"- !DEF [TITLE, Introduction]
+---
+html:
+  - !INCLUDE /blocks/head.block
+  - body:
+      - !INCLUDE /blocks/nav.block
+      - main:
+          - h1: '{TITLE}'
+          - p: 'Welcome to the documentation of {SITE_TITLE}.'
+"#;
+
+const DOCS_PAGE: &str = r#"- !DEF [TITLE, Getting started]
+---
+html:
+  - !INCLUDE /blocks/head.block
+  - body:
+      - !INCLUDE /blocks/nav.block
+      - main:
+          - h1: '{TITLE}'
+          - h2: Installation
+          - p: Build with --heading-ids to link to each section.
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build, Args};
+    use clap::Parser;
+    use std::sync::Arc;
+
+    /// Ensure every template builds without errors and existing files are kept
+    #[test]
+    fn test_init() {
+        for template in Template::value_variants() {
+            let _ = fs::remove_dir_all("/tmp/ssgen_test_source_dir_init");
+            fs::create_dir_all("/tmp/ssgen_test_dest_dir_init").unwrap();
+            let o = Arc::new(
+                Args::parse_from([
+                    "",
+                    "-i",
+                    "/tmp/ssgen_test_source_dir_init",
+                    "-o",
+                    "/tmp/ssgen_test_dest_dir_init",
+                    "-s",
+                    "init",
+                ])
+                .build_options(),
+            );
+            fs::write("/tmp/ssgen_test_source_dir_init/index.page", "p: kept").unwrap();
+            init(&o, *template);
+
+            let pages = build::find_pages(&o);
+            assert_eq!(
+                pages.len(),
+                files(*template)
+                    .iter()
+                    .filter(|(name, _)| name.ends_with(".page"))
+                    .count()
+            );
+            let meta = build::parse_meta(o.clone(), &pages);
+            for page in pages {
+                let (html, _) = build::render_page(o.clone(), meta.clone(), &page, None);
+                if page.ends_with("index.page") {
+                    assert_eq!(html, "<!DOCTYPE html>\n<p>kept</p>");
+                } else {
+                    assert_eq!(html.contains("<h1>"), true, "{template:?}");
+                }
+            }
+            assert_eq!(
+                o.output.join("assets/style.css").exists(),
+                true,
+                "{template:?}"
+            );
+
+            fs::remove_dir_all("/tmp/ssgen_test_source_dir_init").unwrap();
+            fs::remove_dir_all("/tmp/ssgen_test_dest_dir_init").unwrap();
+        }
+    }
+}
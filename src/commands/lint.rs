@@ -0,0 +1,203 @@
+//! Check every page for problems without writing anything
+//!
+//! Pages are rendered like a dry run, so everything a build would warn about is reported: undefined variables, unknown directives, missing includes.
+//! On top of that, lint mode reports !IF conditions that can never change, ids used more than once in a page
+//! and elements that are not closed, or closed without being opened.
+//! Any warning or error makes ssgen exit with a failure, so lint can be used as a CI check
+//! ```sh
+//! $ ssgen -i site/ lint
+//! [WARN] !IF condition "yes" is constant, its else branch is never used source=index.page:4:5 page=index.page
+//! [WARN] Duplicate id "intro" page=index.page
+//! [WARN] Element <div> is never closed page=index.page
+//! [ERRO] Found 3 issues
+//! ```
+
+/* IMPORTS */
+use regex::Regex;
+use std::{
+    collections::HashSet,
+    sync::{Arc, LazyLock},
+    thread,
+    thread::JoinHandle,
+};
+
+/* LOCAL IMPORTS */
+use crate::{build, error, info, manifest, warn, Options};
+
+/* LINT */
+/// Render every page in the input directory and report what is wrong with them
+pub fn lint(o: Arc<Options>) {
+    let pages = build::find_pages(&o);
+    let meta = build::parse_meta(o.clone(), &pages);
+    let mut handlers = Vec::<JoinHandle<()>>::new();
+    for page in pages {
+        let thread_o = o.clone();
+        let thread_meta = meta.clone();
+        handlers.push(thread::spawn(move || {
+            let (html, _) = build::render_page(thread_o.clone(), thread_meta, &page, None);
+            let page_o = thread_o.for_page(&manifest::input_key(&thread_o, &page));
+            for id in duplicate_ids(&html) {
+                warn!(page_o, r#"Duplicate id "{id}""#);
+            }
+            for problem in unbalanced_tags(&html) {
+                warn!(page_o, "{problem}");
+            }
+        }));
+    }
+    for t in handlers {
+        t.join().unwrap();
+    }
+
+    let issues = o.diagnostics.count();
+    if issues == 0 {
+        info!(o, "No issues found");
+        return;
+    }
+    error!(o, "Found {issues} issues");
+    o.diagnostics.fail();
+}
+
+/// Every id that is given to more than one element of html, in the order they are first repeated
+pub(crate) fn duplicate_ids(html: &str) -> Vec<Box<str>> {
+    let re = Regex::new(r#"<[^>]*\sid="([^"]*)""#).unwrap();
+    let mut seen = HashSet::<&str>::new();
+    let mut duplicates = Vec::<Box<str>>::new();
+    for c in re.captures_iter(html) {
+        let id = c.get(1).unwrap().as_str();
+        if !seen.insert(id) && !duplicates.iter().any(|d| &d[..] == id) {
+            duplicates.push(id.into());
+        }
+    }
+    return duplicates;
+}
+
+/// Text of an HTML document whose tags are not checked, since it is not markup
+static UNCHECKED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?is)<!--.*?-->|<script\b.*?</script>|<style\b.*?</style>").unwrap()
+});
+
+/// Opening and closing tags
+static TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(/?)([a-zA-Z][a-zA-Z0-9-]*)(?:\s[^>]*)?>").unwrap());
+
+/// Elements that never have a closing tag
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Every element of html that is never closed, or closed without being opened, described in the order they are found
+///
+/// Elements closed with `/>` are taken as closed, like in SVG
+pub(crate) fn unbalanced_tags(html: &str) -> Vec<Box<str>> {
+    let html = UNCHECKED.replace_all(html, "");
+    let mut open = Vec::<String>::new();
+    let mut problems = Vec::<Box<str>>::new();
+    for c in TAG.captures_iter(&html) {
+        let name = c[2].to_lowercase();
+        let closing = &c[1] == "/";
+        if VOID_ELEMENTS.contains(&&name[..]) || (!closing && c[0].ends_with("/>")) {
+            continue;
+        }
+        if !closing {
+            open.push(name);
+            continue;
+        }
+        match open.iter().rposition(|o| *o == name) {
+            Some(i) => {
+                for unclosed in open.drain(i..).skip(1) {
+                    problems.push(format!("Element <{unclosed}> is never closed").into());
+                }
+            }
+            None => problems.push(format!("Closing tag </{name}> has no opening tag").into()),
+        }
+    }
+    for unclosed in open {
+        problems.push(format!("Element <{unclosed}> is never closed").into());
+    }
+    return problems;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+    use std::fs;
+
+    /// Ensure ids are only reported once, and only when they are actually repeated
+    #[test]
+    fn test_duplicate_ids() {
+        let html = r#"<h1 id="a">a</h1><p id="b" class="a">id="c"</p><div data-id="b"/><p id="a"/><i id="a"/>"#;
+        assert_eq!(duplicate_ids(html), vec!["a".into()]);
+        assert_eq!(duplicate_ids(r#"<p id="a"/><p id="b"/>"#).len(), 0);
+    }
+
+    /// Ensure elements left open or closed twice are found, but not void elements or tags in scripts
+    #[test]
+    fn test_unbalanced_tags() {
+        let html = r#"<!DOCTYPE html><html><head><meta charset="utf-8"><script>if (a<b) {}</script></head><body><p>a<br>b</p><svg><path d="x"/></svg><!-- <div> --></body></html>"#;
+        assert_eq!(unbalanced_tags(html).len(), 0);
+        assert_eq!(
+            unbalanced_tags("<div><p><b>x</p></div></span><i>"),
+            vec![
+                "Element <b> is never closed".into(),
+                "Closing tag </span> has no opening tag".into(),
+                "Element <i> is never closed".into(),
+            ] as Vec<Box<str>>
+        );
+    }
+
+    /// Ensure a site with problems fails lint, without anything being written
+    #[test]
+    fn test_lint() {
+        let dir = "/tmp/ssgen_test_source_dir_lint";
+        fs::create_dir_all(dir).unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_lint").unwrap();
+        let options = || {
+            Arc::new(
+                Args::parse_from([
+                    "",
+                    "-i",
+                    dir,
+                    "-o",
+                    "/tmp/ssgen_test_dest_dir_lint",
+                    "-s",
+                    "lint",
+                ])
+                .build_options(),
+            )
+        };
+
+        fs::write(
+            format!("{dir}/a.page"),
+            "- !DEF [x, a]\n- !IF ['{x}', a, b]\n- p: a",
+        )
+        .unwrap();
+        let o = options();
+        assert_eq!(o.lint, true);
+        assert_eq!(o.dry_run, true);
+        lint(o.clone());
+        assert_eq!(o.diagnostics.failed(), false);
+
+        fs::write(
+            format!("{dir}/a.page"),
+            "- !IF [yes, a]\n- p: [{_id: a}, a]\n- p: [{_id: a}, b]\n- !INCLUDE missing.page\n",
+        )
+        .unwrap();
+        let o = options();
+        lint(o.clone());
+        assert_eq!(o.diagnostics.failed(), true);
+        // the constant condition, the duplicate id, the missing include
+        assert_eq!(o.diagnostics.count() >= 4, true);
+        assert_eq!(
+            fs::read_dir("/tmp/ssgen_test_dest_dir_lint")
+                .unwrap()
+                .count(),
+            0
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_lint").unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+//! Subcommands of ssgen
+//!
+//! Everything ssgen can do besides building the site, see args::Command for the full list
+//! ```
+//! match command {
+//!     Some(Command::Changed { since }) => commands::changed(o.clone(), &since),
+//!     Some(Command::Deploy { target, .. }) => commands::deploy(&o, &target, None, false),
+//!     Some(Command::Diff { old, new }) => commands::diff(o.clone(), &old, new.as_deref()),
+//!     Some(Command::Init { template }) => commands::init(&o, template),
+//!     Some(Command::Lint) => commands::lint(o.clone()),
+//!     Some(Command::Test { update }) => commands::test(o.clone(), update),
+//!     None => build_site(o.clone()),
+//! }
+//! ```
+
+/* LOCAL IMPORTS */
+mod changed;
+pub use changed::changed;
+mod completions;
+pub use completions::{completions, man};
+mod diff;
+pub use diff::diff;
+mod deploy;
+pub use deploy::deploy;
+mod init;
+pub use init::{init, Template};
+mod lint;
+pub use lint::lint;
+pub(crate) use lint::{duplicate_ids, unbalanced_tags};
+mod test;
+pub use test::test;
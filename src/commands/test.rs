@@ -0,0 +1,169 @@
+//! Compare pages under tests/ in the input directory against snapshots of their expected HTML
+//!
+//! The snapshot of a page is next to it, with .html in place of .page.
+//! Pages are rendered like a dry run, and without SOURCE_DATE_EPOCH the build time is the unix epoch so that dates do not go stale.
+//! Running with --update writes the snapshots of pages that are new or changed instead of failing
+//! ```sh
+//! $ ssgen -i site/ test
+//! ok tests/nav.page
+//! FAIL tests/footer.page: line 3 differs
+//!   expected: <p>© 2024</p>
+//!   found:    <p>© 1970</p>
+//! NEW tests/header.page: no snapshot, run with --update to create it
+//! ```
+
+/* IMPORTS */
+use std::{fs, path::PathBuf, sync::Arc, thread, thread::JoinHandle};
+
+/* LOCAL IMPORTS */
+use crate::{build, error, info, manifest, paths, Options};
+
+/* TEST */
+/// Render every test page and compare it against its snapshot, or update the snapshots
+pub fn test(o: Arc<Options>, update: bool) {
+    let tests_dir = paths::join_slash(&o.input, build::TESTS_DIR);
+    let (tests, pages): (Vec<PathBuf>, Vec<PathBuf>) = build::find_all_pages(&o)
+        .into_iter()
+        .partition(|p| p.starts_with(&tests_dir));
+    if tests.len() == 0 {
+        info!(o, "No test pages found in {}", tests_dir.display());
+        return;
+    }
+    // test pages see the same site as the pages they test
+    let meta = build::parse_meta(o.clone(), &pages);
+    let mut handlers = Vec::<(PathBuf, JoinHandle<String>)>::new();
+    for page in tests {
+        let thread_o = o.clone();
+        let thread_meta = meta.clone();
+        let thread_page = page.clone();
+        handlers.push((
+            page,
+            thread::spawn(move || build::render_page(thread_o, thread_meta, &thread_page, None).0),
+        ));
+    }
+
+    let mut failed = 0;
+    let total = handlers.len();
+    for (page, t) in handlers {
+        let html = t.join().unwrap();
+        let key = manifest::input_key(&o, &page);
+        let snapshot = page.with_extension("html");
+        let expected = fs::read_to_string(&snapshot).ok();
+        if expected.as_deref() == Some(&html[..]) {
+            println!("ok {key}");
+            continue;
+        }
+        if update {
+            match fs::write(&snapshot, &html) {
+                Ok(()) => println!("updated {key}"),
+                Err(e) => {
+                    error!(o, "Unable to write {f} | {e}", f = snapshot.display());
+                    failed += 1;
+                }
+            }
+            continue;
+        }
+        failed += 1;
+        match expected {
+            Some(expected) => {
+                let (line, expected_line, found_line) = first_difference(&expected, &html);
+                println!("FAIL {key}: line {line} differs");
+                println!("  expected: {expected_line}");
+                println!("  found:    {found_line}");
+            }
+            None => println!("NEW {key}: no snapshot, run with --update to create it"),
+        }
+    }
+
+    if failed > 0 {
+        error!(o, "{failed} of {total} tests failed");
+        o.diagnostics.fail();
+    } else {
+        info!(o, "All {total} tests passed");
+    }
+}
+
+/// Line number of the first line that differs between expected and found, and that line in both
+fn first_difference<'a>(expected: &'a str, found: &'a str) -> (usize, &'a str, &'a str) {
+    let mut e = expected.lines();
+    let mut f = found.lines();
+    let mut line = 1;
+    loop {
+        match (e.next(), f.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            (a, b) => return (line, a.unwrap_or(""), b.unwrap_or("")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+
+    /// Ensure the first differing line is found, including when one side runs out of lines
+    #[test]
+    fn test_first_difference() {
+        assert_eq!(first_difference("a\nb\nc", "a\nx\nc"), (2, "b", "x"));
+        assert_eq!(first_difference("a", "a\nb"), (2, "", "b"));
+        assert_eq!(first_difference("a\nb", "a"), (2, "b", ""));
+    }
+
+    /// Ensure missing and changed snapshots fail, and --update blesses them
+    #[test]
+    fn test_snapshots() {
+        let dir = "/tmp/ssgen_test_source_dir_snapshots";
+        fs::create_dir_all(format!("{dir}/tests")).unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_snapshots").unwrap();
+        let options = |update: bool| {
+            let mut args = vec![
+                "",
+                "-i",
+                dir,
+                "-o",
+                "/tmp/ssgen_test_dest_dir_snapshots",
+                "-s",
+                "test",
+            ];
+            if update {
+                args.push("--update");
+            }
+            Arc::new(Args::parse_from(args).build_options())
+        };
+        fs::write(format!("{dir}/index.page"), "p: a").unwrap();
+        fs::write(format!("{dir}/tests/a.page"), "p: '{site.build.time}'").unwrap();
+
+        let o = options(false);
+        test(o.clone(), false);
+        assert_eq!(o.diagnostics.failed(), true);
+
+        let o = options(true);
+        test(o.clone(), true);
+        assert_eq!(o.diagnostics.failed(), false);
+        // the build time is fixed, so it can be part of a snapshot
+        assert_eq!(
+            fs::read_to_string(format!("{dir}/tests/a.html")).unwrap(),
+            "<!DOCTYPE html>\n<p>0</p>"
+        );
+
+        let o = options(false);
+        test(o.clone(), false);
+        assert_eq!(o.diagnostics.failed(), false);
+
+        fs::write(format!("{dir}/tests/a.page"), "p: b").unwrap();
+        let o = options(false);
+        test(o.clone(), false);
+        assert_eq!(o.diagnostics.failed(), true);
+        // nothing is written into the output directory
+        assert_eq!(
+            fs::read_dir("/tmp/ssgen_test_dest_dir_snapshots")
+                .unwrap()
+                .count(),
+            0
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_snapshots").unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! Precompressed copies of text files in the output directory, for servers that serve them in place of the original
+//!
+//! Every HTML, CSS, JS and SVG file the build produced gets a `.gz` and a `.br` sibling.
+//! Copies that would not be smaller than the original are left out, and copies newer than their original are kept as they are
+//! ```
+//! compress::precompress(&o, &mut manifest);
+//! manifest.index_files(&o);
+//! ```
+
+/* IMPORTS */
+use brotli::CompressorWriter;
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+};
+
+/* LOCAL IMPORTS */
+use crate::{debug, error, info, paths, Manifest, Options};
+
+/* COMPRESS */
+/// Extensions of the files that are precompressed
+const COMPRESSIBLE: [&str; 6] = ["html", "htm", "css", "js", "mjs", "svg"];
+
+/// Highest brotli quality and window size, like `brotli --best`
+const BROTLI_QUALITY: u32 = 11;
+const BROTLI_WINDOW: u32 = 22;
+
+/// Compressed copies written next to a file, with their sizes
+#[derive(Debug, Default, PartialEq)]
+struct Compressed {
+    /// Size of the original
+    size: u64,
+    /// Size of the `.gz` copy, if there is one
+    gzip: Option<u64>,
+    /// Size of the `.br` copy, if there is one
+    brotli: Option<u64>,
+}
+
+/// Write compressed copies of every compressible file in the manifest, recording them as artifacts of their pages
+pub fn precompress(o: &Options, manifest: &mut Manifest) {
+    // META.yaml artifacts belong to every page, but are only compressed once
+    let outputs: Vec<Box<str>> = manifest
+        .outputs()
+        .into_iter()
+        .filter(|f| {
+            Path::new(&f[..])
+                .extension()
+                .is_some_and(|e| COMPRESSIBLE.contains(&&e.to_string_lossy()[..]))
+        })
+        .collect();
+    // compressing is bound by the CPU, so one thread per core is enough
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = outputs.len().div_ceil(threads).max(1);
+    let compressed: BTreeMap<&str, Compressed> = thread::scope(|s| {
+        let handlers: Vec<_> = outputs
+            .chunks(chunk)
+            .map(|files| {
+                s.spawn(move || {
+                    files
+                        .iter()
+                        .map(|f| (&f[..], compress(o, &paths::join_slash(&o.output, f))))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handlers
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect()
+    });
+
+    for (key, entry) in manifest.pages.iter_mut() {
+        let mut copies = Vec::<(Box<str>, Box<str>)>::new();
+        let files = std::iter::once((&entry.output, key)).chain(entry.artifacts.iter());
+        for (output, source) in files {
+            let c = match compressed.get(&output[..]) {
+                Some(c) => c,
+                None => continue,
+            };
+            for (ext, size) in [("gz", c.gzip), ("br", c.brotli)] {
+                if size.is_some() {
+                    copies.push((format!("{output}.{ext}").into(), source.clone()));
+                }
+            }
+        }
+        entry.artifacts.extend(copies);
+    }
+    let total =
+        |f: fn(&Compressed) -> Option<u64>| -> u64 { compressed.values().filter_map(f).sum() };
+    info!(
+        o,
+        "Precompressed {n} files of {size} bytes into {gzip} bytes of gzip and {brotli} bytes of brotli",
+        n = compressed.len(),
+        size = compressed.values().map(|c| c.size).sum::<u64>(),
+        gzip = total(|c| c.gzip),
+        brotli = total(|c| c.brotli)
+    );
+}
+
+/// Write the `.gz` and `.br` copies of a file
+fn compress(o: &Options, path: &Path) -> Compressed {
+    let data = match fs::read(path) {
+        Ok(d) => d,
+        Err(e) => {
+            error!(o, "Unable to read {f} | {e}", f = path.display());
+            return Compressed::default();
+        }
+    };
+    let size = data.len() as u64;
+    let gzip = copy(o, path, "gz", size, |dest| {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&data)?;
+        return fs::write(dest, encoder.finish()?);
+    });
+    let brotli = copy(o, path, "br", size, |dest| {
+        let mut encoder = CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+        encoder.write_all(&data)?;
+        return fs::write(dest, encoder.into_inner());
+    });
+    return Compressed {
+        size: size,
+        gzip: gzip,
+        brotli: brotli,
+    };
+}
+
+/// Size of the copy of path with the extension ext, writing it first if it is older than path
+///
+/// None if the copy is not smaller than size, the original size
+fn copy(
+    o: &Options,
+    path: &Path,
+    ext: &str,
+    size: u64,
+    write: impl FnOnce(&Path) -> std::io::Result<()>,
+) -> Option<u64> {
+    let mut dest: PathBuf = path.into();
+    dest.as_mut_os_string().push(format!(".{ext}"));
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    let fresh = match (modified(path), modified(&dest)) {
+        (Some(original), Some(copy)) => copy >= original,
+        _ => false,
+    };
+    if !fresh {
+        debug!(o, "Compressing {}", dest.display());
+        match write(&dest) {
+            Ok(()) => (),
+            Err(e) => {
+                error!(o, "Error writing file {f} | {e}", f = dest.display());
+                return None;
+            }
+        }
+    }
+    let compressed = fs::metadata(&dest).map_or(size, |m| m.len());
+    if compressed >= size {
+        // servers would send the bigger copy to every client that accepts it
+        match fs::remove_file(&dest) {
+            Ok(()) => (),
+            Err(e) => debug!(o, "Unable to remove {f} | {e}", f = dest.display()),
+        }
+        return None;
+    }
+    return Some(compressed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, PageEntry};
+    use clap::Parser;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    /// Ensure compressible files get .gz and .br copies that are recorded in the manifest, and other files do not
+    #[test]
+    fn test_precompress() {
+        let dest = "/tmp/ssgen_test_dest_dir_compress";
+        fs::create_dir_all(dest).unwrap();
+        let html = "<p>compress me</p>".repeat(100);
+        fs::write(format!("{dest}/index.html"), &html).unwrap();
+        fs::write(format!("{dest}/style.css"), "a{}").unwrap();
+        fs::write(format!("{dest}/logo.png"), "x".repeat(1000)).unwrap();
+        let o = Args::parse_from(["", "-i", "./", "-o", dest, "-s"]).build_options();
+        let mut manifest = Manifest::default();
+        manifest.pages.insert(
+            "index.page".into(),
+            PageEntry {
+                output: "index.html".into(),
+                artifacts: BTreeMap::from([
+                    ("style.css".into(), "style.css".into()),
+                    ("logo.png".into(), "logo.png".into()),
+                ]),
+                ..Default::default()
+            },
+        );
+        precompress(&o, &mut manifest);
+
+        let mut decoded = String::new();
+        GzDecoder::new(fs::File::open(format!("{dest}/index.html.gz")).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, html);
+        let mut decoded = String::new();
+        brotli::Decompressor::new(
+            fs::File::open(format!("{dest}/index.html.br")).unwrap(),
+            4096,
+        )
+        .read_to_string(&mut decoded)
+        .unwrap();
+        assert_eq!(decoded, html);
+        for copy in ["index.html.gz", "index.html.br"] {
+            assert_eq!(
+                manifest.pages["index.page"].artifacts.get(copy),
+                Some(&"index.page".into())
+            );
+        }
+        // too small to get smaller
+        assert_eq!(Path::new(&format!("{dest}/style.css.gz")).exists(), false);
+        assert_eq!(Path::new(&format!("{dest}/style.css.br")).exists(), false);
+        assert_eq!(Path::new(&format!("{dest}/logo.png.gz")).exists(), false);
+        fs::remove_dir_all(dest).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+//! Favicons in every size browsers and phones ask for, generated from a single image, see directives::favicons
+//!
+//! Every set is generated at most once per build, no matter how many pages use it,
+//! and not at all if its files are newer than the image they were generated from
+//! ```
+//! let set = o.favicon_cache.get(&o, Path::new("site/logo.png"), Path::new("public/"))?;
+//! println!("{}", set.links); // <link rel="icon" ...>
+//! ```
+
+/* IMPORTS */
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/* LOCAL IMPORTS */
+use crate::{debug, info, manifest, warn, Options};
+
+/* FAVICONS */
+/// PNG icons and their sizes, the 192 and 512 pixel ones are also listed in the web manifest
+const PNG_ICONS: [(&str, u32); 5] = [
+    ("favicon-16x16.png", 16),
+    ("favicon-32x32.png", 32),
+    ("apple-touch-icon.png", 180),
+    ("android-chrome-192x192.png", 192),
+    ("android-chrome-512x512.png", 512),
+];
+
+/// Sizes in favicon.ico, for browsers that only look there
+const ICO_SIZES: [u32; 3] = [16, 32, 48];
+
+/// Name of the web manifest listing the icons
+pub const WEBMANIFEST: &str = "site.webmanifest";
+
+/// Favicons generated into a directory
+#[derive(Debug, PartialEq)]
+pub struct Favicons {
+    /// Every file of the set
+    pub files: Vec<PathBuf>,
+    /// Link elements for the head of a page
+    pub links: Box<str>,
+}
+
+/// Favicon sets generated during this build, by source image and directory
+#[derive(Default)]
+pub struct FaviconCache {
+    sets: Mutex<HashMap<(PathBuf, PathBuf), Arc<Favicons>>>,
+}
+
+impl FaviconCache {
+    /// Favicons generated from source into dir, generating them if this build did not yet
+    pub fn get(&self, o: &Options, source: &Path, dir: &Path) -> Result<Arc<Favicons>, Box<str>> {
+        // held while generating, so pages that use the same set wait instead of writing it at once
+        let mut sets = self.sets.lock().unwrap();
+        let key = (source.to_path_buf(), dir.to_path_buf());
+        match sets.get(&key) {
+            Some(set) => return Ok(set.clone()),
+            None => (),
+        }
+        let set = Arc::new(generate(o, source, dir)?);
+        sets.insert(key, set.clone());
+        return Ok(set);
+    }
+}
+
+/// Generate the favicons of source into dir, leaving files that are newer than source alone
+fn generate(o: &Options, source: &Path, dir: &Path) -> Result<Favicons, Box<str>> {
+    let mut files: Vec<PathBuf> = PNG_ICONS.iter().map(|(name, _)| dir.join(name)).collect();
+    files.push(dir.join("favicon.ico"));
+    files.push(dir.join(WEBMANIFEST));
+    let url = |file: &Path| {
+        format!(
+            "{}/{}",
+            o.base_url.as_deref().unwrap_or("").trim_end_matches('/'),
+            manifest::output_key(o, file)
+        )
+    };
+    let links = format!(
+        concat!(
+            r#"<link rel="icon" href="{ico}" sizes="48x48">"#,
+            r#"<link rel="icon" type="image/png" sizes="32x32" href="{png32}">"#,
+            r#"<link rel="icon" type="image/png" sizes="16x16" href="{png16}">"#,
+            r#"<link rel="apple-touch-icon" sizes="180x180" href="{apple}">"#,
+            r#"<link rel="manifest" href="{manifest}">"#
+        ),
+        ico = url(&files[5]),
+        png32 = url(&files[1]),
+        png16 = url(&files[0]),
+        apple = url(&files[2]),
+        manifest = url(&files[6]),
+    );
+
+    let modified = |f: &Path| fs::metadata(f).and_then(|m| m.modified()).ok();
+    let up_to_date = match modified(source) {
+        Some(s) => files.iter().all(|f| modified(f).is_some_and(|m| m >= s)),
+        None => false,
+    };
+    if o.dry_run || up_to_date {
+        debug!(o, "Favicons in {} are up to date", dir.display());
+        return Ok(Favicons {
+            files: files,
+            links: links.into(),
+        });
+    }
+
+    info!(o, "Generating favicons from {}", source.display());
+    let image = image::open(source)
+        .map_err(|e| format!(r#"Error reading image "{f}" | {e}"#, f = source.display()))?;
+    if image.width().min(image.height()) < 512 {
+        warn!(
+            o,
+            "Favicon image {} is smaller than 512x512, the larger icons will be blurry",
+            source.display()
+        );
+    }
+    let mut icons = Vec::new();
+    for (name, size) in PNG_ICONS {
+        icons.push((dir.join(name), png(&image, size)?));
+    }
+    let ico_images = ICO_SIZES
+        .iter()
+        .map(|size| Ok((*size, png(&image, *size)?)))
+        .collect::<Result<Vec<_>, Box<str>>>()?;
+    icons.push((dir.join("favicon.ico"), ico(&ico_images)));
+    let webmanifest = serde_json::json!({
+        "name": &o.title[..],
+        "icons": manifest_icons(o, dir).into_iter().map(|(_, icon)| icon).collect::<Vec<_>>(),
+    });
+    icons.push((dir.join(WEBMANIFEST), webmanifest.to_string().into_bytes()));
+    for (file, data) in icons {
+        o.writes
+            .write(&file, &data)
+            .map_err(|e| format!("Error writing file {f} | {e}", f = file.display()))?;
+    }
+    return Ok(Favicons {
+        files: files,
+        links: links.into(),
+    });
+}
+
+/// Icons of the set in dir that belong in a web manifest, with the entries listing them
+pub fn manifest_icons(o: &Options, dir: &Path) -> Vec<(PathBuf, serde_json::Value)> {
+    return PNG_ICONS
+        .iter()
+        .filter(|(name, _)| name.starts_with("android-chrome"))
+        .map(|(name, size)| {
+            let file = dir.join(name);
+            let icon = serde_json::json!({
+                "src": format!(
+                    "{}/{}",
+                    o.base_url.as_deref().unwrap_or("").trim_end_matches('/'),
+                    manifest::output_key(o, &file)
+                ),
+                "sizes": format!("{size}x{size}"),
+                "type": "image/png",
+            });
+            (file, icon)
+        })
+        .collect();
+}
+
+/// Image cropped to a square and scaled to size, as PNG
+fn png(image: &DynamicImage, size: u32) -> Result<Vec<u8>, Box<str>> {
+    let mut data = Vec::new();
+    image
+        .resize_to_fill(size, size, FilterType::Lanczos3)
+        .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+        .map_err(|e| format!("Error encoding favicon | {e}"))?;
+    return Ok(data);
+}
+
+/// ICO file holding PNG images of the given sizes
+fn ico(images: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let mut ico = vec![0, 0, 1, 0];
+    ico.extend((images.len() as u16).to_le_bytes());
+    let mut offset = 6 + 16 * images.len();
+    for (size, data) in images {
+        // a size of 0 stands for 256
+        ico.extend([*size as u8, *size as u8, 0, 0]);
+        ico.extend(1u16.to_le_bytes());
+        ico.extend(32u16.to_le_bytes());
+        ico.extend((data.len() as u32).to_le_bytes());
+        ico.extend((offset as u32).to_le_bytes());
+        offset += data.len();
+    }
+    for (_, data) in images {
+        ico.extend(data);
+    }
+    return ico;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{qr, Args};
+    use clap::Parser;
+
+    /// Ensure every icon is written in its size once, and kept while it is newer than the image
+    #[test]
+    fn test_generate() {
+        let src = "/tmp/ssgen_test_source_dir_favicons";
+        let dest = "/tmp/ssgen_test_dest_dir_favicons";
+        fs::create_dir_all(src).unwrap();
+        fs::create_dir_all(dest).unwrap();
+        let source = Path::new(src).join("logo.png");
+        fs::write(&source, qr::png(&qr::encode("x").unwrap())).unwrap();
+        let o = Args::parse_from(["", "-i", src, "-o", dest, "-s"]).build_options();
+
+        let set = FaviconCache::default()
+            .get(&o, &source, Path::new(dest))
+            .unwrap();
+        assert_eq!(set.files.len(), 7);
+        assert!(set
+            .links
+            .starts_with(r#"<link rel="icon" href="/favicon.ico" sizes="48x48">"#));
+        assert_eq!(
+            imagesize::size(format!("{dest}/apple-touch-icon.png"))
+                .map(|s| (s.width, s.height))
+                .unwrap(),
+            (180, 180)
+        );
+        let ico = fs::read(format!("{dest}/favicon.ico")).unwrap();
+        assert_eq!(&ico[..6], &[0, 0, 1, 0, 3, 0]);
+        assert_eq!(&ico[6 + 16 * 3..6 + 16 * 3 + 4], b"\x89PNG");
+        let webmanifest = fs::read_to_string(format!("{dest}/{WEBMANIFEST}")).unwrap();
+        assert!(webmanifest.contains(r#""src":"/android-chrome-512x512.png""#));
+
+        // files newer than the image are not generated again
+        fs::write(format!("{dest}/favicon.ico"), "kept").unwrap();
+        FaviconCache::default()
+            .get(&o, &source, Path::new(dest))
+            .unwrap();
+        assert_eq!(fs::read(format!("{dest}/favicon.ico")).unwrap(), b"kept");
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+}
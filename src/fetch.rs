@@ -0,0 +1,211 @@
+//! Fetch remote resources at build time, see directives::fetch
+//!
+//! Every URL is requested at most once per build, no matter how many pages fetch it.
+//! Responses are also kept in the fetch cache directory, which is used instead if a later request fails,
+//! while it is younger than the TTL, or for every request with --offline
+//! ```
+//! let body = o.fetch_cache.get(&o, "https://example.com/snippet.html", o.fetch_ttl)?;
+//! ```
+
+/* IMPORTS */
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/* LOCAL IMPORTS */
+use crate::{build, debug, warn, Options};
+
+/* FETCH */
+/// Responses fetched during this build, by URL
+#[derive(Default)]
+pub struct FetchCache {
+    responses: Mutex<HashMap<Box<str>, Arc<str>>>,
+}
+
+impl FetchCache {
+    /// Get the body of url, requesting it only if it was not fetched yet during this build
+    ///
+    /// Uses the response stored by an earlier build instead if it is less than ttl seconds old,
+    /// if the request fails, or if requests are not allowed with --offline
+    pub fn get(&self, o: &Options, url: &str, ttl: u64) -> Result<Arc<str>, Box<str>> {
+        match self.responses.lock().unwrap().get(url) {
+            Some(body) => return Ok(body.clone()),
+            None => (),
+        }
+
+        // requests happen without holding the lock, two threads may fetch the same URL at once
+        let file = cache_file(&o.fetch_cache_dir, url);
+        let stored = || fs::read_to_string(&file).map(Arc::<str>::from);
+        let body: Arc<str> = if o.offline {
+            match stored() {
+                Ok(body) => body,
+                Err(_) => {
+                    return Err(format!(
+                        "{url} is not in the fetch cache, build without --offline to fetch it"
+                    )
+                    .into())
+                }
+            }
+        } else if age(&file).is_some_and(|a| a < Duration::from_secs(ttl)) {
+            debug!(o, "Using the stored response of {url}");
+            stored().map_err(|e| format!("Unable to read the stored response of {url} | {e}"))?
+        } else {
+            refresh(o, url, &file)?
+        };
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(url.into(), body.clone());
+        return Ok(body);
+    }
+}
+
+/// Request url, storing the response in file, or reading it from there if the request fails
+fn refresh(o: &Options, url: &str, file: &Path) -> Result<Arc<str>, Box<str>> {
+    return match request(url, Duration::from_secs(o.fetch_timeout)) {
+        Ok(body) => {
+            debug!(o, "Fetched {url}, storing it in {}", file.display());
+            match fs::create_dir_all(&o.fetch_cache_dir).and_then(|()| fs::write(file, &body)) {
+                Ok(()) => (),
+                Err(e) => warn!(o, "Unable to store {url} in the fetch cache | {e}"),
+            }
+            Ok(body.into())
+        }
+        Err(e) => match fs::read_to_string(file) {
+            Ok(body) => {
+                warn!(o, "Unable to fetch {url}, using the stored response | {e}");
+                Ok(body.into())
+            }
+            Err(_) => Err(format!("Unable to fetch {url} | {e}").into()),
+        },
+    };
+}
+
+/// File the response of url is stored in
+fn cache_file(dir: &Path, url: &str) -> PathBuf {
+    let mut file = dir.to_path_buf();
+    file.push(&*build::hash(url.as_bytes()));
+    return file;
+}
+
+/// Time since file was last written, None if it does not exist
+fn age(file: &Path) -> Option<Duration> {
+    let modified = fs::metadata(file).and_then(|m| m.modified()).ok()?;
+    return SystemTime::now().duration_since(modified).ok();
+}
+
+/// Send a GET request to url and read the whole response as text
+fn request(url: &str, timeout: Duration) -> Result<String, Box<str>> {
+    let agent = ureq::AgentBuilder::new().timeout(timeout).build();
+    return agent
+        .get(url)
+        .call()
+        .map_err(|e| e.to_string())?
+        .into_string()
+        .map_err(|e| e.to_string().into());
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Serve body to the next n requests on a local port, returning its URL
+    pub fn serve(body: &'static str, n: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming().take(n) {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+        return url;
+    }
+
+    /// Options using dir as the fetch cache, with extra arguments
+    fn options(dir: &str, extra: &[&str]) -> Options {
+        let mut args = vec![
+            "",
+            "-i",
+            "./",
+            "-o",
+            "/tmp/",
+            "-s",
+            "--allow-network",
+            "--fetch-cache",
+            dir,
+            "--fetch-timeout",
+            "2",
+        ];
+        args.extend_from_slice(extra);
+        return Args::parse_from(args).build_options();
+    }
+
+    /// Ensure responses are fetched once per build, and stored ones are used when the server is gone
+    #[test]
+    fn test_fetch_cache() {
+        let dir = "/tmp/ssgen_test_fetch_cache";
+        let _ = fs::remove_dir_all(dir);
+        let o = options(dir, &[]);
+        let url = serve("hello", 1);
+        let cache = FetchCache::default();
+        assert_eq!(&*cache.get(&o, &url, 0).unwrap(), "hello");
+        // the server only answers once
+        assert_eq!(&*cache.get(&o, &url, 0).unwrap(), "hello");
+        assert_eq!(&*FetchCache::default().get(&o, &url, 0).unwrap(), "hello");
+        assert_eq!(
+            FetchCache::default()
+                .get(&o, "http://127.0.0.1:1/", 0)
+                .is_err(),
+            true
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Ensure fresh stored responses are used without a request, and --offline never requests
+    #[test]
+    fn test_fetch_ttl_offline() {
+        let dir = "/tmp/ssgen_test_fetch_ttl";
+        let _ = fs::remove_dir_all(dir);
+        let o = options(dir, &[]);
+        let url = serve("fresh", 1);
+        fs::create_dir_all(dir).unwrap();
+        fs::write(cache_file(Path::new(dir), &url), "stored").unwrap();
+        assert_eq!(
+            &*FetchCache::default().get(&o, &url, 3600).unwrap(),
+            "stored"
+        );
+        // an expired response is fetched again
+        assert_eq!(&*FetchCache::default().get(&o, &url, 0).unwrap(), "fresh");
+
+        let offline = options(dir, &["--offline"]);
+        assert_eq!(
+            &*FetchCache::default().get(&offline, &url, 0).unwrap(),
+            "fresh"
+        );
+        assert_eq!(
+            FetchCache::default()
+                .get(&offline, "http://127.0.0.1:1/", 0)
+                .is_err(),
+            true
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,328 @@
+//! History of the input directory, when it is part of a git repository
+//!
+//! Everything here is optional, a site outside of a repository simply has no history.
+//! The history is read once, by the first page that needs it, and shared by every page after that
+//! ```
+//! let commit = git::head_commit(&o.input); // Some("3f2a9c1...")
+//! let commits = o.git_history.commits(&o, &page); // newest first
+//! ```
+//! ```YAML
+//! footer: 'Last updated {git.last_modified} by {git.author} in {git.short_commit}'
+//! ```
+
+/* IMPORTS */
+use chrono::DateTime;
+use git2::{DiffOptions, Repository, Sort};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/* LOCAL IMPORTS */
+use crate::{debug, paths, Options, Var};
+
+/* GIT */
+/// Commit that changed a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Commit {
+    /// Full hash of the commit
+    pub id: Box<str>,
+    /// Unix time the commit was made at
+    pub time: i64,
+    /// Name of the author
+    pub author: Box<str>,
+    /// Email address of the author
+    pub email: Box<str>,
+}
+
+/// Person who changed a file, see contributors
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contributor {
+    /// Name in their latest commit
+    pub name: Box<str>,
+    /// Email address, which tells contributors apart
+    pub email: Box<str>,
+    /// Number of commits they made
+    pub commits: usize,
+}
+
+/// Commits of every file in the repository of the input directory, by canonical path
+#[derive(Default)]
+pub struct GitHistory {
+    /// None until the history is read
+    files: Mutex<Option<HashMap<PathBuf, Vec<Commit>>>>,
+}
+
+impl GitHistory {
+    /// Commits that changed file, newest first, empty if it is not in a repository or not committed
+    pub fn commits(&self, o: &Options, file: &Path) -> Vec<Commit> {
+        // held while reading, so pages that need the history wait for it instead of reading it again
+        let mut files = self.files.lock().unwrap();
+        let files = files.get_or_insert_with(|| match read_history(&o.input) {
+            Ok(h) => h,
+            Err(e) => {
+                debug!(o, "No git history for {} | {e}", o.input.display());
+                HashMap::new()
+            }
+        });
+        return files.get(file).cloned().unwrap_or_default();
+    }
+}
+
+/// Hash of the commit checked out in the repository dir is in, None outside of a repository
+pub fn head_commit(dir: &Path) -> Option<Box<str>> {
+    let repo = Repository::discover(dir).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    return Some(commit.id().to_string().into());
+}
+
+/// Every commit reachable from HEAD of the repository dir is in, by the files they changed in dir
+///
+/// Merges count for the files they changed compared to their first parent, and renames are not followed
+fn read_history(dir: &Path) -> Result<HashMap<PathBuf, Vec<Commit>>, git2::Error> {
+    let repo = Repository::discover(dir)?;
+    let workdir = match repo.workdir() {
+        Some(w) => paths::canonicalize(w).unwrap_or(w.to_path_buf()),
+        None => return Err(git2::Error::from_str("repository is bare")),
+    };
+    // files outside of dir are never pages, so their changes are not diffed at all
+    let mut opts = DiffOptions::new();
+    let dir = paths::canonicalize(dir).unwrap_or(dir.to_path_buf());
+    match dir.strip_prefix(&workdir) {
+        Ok(rel) if rel.as_os_str().len() != 0 => {
+            opts.pathspec(&*paths::to_slash(rel));
+        }
+        _ => (),
+    }
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.set_sorting(Sort::TIME)?;
+    let mut files: HashMap<PathBuf, Vec<Commit>> = HashMap::new();
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+        let parent = match commit.parent(0) {
+            Ok(p) => Some(p.tree()?),
+            Err(_) => None,
+        };
+        let diff =
+            repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), Some(&mut opts))?;
+        let author = commit.author();
+        let info = Commit {
+            id: commit.id().to_string().into(),
+            time: commit.time().seconds(),
+            author: author.name().unwrap_or("").into(),
+            email: author.email().unwrap_or("").into(),
+        };
+        for delta in diff.deltas() {
+            match delta.new_file().path().or(delta.old_file().path()) {
+                Some(path) => files
+                    .entry(workdir.join(path))
+                    .or_default()
+                    .push(info.clone()),
+                None => (),
+            }
+        }
+    }
+    return Ok(files);
+}
+
+/// Distinct authors of commits, newest first, by the number of commits they made and then by name
+///
+/// Authors with the same email address in any case are the same person, under the name of their newest commit
+pub fn contributors(commits: &[Commit]) -> Vec<Contributor> {
+    let mut people: Vec<Contributor> = Vec::new();
+    for c in commits {
+        match people
+            .iter_mut()
+            .find(|p| p.email.eq_ignore_ascii_case(&c.email))
+        {
+            Some(p) => p.commits += 1,
+            None => people.push(Contributor {
+                name: c.author.clone(),
+                email: c.email.clone(),
+                commits: 1,
+            }),
+        }
+    }
+    people.sort_by(|a, b| b.commits.cmp(&a.commits).then(a.name.cmp(&b.name)));
+    return people;
+}
+
+/// The `git` variable of a page, describing the last commit that changed file
+///
+/// The first call reads the whole history, so pages get this as a Deferred variable that calls it on first use.
+/// Contains `commit`, `short_commit`, `author`, `author_email` and `last_modified` as a `%Y-%m-%d` date,
+/// all empty if the file has no history
+pub fn page_var(o: &Options, file: &Path) -> Var {
+    let last = o.git_history.commits(o, file).into_iter().next();
+    let field = |f: fn(&Commit) -> String| last.as_ref().map(f).unwrap_or_default();
+    return Var::Map(vec![
+        (
+            "commit".into(),
+            Var::Str(field(|c| c.id.to_string()).into()),
+        ),
+        (
+            "short_commit".into(),
+            Var::Str(field(|c| c.id.chars().take(7).collect()).into()),
+        ),
+        (
+            "author".into(),
+            Var::Str(field(|c| c.author.to_string()).into()),
+        ),
+        (
+            "author_email".into(),
+            Var::Str(field(|c| c.email.to_string()).into()),
+        ),
+        (
+            "last_modified".into(),
+            Var::Str(
+                field(|c| {
+                    DateTime::from_timestamp(c.time, 0)
+                        .map(|t| t.format("%Y-%m-%d").to_string())
+                        .unwrap_or_default()
+                })
+                .into(),
+            ),
+        ),
+    ]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+    use git2::{Signature, Time};
+    use std::fs;
+
+    /// Ensure the checked out commit is found from any directory of the repository
+    #[test]
+    fn test_head_commit() {
+        let src = "/tmp/ssgen_test_source_dir_git_head";
+        fs::create_dir_all(format!("{src}/blog")).unwrap();
+        let repo = Repository::init(src).unwrap();
+        assert_eq!(head_commit(Path::new(src)), None);
+
+        let tree = repo.treebuilder(None).unwrap().write().unwrap();
+        let tree = repo.find_tree(tree).unwrap();
+        let author = Signature::now("A", "a@example.com").unwrap();
+        let id = repo
+            .commit(Some("HEAD"), &author, &author, "first", &tree, &[])
+            .unwrap();
+        assert_eq!(
+            head_commit(Path::new(&format!("{src}/blog"))),
+            Some(id.to_string().into())
+        );
+        fs::remove_dir_all(src).unwrap();
+    }
+
+    /// Commit the files in the working directory of repo as author, at time
+    fn commit_all(repo: &Repository, author: &str, time: i64) -> Box<str> {
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::new(
+            author,
+            &format!("{}@example.com", author.to_lowercase()),
+            &Time::new(time, 0),
+        )
+        .unwrap();
+        let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+        let id = repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "change",
+                &tree,
+                &parent.iter().collect::<Vec<_>>(),
+            )
+            .unwrap();
+        return id.to_string().into();
+    }
+
+    /// Ensure pages get the last commit that changed them and their contributors, and files without history get nothing
+    #[test]
+    fn test_history() {
+        let src = "/tmp/ssgen_test_source_dir_git_history";
+        fs::create_dir_all(src).unwrap();
+        let repo = Repository::init(src).unwrap();
+        fs::write(format!("{src}/a.page"), "p: 1").unwrap();
+        fs::write(format!("{src}/b.page"), "p: 1").unwrap();
+        commit_all(&repo, "Ada", 86400);
+        fs::write(format!("{src}/a.page"), "p: 2").unwrap();
+        let second = commit_all(&repo, "Bob", 86400 * 2);
+        fs::write(format!("{src}/new.page"), "p: 1").unwrap();
+
+        let o = Args::parse_from(["", "-i", src, "-o", "/tmp/", "-s"]).build_options();
+        let a = o.input.join("a.page");
+        assert_eq!(
+            o.git_history
+                .commits(&o, &a)
+                .iter()
+                .map(|c| &c.author[..])
+                .collect::<Vec<_>>(),
+            ["Bob", "Ada"]
+        );
+        let field = |var: &Var, k: &str| match var.get_path(&format!(".{k}")) {
+            Some(Var::Str(s)) => s.to_string(),
+            _ => panic!("{k} is not defined"),
+        };
+        let var = page_var(&o, &a);
+        assert_eq!(field(&var, "commit"), &second[..]);
+        assert_eq!(field(&var, "short_commit"), &second[..7]);
+        assert_eq!(field(&var, "author"), "Bob");
+        assert_eq!(field(&var, "author_email"), "bob@example.com");
+        assert_eq!(field(&var, "last_modified"), "1970-01-03");
+        assert_eq!(
+            field(&page_var(&o, &o.input.join("b.page")), "author"),
+            "Ada"
+        );
+        assert_eq!(
+            field(&page_var(&o, &o.input.join("new.page")), "commit"),
+            ""
+        );
+
+        fs::write(format!("{src}/a.page"), "p: 3").unwrap();
+        commit_all(&repo, "Ada", 86400 * 3);
+        let o = Args::parse_from(["", "-i", src, "-o", "/tmp/", "-s"]).build_options();
+        assert_eq!(
+            contributors(&o.git_history.commits(&o, &a)),
+            [
+                Contributor {
+                    name: "Ada".into(),
+                    email: "ada@example.com".into(),
+                    commits: 2,
+                },
+                Contributor {
+                    name: "Bob".into(),
+                    email: "bob@example.com".into(),
+                    commits: 1,
+                }
+            ]
+        );
+        fs::remove_dir_all(src).unwrap();
+    }
+
+    /// Ensure only the history of files in the input directory is read
+    #[test]
+    fn test_history_input_dir() {
+        let src = "/tmp/ssgen_test_source_dir_git_input_dir";
+        fs::create_dir_all(format!("{src}/site")).unwrap();
+        let repo = Repository::init(src).unwrap();
+        fs::write(format!("{src}/site/a.page"), "p: 1").unwrap();
+        fs::write(format!("{src}/README.md"), "readme").unwrap();
+        commit_all(&repo, "Ada", 86400);
+
+        let site = format!("{src}/site");
+        let o = Args::parse_from(["", "-i", &site, "-o", "/tmp/", "-s"]).build_options();
+        assert_eq!(o.git_history.commits(&o, &o.input.join("a.page")).len(), 1);
+        let readme = paths::canonicalize(Path::new(&format!("{src}/README.md"))).unwrap();
+        assert_eq!(o.git_history.commits(&o, &readme), []);
+        fs::remove_dir_all(src).unwrap();
+    }
+}
@@ -0,0 +1,534 @@
+//! External commands ssgen runs while building, configured in the `!SITE` block of META.yaml
+//!
+//! Asset hooks compile files like TypeScript into something browsers understand as they are copied,
+//! with `{input}` and `{output}` in the command standing for the source file and the file to write.
+//! Build hooks run before anything is parsed and after everything is written,
+//! with the input and output directories and the profile in SSGEN_INPUT, SSGEN_OUTPUT and SSGEN_PROFILE.
+//! The HTML filter gets every rendered page on stdin and writes the page to use instead to stdout.
+//! Diagram renderers turn the source of a !DIAGRAM into SVG, read from `{input}` and written to `{output}`,
+//! or through stdin and stdout if the command does not mention them.
+//! Like !SHELL_CMD, hooks only run with --enable-shell
+//! ```YAML
+//! - !SITE
+//!   asset_hooks:
+//!     - {pattern: '*.ts', output: js, command: [esbuild, '{input}', --bundle, '--outfile={output}']}
+//!   pre_build: [python3 scripts/fetch_data.py]
+//!   post_build:
+//!     - {command: 'curl -fsS -X POST https://example.com/deployed', on_failure: warn}
+//!   html_filter: {command: [npx, prettier, --parser, html], jobs: 4, on_failure: warn}
+//!   diagrams:
+//!     dot: dot -Tsvg
+//!     mermaid: [mmdc, -i, '{input}', -o, '{output}']
+//! ```
+//! ```
+//! match hooks::asset_hook(&o, Path::new("js/app.ts")) {
+//!     Some(hook) => hooks::compile(&o, hook, &source), // js/app.js
+//!     None => fs::read(&source),
+//! }
+//! ```
+
+/* IMPORTS */
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Condvar, Mutex,
+    },
+    thread,
+};
+
+/* LOCAL IMPORTS */
+use crate::{build, debug, error, info, paths, warn, Options};
+
+/* HOOKS */
+/// Command that turns files matching a pattern into files with another extension
+#[derive(Clone, Debug)]
+pub struct AssetHook {
+    /// Glob the files are matched against, against their name only if it has no `/`
+    pub pattern: Box<str>,
+    /// Extension of the files the command writes
+    pub output: Box<str>,
+    /// Program and arguments to run, with `{input}` and `{output}` replaced by the paths of the files
+    pub command: Vec<Box<str>>,
+}
+
+impl AssetHook {
+    /// Whether the file at relative, a path inside of the input directory, is handled by this hook
+    pub fn matches(&self, relative: &Path) -> bool {
+        return paths::matches_glob(&self.pattern, &paths::to_slash(relative));
+    }
+}
+
+/// Command run before or after the build
+#[derive(Clone, Debug, PartialEq)]
+pub struct BuildHook {
+    /// Program and arguments to run
+    pub command: Vec<Box<str>>,
+    /// Whether the build fails if the command does, otherwise it only warns
+    pub abort: bool,
+}
+
+/// Run every hook in order, stage is what they run for, like `pre_build`
+///
+/// Returns false if a hook that aborts failed, after which the remaining hooks are not run
+pub fn run_build_hooks(o: &Options, hooks: &[BuildHook], stage: &str) -> bool {
+    for hook in hooks {
+        info!(o, "Running {stage} hook {:?}", hook.command);
+        let status = Command::new(&hook.command[0][..])
+            .args(hook.command[1..].iter().map(|a| &a[..]))
+            .current_dir(&o.input)
+            .env("SSGEN_INPUT", &o.input)
+            .env("SSGEN_OUTPUT", &o.output)
+            .env("SSGEN_PROFILE", o.profile.as_deref().unwrap_or(""))
+            .stdin(Stdio::null())
+            .status();
+        let failure = match status {
+            Ok(s) if s.success() => continue,
+            Ok(s) => format!("{stage} hook {:?} failed with {s}", hook.command),
+            Err(e) => format!("Unable to run {stage} hook {:?} | {e}", hook.command),
+        };
+        if !hook.abort {
+            warn!(o, "{failure}");
+            continue;
+        }
+        error!(o, "{failure}");
+        o.diagnostics.fail();
+        return false;
+    }
+    return true;
+}
+
+/// Command every rendered page is passed through before it is written
+#[derive(Debug)]
+pub struct HtmlFilter {
+    /// Program and arguments to run
+    pub command: Vec<Box<str>>,
+    /// Number of pages filtered at the same time at most
+    pub jobs: usize,
+    /// Whether the build fails if the command does for a page, otherwise the page is kept as rendered
+    pub abort: bool,
+    /// Number of pages being filtered
+    running: Mutex<usize>,
+    /// Notified whenever a page is done
+    done: Condvar,
+}
+
+impl HtmlFilter {
+    /// Filter running command, at most jobs at a time
+    pub fn new(command: Vec<Box<str>>, jobs: usize, abort: bool) -> HtmlFilter {
+        return HtmlFilter {
+            command: command,
+            jobs: jobs.max(1),
+            abort: abort,
+            running: Mutex::new(0),
+            done: Condvar::new(),
+        };
+    }
+
+    /// Pass html through the command, waiting for one of the jobs to be free first
+    pub fn run(&self, html: &str) -> Result<String, Box<str>> {
+        let mut running = self
+            .done
+            .wait_while(self.running.lock().unwrap(), |r| *r >= self.jobs)
+            .unwrap();
+        *running += 1;
+        drop(running);
+        let filtered = self.pipe(html);
+        *self.running.lock().unwrap() -= 1;
+        self.done.notify_one();
+        return filtered;
+    }
+
+    /// Pass html through the command
+    fn pipe(&self, html: &str) -> Result<String, Box<str>> {
+        let mut child = Command::new(&self.command[0][..])
+            .args(self.command[1..].iter().map(|a| &a[..]))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Unable to run HTML filter {:?} | {e}", self.command))?;
+        let mut stdin = child.stdin.take().unwrap();
+        // written from another thread, since a filter that writes as it reads would block on a full stdout
+        let output = thread::scope(|s| {
+            s.spawn(move || stdin.write_all(html.as_bytes()));
+            return child.wait_with_output();
+        })
+        .map_err(|e| format!("Unable to run HTML filter {:?} | {e}", self.command))?;
+        if !output.status.success() {
+            return Err(format!(
+                "HTML filter {:?} failed with {s} | {e}",
+                self.command,
+                s = output.status,
+                e = String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        return String::from_utf8(output.stdout).map_err(|e| {
+            format!("HTML filter {:?} wrote invalid UTF-8 | {e}", self.command).into()
+        });
+    }
+}
+
+/// Pass a rendered page through the HTML filter of o, if there is one
+///
+/// If the filter fails, the page is kept as it was rendered
+pub fn filter_html(o: &Options, html: String) -> String {
+    let filter = match &o.html_filter {
+        Some(f) => f,
+        None => return html,
+    };
+    return match filter.run(&html) {
+        Ok(filtered) => filtered,
+        Err(e) if filter.abort => {
+            error!(o, "{e}");
+            o.diagnostics.fail();
+            html
+        }
+        Err(e) => {
+            warn!(o, "{e}");
+            html
+        }
+    };
+}
+
+/// First asset hook of o that handles the file at relative, a path inside of the input directory
+pub fn asset_hook<'a>(o: &'a Options, relative: &Path) -> Option<&'a AssetHook> {
+    return o.asset_hooks.iter().find(|h| h.matches(relative));
+}
+
+/// Run hook on source and return what it wrote
+pub fn compile(o: &Options, hook: &AssetHook, source: &Path) -> Result<Vec<u8>, Box<str>> {
+    let mut output: PathBuf = std::env::temp_dir();
+    output.push(format!(
+        "ssgen-hook-{}-{}.{}",
+        std::process::id(),
+        build::hash(source.to_string_lossy().as_bytes()),
+        hook.output
+    ));
+    let args: Vec<String> = hook
+        .command
+        .iter()
+        .map(|a| {
+            a.replace("{input}", &source.to_string_lossy())
+                .replace("{output}", &output.to_string_lossy())
+        })
+        .collect();
+    debug!(o, "Running asset hook {args:?}");
+    let result = Command::new(&args[0])
+        .args(&args[1..])
+        .current_dir(&o.input)
+        .stdin(Stdio::null())
+        .output();
+    let compiled = match result {
+        Ok(out) if out.status.success() => fs::read(&output).map_err(|e| {
+            format!(
+                "Asset hook {} wrote nothing to {{output}} | {e}",
+                hook.pattern
+            )
+            .into()
+        }),
+        Ok(out) => Err(format!(
+            "Asset hook {p} failed on {f} with {s} | {e}",
+            p = hook.pattern,
+            f = source.display(),
+            s = out.status,
+            e = String::from_utf8_lossy(&out.stderr).trim()
+        )
+        .into()),
+        Err(e) => Err(format!("Unable to run asset hook {} | {e}", hook.pattern).into()),
+    };
+    match fs::remove_file(&output) {
+        Ok(()) => (),
+        Err(e) => debug!(o, "Unable to remove {f} | {e}", f = output.display()),
+    }
+    return compiled;
+}
+
+/// Diagrams rendered so far, which tells apart the temporary files of diagrams rendered at the same time
+static DIAGRAMS: AtomicUsize = AtomicUsize::new(0);
+
+/// Render the source of a diagram in language to inline SVG with the renderer configured for it
+///
+/// Rendered diagrams are stored in the cache directory by their source and renderer,
+/// so a diagram is only rendered again once either changes.
+/// Pages rendering the same diagram at the same time each use their own temporary files,
+/// and the rendering is moved into the cache in one step so nothing reads half of it
+pub fn render_diagram(o: &Options, language: &str, source: &str) -> Result<String, Box<str>> {
+    let command = match o.diagram_renderers.get(language) {
+        Some(c) => c,
+        None => {
+            return Err(format!(
+                "No renderer for {language} diagrams, configure one in diagrams of !SITE"
+            )
+            .into())
+        }
+    };
+    let hash = build::hash(format!("{command:?}\n{source}").as_bytes());
+    let mut cached = o.fetch_cache_dir.join("diagrams");
+    cached.push(format!("{hash}.svg"));
+    match fs::read_to_string(&cached) {
+        Ok(svg) => {
+            debug!(o, "Using the stored rendering of {language} diagram {hash}");
+            return Ok(svg);
+        }
+        Err(_) => (),
+    }
+
+    let thread = format!("{:?}", thread::current().id()).replace(|c: char| !c.is_ascii_digit(), "");
+    let unique = format!(
+        "{pid}-{thread}-{n}",
+        pid = std::process::id(),
+        n = DIAGRAMS.fetch_add(1, Ordering::Relaxed)
+    );
+    let temp =
+        |ext: &str| std::env::temp_dir().join(format!("ssgen-diagram-{hash}-{unique}.{ext}"));
+    let (input, output) = (temp(language), temp("svg"));
+    let uses = |file: &str| command.iter().any(|a| a.contains(file));
+    let (stdin, stdout) = (!uses("{input}"), !uses("{output}"));
+    let args: Vec<String> = command
+        .iter()
+        .map(|a| {
+            a.replace("{input}", &input.to_string_lossy())
+                .replace("{output}", &output.to_string_lossy())
+        })
+        .collect();
+    debug!(o, "Rendering {language} diagram {hash} with {args:?}");
+    let rendered = (|| {
+        if !stdin {
+            fs::write(&input, source).map_err(|e| e.to_string())?;
+        }
+        let mut child = Command::new(&args[0])
+            .args(&args[1..])
+            .current_dir(&o.input)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        let mut pipe = child.stdin.take().unwrap();
+        let out = thread::scope(|s| {
+            s.spawn(move || match stdin {
+                true => pipe.write_all(source.as_bytes()),
+                false => Ok(()),
+            });
+            return child.wait_with_output();
+        })
+        .map_err(|e| e.to_string())?;
+        if !out.status.success() {
+            return Err(format!(
+                "failed with {s} | {e}",
+                s = out.status,
+                e = String::from_utf8_lossy(&out.stderr).trim()
+            ));
+        }
+        return match stdout {
+            true => String::from_utf8(out.stdout).map_err(|e| e.to_string()),
+            false => fs::read_to_string(&output).map_err(|e| e.to_string()),
+        };
+    })();
+    for file in [&input, &output] {
+        let _ = fs::remove_file(file);
+    }
+    let rendered =
+        rendered.map_err(|e| format!("Unable to render {language} diagram with {args:?} | {e}"))?;
+
+    // the XML declaration and doctype are not allowed inside of HTML
+    let svg = match rendered.find("<svg") {
+        Some(start) => rendered[start..].trim_end().to_string(),
+        None => return Err(format!("Renderer of {language} diagrams wrote no SVG").into()),
+    };
+    let partial = cached.with_extension(format!("svg.{unique}"));
+    match fs::create_dir_all(cached.parent().unwrap())
+        .and_then(|()| fs::write(&partial, &svg))
+        .and_then(|()| fs::rename(&partial, &cached))
+    {
+        Ok(()) => (),
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            warn!(o, "Unable to store {language} diagram in the cache | {e}")
+        }
+    }
+    return Ok(svg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+    use std::sync::Arc;
+
+    /// Ensure patterns match file names, or whole paths if they have a /
+    #[test]
+    fn test_matches() {
+        let hook = |pattern: &str| AssetHook {
+            pattern: pattern.into(),
+            output: "js".into(),
+            command: Vec::new(),
+        };
+        assert_eq!(hook("*.ts").matches(Path::new("js/app.ts")), true);
+        assert_eq!(hook("*.ts").matches(Path::new("js/app.tsx")), false);
+        assert_eq!(hook("/js/*.ts").matches(Path::new("js/app.ts")), true);
+        assert_eq!(hook("js/*.ts").matches(Path::new("js/lib/app.ts")), false);
+        assert_eq!(hook("js/**.ts").matches(Path::new("js/lib/app.ts")), true);
+        assert_eq!(hook("a?.css").matches(Path::new("ab.css")), true);
+    }
+
+    /// Ensure the command gets the input and output files, and its failures are reported
+    #[test]
+    #[cfg(unix)]
+    fn test_compile() {
+        let dir = "/tmp/ssgen_test_source_dir_hooks";
+        fs::create_dir_all(dir).unwrap();
+        fs::write(format!("{dir}/a.txt"), "hello").unwrap();
+        let o = Args::parse_from(["", "-i", dir, "-o", "/tmp/", "-s"]).build_options();
+        let hook = AssetHook {
+            pattern: "*.txt".into(),
+            output: "out".into(),
+            command: ["cp", "{input}", "{output}"].map(|a| a.into()).to_vec(),
+        };
+        let source = Path::new(dir).join("a.txt");
+        assert_eq!(compile(&o, &hook, &source), Ok(b"hello".to_vec()));
+        let failing = AssetHook {
+            command: ["false"].map(|a| a.into()).to_vec(),
+            ..hook
+        };
+        assert_eq!(compile(&o, &failing, &source).is_err(), true);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Ensure pages are passed through the filter, and kept as they are when it fails
+    #[test]
+    #[cfg(unix)]
+    fn test_filter_html() {
+        let mut o = Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options();
+        let html = "<p>a</p>".repeat(100000);
+        o.html_filter = Some(Arc::new(HtmlFilter::new(
+            ["tr", "a", "b"].map(|a| a.into()).to_vec(),
+            2,
+            true,
+        )));
+        let filtered = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| filter_html(&o, html.clone())))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(filtered, vec!["<p>b</p>".repeat(100000); 4]);
+
+        o.html_filter = Some(Arc::new(HtmlFilter::new(
+            ["false"].map(|a| a.into()).to_vec(),
+            1,
+            false,
+        )));
+        assert_eq!(filter_html(&o, html.clone()), html);
+        assert_eq!(o.diagnostics.failed(), false);
+    }
+
+    /// Ensure diagrams are rendered through files or stdin, and stored renderings are used again
+    ///
+    /// The same diagram can be rendered on several threads at once
+    #[test]
+    #[cfg(unix)]
+    fn test_render_diagram() {
+        let cache = "/tmp/ssgen_test_cache_dir_diagrams";
+        let mut o = Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s", "--fetch-cache", cache])
+            .build_options();
+        o.diagram_renderers.insert(
+            "piped".into(),
+            [
+                "sh",
+                "-c",
+                r#"printf '<?xml version="1.0"?>\n<svg>'; cat; echo '</svg>'"#,
+            ]
+            .map(|a| a.into())
+            .to_vec(),
+        );
+        o.diagram_renderers.insert(
+            "files".into(),
+            ["cp", "{input}", "{output}"].map(|a| a.into()).to_vec(),
+        );
+        assert_eq!(
+            render_diagram(&o, "piped", "a -> b"),
+            Ok("<svg>a -> b</svg>".into())
+        );
+        assert_eq!(
+            render_diagram(&o, "files", "<svg>x</svg>"),
+            Ok("<svg>x</svg>".into())
+        );
+        assert_eq!(render_diagram(&o, "files", "x").is_err(), true);
+        assert_eq!(render_diagram(&o, "unknown", "x").is_err(), true);
+
+        let stored = fs::read_dir(format!("{cache}/diagrams"))
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(stored.len(), 2);
+        for file in stored {
+            fs::write(file, "<svg>stored</svg>").unwrap();
+        }
+        assert_eq!(
+            render_diagram(&o, "piped", "a -> b"),
+            Ok("<svg>stored</svg>".into())
+        );
+
+        // pages rendering the same diagram at once do not share temporary files
+        o.diagram_renderers.insert(
+            "slow".into(),
+            ["sh", "-c", "sleep 0.2; cp {input} {output}"]
+                .map(|a| a.into())
+                .to_vec(),
+        );
+        thread::scope(|s| {
+            let renders: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| render_diagram(&o, "slow", "<svg>y</svg>")))
+                .collect();
+            for r in renders {
+                assert_eq!(r.join().unwrap(), Ok("<svg>y</svg>".into()));
+            }
+        });
+        assert_eq!(
+            fs::read_dir(format!("{cache}/diagrams")).unwrap().count(),
+            3
+        );
+        fs::remove_dir_all(cache).unwrap();
+    }
+
+    /// Ensure build hooks see the directories, and only failures of aborting hooks stop the rest
+    #[test]
+    #[cfg(unix)]
+    fn test_run_build_hooks() {
+        let dir = "/tmp/ssgen_test_source_dir_build_hooks";
+        fs::create_dir_all(dir).unwrap();
+        let o = Args::parse_from(["", "-i", dir, "-o", "/tmp/", "-s"]).build_options();
+        let hook = |command: &str, abort: bool| BuildHook {
+            command: ["sh", "-c", command].map(|a| a.into()).to_vec(),
+            abort: abort,
+        };
+        let hooks = [
+            hook(r#"echo "$SSGEN_OUTPUT" > out.txt"#, true),
+            hook("exit 1", false),
+            hook("touch after.txt", true),
+        ];
+        assert_eq!(run_build_hooks(&o, &hooks, "pre_build"), true);
+        assert_eq!(
+            fs::read_to_string(format!("{dir}/out.txt")).unwrap(),
+            "/tmp\n"
+        );
+        assert_eq!(Path::new(&format!("{dir}/after.txt")).exists(), true);
+        fs::remove_file(format!("{dir}/after.txt")).unwrap();
+
+        let hooks = [hook("exit 1", true), hook("touch after.txt", true)];
+        assert_eq!(run_build_hooks(&o, &hooks, "pre_build"), false);
+        assert_eq!(Path::new(&format!("{dir}/after.txt")).exists(), false);
+        assert_eq!(o.diagnostics.failed(), true);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
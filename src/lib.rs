@@ -0,0 +1,61 @@
+//! ssgen
+//!
+//! Easy to use, highly flexible website builder, written in Rust
+//! - Use YAML as a templating language to build your website
+//! - High flexibility, yet easy to get started with
+//! - Intelligent design becomes intuitive
+//!
+//! Besides the ssgen binary, pieces of YAML can be rendered on their own, for example to test components or to render at runtime
+//! ```
+//! let o = Args::parse_from(["ssgen", "--input", "site/", "--silent"]).build_options();
+//! let vars = HashMap::from([("name".into(), Var::Str("World".into()))]);
+//! let html = ssgen::render_fragment(&o, "p: Hello, {name}!", vars, Path::new("site/components"))?;
+//! assert_eq!(html, "<p>Hello, World!</p>");
+//! ```
+
+// code style lints that go against the conventions of this codebase
+#![allow(
+    clippy::needless_return,
+    clippy::redundant_field_names,
+    clippy::len_zero,
+    clippy::single_match,
+    clippy::while_let_loop,
+    clippy::bool_assert_comparison
+)]
+
+/* LOCAL IMPORTS */
+pub mod args;
+pub use args::{Args, Command, Options};
+pub mod build;
+pub use build::render_fragment;
+pub mod buildinfo;
+pub mod cdn;
+pub mod commands;
+pub mod compress;
+pub mod favicons;
+pub mod fetch;
+pub mod git;
+pub mod hooks;
+pub mod manifest;
+pub use manifest::{Manifest, PageEntry};
+pub mod math;
+pub mod memory;
+pub mod minify;
+pub mod pagenode;
+pub use pagenode::{Budget, NodeCell, PageNode};
+pub mod parser;
+pub use parser::Parser;
+pub mod paths;
+pub mod pwa;
+pub mod qr;
+pub mod redirects;
+pub mod robots;
+pub mod server;
+pub mod settings;
+pub mod stats;
+pub use stats::Stats;
+pub mod timings;
+pub use timings::Timings;
+pub mod transforms;
+pub mod var;
+pub use var::Var;
@@ -1,28 +1,41 @@
 //! ssgen
 //!
-//! Easy to use, highly flexible website builder, written in Rust
-//! - Use YAML as a templating language to build your website
-//! - High flexibility, yet easy to get started with
-//! - Intelligent design becomes intuitive
+//! Command-line entry point, everything it runs lives in the ssgen library
+
+// code style lints that go against the conventions of this codebase
+#![allow(
+    clippy::needless_return,
+    clippy::redundant_field_names,
+    clippy::single_match
+)]
 
 /* IMPORTS */
 use clap::Parser as ClapParser;
-use glob::{glob_with, MatchOptions};
 use indicatif::ProgressBar;
-use pathdiff::diff_paths;
 use std::{
-    collections::HashMap, fs, path::PathBuf, sync::Arc, thread, thread::JoinHandle, time::Instant,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 /* LOCAL IMPORTS */
-mod args;
-use args::{Args, Options};
-mod pagenode;
-use pagenode::PageNode;
-mod parser;
-use parser::Parser;
+use ssgen::{
+    build, buildinfo, cdn, commands, compress, debug, error, hooks, info, manifest, memory,
+    memory::MemoryLimit, paths, pwa, redirects, robots, server, settings, warn, Args, Command,
+    Manifest, Options, PageEntry, Stats,
+};
 
 /* MAIN */
+/// Time between status lines when progress bars are not drawn
+const STATUS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Directives and included files listed by --profile-templates
+const TIMINGS_SHOWN: usize = 10;
+
 fn main() {
     // this exists so slog async can really flush correctly
     main_runner();
@@ -31,136 +44,338 @@ fn main() {
 fn main_runner() {
     /* SETUP */
     let start_time = Instant::now();
-    let o: Arc<Options> = Arc::new(Args::parse().build_options());
+    let args = Args::parse();
+    // commands that only print information about ssgen itself do not need any Options
+    match args.command {
+        Some(Command::Completions { shell }) => return commands::completions(shell),
+        Some(Command::Man) => return commands::man(),
+        _ => (),
+    }
+    let command = args.command.clone();
+    // diff only compares output directories, so it needs neither an input directory nor its settings
+    let o: Arc<Options> = match command {
+        Some(Command::Diff { .. }) => Arc::new(args.build_options()),
+        _ => Arc::new(settings::apply(&args.build_options())),
+    };
     info!(o, "Starting SSGen...");
 
-    /* PARSE PAGES */
-    info!(o, "Walking input directory");
-    // match any file in input directory that ends with .page (case insensitive)
-    // safe because Options contains canonical paths
-    let match_pages = o.input.clone().into_os_string().into_string().unwrap() + "/**/*.page";
-    let mut pages = Vec::<PathBuf>::new();
-    let walkspin = o.progress.add(ProgressBar::new_spinner());
-    for entry in glob_with(
-        match_pages.as_str(),
-        MatchOptions {
-            case_sensitive: false,
-            require_literal_separator: false,
-            require_literal_leading_dot: false,
-        },
-    )
-    .unwrap()
-    {
-        match entry {
-            Ok(path) => {
-                debug!(o, "Found file {}", path.display());
-                walkspin.tick();
-                pages.push(path);
+    match command {
+        Some(Command::Changed { since }) => commands::changed(o.clone(), &since),
+        Some(Command::Deploy {
+            target,
+            endpoint,
+            dry_run,
+        }) => commands::deploy(&o, &target, endpoint.as_deref(), dry_run),
+        Some(Command::Diff { old, new }) => commands::diff(o.clone(), &old, new.as_deref()),
+        Some(Command::Init { template }) => commands::init(&o, template),
+        Some(Command::Lint) => commands::lint(o.clone()),
+        Some(Command::Test { update }) => commands::test(o.clone(), update),
+        Some(Command::Completions { .. }) | Some(Command::Man) => (),
+        None => build_site(o.clone(), Stats::new(start_time)),
+    }
+
+    /* CLEANUP */
+    for line in o.diagnostics.summary() {
+        warn!(o, "{line}");
+    }
+    match &o.timings {
+        Some(t) => {
+            for line in t.report(TIMINGS_SHOWN) {
+                eprintln!("{line}");
             }
-            Err(e) => error!(o, "Error finding file {}", e),
         }
+        None => (),
+    }
+    info!(
+        o,
+        "Completed in {t} Seconds!",
+        t = start_time.elapsed().as_secs_f32()
+    );
+    // strict builds treat every warning like an error
+    let (warnings, errors) = o.diagnostics.counts();
+    if o.strict && warnings + errors > 0 {
+        error!(
+            o,
+            "Failing strict build because of {warnings} warnings and {errors} errors"
+        );
+        o.diagnostics.fail();
+    }
+    let failed = o.diagnostics.failed();
+    if failed {
+        error!(o, "Build failed! See the errors above.");
+    }
+    let interrupted = o.interrupted.load(Ordering::SeqCst);
+    drop(o); // ensures logger gets flushed
+
+    // for some reason we need to wait extra time for logger to flush
+    thread::sleep(Duration::from_millis(100));
+    if interrupted {
+        std::process::exit(130);
+    }
+    if failed {
+        std::process::exit(0x1);
+    }
+}
+
+/// Build every page in the input directory and write them to the output directory
+fn build_site(o: Arc<Options>, mut stats: Stats) {
+    stats.phase("setup");
+
+    /* INTERRUPT */
+    // the first Ctrl-C lets the pages being built finish, a second one stops right away like --fail-fast
+    let handler_o = o.clone();
+    match ctrlc::set_handler(move || {
+        if !handler_o.fail_fast && !handler_o.interrupted.swap(true, Ordering::SeqCst) {
+            warn!(
+                handler_o,
+                "Interrupted, finishing the pages being built. Press Ctrl-C again to stop right away"
+            );
+            return;
+        }
+        // files being written are removed rather than left half written, so the output directory stays usable
+        handler_o.writes.roll_back();
+        let _ = handler_o.progress.clear();
+        error!(
+            handler_o,
+            "Interrupted after writing {n} files, the output directory is only partially built",
+            n = handler_o.writes.written()
+        );
+        thread::sleep(Duration::from_millis(100)); // lets the logger flush
+        std::process::exit(130);
+    }) {
+        Ok(()) => (),
+        Err(e) => warn!(o, "Unable to handle Ctrl-C | {e}"),
+    }
+
+    /* PRE BUILD */
+    // hooks can write files the build reads, so they run before anything is found or parsed
+    if !hooks::run_build_hooks(&o, &o.pre_build, "pre_build") {
+        return;
+    }
+
+    /* PARSE PAGES */
+    let mut pages = build::find_pages(&o);
+    stats.phase("discover");
+
+    /* MANIFEST */
+    // previous build is only relevant when building incrementally, cleaning up after it or purging changed pages
+    let old_manifest = if o.incremental || o.clean || o.purge_cdn.is_some() {
+        info!(o, "Loading manifest of previous build");
+        Manifest::load(&o)
+    } else {
+        Manifest::default()
+    };
+
+    /* CLEAN */
+    // before META.yaml is parsed, since it may copy files into the output directory
+    if o.clean_all {
+        build::wipe_output(&o);
     }
-    walkspin.finish();
 
     /* METADATA */
-    // parse the special "META.yaml" file
-    let mut meta_file: PathBuf = o.input.clone();
-    meta_file.push("META.yaml");
-    let meta_vars: HashMap<Box<str>, Box<str>> = PageNode::consume_into_vars(
-        if meta_file.exists() {
-            info!(o, "META.yaml found! Parsing...");
-            match fs::read_to_string(meta_file) {
-                Ok(s) => {
-                    let mut parser = Parser::new(o.clone());
-                    parser.parse_yaml(s.as_str());
-                    Parser::consume_into_root_node(parser)
-                }
-                Err(e) => {
-                    panic!("Unable to read META.yaml despite file existing, please ensure permissions are correct: {e}");
-                }
-            }
-        } else {
-            info!(o, "META.yaml not found! Creating empty root node");
-            PageNode::new(o.clone())
-        },
-    );
+    let mut meta = build::parse_meta(o.clone(), &pages);
+    // whether a page is built can depend on the variables of META.yaml
+    build::skip_pages(&o, &mut meta, &mut pages);
+    stats.phase("metadata");
 
     /* THREADING */
-    // one thread per page, scheduler will handle the hard part for us (TODO RIP memory usage)
+    // a thread for every core takes pages from a queue until it is empty, --max-memory keeps memory usage in check
     debug!(o, "Creating Page threads!");
+    let memory = MemoryLimit::new(o.max_memory);
     let pagebar = Arc::new(o.progress.add(ProgressBar::new(pages.len() as u64 + 1)));
     o.progress.set_move_cursor(true); // reduces flickering
     pagebar.tick();
 
-    // create threads
-    let mut handlers = Vec::<JoinHandle<()>>::new();
+    // queue pages
+    let mut manifest = Manifest::default();
+    let mut queue = Vec::<(Box<str>, &PathBuf)>::new();
     pages.iter().for_each(|p| {
-        let thread_pagefile = p.clone();
-        let thread_o = o.clone();
-        let thread_pagebar = pagebar.clone();
-        let thread_meta_vars = meta_vars.clone();
-        handlers.push(thread::spawn(move || {
-            let mut parser = Parser::new_with_vars(thread_o.clone(), thread_meta_vars);
-            let mut root_file = thread_pagefile.clone();
-            root_file.pop();
-            parser.set_root_dir(root_file.into());
-            parser.add_progressbar(thread_pagebar);
-            // read input
-            info!(thread_o, "Reading file {}", thread_pagefile.display());
-            match fs::read_to_string(thread_pagefile.clone()) {
-                Ok(yaml) => parser.parse_yaml(yaml.as_str()),
-                Err(e) => error!(
-                    thread_o,
-                    "Error reading file {f} | {e}",
-                    f = thread_pagefile.display()
-                ),
-            }
-            // write output
-            let mut out_f = thread_o.output.clone();
-            out_f.push(diff_paths(thread_pagefile, thread_o.input.clone()).unwrap());
-            out_f.set_extension("html");
-            let mut out_d = out_f.clone();
-            out_d.pop(); // out_d now just directory containing file
-            info!(thread_o, "Writing file {}", out_f.display());
-            match fs::create_dir_all(out_d) {
-                Ok(()) => match fs::write(out_f.clone(), format!("<!DOCTYPE html>\n{}", parser)) {
-                    Ok(()) => (),
-                    Err(e) => error!(
-                        thread_o,
-                        "Error writing file {f} | {e}",
-                        f = out_f.display()
-                    ),
-                },
-                Err(e) => error!(
-                    thread_o,
-                    "Error writing file {f} | {e}",
-                    f = out_f.display()
-                ),
+        let key = manifest::input_key(&o, p);
+        let out_f = build::output_path(&o, p);
+        if o.incremental && !old_manifest.is_stale(&o, &key, &out_f, &meta.hash) {
+            info!(o, "Skipping unchanged file {}", p.display());
+            let variants = build::variant_key(&key, "");
+            for (k, entry) in old_manifest.pages.iter() {
+                if *k == key || k.starts_with(&variants[..]) {
+                    manifest.pages.insert(k.clone(), entry.clone());
+                }
             }
-        }))
+            pagebar.inc(1);
+            return;
+        }
+        queue.push((key, p));
     });
 
-    // collect threads
-    debug!(o, "Collecting Page threads!");
-    loop {
-        match handlers.pop() {
-            Some(t) => {
-                t.join().unwrap();
+    // create threads
+    // every thread builds a page and its variants at a time, see build::variants
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(queue.len());
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<(usize, Vec<(Box<str>, PageEntry, Option<String>)>)>();
+    let mut results = Vec::with_capacity(queue.len());
+    thread::scope(|s| {
+        for _ in 0..workers {
+            let (o, meta, queue, next, memory, pagebar) =
+                (&o, &meta, &queue, &next, &memory, &pagebar);
+            let sender = sender.clone();
+            s.spawn(move || loop {
+                // pages that have not started by the time Ctrl-C is pressed are left out
+                if o.interrupted.load(Ordering::SeqCst) {
+                    return;
+                }
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let (key, page) = match queue.get(i) {
+                    Some(p) => p,
+                    None => return,
+                };
+                let _reservation = memory.reserve(memory::estimate(page));
+                // waiting for memory can take long enough for Ctrl-C to be pressed
+                if o.interrupted.load(Ordering::SeqCst) {
+                    return;
+                }
+                let (html, entry) =
+                    build::render_page(o.clone(), meta.clone(), page, Some(pagebar.clone()));
+                let mut built = vec![(key.clone(), entry, html)];
+                for variant in build::variants(o, page) {
+                    let (html, entry) =
+                        build::render_variant(o.clone(), meta.clone(), page, &variant);
+                    built.push((build::variant_key(key, &variant.name), entry, html));
+                }
+                // reproducible builds write pages one at a time, in order, once every thread is done
+                let built = match o.reproducible {
+                    true => built
+                        .into_iter()
+                        .map(|(key, entry, html)| (key, entry, Some(html)))
+                        .collect(),
+                    false => built
+                        .into_iter()
+                        .map(|(key, entry, html)| {
+                            build::write_page(
+                                o,
+                                &paths::join_slash(&o.output, &entry.output),
+                                &html,
+                            );
+                            (key, entry, None)
+                        })
+                        .collect(),
+                };
+                let _ = sender.send((i, built));
+            });
+        }
+        drop(sender);
+
+        // collect pages as they are built
+        debug!(o, "Collecting Page threads!");
+        let mut done = pages.len() - queue.len();
+        let mut last_status = Instant::now();
+        for result in receiver {
+            done += 1;
+            if o.status_lines && (last_status.elapsed() >= STATUS_INTERVAL || done == pages.len()) {
+                eprintln!("Built {done}/{n} pages", n = pages.len());
+                last_status = Instant::now();
+            }
+            results.push(result);
+        }
+    });
+    results.sort_by_key(|(i, _)| *i);
+    for (_, built) in results {
+        for (key, entry, html) in built {
+            match html {
+                Some(html) => {
+                    build::write_page(&o, &paths::join_slash(&o.output, &entry.output), &html)
+                }
+                None => (),
             }
-            None => break,
-        };
+            manifest.pages.insert(key, entry);
+        }
     }
 
-    /* CLEANUP */
+    /* INTERRUPTED */
+    // nothing after this point is worth doing for part of the site
+    if o.interrupted.load(Ordering::SeqCst) {
+        pagebar.abandon();
+        let not_built: Vec<Box<str>> = pages
+            .iter()
+            .map(|p| manifest::input_key(&o, p))
+            .filter(|k| !manifest.pages.contains_key(k))
+            .collect();
+        warn!(
+            o,
+            "Built {n} of {total} pages before stopping, the manifest of the previous build is kept",
+            n = pages.len() - not_built.len(),
+            total = pages.len()
+        );
+        for key in not_built {
+            warn!(o, "Not built: {key}");
+        }
+        return;
+    }
+
+    /* TAGS */
+    // every page can change the tags, so their pages are built every time
+    for tag in build::tag_pages(&meta) {
+        let (html, entry) = build::render_tag_page(o.clone(), meta.clone(), &tag);
+        build::write_page(&o, &paths::join_slash(&o.output, &entry.output), &html);
+        let key = format!("{}#{}", build::TAG_TEMPLATE, entry.output);
+        manifest.pages.insert(key.into(), entry);
+    }
+    stats.phase("pages");
+
+    /* REDIRECTS */
+    let redirects = redirects::write(&o, redirects::collect(&o, &meta), &mut manifest);
+
+    /* BUILD INFO */
+    buildinfo::write(&o, &mut manifest);
+
+    /* ROBOTS */
+    robots::write(&o, &mut manifest);
+
+    /* WEB APP */
+    // before the server configuration and compressed copies, which the service worker should not precache
+    pwa::write(&o, &mut manifest);
+
+    /* SERVER */
+    server::write(&o, &redirects, &mut manifest);
+
+    /* PRECOMPRESS */
+    if o.precompress {
+        compress::precompress(&o, &mut manifest);
+    }
+    manifest.index_files(&o);
+    manifest.save(&o);
+    if o.clean {
+        build::clean_output(&o, &old_manifest, &manifest);
+    }
     pagebar.inc(1);
     pagebar.tick();
-    info!(
-        o,
-        "Completed in {t} Seconds!",
-        t = start_time.elapsed().as_secs_f32()
-    );
-    drop(o); // ensures logger gets flushed
+    stats.phase("manifest");
 
-    // for some reason we need to wait extra time for logger to flush
-    thread::sleep(std::time::Duration::from_millis(100));
+    /* CDN */
+    match (o.purge_cdn, &o.base_url) {
+        (Some(provider), Some(base_url)) => {
+            let urls = cdn::changed_urls(base_url, &manifest.changes_since(&old_manifest));
+            cdn::purge(&o, provider, &urls);
+            stats.phase("cdn");
+        }
+        _ => (),
+    }
+
+    /* POST BUILD */
+    if !o.post_build.is_empty() {
+        match o.diagnostics.failed() {
+            true => warn!(o, "Not running post_build hooks, since the build failed"),
+            false => {
+                hooks::run_build_hooks(&o, &o.post_build, "post_build");
+                stats.phase("hooks");
+            }
+        }
+    }
+
+    /* SUMMARY */
+    if o.summary {
+        for line in stats.lines(&o, &manifest) {
+            eprintln!("{line}");
+        }
+    }
 }
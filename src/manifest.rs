@@ -0,0 +1,433 @@
+//! Build manifest describing what every page was built from
+//!
+//! The manifest is stored as JSON in the output directory so that subsequent builds can find out which pages are out of date.
+//! It also lists every file in the output directory with its source, hash and size, for use by deploy scripts
+//! ```
+//! let mut m = Manifest::load(&o);
+//! m.pages.insert("index.page".into(), PageEntry::default());
+//! m.save(&o);
+//! ```
+
+/* IMPORTS */
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/* LOCAL IMPORTS */
+use crate::{build, debug, error, paths, warn, Options};
+
+/* MANIFEST */
+/// Name of the manifest file inside the output directory
+pub const MANIFEST_FILE: &str = "ssgen-manifest.json";
+
+/// Record of a previous build
+#[derive(Serialize, Deserialize, Default)]
+pub struct Manifest {
+    /// Entries for every page, keyed by the page path relative to the input directory
+    pub pages: BTreeMap<Box<str>, PageEntry>,
+
+    /// Every generated or copied file, keyed by its path relative to the output directory
+    #[serde(default)]
+    pub files: BTreeMap<Box<str>, FileEntry>,
+}
+
+/// Record of a single page from a previous build
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct PageEntry {
+    /// Files other than the page itself that were read while building the page, relative to the input directory
+    pub dependencies: Vec<Box<str>>,
+
+    /// Generated HTML file, relative to the output directory
+    #[serde(default)]
+    pub output: Box<str>,
+
+    /// SHA-256 hash of the generated HTML
+    #[serde(default)]
+    pub hash: Box<str>,
+
+    /// Other files written into the output directory while building the page, mapped to the input file they came from.
+    /// Files generated by the page itself come from the page
+    #[serde(default)]
+    pub artifacts: BTreeMap<Box<str>, Box<str>>,
+
+    /// Environment variables read while building the page, with their values at the time or null if they were unset
+    #[serde(default)]
+    pub env: BTreeMap<Box<str>, Option<Box<str>>>,
+
+    /// Profile the page was built with, see --profile
+    #[serde(default)]
+    pub profile: Option<Box<str>>,
+
+    /// Hash of the variables every page sees, such as site.pages and those of META.yaml, see build::site_hash
+    #[serde(default)]
+    pub site: Box<str>,
+}
+
+/// Record of a single file in the output directory
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct FileEntry {
+    /// Input file the output was generated or copied from, relative to the input directory
+    pub source: Box<str>,
+
+    /// SHA-256 hash of the file
+    pub hash: Box<str>,
+
+    /// Size of the file in bytes
+    pub size: u64,
+}
+
+/// How a page differs between two manifests
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// Page only exists in the newer manifest
+    Added,
+    /// Page exists in both manifests but its output differs
+    Modified,
+    /// Page only exists in the older manifest
+    Deleted,
+}
+
+impl Manifest {
+    /// Get the path to the manifest file
+    pub fn path(o: &Options) -> PathBuf {
+        let mut p = o.output.clone();
+        p.push(MANIFEST_FILE);
+        return p;
+    }
+
+    /// Load the manifest of the previous build, or an empty manifest if there is none
+    pub fn load(o: &Options) -> Manifest {
+        return Manifest::load_from(o, &Manifest::path(o));
+    }
+
+    /// Load a manifest from anywhere, or an empty manifest if there is none
+    pub fn load_from(o: &Options, path: &Path) -> Manifest {
+        if !path.exists() {
+            debug!(o, "No manifest found at {}", path.display());
+            return Manifest::default();
+        }
+        match fs::read_to_string(path) {
+            Ok(s) => match serde_json::from_str(s.as_str()) {
+                Ok(m) => return m,
+                Err(e) => warn!(
+                    o,
+                    "Ignoring malformed manifest {f} | {e}",
+                    f = path.display()
+                ),
+            },
+            Err(e) => warn!(o, "Unable to read manifest {f} | {e}", f = path.display()),
+        }
+        return Manifest::default();
+    }
+
+    /// Write the manifest into the output directory
+    pub fn save(&self, o: &Options) {
+        let path = Manifest::path(o);
+        debug!(o, "Writing manifest {}", path.display());
+        match serde_json::to_string_pretty(self) {
+            Ok(s) => match o.writes.write(&path, s.as_bytes()) {
+                Ok(()) => (),
+                Err(e) => error!(o, "Error writing file {f} | {e}", f = path.display()),
+            },
+            Err(e) => error!(o, "Error serialising manifest | {e}"),
+        }
+    }
+
+    /// Get every file in the output directory the build produced, including the manifest itself
+    pub fn outputs(&self) -> BTreeSet<Box<str>> {
+        let mut outputs = BTreeSet::from([MANIFEST_FILE.into()]);
+        for entry in self.pages.values() {
+            outputs.insert(entry.output.clone());
+            outputs.extend(entry.artifacts.keys().cloned());
+        }
+        return outputs;
+    }
+
+    /// Fill in files from the pages and the files they wrote into the output directory
+    ///
+    /// Sizes are read from the output directory, so this has to happen after everything was written
+    pub fn index_files(&mut self, o: &Options) {
+        self.files.clear();
+        for (key, entry) in self.pages.iter() {
+            let mut files = vec![(&entry.output, key, Some(entry.hash.clone()))];
+            entry
+                .artifacts
+                .iter()
+                .for_each(|(output, source)| files.push((output, source, None)));
+            for (output, source, hash) in files {
+                let path = paths::join_slash(&o.output, output);
+                let size = match fs::metadata(&path) {
+                    Ok(m) => m.len(),
+                    Err(e) => {
+                        warn!(o, "Unable to index {f} | {e}", f = path.display());
+                        continue;
+                    }
+                };
+                // pages already know their hash, only copied files have to be read again
+                let hash = match hash {
+                    Some(h) if h.len() != 0 => h,
+                    _ => match fs::read(&path) {
+                        Ok(data) => build::hash(&data),
+                        Err(e) => {
+                            warn!(o, "Unable to index {f} | {e}", f = path.display());
+                            continue;
+                        }
+                    },
+                };
+                self.files.insert(
+                    output.clone(),
+                    FileEntry {
+                        source: source.clone(),
+                        hash: hash,
+                        size: size,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Check if a page has to be rebuilt
+    ///
+    /// A page is stale if any of the following are true:
+    /// - The page was not part of the previous build
+    /// - The output file is missing
+    /// - The profile, any environment variable the page read, or site, the hash of what every page sees, changed
+    /// - The page or any of its dependencies were modified after the output file was written
+    /// - Any of the dependencies no longer exist
+    pub fn is_stale(&self, o: &Options, page: &str, output: &Path, site: &str) -> bool {
+        let entry = match self.pages.get(page) {
+            Some(e) => e,
+            None => return true,
+        };
+        if entry.profile != o.profile
+            || &entry.site[..] != site
+            || entry
+                .env
+                .iter()
+                .any(|(name, value)| env::var(&name[..]).ok().as_deref() != value.as_deref())
+        {
+            return true;
+        }
+        let built = match modified(output) {
+            Some(t) => t,
+            None => return true,
+        };
+        return std::iter::once(page)
+            .chain(entry.dependencies.iter().map(|d| &d[..]))
+            .any(|dep| {
+                let mut p = o.input.clone();
+                p.push(dep);
+                match modified(&p) {
+                    Some(t) => t > built,
+                    None => true,
+                }
+            });
+    }
+
+    /// List the output files of every page that differs from an older manifest
+    ///
+    /// Pages without a recorded hash are always considered modified
+    pub fn changes_since(&self, old: &Manifest) -> Vec<(Change, Box<str>)> {
+        let mut changes = Vec::new();
+        for (key, entry) in self.pages.iter() {
+            match old.pages.get(key) {
+                Some(o) if o.hash.len() != 0 && o.hash == entry.hash => (),
+                Some(_) => changes.push((Change::Modified, entry.output.clone())),
+                None => changes.push((Change::Added, entry.output.clone())),
+            }
+        }
+        for (key, entry) in old.pages.iter() {
+            if !self.pages.contains_key(key) {
+                changes.push((Change::Deleted, entry.output.clone()));
+            }
+        }
+        return changes;
+    }
+}
+
+/// Convert an absolute path in the output directory into a manifest value
+pub fn output_key(o: &Options, path: &Path) -> Box<str> {
+    return match path.strip_prefix(&o.output) {
+        Ok(p) => paths::to_slash(p),
+        Err(_) => path.display().to_string().into(),
+    };
+}
+
+/// Convert an absolute path in the input directory into a manifest key
+pub fn input_key(o: &Options, path: &Path) -> Box<str> {
+    return match path.strip_prefix(&o.input) {
+        Ok(p) => paths::to_slash(p),
+        Err(_) => path.display().to_string().into(),
+    };
+}
+
+/// Get the modification time of a file, if it exists
+fn modified(path: &Path) -> Option<SystemTime> {
+    return fs::metadata(path).and_then(|m| m.modified()).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+    use std::{thread, time::Duration};
+
+    /// Ensure stale pages are detected through their dependencies
+    #[test]
+    fn test_is_stale() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_manifest").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_manifest").unwrap();
+        let o = Args::parse_from([
+            "",
+            "-i",
+            "/tmp/ssgen_test_source_dir_manifest",
+            "-o",
+            "/tmp/ssgen_test_dest_dir_manifest",
+            "-s",
+        ])
+        .build_options();
+        fs::write("/tmp/ssgen_test_source_dir_manifest/index.page", "p: a").unwrap();
+        fs::write("/tmp/ssgen_test_source_dir_manifest/dep.block", "p: b").unwrap();
+        thread::sleep(Duration::from_millis(10));
+        let out = PathBuf::from("/tmp/ssgen_test_dest_dir_manifest/index.html");
+        fs::write(&out, "<p>a</p>").unwrap();
+
+        let mut m = Manifest::default();
+        assert_eq!(m.is_stale(&o, "index.page", &out, "site"), true);
+        m.pages.insert(
+            "index.page".into(),
+            PageEntry {
+                dependencies: vec!["dep.block".into()],
+                env: BTreeMap::from([
+                    ("SSGEN_TEST_UNSET_VARIABLE".into(), None),
+                    ("PATH".into(), env::var("PATH").ok().map(Into::into)),
+                ]),
+                site: "site".into(),
+                ..Default::default()
+            },
+        );
+        assert_eq!(m.is_stale(&o, "index.page", &out, "site"), false);
+
+        // round trip through the output directory
+        m.save(&o);
+        let mut m = Manifest::load(&o);
+        assert_eq!(m.is_stale(&o, "index.page", &out, "site"), false);
+
+        // anything every page sees, the profile and environment variables that were read
+        assert_eq!(m.is_stale(&o, "index.page", &out, "new page"), true);
+        let mut production = o.clone();
+        production.profile = Some("production".into());
+        assert_eq!(m.is_stale(&production, "index.page", &out, "site"), true);
+        let entry = m.pages.get_mut("index.page").unwrap();
+        entry
+            .env
+            .insert("SSGEN_TEST_UNSET_VARIABLE".into(), Some("set".into()));
+        assert_eq!(m.is_stale(&o, "index.page", &out, "site"), true);
+        let entry = m.pages.get_mut("index.page").unwrap();
+        entry.env.insert("SSGEN_TEST_UNSET_VARIABLE".into(), None);
+        assert_eq!(m.is_stale(&o, "index.page", &out, "site"), false);
+
+        thread::sleep(Duration::from_millis(10));
+        fs::write("/tmp/ssgen_test_source_dir_manifest/dep.block", "p: c").unwrap();
+        assert_eq!(m.is_stale(&o, "index.page", &out, "site"), true);
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_manifest").unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_manifest").unwrap();
+    }
+
+    /// Ensure every page and copied file is indexed with its source, hash and size
+    #[test]
+    fn test_index_files() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_index").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_index/assets").unwrap();
+        let o = Args::parse_from([
+            "",
+            "-i",
+            "/tmp/ssgen_test_source_dir_index",
+            "-o",
+            "/tmp/ssgen_test_dest_dir_index",
+            "-s",
+        ])
+        .build_options();
+        fs::write("/tmp/ssgen_test_dest_dir_index/index.html", "<p>a</p>").unwrap();
+        fs::write("/tmp/ssgen_test_dest_dir_index/assets/a.css", "p{}").unwrap();
+
+        let mut m = Manifest::default();
+        m.pages.insert(
+            "index.page".into(),
+            PageEntry {
+                output: "index.html".into(),
+                hash: "known".into(),
+                artifacts: BTreeMap::from([
+                    ("assets/a.css".into(), "assets/a.css".into()),
+                    ("missing.txt".into(), "missing.txt".into()),
+                ]),
+                ..Default::default()
+            },
+        );
+        m.index_files(&o);
+        assert_eq!(
+            m.files,
+            BTreeMap::from([
+                (
+                    "index.html".into(),
+                    FileEntry {
+                        source: "index.page".into(),
+                        hash: "known".into(),
+                        size: 8,
+                    }
+                ),
+                (
+                    "assets/a.css".into(),
+                    FileEntry {
+                        source: "assets/a.css".into(),
+                        hash: build::hash(b"p{}"),
+                        size: 3,
+                    }
+                ),
+            ])
+        );
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_index").unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_index").unwrap();
+    }
+
+    /// Ensure differences between manifests are found
+    #[test]
+    fn test_changes_since() {
+        let entry = |output: &str, hash: &str| PageEntry {
+            output: output.into(),
+            hash: hash.into(),
+            ..Default::default()
+        };
+        let mut old = Manifest::default();
+        old.pages
+            .insert("same.page".into(), entry("same.html", "1"));
+        old.pages.insert("mod.page".into(), entry("mod.html", "2"));
+        old.pages
+            .insert("nohash.page".into(), entry("nohash.html", ""));
+        old.pages.insert("del.page".into(), entry("del.html", "3"));
+        let mut new = Manifest::default();
+        new.pages
+            .insert("same.page".into(), entry("same.html", "1"));
+        new.pages.insert("mod.page".into(), entry("mod.html", "4"));
+        new.pages
+            .insert("nohash.page".into(), entry("nohash.html", ""));
+        new.pages.insert("add.page".into(), entry("add.html", "5"));
+
+        assert_eq!(
+            new.changes_since(&old),
+            vec![
+                (Change::Added, "add.html".into()),
+                (Change::Modified, "mod.html".into()),
+                (Change::Modified, "nohash.html".into()),
+                (Change::Deleted, "del.html".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,703 @@
+//! LaTeX math converted to MathML for the !MATH directive, so formulas need no scripts to display
+//!
+//! Covers the commonly used part of LaTeX math: scripts, fractions, roots, delimiters, accents,
+//! fonts, matrices and cases, and the usual Greek letters, symbols and functions.
+//! Anything else is an error rather than being shown wrong
+//! ```
+//! let html = math::to_mathml(r"\frac{-b \pm \sqrt{b^2 - 4ac}}{2a}", true)?;
+//! ```
+
+/* MATH */
+/// Greek letters and other symbols that are identifiers
+const IDENTIFIERS: [(&str, &str); 46] = [
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ϵ"),
+    ("varepsilon", "ε"),
+    ("zeta", "ζ"),
+    ("eta", "η"),
+    ("theta", "θ"),
+    ("vartheta", "ϑ"),
+    ("iota", "ι"),
+    ("kappa", "κ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("nu", "ν"),
+    ("xi", "ξ"),
+    ("pi", "π"),
+    ("varpi", "ϖ"),
+    ("rho", "ρ"),
+    ("varrho", "ϱ"),
+    ("sigma", "σ"),
+    ("varsigma", "ς"),
+    ("tau", "τ"),
+    ("upsilon", "υ"),
+    ("phi", "ϕ"),
+    ("varphi", "φ"),
+    ("chi", "χ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Xi", "Ξ"),
+    ("Pi", "Π"),
+    ("Sigma", "Σ"),
+    ("Upsilon", "Υ"),
+    ("Phi", "Φ"),
+    ("Psi", "Ψ"),
+    ("Omega", "Ω"),
+    ("infty", "∞"),
+    ("ell", "ℓ"),
+    ("hbar", "ℏ"),
+    ("nabla", "∇"),
+    ("emptyset", "∅"),
+    ("aleph", "ℵ"),
+];
+
+/// Symbols that are operators, relations or delimiters
+const OPERATORS: [(&str, &str); 70] = [
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("times", "×"),
+    ("div", "÷"),
+    ("cdot", "⋅"),
+    ("ast", "∗"),
+    ("star", "⋆"),
+    ("circ", "∘"),
+    ("bullet", "∙"),
+    ("oplus", "⊕"),
+    ("otimes", "⊗"),
+    ("le", "≤"),
+    ("leq", "≤"),
+    ("ge", "≥"),
+    ("geq", "≥"),
+    ("ne", "≠"),
+    ("neq", "≠"),
+    ("approx", "≈"),
+    ("equiv", "≡"),
+    ("sim", "∼"),
+    ("simeq", "≃"),
+    ("cong", "≅"),
+    ("propto", "∝"),
+    ("ll", "≪"),
+    ("gg", "≫"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("ni", "∋"),
+    ("subset", "⊂"),
+    ("subseteq", "⊆"),
+    ("supset", "⊃"),
+    ("supseteq", "⊇"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("setminus", "∖"),
+    ("wedge", "∧"),
+    ("land", "∧"),
+    ("vee", "∨"),
+    ("lor", "∨"),
+    ("neg", "¬"),
+    ("lnot", "¬"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("partial", "∂"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("leftrightarrow", "↔"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("Leftrightarrow", "⇔"),
+    ("implies", "⟹"),
+    ("iff", "⟺"),
+    ("mapsto", "↦"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("cdots", "⋯"),
+    ("vdots", "⋮"),
+    ("ddots", "⋱"),
+    ("mid", "∣"),
+    ("parallel", "∥"),
+    ("perp", "⊥"),
+    ("angle", "∠"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("lfloor", "⌊"),
+    ("rfloor", "⌋"),
+    ("lceil", "⌈"),
+    ("rceil", "⌉"),
+    ("prime", "′"),
+];
+
+/// Operators whose scripts go above and below them
+const LARGE_OPERATORS: [(&str, &str); 5] = [
+    ("sum", "∑"),
+    ("prod", "∏"),
+    ("coprod", "∐"),
+    ("bigcup", "⋃"),
+    ("bigcap", "⋂"),
+];
+
+/// Integrals, whose scripts stay to the side
+const INTEGRALS: [(&str, &str); 4] = [("int", "∫"), ("iint", "∬"), ("iiint", "∭"), ("oint", "∮")];
+
+/// Functions written upright, with true when their scripts go below them like limits
+const FUNCTIONS: [(&str, bool); 26] = [
+    ("sin", false),
+    ("cos", false),
+    ("tan", false),
+    ("cot", false),
+    ("sec", false),
+    ("csc", false),
+    ("arcsin", false),
+    ("arccos", false),
+    ("arctan", false),
+    ("sinh", false),
+    ("cosh", false),
+    ("tanh", false),
+    ("log", false),
+    ("ln", false),
+    ("exp", false),
+    ("det", false),
+    ("dim", false),
+    ("ker", false),
+    ("deg", false),
+    ("gcd", false),
+    ("arg", false),
+    ("lim", true),
+    ("max", true),
+    ("min", true),
+    ("sup", true),
+    ("inf", true),
+];
+
+/// Accents, with true when they stretch over all of their argument
+const ACCENTS: [(&str, &str, bool); 7] = [
+    ("hat", "^", false),
+    ("bar", "¯", false),
+    ("vec", "→", false),
+    ("dot", "˙", false),
+    ("ddot", "¨", false),
+    ("tilde", "~", false),
+    ("overline", "‾", true),
+];
+
+/// Spacing commands and their widths in em
+const SPACES: [(&str, f32); 7] = [
+    (",", 0.1667),
+    (":", 0.2222),
+    (">", 0.2222),
+    (";", 0.2778),
+    ("!", -0.1667),
+    ("quad", 1.0),
+    ("qquad", 2.0),
+];
+
+/// Matrix environments and the delimiters around them
+const MATRICES: [(&str, &str, &str); 6] = [
+    ("matrix", "", ""),
+    ("pmatrix", "(", ")"),
+    ("bmatrix", "[", "]"),
+    ("Bmatrix", "{", "}"),
+    ("vmatrix", "|", "|"),
+    ("Vmatrix", "‖", "‖"),
+];
+
+/// Convert LaTeX math to a MathML element, displayed as its own block if display is true
+///
+/// The source is kept as an annotation, so it is what gets copied along with the formula
+pub fn to_mathml(latex: &str, display: bool) -> Result<String, Box<str>> {
+    let mut tex = Tex {
+        chars: latex.chars().collect(),
+        pos: 0,
+    };
+    let items = tex.row()?;
+    tex.skip_space();
+    if tex.pos < tex.chars.len() {
+        let rest: String = tex.chars[tex.pos..].iter().take(10).collect();
+        return Err(format!(r#"Unexpected "{rest}" in math"#).into());
+    }
+    return Ok(format!(
+        r#"<math{}><semantics>{}<annotation encoding="application/x-tex">{}</annotation></semantics></math>"#,
+        if display { r#" display="block""# } else { "" },
+        mrow(items),
+        escape(latex.trim())
+    ));
+}
+
+/// LaTeX being converted
+struct Tex {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Tex {
+    /// Convert everything up to the end of the current group, cell or delimiter, which is left to the caller
+    fn row(&mut self) -> Result<Vec<String>, Box<str>> {
+        let mut items = Vec::new();
+        while let Some((base, limits)) = self.atom()? {
+            items.push(self.scripts(base, limits)?);
+        }
+        return Ok(items);
+    }
+
+    /// Convert one element, or give None at the end of a group, cell or delimiter
+    ///
+    /// Also gives whether scripts go above and below the element
+    fn atom(&mut self) -> Result<Option<(String, bool)>, Box<str>> {
+        self.skip_space();
+        let c = match self.chars.get(self.pos) {
+            Some(c) => *c,
+            None => return Ok(None),
+        };
+        let ml = match c {
+            '}' | '&' => return Ok(None),
+            // scripts without a base
+            '^' | '_' => "<mrow></mrow>".to_string(),
+            '{' => {
+                self.pos += 1;
+                let items = self.row()?;
+                self.expect('}')?;
+                mrow(items)
+            }
+            '\\' => return self.command(),
+            '0'..='9' | '.' => {
+                let start = self.pos;
+                while self
+                    .chars
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+                {
+                    self.pos += 1;
+                }
+                let n: String = self.chars[start..self.pos].iter().collect();
+                format!("<mn>{n}</mn>")
+            }
+            c if c.is_alphabetic() => {
+                self.pos += 1;
+                format!("<mi>{c}</mi>")
+            }
+            '-' => {
+                self.pos += 1;
+                "<mo>−</mo>".to_string()
+            }
+            '\'' => {
+                self.pos += 1;
+                "<mo>′</mo>".to_string()
+            }
+            '*' => {
+                self.pos += 1;
+                "<mo>∗</mo>".to_string()
+            }
+            c => {
+                self.pos += 1;
+                format!("<mo>{}</mo>", escape(&c.to_string()))
+            }
+        };
+        return Ok(Some((ml, false)));
+    }
+
+    /// Convert a command, or give None if it ends a row
+    fn command(&mut self) -> Result<Option<(String, bool)>, Box<str>> {
+        let start = self.pos;
+        let name = self.command_name();
+        let lookup = |table: &[(&str, &'static str)]| {
+            table.iter().find(|(n, _)| *n == name).map(|(_, s)| *s)
+        };
+
+        let ml = match &name[..] {
+            "\\" | "right" | "end" => {
+                self.pos = start;
+                return Ok(None);
+            }
+            "frac" | "dfrac" | "tfrac" => {
+                format!("<mfrac>{}{}</mfrac>", self.argument()?, self.argument()?)
+            }
+            "binom" => format!(
+                r#"<mrow><mo>(</mo><mfrac linethickness="0">{}{}</mfrac><mo>)</mo></mrow>"#,
+                self.argument()?,
+                self.argument()?
+            ),
+            "sqrt" => {
+                self.skip_space();
+                match self.chars.get(self.pos) {
+                    Some('[') => {
+                        self.pos += 1;
+                        let end = self.chars[self.pos..]
+                            .iter()
+                            .position(|c| *c == ']')
+                            .ok_or("Missing ] after \\sqrt[")?;
+                        let mut index = Tex {
+                            chars: self.chars[self.pos..self.pos + end].to_vec(),
+                            pos: 0,
+                        };
+                        let index = mrow(index.row()?);
+                        self.pos += end + 1;
+                        format!("<mroot>{}{index}</mroot>", self.argument()?)
+                    }
+                    _ => format!("<msqrt>{}</msqrt>", self.argument()?),
+                }
+            }
+            "left" => {
+                let open = self.delimiter()?;
+                let items = self.row()?;
+                if self.command_name() != "right" {
+                    return Err("Missing \\right after \\left".into());
+                }
+                let close = self.delimiter()?;
+                format!("<mrow>{open}{}{close}</mrow>", items.join(""))
+            }
+            "begin" => self.environment()?,
+            "text" | "textrm" | "mbox" => format!("<mtext>{}</mtext>", escape(&self.text()?)),
+            "mathrm" | "operatorname" => {
+                format!(r#"<mi mathvariant="normal">{}</mi>"#, escape(&self.text()?))
+            }
+            "mathbb" | "mathbf" | "mathcal" => {
+                let text = self.text()?;
+                let styled: String = text.chars().map(|c| styled(&name, c)).collect();
+                format!("<mi>{}</mi>", escape(&styled))
+            }
+            "underline" => format!(
+                r#"<munder accentunder="true">{}<mo stretchy="true">_</mo></munder>"#,
+                self.argument()?
+            ),
+            _ => {
+                if let Some((_, accent, stretchy)) = ACCENTS.iter().find(|(n, _, _)| *n == name) {
+                    format!(
+                        r#"<mover accent="true">{}<mo stretchy="{stretchy}">{accent}</mo></mover>"#,
+                        self.argument()?
+                    )
+                } else if let Some(width) = SPACES.iter().find(|(n, _)| *n == name).map(|s| s.1) {
+                    format!(r#"<mspace width="{width}em"/>"#)
+                } else if let Some(s) = lookup(&IDENTIFIERS) {
+                    format!("<mi>{s}</mi>")
+                } else if let Some(s) = lookup(&OPERATORS) {
+                    format!("<mo>{s}</mo>")
+                } else if let Some(s) = lookup(&LARGE_OPERATORS) {
+                    return Ok(Some((format!("<mo>{s}</mo>"), true)));
+                } else if let Some(s) = lookup(&INTEGRALS) {
+                    format!("<mo>{s}</mo>")
+                } else if let Some((_, limits)) = FUNCTIONS.iter().find(|(n, _)| *n == name) {
+                    return Ok(Some((format!("<mi>{name}</mi>"), *limits)));
+                } else {
+                    match &name[..] {
+                        " " => "<mtext>\u{a0}</mtext>".to_string(),
+                        "{" | "}" | "|" | "#" | "%" | "$" | "&" | "_" => {
+                            let c = if name == "|" { "‖" } else { &name };
+                            format!("<mo>{}</mo>", escape(c))
+                        }
+                        "" => return Err("Unexpected \\ at the end of math".into()),
+                        _ => return Err(format!("Unknown command \\{name} in math").into()),
+                    }
+                }
+            }
+        };
+        return Ok(Some((ml, false)));
+    }
+
+    /// Attach any scripts following base, placed above and below it if limits is true
+    fn scripts(&mut self, base: String, limits: bool) -> Result<String, Box<str>> {
+        let (mut sub, mut sup) = (None, None);
+        loop {
+            self.skip_space();
+            match self.chars.get(self.pos) {
+                Some('_') if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.argument()?);
+                }
+                Some('^') if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.argument()?);
+                }
+                _ => break,
+            }
+        }
+        let (under, over, both) = match limits {
+            true => ("munder", "mover", "munderover"),
+            false => ("msub", "msup", "msubsup"),
+        };
+        return Ok(match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{under}>{base}{sub}</{under}>"),
+            (None, Some(sup)) => format!("<{over}>{base}{sup}</{over}>"),
+            (Some(sub), Some(sup)) => format!("<{both}>{base}{sub}{sup}</{both}>"),
+        });
+    }
+
+    /// Convert the argument of a command or script, a group or a single element
+    fn argument(&mut self) -> Result<String, Box<str>> {
+        self.skip_space();
+        match self.chars.get(self.pos) {
+            // only the first digit of an ungrouped number, like x^23 is x squared then 3
+            Some(c) if c.is_ascii_digit() => {
+                self.pos += 1;
+                return Ok(format!("<mn>{c}</mn>"));
+            }
+            _ => (),
+        }
+        return match self.atom()? {
+            Some((ml, _)) => Ok(ml),
+            None => Err("Missing argument in math".into()),
+        };
+    }
+
+    /// Read the text of a group as written, for commands that take text instead of math
+    fn text(&mut self) -> Result<String, Box<str>> {
+        self.skip_space();
+        match self.chars.get(self.pos) {
+            Some('{') => {
+                self.pos += 1;
+                let end = self.chars[self.pos..]
+                    .iter()
+                    .position(|c| *c == '}')
+                    .ok_or("Missing }")?;
+                let text = self.chars[self.pos..self.pos + end].iter().collect();
+                self.pos += end + 1;
+                return Ok(text);
+            }
+            Some(c) => {
+                self.pos += 1;
+                return Ok(c.to_string());
+            }
+            None => return Err("Missing argument in math".into()),
+        }
+    }
+
+    /// Convert the delimiter after \left or \right, where `.` is no delimiter
+    fn delimiter(&mut self) -> Result<String, Box<str>> {
+        self.skip_space();
+        let c = match self.chars.get(self.pos) {
+            Some('\\') => {
+                let name = self.command_name();
+                match &name[..] {
+                    "{" | "}" => name,
+                    "|" => "‖".to_string(),
+                    _ => match OPERATORS.iter().find(|(n, _)| *n == name) {
+                        Some((_, s)) => s.to_string(),
+                        None => return Err(format!("Invalid delimiter \\{name} in math").into()),
+                    },
+                }
+            }
+            Some('.') => {
+                self.pos += 1;
+                return Ok(String::new());
+            }
+            Some(c) => {
+                self.pos += 1;
+                c.to_string()
+            }
+            None => return Err("Missing delimiter in math".into()),
+        };
+        return Ok(fence(&c));
+    }
+
+    /// Convert the environment after \begin into a table
+    fn environment(&mut self) -> Result<String, Box<str>> {
+        let name = self.text()?;
+        let (open, close, attributes) = match &name[..] {
+            "cases" => ("{", "", r#" columnalign="left left""#),
+            "aligned" | "align" | "align*" => ("", "", r#" columnalign="right left""#),
+            _ => match MATRICES.iter().find(|(n, _, _)| *n == name) {
+                Some((_, open, close)) => (*open, *close, ""),
+                None => return Err(format!("Unknown environment {name} in math").into()),
+            },
+        };
+
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        loop {
+            let items = self.row()?;
+            cells.push(format!("<mtd>{}</mtd>", mrow(items)));
+            self.skip_space();
+            if self.chars.get(self.pos) == Some(&'&') {
+                self.pos += 1;
+                continue;
+            }
+            let last = match &self.command_name()[..] {
+                "\\" => false,
+                "end" if self.text()? == name => true,
+                _ => return Err(format!("Missing \\end{{{name}}} in math").into()),
+            };
+            // a row break before the end does not start another row
+            if !(last && cells.len() == 1 && cells[0] == "<mtd><mrow></mrow></mtd>") {
+                rows.push(format!("<mtr>{}</mtr>", cells.join("")));
+            }
+            cells.clear();
+            if last {
+                break;
+            }
+        }
+
+        let table = format!("<mtable{attributes}>{}</mtable>", rows.join(""));
+        if open.is_empty() && close.is_empty() {
+            return Ok(table);
+        }
+        let fence = |s: &str| {
+            if s.is_empty() {
+                String::new()
+            } else {
+                fence(s)
+            }
+        };
+        return Ok(format!(
+            "<mrow>{}{table}{}</mrow>",
+            fence(open),
+            fence(close)
+        ));
+    }
+
+    /// Read the name of the command at the current position, letters or a single other character
+    fn command_name(&mut self) -> String {
+        if self.chars.get(self.pos) != Some(&'\\') {
+            return String::new();
+        }
+        self.pos += 1;
+        let start = self.pos;
+        while self
+            .chars
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_alphabetic())
+        {
+            self.pos += 1;
+        }
+        if self.pos == start && self.pos < self.chars.len() {
+            self.pos += 1;
+        }
+        return self.chars[start..self.pos].iter().collect();
+    }
+
+    /// Consume c, or fail if it is not next
+    fn expect(&mut self, c: char) -> Result<(), Box<str>> {
+        self.skip_space();
+        if self.chars.get(self.pos) != Some(&c) {
+            return Err(format!("Missing {c} in math").into());
+        }
+        self.pos += 1;
+        return Ok(());
+    }
+
+    /// Skip whitespace, which means nothing in math
+    fn skip_space(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+}
+
+/// Group items into a single element
+fn mrow(items: Vec<String>) -> String {
+    return match items.len() {
+        1 => items.into_iter().next().unwrap(),
+        _ => format!("<mrow>{}</mrow>", items.join("")),
+    };
+}
+
+/// A delimiter that grows with what it surrounds
+fn fence(c: &str) -> String {
+    return format!(r#"<mo fence="true" stretchy="true">{}</mo>"#, escape(c));
+}
+
+/// Letter c in the font of command, using the Mathematical Alphanumeric Symbols
+fn styled(command: &str, c: char) -> char {
+    // letters that were in Unicode before the rest, and are missing from the blocks
+    let special = match (command, c) {
+        ("mathbb", 'C') => Some('ℂ'),
+        ("mathbb", 'H') => Some('ℍ'),
+        ("mathbb", 'N') => Some('ℕ'),
+        ("mathbb", 'P') => Some('ℙ'),
+        ("mathbb", 'Q') => Some('ℚ'),
+        ("mathbb", 'R') => Some('ℝ'),
+        ("mathbb", 'Z') => Some('ℤ'),
+        ("mathcal", 'B') => Some('ℬ'),
+        ("mathcal", 'E') => Some('ℰ'),
+        ("mathcal", 'F') => Some('ℱ'),
+        ("mathcal", 'H') => Some('ℋ'),
+        ("mathcal", 'I') => Some('ℐ'),
+        ("mathcal", 'L') => Some('ℒ'),
+        ("mathcal", 'M') => Some('ℳ'),
+        ("mathcal", 'R') => Some('ℛ'),
+        _ => None,
+    };
+    if let Some(s) = special {
+        return s;
+    }
+    let start: u32 = match command {
+        "mathbf" => 0x1D400,
+        "mathcal" => 0x1D49C,
+        _ => 0x1D538,
+    };
+    let offset = match c {
+        'A'..='Z' => c as u32 - 'A' as u32,
+        'a'..='z' if command != "mathcal" => c as u32 - 'a' as u32 + 26,
+        _ => return c,
+    };
+    return char::from_u32(start + offset).unwrap_or(c);
+}
+
+/// Escape text for use in MathML
+fn escape(s: &str) -> String {
+    return s
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Convert latex and strip the math element and annotation around it
+    fn convert(latex: &str) -> String {
+        let ml = to_mathml(latex, false).unwrap();
+        let start = "<math><semantics>".len();
+        let end = ml.find("<annotation").unwrap();
+        return ml[start..end].to_string();
+    }
+
+    /// Ensure common LaTeX is converted to the right MathML, and unknown LaTeX is an error
+    #[test]
+    fn test_to_mathml() {
+        assert_eq!(
+            to_mathml("x < 1", true).unwrap(),
+            r#"<math display="block"><semantics><mrow><mi>x</mi><mo>&lt;</mo><mn>1</mn></mrow><annotation encoding="application/x-tex">x &lt; 1</annotation></semantics></math>"#
+        );
+        assert_eq!(
+            convert("x^23"),
+            "<mrow><msup><mi>x</mi><mn>2</mn></msup><mn>3</mn></mrow>"
+        );
+        assert_eq!(
+            convert(r"\frac{-b \pm \sqrt{b^2-4ac}}{2a}"),
+            "<mfrac><mrow><mo>−</mo><mi>b</mi><mo>±</mo><msqrt><mrow><msup><mi>b</mi><mn>2</mn></msup><mo>−</mo><mn>4</mn><mi>a</mi><mi>c</mi></mrow></msqrt></mrow><mrow><mn>2</mn><mi>a</mi></mrow></mfrac>"
+        );
+        assert_eq!(
+            convert(r"\sum_{i=1}^n i_k^2"),
+            "<mrow><munderover><mo>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><msubsup><mi>i</mi><mi>k</mi><mn>2</mn></msubsup></mrow>"
+        );
+        assert_eq!(
+            convert(r"\lim_{x \to 0} \sin x"),
+            "<mrow><munder><mi>lim</mi><mrow><mi>x</mi><mo>→</mo><mn>0</mn></mrow></munder><mi>sin</mi><mi>x</mi></mrow>"
+        );
+        assert_eq!(
+            convert(r"\left( \alpha \right.\vec v"),
+            r#"<mrow><mrow><mo fence="true" stretchy="true">(</mo><mi>α</mi></mrow><mover accent="true"><mi>v</mi><mo stretchy="false">→</mo></mover></mrow>"#
+        );
+        assert_eq!(
+            convert(r"\sqrt[3]{8}\,\text{m & s}\mathbb{R}\mathbf{x}"),
+            r#"<mrow><mroot><mn>8</mn><mn>3</mn></mroot><mspace width="0.1667em"/><mtext>m &amp; s</mtext><mi>ℝ</mi><mi>𝐱</mi></mrow>"#
+        );
+        assert_eq!(
+            convert(r"\begin{pmatrix} 1 & 0 \\ 0 & 1 \\ \end{pmatrix}"),
+            r#"<mrow><mo fence="true" stretchy="true">(</mo><mtable><mtr><mtd><mn>1</mn></mtd><mtd><mn>0</mn></mtd></mtr><mtr><mtd><mn>0</mn></mtd><mtd><mn>1</mn></mtd></mtr></mtable><mo fence="true" stretchy="true">)</mo></mrow>"#
+        );
+
+        assert!(to_mathml(r"\unknown", false).is_err());
+        assert!(to_mathml(r"\frac{1}", false).is_err());
+        assert!(to_mathml(r"{x", false).is_err());
+        assert!(to_mathml(r"x}", false).is_err());
+        assert!(to_mathml(r"\left( x", false).is_err());
+        assert!(to_mathml(r"\begin{matrix} x \end{cases}", false).is_err());
+    }
+}
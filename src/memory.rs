@@ -0,0 +1,111 @@
+//! Limit on the memory pages being built at the same time can use, see --max-memory
+//!
+//! How much memory a page needs is estimated from the size of its file, since its tree only exists once it is built.
+//! Pages wait for enough of the limit to be free before they start, and give it back once they are done
+//! ```
+//! let limit = Arc::new(MemoryLimit::new(o.max_memory));
+//! let _reservation = limit.reserve(memory::estimate(&page)); // blocks until there is room
+//! build::render_page(o, meta, &page, None);
+//! ```
+
+/* IMPORTS */
+use std::{
+    fs,
+    path::Path,
+    sync::{Condvar, Mutex},
+};
+
+/* MEMORY */
+/// Bytes of memory a page is assumed to need for every byte of its file, once parsed into a tree and rendered
+const BYTES_PER_BYTE: u64 = 20;
+
+/// Estimated memory needed to build page
+pub fn estimate(page: &Path) -> u64 {
+    return fs::metadata(page).map_or(0, |m| m.len()) * BYTES_PER_BYTE;
+}
+
+/// Memory shared by every page being built
+pub struct MemoryLimit {
+    /// Bytes that can be reserved at once, 0 for no limit
+    limit: u64,
+    /// Bytes reserved so far
+    reserved: Mutex<u64>,
+    /// Notified whenever a reservation is given back
+    freed: Condvar,
+}
+
+/// Memory reserved for a page, given back when dropped
+pub struct Reservation<'a> {
+    limit: &'a MemoryLimit,
+    bytes: u64,
+}
+
+impl MemoryLimit {
+    /// Limit pages to limit bytes of memory at once, 0 for no limit
+    pub fn new(limit: u64) -> MemoryLimit {
+        return MemoryLimit {
+            limit: limit,
+            reserved: Mutex::new(0),
+            freed: Condvar::new(),
+        };
+    }
+
+    /// Wait until bytes of memory are free and reserve them
+    ///
+    /// Pages estimated to need more than the whole limit wait until nothing else is reserved, then run alone
+    pub fn reserve(&self, bytes: u64) -> Reservation<'_> {
+        if self.limit == 0 {
+            return Reservation {
+                limit: self,
+                bytes: 0,
+            };
+        }
+        let bytes = bytes.min(self.limit);
+        let mut reserved = self
+            .freed
+            .wait_while(self.reserved.lock().unwrap(), |r| *r + bytes > self.limit)
+            .unwrap();
+        *reserved += bytes;
+        return Reservation {
+            limit: self,
+            bytes: bytes,
+        };
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if self.bytes == 0 {
+            return;
+        }
+        *self.limit.reserved.lock().unwrap() -= self.bytes;
+        self.limit.freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread, time::Duration};
+
+    /// Ensure reservations wait for memory to be given back, and oversized ones run alone
+    #[test]
+    fn test_reserve() {
+        let limit = Arc::new(MemoryLimit::new(100));
+        let first = limit.reserve(60);
+        let thread_limit = limit.clone();
+        let waiting = thread::spawn(move || {
+            let _second = thread_limit.reserve(1000);
+            return *thread_limit.reserved.lock().unwrap();
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(waiting.is_finished(), false);
+        drop(first);
+        assert_eq!(waiting.join().unwrap(), 100);
+        assert_eq!(*limit.reserved.lock().unwrap(), 0);
+
+        let unlimited = MemoryLimit::new(0);
+        let _a = unlimited.reserve(u64::MAX);
+        let _b = unlimited.reserve(u64::MAX);
+    }
+}
@@ -0,0 +1,220 @@
+//! Minification of text that ends up in the output directory
+//!
+//! Only removes what can never change the meaning of the text, so it is safe to use on anything
+//! ```
+//! let css = minify::css("a { color: red; }"); // a{color:red}
+//! let js = minify::js("let a = 1 ;\n// done\nf( a );"); // let a=1;f(a);
+//! ```
+
+/* MINIFY */
+/// Remove comments and unneeded whitespace from CSS, leaving strings alone
+///
+/// Whitespace is only removed around `{`, `}`, `;` and `,`, and after `:`, since elsewhere it can separate selectors or operands
+pub fn css(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    // whitespace is written lazily, once it is known whether it is needed
+    let mut space = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                space = true;
+            }
+            '"' | '\'' => {
+                if space && !out.is_empty() && !ends_with_separator(&out) {
+                    out.push(' ');
+                }
+                space = false;
+                out.push(c);
+                let mut escaped = false;
+                for q in chars.by_ref() {
+                    out.push(q);
+                    match q {
+                        '\\' if !escaped => escaped = true,
+                        _ if q == c && !escaped => break,
+                        _ => escaped = false,
+                    }
+                }
+            }
+            c if c.is_whitespace() => space = true,
+            '{' | '}' | ';' | ',' => {
+                // the last declaration of a block does not need its semicolon
+                if c == '}' && out.ends_with(';') {
+                    out.pop();
+                }
+                out.push(c);
+                space = false;
+            }
+            c => {
+                if space && !out.is_empty() && !ends_with_separator(&out) {
+                    out.push(' ');
+                }
+                space = false;
+                out.push(c);
+            }
+        }
+    }
+    return out;
+}
+
+/// Whether whitespace after the end of s can be dropped
+fn ends_with_separator(s: &str) -> bool {
+    return s.ends_with(['{', '}', ';', ',', ':']);
+}
+
+/// Keywords a regular expression can follow, where `/` is never a division
+const BEFORE_REGEX: [&str; 11] = [
+    "return", "typeof", "case", "do", "else", "in", "of", "new", "delete", "void", "throw",
+];
+
+/// Remove comments and unneeded whitespace from JavaScript, leaving strings, template literals and regular expressions alone
+///
+/// Line breaks are kept wherever a semicolon could be left out, since they can end statements there
+pub fn js(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    // whitespace is written lazily, once it is known whether it is needed
+    let mut space = false;
+    let mut newline = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                newline = true;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    newline |= c == '\n';
+                    last = c;
+                }
+                space = true;
+            }
+            c if c.is_whitespace() => {
+                newline |= c == '\n';
+                space = true;
+            }
+            c => {
+                separate(&mut out, c, space, newline);
+                space = false;
+                newline = false;
+                let quote = match c {
+                    '"' | '\'' | '`' => Some(c),
+                    '/' if regex_allowed(&out) => Some('/'),
+                    _ => None,
+                };
+                out.push(c);
+                match quote {
+                    Some(q) => copy_literal(&mut out, &mut chars, q),
+                    None => (),
+                }
+            }
+        }
+    }
+    return out;
+}
+
+/// Write the whitespace needed between the end of out and c, if there was any between them
+fn separate(out: &mut String, c: char, space: bool, newline: bool) {
+    let last = match out.chars().last() {
+        Some(l) if space => l,
+        _ => return,
+    };
+    let word = |c: char| c.is_alphanumeric() || matches!(c, '_' | '$') || !c.is_ascii();
+    if newline
+        && (word(last) || matches!(last, ')' | ']' | '}' | '"' | '\'' | '`' | '+' | '-'))
+        && (word(c)
+            || matches!(
+                c,
+                '(' | '[' | '{' | '"' | '\'' | '`' | '+' | '-' | '/' | '!' | '~'
+            ))
+    {
+        out.push('\n');
+    } else if (word(last) && word(c)) || (last == c && matches!(c, '+' | '-' | '/')) {
+        out.push(' ');
+    }
+}
+
+/// Whether a `/` after out starts a regular expression rather than being a division
+fn regex_allowed(out: &str) -> bool {
+    let trimmed = out.trim_end();
+    return match trimmed.chars().last() {
+        None => true,
+        Some(c) if "(,=:[!&|?{};+-*%<>~^".contains(c) => true,
+        Some(_) => BEFORE_REGEX.iter().any(|k| {
+            trimmed.ends_with(k)
+                && !trimmed[..trimmed.len() - k.len()]
+                    .ends_with(|c: char| c.is_alphanumeric() || matches!(c, '_' | '$'))
+        }),
+    };
+}
+
+/// Copy a string, template literal or regular expression that started with quote into out, up to and including its end
+fn copy_literal(out: &mut String, chars: &mut impl Iterator<Item = char>, quote: char) {
+    let mut escaped = false;
+    // a `/` inside of a character class like [/] does not end a regular expression
+    let mut class = false;
+    for c in chars.by_ref() {
+        out.push(c);
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' if quote == '/' => class = true,
+            ']' if quote == '/' => class = false,
+            _ if c == quote && !class => return,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure comments and whitespace are removed, without joining words or touching strings
+    #[test]
+    fn test_css() {
+        assert_eq!(
+            css("/* header */\nh1 ,\nh2 > a:hover {\n  color: red;\n  margin: 0 auto ;\n}\n"),
+            "h1,h2 > a:hover{color:red;margin:0 auto}"
+        );
+        assert_eq!(
+            css(r#"a::after { content: "  /* kept */ \"x\" "; width: calc(1px + 2px) }"#),
+            r#"a::after{content:"  /* kept */ \"x\" ";width:calc(1px + 2px)}"#
+        );
+        assert_eq!(css("a/**/b {}"), "a b{}");
+    }
+
+    /// Ensure comments and whitespace are removed, keeping line breaks that can end statements and every literal
+    #[test]
+    fn test_js() {
+        assert_eq!(
+            js("// setup\nconst a = 1 ;\nlet b = a + +1\nif ( a ) {\n  /* x */ f( a , b );\n}\n"),
+            "const a=1;let b=a+ +1\nif(a){f(a,b);}"
+        );
+        assert_eq!(
+            js(r#"let s = "a // b" + 'c /* d */' + `e  ${ f }`;"#),
+            r#"let s="a // b"+'c /* d */'+`e  ${ f }`;"#
+        );
+        assert_eq!(
+            js("x = a / b / c;\ny = s.replace( /[/ ]+/g , '' );\nreturn /a b/"),
+            "x=a/b/c;y=s.replace(/[/ ]+/g,'');return/a b/"
+        );
+    }
+}
@@ -17,17 +17,67 @@
 //! ```
 
 /* IMPORTS */
+use regex::Regex;
 use std::{
-    cell::RefCell,
-    collections::{HashMap, LinkedList},
+    cell::Cell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, LinkedList},
     fmt,
-    sync::Arc,
+    iter::Peekable,
+    path::PathBuf,
+    str::Chars,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
 };
 
 /* LOCAL IMPORTS */
-use crate::{debug, error, info, warn, Options};
+use crate::{
+    debug, error,
+    var::{self, UndefinedVars},
+    warn, Options, Var,
+};
 
 /* PAGENODE */
+/// Maximum number of lazy variables that can be resolving at once, protects against self-referencing variables
+const MAX_LAZY_DEPTH: u8 = 64;
+
+thread_local! {
+    /// Number of lazy variables currently being resolved on this thread
+    static LAZY_DEPTH: Cell<u8> = const { Cell::new(0) };
+}
+
+/// Directive invocations that are limited per page, see Options::max_iterations and Options::max_includes
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Budget {
+    Iterations,
+    Includes,
+}
+
+/// A PageNode shared between the nodes of a tree, which can be sent to and read from any thread
+///
+/// Works like a RefCell, except that conflicting borrows from other threads wait instead of panicking
+pub struct NodeCell(RwLock<PageNode>);
+
+impl NodeCell {
+    /// Wrap node so that it can be shared
+    pub fn new(node: PageNode) -> Self {
+        return NodeCell(RwLock::new(node));
+    }
+
+    /// Read the node, waiting for any thread that is changing it
+    pub fn borrow(&self) -> RwLockReadGuard<'_, PageNode> {
+        return self.0.read().unwrap();
+    }
+
+    /// Change the node, waiting for any thread that is reading or changing it
+    pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, PageNode> {
+        return self.0.write().unwrap();
+    }
+
+    /// Unwrap the node
+    pub fn into_inner(self) -> PageNode {
+        return self.0.into_inner().unwrap();
+    }
+}
+
 /// A PageNode is a node in a tree, where the tree can be resolved into a complete webpage
 pub struct PageNode {
     /// Name of the node
@@ -40,13 +90,35 @@ pub struct PageNode {
     content: String,
 
     /// Children nodes of this page node
-    children: LinkedList<Arc<RefCell<PageNode>>>,
+    children: LinkedList<Arc<NodeCell>>,
 
     /// parent node of this page node
-    parent: Option<Arc<RefCell<PageNode>>>,
+    parent: Option<Arc<NodeCell>>,
 
     /// Mapping containing variables inside the current scope
-    vars: HashMap<Box<str>, Box<str>>,
+    vars: HashMap<Box<str>, Var>,
+
+    /// Files read while building the page, only populated on the root node
+    dependencies: BTreeSet<PathBuf>,
+
+    /// Files written into the output directory while building the page, along with the file they came from if there is one.
+    /// Only populated on the root node
+    artifacts: BTreeMap<PathBuf, Option<PathBuf>>,
+
+    /// Environment variables read while building the page and their values, None if unset. Only populated on the root node
+    env_vars: BTreeMap<Box<str>, Option<Box<str>>>,
+
+    /// Patterns replaced in the finished page and their replacements, only populated on the root node
+    rewrites: Vec<(Regex, Box<str>)>,
+
+    /// Number of limited directive invocations so far, only populated on the root node
+    spent: HashMap<Budget, usize>,
+
+    /// A transparent node only introduces a new variable scope, its metadata is given to the parent instead
+    transparent: bool,
+
+    /// Whether this node is one iteration of a loop, which `{outer.name}` looks past
+    iteration: bool,
 
     /// Program-wide options and logger, see args::Options for more.
     pub o: Arc<Options>,
@@ -62,17 +134,24 @@ impl PageNode {
             content: "".into(),
             parent: None,
             vars: HashMap::new(),
+            dependencies: BTreeSet::new(),
+            artifacts: BTreeMap::new(),
+            env_vars: BTreeMap::new(),
+            rewrites: Vec::new(),
+            spent: HashMap::new(),
+            transparent: false,
+            iteration: false,
             o: o,
         };
     }
 
     /// Override vars variable with a new Hashmap
-    pub fn override_vars(&mut self, new_vars: HashMap<Box<str>, Box<str>>) {
+    pub fn override_vars(&mut self, new_vars: HashMap<Box<str>, Var>) {
         self.vars = new_vars;
     }
 
-    /// Override vars variable with a new Hashmap
-    pub fn consume_into_vars(p: PageNode) -> HashMap<Box<str>, Box<str>> {
+    /// Consume the node and return its variables
+    pub fn consume_into_vars(p: PageNode) -> HashMap<Box<str>, Var> {
         return p.vars;
     }
 
@@ -81,42 +160,338 @@ impl PageNode {
         let key = self.parse_string(k);
         let val = self.parse_string(v);
         debug!(self.o, "Registering variable {key}");
-        self.vars.insert(key, val);
+        self.vars.insert(key, Var::Str(val));
+    }
+
+    /// Register a variable into this node that is resolved every time it is used
+    ///
+    /// Only the key is parsed now, the value is parsed in the scope of wherever the variable is used
+    pub fn register_var_lazy(&mut self, k: Box<str>, v: Box<str>) {
+        let key = self.parse_string(k);
+        debug!(self.o, "Registering lazy variable {key}");
+        self.vars.insert(key, Var::Lazy(v));
+    }
+
+    /// Register an already built variable into this node, only parsing the key
+    pub fn set_var(&mut self, k: Box<str>, v: Var) {
+        let key = self.parse_string(k);
+        debug!(self.o, "Registering variable {key}");
+        self.vars.insert(key, v);
     }
 
     /// Get the value of a variable from this node or its parents
     ///
     /// Search the current node first, then sequentially search parent nodes until variable is found.
-    /// If variable does not exist in the node tree, return a placeholder
+    /// Names with an accessor path such as `items[0]` look up `items` then index into it.
+    /// If variable does not exist in the node tree, return what Options::undefined_vars asks for
     pub fn get_var(&self, k: Box<str>) -> Box<str> {
-        // search self
-        match self.vars.get(&k) {
-            Some(v) => return v.clone(),
+        return match self.lookup_var(&k) {
+            Some(v) => self.resolve_var(&k, &v),
+            None => match self.o.undefined_vars {
+                UndefinedVars::Empty => {
+                    warn!(self.o, "Undefined variable {k}");
+                    "".into()
+                }
+                UndefinedVars::Keep => {
+                    warn!(self.o, "Undefined variable {k}");
+                    format!("{{{k}}}").into()
+                }
+                UndefinedVars::Placeholder => {
+                    warn!(self.o, "Undefined variable {k}");
+                    self.o.undefined_placeholder.clone()
+                }
+                UndefinedVars::Error => {
+                    error!(self.o, "Undefined variable {k}");
+                    self.o.diagnostics.fail();
+                    "".into()
+                }
+            },
+        };
+    }
+
+    /// Find a variable by name, following its accessor path if it has one
+    pub fn lookup_var(&self, k: &str) -> Option<Var> {
+        // the exact name comes first so that names containing accessor characters keep working
+        match self.find_var(k) {
+            Some(v) => return Some(v),
+            None => (),
+        }
+        // inside of a loop, outer.name skips the bindings of the innermost iteration
+        match k
+            .strip_prefix("outer.")
+            .and_then(|rest| self.lookup_outer(rest))
+        {
+            Some(v) => return Some(v),
+            None => (),
+        }
+        let (name, path) = var::split_path(k);
+        if path.len() == 0 {
+            return None;
+        }
+        return self.find_var(name).and_then(|v| v.get_path(path).cloned());
+    }
+
+    /// Look up k in the scope around the closest loop iteration, or None outside of loops
+    fn lookup_outer(&self, k: &str) -> Option<Var> {
+        return match (&self.parent, self.iteration) {
+            (Some(p), true) => p.borrow().lookup_var(k),
+            (Some(p), false) => p.borrow().lookup_outer(k),
+            (None, _) => None,
+        };
+    }
+
+    /// Find a variable by its exact name in this node or its parents
+    fn find_var(&self, k: &str) -> Option<Var> {
+        match self.vars.get(k) {
+            Some(Var::Deferred(d)) => return Some(d.get().clone()),
+            Some(v) => return Some(v.clone()),
             None => (),
+        }
+        return match &self.parent {
+            Some(p) => p.borrow().find_var(k),
+            None => None,
         };
-        // search parent
-        match &self.parent {
-            Some(p) => return p.borrow().get_var(k),
-            None => {
-                warn!(self.o, "Undefined variable {k}");
-                return "".to_string().into_boxed_str();
+    }
+
+    /// Convert a variable into text, resolving lazy variables in the scope of this node
+    ///
+    /// Lists and maps are joined together the same way a YAML sequence or mapping would be
+    pub fn resolve_var(&self, k: &str, v: &Var) -> Box<str> {
+        return match v {
+            Var::Str(s) => s.clone(),
+            Var::Lazy(s) => {
+                let depth = LAZY_DEPTH.get();
+                if depth >= MAX_LAZY_DEPTH {
+                    error!(
+                        self.o,
+                        "Lazy variable {k} is too deeply nested, does it refer to itself?"
+                    );
+                    return "".into();
+                }
+                LAZY_DEPTH.set(depth + 1);
+                let val = self.parse_string(s.clone());
+                LAZY_DEPTH.set(depth);
+                val
+            }
+            Var::List(l) => l
+                .iter()
+                .map(|i| self.resolve_var(k, i))
+                .collect::<String>()
+                .into(),
+            Var::Map(m) => m
+                .iter()
+                .map(|(name, i)| format!("<{name}>{}</{name}>", self.resolve_var(k, i)))
+                .collect::<String>()
+                .into(),
+            Var::Component(_) => {
+                warn!(self.o, "{k} is a component, add it with !USE [{k}]");
+                "".into()
+            }
+            Var::Deferred(d) => self.resolve_var(k, d.get()),
+        };
+    }
+
+    /// Create a transparent child of parent that acts as a new variable scope
+    ///
+    /// The child is added to parent's children and is invisible in the output, apart from its content and children
+    pub fn new_scope(parent: Arc<NodeCell>) -> Arc<NodeCell> {
+        let scope = Arc::new(NodeCell::new(PageNode::new(parent.borrow().o.clone())));
+        scope.borrow_mut().set_parent(parent.clone());
+        scope.borrow_mut().transparent = true;
+        parent.borrow_mut().add_child(scope.clone());
+        return scope;
+    }
+
+    /// Create a child of parent for one iteration of a loop
+    ///
+    /// Iterations see every variable of the scopes around them, shadowed ones are reached with `{outer.name}`
+    pub fn new_iteration(parent: Arc<NodeCell>) -> Arc<NodeCell> {
+        let child = Arc::new(NodeCell::new(PageNode::new(parent.borrow().o.clone())));
+        child.borrow_mut().set_parent(parent.clone());
+        child.borrow_mut().iteration = true;
+        parent.borrow_mut().add_child(child.clone());
+        return child;
+    }
+
+    /// Check if this node is a transparent scope that produces no output
+    fn is_empty_scope(&self) -> bool {
+        return self.transparent
+            && self.content.len() == 0
+            && self.children.iter().all(|c| c.borrow().is_empty_scope());
+    }
+
+    /// Get the root node of the tree containing node
+    pub fn root(node: Arc<NodeCell>) -> Arc<NodeCell> {
+        let mut root = node;
+        loop {
+            let parent = root.borrow().parent.clone();
+            match parent {
+                Some(p) => root = p,
+                None => break,
+            }
+        }
+        return root;
+    }
+
+    /// Add metadata to the closest element around node called name, unless it already has the key
+    ///
+    /// Returns false if node is not inside such an element
+    pub fn add_enclosing_metadata(
+        node: Arc<NodeCell>,
+        name: &str,
+        kvpair: (Box<str>, Box<str>),
+    ) -> bool {
+        let mut current = node.borrow().parent.clone();
+        loop {
+            let n = match current {
+                Some(n) => n,
+                None => return false,
+            };
+            if &n.borrow().name[..] == name {
+                let mut n = n.borrow_mut();
+                if !n.metadata.iter().any(|(k, _)| k == &kvpair.0) {
+                    n.metadata.push_back(kvpair);
+                }
+                return true;
             }
+            current = n.borrow().parent.clone();
+        }
+    }
+
+    /// Record a file that the page containing node was built from
+    ///
+    /// Dependencies are stored on the root node of the tree
+    pub fn add_dependency(node: Arc<NodeCell>, path: PathBuf) {
+        let root = PageNode::root(node);
+        debug!(root.borrow().o, "Registering dependency {}", path.display());
+        root.borrow_mut().dependencies.insert(path);
+    }
+
+    /// Record a file the page depends on, for when there is no node to find the root from
+    ///
+    /// self must be the root node
+    pub fn record_dependency(&mut self, path: PathBuf) {
+        debug!(self.o, "Registering dependency {}", path.display());
+        self.dependencies.insert(path);
+    }
+
+    /// Record a file that the page containing node wrote into the output directory
+    ///
+    /// source is the input file it was copied or generated from, if there is one.
+    /// Artifacts are stored on the root node of the tree
+    pub fn add_artifact(node: Arc<NodeCell>, path: PathBuf, source: Option<PathBuf>) {
+        let root = PageNode::root(node);
+        debug!(root.borrow().o, "Registering artifact {}", path.display());
+        root.borrow_mut().artifacts.insert(path, source);
+    }
+
+    /// Record an environment variable the page containing node was built with, along with its value
+    ///
+    /// Environment variables are stored on the root node of the tree
+    pub fn add_env_var(node: Arc<NodeCell>, name: &str, value: Option<Box<str>>) {
+        let root = PageNode::root(node);
+        debug!(root.borrow().o, "Registering environment variable {name}");
+        root.borrow_mut().env_vars.insert(name.into(), value);
+    }
+
+    /// Get every environment variable read while building this node, along with its value
+    pub fn get_env_vars(&self) -> &BTreeMap<Box<str>, Option<Box<str>>> {
+        return &self.env_vars;
+    }
+
+    /// Register a rule rewriting the finished page, stored on the root node of the tree
+    pub fn add_rewrite(node: Arc<NodeCell>, pattern: Regex, replacement: Box<str>) {
+        let root = PageNode::root(node);
+        debug!(root.borrow().o, "Registering rewrite of {pattern}");
+        root.borrow_mut().rewrites.push((pattern, replacement));
+    }
+
+    /// Get every rewrite rule registered in this node, in order
+    pub fn get_rewrites(&self) -> &Vec<(Regex, Box<str>)> {
+        return &self.rewrites;
+    }
+
+    /// Get all files this node wrote into the output directory, along with their sources
+    pub fn get_artifacts(&self) -> &BTreeMap<PathBuf, Option<PathBuf>> {
+        return &self.artifacts;
+    }
+
+    /// Count one invocation of a limited directive against the page containing node
+    ///
+    /// Returns false once the limit is exceeded, in which case the directive should stop.
+    /// The error is only logged the first time so runaway loops do not flood the output
+    pub fn spend_budget(node: Arc<NodeCell>, budget: Budget) -> bool {
+        let root = PageNode::root(node);
+        let mut root = root.borrow_mut();
+        let (limit, what, flag) = match budget {
+            Budget::Iterations => (root.o.max_iterations, "loop iterations", "--max-iterations"),
+            Budget::Includes => (root.o.max_includes, "included files", "--max-includes"),
+        };
+        let spent = root.spent.entry(budget).or_insert(0);
+        *spent += 1;
+        if limit == 0 || *spent <= limit {
+            return true;
+        }
+        if *spent == limit + 1 {
+            error!(
+                root.o,
+                "Page exceeded the limit of {limit} {what}, raise it with {flag} if this is intended"
+            );
         }
+        return false;
+    }
+
+    /// Get all files this node depends on
+    pub fn get_dependencies(&self) -> &BTreeSet<PathBuf> {
+        return &self.dependencies;
     }
 
     /// Add a new child to the end of children
-    pub fn add_child(&mut self, child: Arc<RefCell<PageNode>>) {
+    pub fn add_child(&mut self, child: Arc<NodeCell>) {
         self.children.push_back(child);
     }
 
     /// Add some new metadata to the node
+    ///
+    /// Transparent nodes pass the metadata on to their parent
     pub fn add_metadata(&mut self, kvpair: (Box<str>, Box<str>)) {
-        self.metadata.push_back(kvpair);
+        match (&self.parent, self.transparent) {
+            (Some(p), true) => p.borrow_mut().add_metadata(kvpair),
+            _ => self.metadata.push_back(kvpair),
+        }
+    }
+
+    /// Name of the node, empty if it is only text
+    pub fn get_name(&self) -> &str {
+        return &self.name;
+    }
+
+    /// Value of the attribute key of the node, if it has one
+    pub fn get_attribute(&self, key: &str) -> Option<&str> {
+        return self
+            .metadata
+            .iter()
+            .find(|(k, _)| &k[..] == key)
+            .map(|(_, v)| &v[..]);
+    }
+
+    /// Set the attribute key of the node to value, replacing the value it had
+    pub fn set_attribute(&mut self, key: &str, value: Box<str>) {
+        match self.metadata.iter_mut().find(|(k, _)| &k[..] == key) {
+            Some((_, v)) => *v = value,
+            None => self.metadata.push_back((key.into(), value)),
+        }
+    }
+
+    /// Call f on this node and every node below it, each node before its children
+    pub fn visit(&mut self, f: &mut dyn FnMut(&mut PageNode)) {
+        f(self);
+        self.children.iter().for_each(|c| c.borrow_mut().visit(f));
     }
 
     /// Set content of node, taking ownership of passed text
     pub fn add_content(&mut self, s: Box<str>) {
-        self.content += &self.parse_string(s.into());
+        self.content += &self.parse_string(s);
     }
 
     /// Set content of node, taking ownership of passed text
@@ -125,7 +500,7 @@ impl PageNode {
     }
 
     /// Set parent of node, taking ownership of passed Arc
-    pub fn set_parent(&mut self, p: Arc<RefCell<PageNode>>) {
+    pub fn set_parent(&mut self, p: Arc<NodeCell>) {
         self.parent = Some(p.clone());
     }
 
@@ -134,6 +509,67 @@ impl PageNode {
         self.name = self.parse_string(s);
     }
 
+    /// Give every heading in the tree without an id one generated from its text
+    ///
+    /// Ids that already exist in the tree are never reused, duplicates get a number appended
+    pub fn add_heading_ids(&mut self) {
+        let mut used = HashSet::<Box<str>>::new();
+        self.collect_ids(&mut used);
+        self.assign_heading_ids(&mut used);
+    }
+
+    /// Collect the id of this node and all of its children
+    fn collect_ids(&self, used: &mut HashSet<Box<str>>) {
+        self.metadata
+            .iter()
+            .filter(|(k, _)| &k[..] == "id")
+            .for_each(|(_, v)| {
+                used.insert(v.clone());
+            });
+        self.children
+            .iter()
+            .for_each(|c| c.borrow().collect_ids(used));
+    }
+
+    /// Give this node and its children an id if they are headings without one
+    fn assign_heading_ids(&mut self, used: &mut HashSet<Box<str>>) {
+        let is_heading = matches!(
+            self.name.to_lowercase().as_str(),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+        );
+        if is_heading && !self.metadata.iter().any(|(k, _)| &k[..] == "id") {
+            let mut base = slugify(&strip_tags(&format!("{self}")));
+            if base.len() == 0 {
+                base = "section".into();
+            }
+            let mut id = base.clone();
+            let mut n = 1;
+            while used.contains(id.as_str()) {
+                id = format!("{base}-{n}");
+                n += 1;
+            }
+            debug!(self.o, "Giving heading id {id}");
+            used.insert(id.clone().into());
+            self.metadata.push_back(("id".into(), id.into()));
+        }
+        self.children
+            .iter()
+            .for_each(|c| c.borrow_mut().assign_heading_ids(used));
+    }
+
+    /// Apply f to the text of this node and its children in document order, see map_text
+    ///
+    /// Nodes named in verbatim are left out along with their children
+    pub fn map_text(&mut self, verbatim: &[&str], f: &mut dyn FnMut(&str) -> String) {
+        if verbatim.contains(&&self.name.to_ascii_lowercase()[..]) {
+            return;
+        }
+        self.content = map_text(&self.content, verbatim, f);
+        self.children
+            .iter()
+            .for_each(|c| c.borrow_mut().map_text(verbatim, f));
+    }
+
     /// Formats strings according to settings
     ///
     /// Does the following:
@@ -141,81 +577,202 @@ impl PageNode {
     ///   - Variables can be inserted anywhere users can define text
     ///   - This means that regiestering a variable k='{var}' v='value' is 'somename: value' where 'var' is defined as 'somename'
     ///   - Setting content to '{{x}}' is also allowed and will evaluate (where 'x' = 'var', 'var' = '2') to '${var}' then to 'two'
-    ///   - Variables can be escaped with '\\{' (literal backslash)
+    /// - Replaces escape sequences, see unescape
     pub fn parse_string(&self, s: Box<str>) -> Box<str> {
         const BUFSIZE: usize = 60; // should be divisible by 3
         let mut builder = String::with_capacity(BUFSIZE);
 
         // iterate over chars
-        let mut prev: char = ' ';
         let mut iter = s.chars().peekable();
-        let mut c: char;
         loop {
-            match iter.next() {
-                Some(x) => c = x,
+            let c = match iter.next() {
+                Some(x) => x,
                 None => break,
-            }
+            };
             match c {
-                // potentially start variable
+                // start of the variable!!! :D
                 '{' => {
-                    if prev == '\\' {
-                        // brace is escaped, add as normal
-                        builder.push(c)
-                    } else {
-                        // start of the variable!!! :D
-                        let mut brace_depth: u8 = 0;
-                        let mut var_builder = String::with_capacity(BUFSIZE / 3);
-                        loop {
-                            match iter.next() {
-                                Some(x) => c = x,
-                                None => {
-                                    error!(
-                                        self.o,
-                                        "Unclosed variable delimiter in {}...",
-                                        if s.len() > 40 { &s[0..39] } else { &s }
-                                    );
+                    let mut brace_depth: u8 = 0;
+                    let mut var_builder = String::with_capacity(BUFSIZE / 3);
+                    loop {
+                        match iter.next() {
+                            // start of sub-variable
+                            Some('{') => {
+                                var_builder.push('{');
+                                brace_depth += 1;
+                            }
+                            // end of variable or sub-variable
+                            Some('}') => {
+                                if brace_depth == 0 {
                                     break;
                                 }
+                                brace_depth -= 1;
+                                var_builder.push('}');
                             }
-                            match c {
-                                // start of sub-variable
-                                '{' => {
-                                    var_builder.push(c);
-                                    brace_depth += 1;
+                            // escapes are kept for when the name is parsed, so escaped braces do not count
+                            Some('\\') => {
+                                var_builder.push('\\');
+                                match iter.next() {
+                                    Some(x) => var_builder.push(x),
+                                    None => (),
                                 }
-                                // end of variable or sub-variable
-                                '}' => {
-                                    if brace_depth == 0 {
-                                        break;
+                            }
+                            Some(x) => var_builder.push(x),
+                            None => {
+                                error!(
+                                    self.o,
+                                    "Unclosed variable delimiter in {}...",
+                                    match s.char_indices().nth(39) {
+                                        Some((i, _)) => &s[..i],
+                                        None => &s,
                                     }
-                                    brace_depth -= 1;
-                                    var_builder.push(c);
-                                }
-                                // other
-                                _ => var_builder.push(c),
+                                );
+                                break;
                             }
                         }
-                        // variable built, get var now
-                        var_builder = self.parse_string(var_builder.into()).into();
-                        builder += &self.get_var(var_builder.into());
                     }
+                    // variable built, get var now
+                    var_builder = self.parse_string(var_builder.into()).into();
+                    builder += &self.get_var(var_builder.into());
                 }
                 // escape sequence
-                '\\' => {
-                    if prev == '\\' {
-                        builder.push(c);
-                        c = ' ';
+                '\\' => unescape(&mut iter, &mut builder),
+                // not the start of anything
+                _ => builder.push(c),
+            }
+        }
+        return builder.into_boxed_str();
+    }
+}
+
+/// Push the character an escape sequence stands for onto builder, iter is just past the backslash
+///
+/// `\n` is a newline, `\t` a tab and `\u{1F600}` a unicode code point, a code point that does not exist is kept as written.
+/// Any other character is itself, such as `\{`, `\}`, `\\` or `\#`, and a backslash at the very end is dropped
+fn unescape(iter: &mut Peekable<Chars>, builder: &mut String) {
+    match iter.peek() {
+        Some('n') => builder.push('\n'),
+        Some('t') => builder.push('\t'),
+        Some('u') => {
+            let mut ahead = iter.clone();
+            ahead.next();
+            if ahead.next() == Some('{') {
+                let mut hex = String::new();
+                let mut closed = false;
+                for c in ahead.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
                     }
+                    hex.push(c);
                 }
-                // not the start of anything
-                _ => {
-                    builder.push(c);
+                // a code point that does not exist is kept as written rather than becoming a variable
+                if closed {
+                    match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                        Some(c) => builder.push(c),
+                        None => builder.push_str(&format!("\\u{{{hex}}}")),
+                    }
+                    *iter = ahead;
+                    return;
                 }
             }
-            prev = c
+            builder.push('u');
         }
-        return builder.into_boxed_str();
+        Some(c) => builder.push(*c),
+        None => return,
+    }
+    iter.next();
+}
+
+/// Escape text so that parse_string gives it back unchanged
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '{' | '}' | '\\' => escaped.push('\\'),
+            _ => (),
+        }
+        escaped.push(c);
+    }
+    return escaped;
+}
+
+/// Apply f to the text of html, leaving tags, character references and elements named in verbatim as they are
+///
+/// f gets the pieces of text in the order they appear
+pub fn map_text(html: &str, verbatim: &[&str], f: &mut dyn FnMut(&str) -> String) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while rest.len() > 0 {
+        let end = rest.find(['<', '&']).unwrap_or(rest.len());
+        out += &f(&rest[..end]);
+        rest = &rest[end..];
+        // length of the tag or reference at the start of rest, if there is one
+        let mut skip = match rest.chars().next() {
+            Some('<') => rest.find('>').map(|i| i + 1).unwrap_or(rest.len()),
+            Some('&') => match rest[1..].find(|c: char| !c.is_ascii_alphanumeric() && c != '#') {
+                Some(i) if i > 0 && rest[1 + i..].starts_with(';') => i + 2,
+                _ => 1,
+            },
+            _ => 0,
+        };
+        // an element kept verbatim is skipped along with everything up to its closing tag
+        if rest.starts_with('<') && !rest[..skip].ends_with("/>") {
+            let name = rest[1..]
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .next()
+                .unwrap_or("")
+                .to_ascii_lowercase();
+            if verbatim.contains(&&name[..]) {
+                let close = format!("</{name}");
+                skip = match rest.to_ascii_lowercase().find(&close) {
+                    Some(i) => rest[i..].find('>').map_or(rest.len(), |j| i + j + 1),
+                    None => rest.len(),
+                };
+            }
+        }
+        out += &rest[..skip];
+        rest = &rest[skip..];
     }
+    return out;
+}
+
+/// Number of words in the text of HTML, leaving out the head, scripts and styles
+///
+/// Tags separate words, so that `<p>a</p><p>b</p>` is two of them
+pub fn word_count(html: &str) -> usize {
+    let hidden =
+        Regex::new(r"(?is)<head\b.*?</head>|<script\b.*?</script>|<style\b.*?</style>|<[^>]*>")
+            .unwrap();
+    return hidden.replace_all(html, " ").split_whitespace().count();
+}
+
+/// Remove everything between angle brackets from HTML, leaving only its text
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+    return text;
+}
+
+/// Turn text into a lowercase identifier usable in URLs, i.e. `Hello, World!` into `hello-world`
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    return slug.trim_matches('-').to_string();
 }
 
 impl fmt::Display for PageNode {
@@ -227,8 +784,10 @@ impl fmt::Display for PageNode {
     /// - Name and no children: `"{content}<{name} {metadata}/>"`
     /// - Name and children: `"<{name} {metadata}>{content}{children}</{name}>"`
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let case = (self.children.len() != 0 || self.content.len() != 0) as u8
-            + (self.name.len() != 0) as u8 * 2;
+        // empty scopes do not count as children, so void elements stay void
+        let has_children = self.children.iter().any(|c| !c.borrow().is_empty_scope());
+        let case =
+            (has_children || self.content.len() != 0) as u8 + (self.name.len() != 0) as u8 * 2;
         match case {
             // no name, children(?)
             0 | 1 => {
@@ -279,6 +838,7 @@ mod tests {
     use super::*;
     use crate::Args;
     use clap::Parser;
+    use quickcheck::{Arbitrary, Gen};
 
     /// ensure a pagenode can be created and its contents can be accessed as needed
     #[test]
@@ -288,6 +848,75 @@ mod tests {
         assert_eq!(format!("{}", p), "");
     }
 
+    /// Ensure a tree can be shared with other threads, which see the variables of its ancestors
+    #[test]
+    fn test_threads() {
+        fn shareable<T: Send + Sync>() {}
+        shareable::<NodeCell>();
+
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let root = Arc::new(NodeCell::new(PageNode::new(o.clone())));
+        root.borrow_mut().register_var("x".into(), "a".into());
+        let children: Vec<_> = (0..4)
+            .map(|i| {
+                let root = root.clone();
+                let o = o.clone();
+                std::thread::spawn(move || {
+                    let child = Arc::new(NodeCell::new(PageNode::new(o)));
+                    child.borrow_mut().set_parent(root.clone());
+                    child.borrow_mut().set_name("p".into());
+                    child.borrow_mut().add_content(format!("{{x}}{i}").into());
+                    child
+                })
+            })
+            .collect();
+        for c in children {
+            let child = c.join().unwrap();
+            root.borrow_mut().add_child(child);
+        }
+        assert_eq!(
+            format!("{}", root.borrow()),
+            "<p>a0</p><p>a1</p><p>a2</p><p>a3</p>"
+        );
+    }
+
+    /// Ensure headings are given unique ids generated from their text
+    #[test]
+    fn test_heading_ids() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut root = PageNode::new(o.clone());
+        for (name, text, id) in [
+            ("h1", "Hello, <b>World</b>!", None),
+            ("H2", "Hello   World", None),
+            ("h3", "Taken", None),
+            ("h2", "", Some("taken")),
+            ("p", "Not a heading", None),
+            ("h4", "!!", None),
+        ] {
+            let child = Arc::new(NodeCell::new(PageNode::new(o.clone())));
+            child.borrow_mut().set_name(name.into());
+            child.borrow_mut().add_content_unparsed(text.into());
+            match id {
+                Some(id) => child.borrow_mut().add_metadata(("id".into(), id.into())),
+                None => (),
+            }
+            root.add_child(child);
+        }
+        root.add_heading_ids();
+        assert_eq!(
+            format!("{root}"),
+            concat!(
+                r#"<h1 id="hello-world">Hello, <b>World</b>!</h1>"#,
+                r#"<H2 id="hello-world-1">Hello   World</H2>"#,
+                r#"<h3 id="taken-1">Taken</h3>"#,
+                r#"<h2 id="taken"/>"#,
+                r#"<p>Not a heading</p>"#,
+                r#"<h4 id="section">!!</h4>"#,
+            )
+        );
+        assert_eq!(slugify(" -Ünïcode_ and  spaces- "), "ünïcode-and-spaces");
+    }
+
     /// Test the four different display formats for a PageNode
     #[test]
     fn test_pagenode_display() {
@@ -298,7 +927,7 @@ mod tests {
         assert_eq!(format!("{}", noname_nochild), "some content");
 
         let mut noname_child = PageNode::new(o.clone());
-        noname_child.add_child(Arc::new(RefCell::new(noname_nochild)));
+        noname_child.add_child(Arc::new(NodeCell::new(noname_nochild)));
         assert_eq!(format!("{}", noname_child), "some content");
 
         let mut name_nochild = PageNode::new(o.clone());
@@ -319,6 +948,84 @@ mod tests {
         );
     }
 
+    /// Ensure every escape sequence gives the character it stands for
+    #[test]
+    fn test_escapes() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut node = PageNode::new(o.clone());
+        node.register_var("a}b".into(), "x".into());
+        for (input, expected) in [
+            (r"\{a\}", "{a}"),
+            (r"\\", "\\"),
+            (r"a\nb\tc", "a\nb\tc"),
+            (r"\u{1F600}\u{e9}", "😀é"),
+            (r"\u{110000}\u", "\\u{110000}u"),
+            (r"\# not a comment", "# not a comment"),
+            (r"end\", "end"),
+            (r"{a\}b}", "x"),
+        ] {
+            assert_eq!(&node.parse_string(input.into())[..], expected, "{input}");
+        }
+    }
+
+    /// Ensure text without any special characters is never changed
+    #[test]
+    fn test_plain_unchanged() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let node = PageNode::new(o.clone());
+        let mut g = Gen::new(100);
+        for _ in 0..1000 {
+            let s: String = String::arbitrary(&mut g)
+                .chars()
+                .filter(|c| !"{}\\".contains(*c))
+                .collect();
+            assert_eq!(&node.parse_string(s.clone().into())[..], s);
+        }
+    }
+
+    /// Ensure any text survives being escaped then parsed
+    #[test]
+    fn test_escape_roundtrip() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let node = PageNode::new(o.clone());
+        let mut g = Gen::new(100);
+        for _ in 0..1000 {
+            let s = String::arbitrary(&mut g);
+            assert_eq!(&node.parse_string(escape(&s).into())[..], s);
+        }
+    }
+
+    /// Ensure every undefined variable mode gives what it says
+    #[test]
+    fn test_undefined_vars() {
+        for (mode, expected, failed) in [
+            ("empty", "a  b", false),
+            ("keep", "a {missing.x} b", false),
+            ("placeholder", "a ?? b", false),
+            ("error", "a  b", true),
+        ] {
+            let o = Arc::new(
+                Args::parse_from([
+                    "",
+                    "-i",
+                    "./",
+                    "-o",
+                    "/tmp/",
+                    "-s",
+                    "--undefined-vars",
+                    mode,
+                    "--undefined-placeholder",
+                    "??",
+                ])
+                .build_options(),
+            );
+            let mut node = PageNode::new(o.clone());
+            node.add_content("a {missing.x} b".into());
+            assert_eq!(format!("{}", node), expected);
+            assert_eq!(o.diagnostics.failed(), failed);
+        }
+    }
+
     /// Test string parsing
     #[test]
     fn test_parse_string() {
@@ -359,10 +1066,10 @@ mod tests {
         node.add_content("{undefined variable}".into());
         assert_eq!(format!("{}", node), "");
 
-        let node = Arc::new(RefCell::new(PageNode::new(o.clone())));
+        let node = Arc::new(NodeCell::new(PageNode::new(o.clone())));
         node.borrow_mut().register_var("x".into(), "y".into());
         node.borrow_mut().set_name("name".into());
-        let child = Arc::new(RefCell::new(PageNode::new(o.clone())));
+        let child = Arc::new(NodeCell::new(PageNode::new(o.clone())));
         node.borrow_mut().add_child(child.clone());
         child.borrow_mut().set_parent(node.clone());
         child.borrow_mut().add_content("{x}".into());
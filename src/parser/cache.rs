@@ -0,0 +1,94 @@
+//! Cache of parsed include files, shared by every page of a build
+//!
+//! Files are keyed by their canonical path, and parsed again if they were modified since
+//! ```
+//! let docs = o.include_cache.get(&file)?;
+//! for doc in docs.values.iter() {
+//!     Parser::add_value(node.clone(), doc, Some(dir.clone()));
+//! }
+//! ```
+
+/* IMPORTS */
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/* LOCAL IMPORTS */
+use super::yaml::{self, Documents};
+
+/* CACHE */
+/// Modification time of a file when it was parsed, and its YAML documents
+type Entry = (Option<SystemTime>, Arc<Documents>);
+
+/// Parsed YAML documents of included files
+#[derive(Default)]
+pub struct IncludeCache {
+    files: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+impl IncludeCache {
+    /// Get the YAML documents in file, parsing it only if it is not cached or changed since
+    pub fn get(&self, file: &Path) -> Result<Arc<Documents>, Box<str>> {
+        let modified = fs::metadata(file).and_then(|m| m.modified()).ok();
+        match self.files.lock().unwrap().get(file) {
+            Some((m, docs)) if m.is_some() && *m == modified => return Ok(docs.clone()),
+            _ => (),
+        }
+
+        // parsing happens without holding the lock, two threads may parse the same file at once
+        let data = fs::read_to_string(file)
+            .map_err(|e| format!(r#"Error reading file "{f}" | {e}"#, f = file.display()))?;
+        let docs = yaml::parse(&data)
+            .map_err(|e| format!("Error while parsing YAML: {e} in {f}", f = file.display()))?;
+        let docs = Arc::new(docs);
+        self.files
+            .lock()
+            .unwrap()
+            .insert(file.to_path_buf(), (modified, docs.clone()));
+        return Ok(docs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, time::Duration};
+
+    /// Ensure files are only parsed again once they change
+    #[test]
+    fn test_get() {
+        let path = Path::new("/tmp/ssgen_test_include_cache.block");
+        fs::write(path, "p: a\n---\np: b").unwrap();
+        let cache = IncludeCache::default();
+
+        let first = cache.get(path).unwrap();
+        assert_eq!(first.values.len(), 2);
+        assert_eq!(Arc::ptr_eq(&first, &cache.get(path).unwrap()), true);
+
+        fs::write(path, "p: c").unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        let changed = cache.get(path).unwrap();
+        assert_eq!(changed.values.len(), 1);
+        assert_eq!(Arc::ptr_eq(&first, &changed), false);
+
+        fs::write(path, "p: [").unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(20))
+            .unwrap();
+        assert_eq!(cache.get(path).is_err(), true);
+        fs::remove_file(path).unwrap();
+        assert_eq!(cache.get(path).is_err(), true);
+    }
+}
@@ -3,62 +3,88 @@
 //! Includes helper functions to break apart TaggedValue parsing
 
 /* IMPORTS */
-use glob::{glob_with, MatchOptions};
-use serde::Deserialize;
-use serde_yaml::{value::TaggedValue, Deserializer, Value};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::{format::StrftimeItems, DateTime};
+use regex::{NoExpand, Regex};
+use serde_yaml::{value::TaggedValue, Value};
+use sha2::{Digest, Sha384};
 use std::{
-    cell::RefCell,
-    cmp::{max, min},
+    cell::Cell,
+    cmp::{max, min, Ordering},
+    collections::{BTreeMap, HashMap},
+    env,
     ffi::OsStr,
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::Command,
     sync::Arc,
+    thread,
+    time::Instant,
 };
 
 /* LOCAL IMPORTS */
-use crate::{debug, error, info, warn, Options, PageNode, Parser};
+use crate::{
+    build, debug, error, git, hooks, info, manifest, math, minify, pagenode,
+    parser::yaml,
+    paths::{self, Symlinks},
+    qr,
+    var::Component,
+    warn, Budget, NodeCell, PageNode, Parser, Var,
+};
 
 /* DIRECTIVES */
+/// Maximum number of components that can be instantiating at once, protects against components using themselves
+const MAX_COMPONENT_DEPTH: u8 = 64;
+
+thread_local! {
+    /// Number of components currently being instantiated on this thread
+    static COMPONENT_DEPTH: Cell<u8> = const { Cell::new(0) };
+}
+
 /// Macro to automate parsing a Value into a boxed str given a target and Value
 ///
-/// $parent: Arc<RefCell<PageNode>>
+/// $parent: Arc<NodeCell>
 /// $value: &serde_yaml::Value
 #[macro_export]
 macro_rules! parse_value {
     ($parent:expr, $value:expr, $dir:expr) => {{
-        let child = Arc::new(RefCell::new(PageNode::new($parent.borrow().o.clone())));
+        let o = $parent.borrow().o.clone();
+        let child = Arc::new(NodeCell::new(PageNode::new(o.clone())));
         child.borrow_mut().set_parent($parent.clone());
         Parser::add_value(child.clone(), $value, $dir);
-        format!("{}", child.borrow()).into_boxed_str()
+        // whatever reads the value needs the contents of streamed files, not their stand-ins
+        let text = format!("{}", child.borrow());
+        $crate::build::unstreamed(&o, text).into_boxed_str()
     }};
 }
 
 /// If a value exists / is not an empty string, do something. Otherwise, do something else (if it exists)
 ///
+/// The executed branch is its own variable scope, so a !DEF inside it is not visible after the !IF
 /// Usage:
 /// ```YAML
 /// !IF [condition, exec if true, ?exec if false]
 /// ```
 /// Where `?exec if false` is optional
-pub fn if_else(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBuf>) {
+pub fn if_else(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
     debug!(target.borrow().o, "Evaluating conditional...");
     match &tv.value {
         Value::Sequence(seq) => {
             if seq.len() >= 2 && seq.len() <= 3 {
                 let condition = parse_value!(target, &seq[0], dir.clone());
-                match &condition[..] {
-                    "" => {
-                        // exec 'else' block
-                        if seq.len() == 3 {
-                            Parser::add_value(target.clone(), &seq[2], dir.clone());
-                        }
-                    }
-                    _ => {
-                        // exec 'if' block
-                        Parser::add_value(target.clone(), &seq[1], dir.clone());
-                    }
+                if target.borrow().o.lint && is_constant(&seq[0]) {
+                    warn!(
+                        target.borrow().o,
+                        "!IF condition {c} is constant, its {b} branch is never used",
+                        c = value_tostring(&seq[0]),
+                        b = if condition.is_empty() { "if" } else { "else" }
+                    );
                 }
+                let branch = match &condition[..] {
+                    "" => seq.get(2),   // exec 'else' block
+                    _ => Some(&seq[1]), // exec 'if' block
+                };
+                add_branch(target, branch, dir);
             }
             return;
         }
@@ -71,34 +97,302 @@ pub fn if_else(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<Pat
     );
 }
 
+/// Add the branch a conditional chose, if there is one, in its own variable scope
+fn add_branch(target: Arc<NodeCell>, branch: Option<&Value>, dir: Option<PathBuf>) {
+    match branch {
+        Some(b) => {
+            let scope = if target.borrow().o.legacy_scoping {
+                target.clone()
+            } else {
+                PageNode::new_scope(target.clone())
+            };
+            Parser::add_value(scope, b, dir);
+        }
+        None => (),
+    }
+}
+
+/// Conditional like !IF whose condition is checked by test instead of being empty or not
+fn if_with(
+    target: Arc<NodeCell>,
+    tv: &TaggedValue,
+    dir: Option<PathBuf>,
+    test: impl FnOnce(&Value) -> bool,
+) {
+    match &tv.value {
+        Value::Sequence(seq) if seq.len() >= 2 && seq.len() <= 3 => {
+            let branch = match test(&seq[0]) {
+                true => Some(&seq[1]),
+                false => seq.get(2),
+            };
+            add_branch(target, branch, dir);
+        }
+        _ => error!(
+            target.borrow().o,
+            r#"Invalid arguments to {} directive: "{}""#,
+            tv.tag,
+            value_tostring(&tv.value)
+        ),
+    }
+}
+
+/// Like !IF, but true when building with one of the given profiles, see --profile
+///
+/// Usage:
+/// ```YAML
+/// !IF_PROFILE [production, !INCLUDE analytics.block]
+/// !IF_PROFILE [[staging, preview], {div: Preview build}, ?exec otherwise]
+/// ```
+pub fn if_profile(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let profile = target.borrow().o.profile.clone();
+    if_with(target.clone(), tv, dir.clone(), |names| {
+        let names = match names {
+            Value::Sequence(s) => s.iter().collect(),
+            v => vec![v],
+        };
+        return names
+            .into_iter()
+            .any(|n| Some(parse_value!(target, n, dir.clone())) == profile);
+    });
+}
+
+/// Like !IF, but true when an environment variable is set and not empty, or has the given value
+///
+/// Usage:
+/// ```YAML
+/// !IF_ENV [CI, p: Built by CI]
+/// !IF_ENV [DEPLOY_ENV=staging, {div: Staging build}, ?exec otherwise]
+/// ```
+pub fn if_env(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    if_with(target.clone(), tv, dir.clone(), |condition| {
+        let condition = parse_value!(target, condition, dir.clone());
+        let (name, expected) = match condition.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (&condition[..], None),
+        };
+        let value = env::var(name).ok();
+        // the page has to be built again when the variable changes, see Manifest::is_stale
+        PageNode::add_env_var(target.clone(), name, value.as_deref().map(Into::into));
+        return match (value, expected) {
+            (Some(v), Some(expected)) => v == expected,
+            (Some(v), None) => !v.is_empty(),
+            (None, _) => false,
+        };
+    });
+}
+
+/// Add a value if it is added without errors, or a fallback in its place
+///
+/// Errors while adding the value, like a missing include or a failed !FETCH or !SHELL_CMD, are caught
+/// instead of failing the build, and only reported with --verbose. Without a fallback nothing is added
+/// Usage:
+/// ```YAML
+/// !TRY [!INCLUDE generated/stats.block, stats unavailable]
+/// !TRY [!FETCH https://example.com/status.html]
+/// ```
+pub fn try_else(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let (attempt, fallback) = match &tv.value {
+        Value::Sequence(s) if s.len() == 1 || s.len() == 2 => (&s[0], s.get(1)),
+        _ => {
+            error!(
+                target.borrow().o,
+                r#"Invalid arguments to !TRY directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+    };
+    let o = target.borrow().o.clone();
+    let (catching, caught) = o.catching();
+    let catching = Arc::new(catching);
+    let p = Arc::new(NodeCell::new(PageNode::new(catching.clone())));
+    p.borrow_mut().set_parent(target.clone());
+    Parser::add_value(p.clone(), attempt, dir.clone());
+    p.borrow_mut().o = o.clone();
+
+    let errors = caught.lock().unwrap().clone();
+    if errors.len() == 0 && !catching.diagnostics.failed() {
+        target.borrow_mut().add_child(p);
+        return;
+    }
+    for e in errors.iter() {
+        info!(o, "!TRY caught: {e}");
+    }
+    match fallback {
+        Some(f) => {
+            debug!(o, "!TRY failed, adding its fallback");
+            let p = Arc::new(NodeCell::new(PageNode::new(o.clone())));
+            p.borrow_mut().set_parent(target.clone());
+            Parser::add_value(p.clone(), f, dir);
+            target.borrow_mut().add_child(p);
+        }
+        None => debug!(o, "!TRY failed, and has no fallback"),
+    }
+}
+
+/// Whether v renders the same no matter where it is used, as it has no variables or directives
+fn is_constant(v: &Value) -> bool {
+    return match v {
+        Value::String(s) => !s.contains('{'),
+        Value::Null | Value::Bool(_) | Value::Number(_) => true,
+        Value::Sequence(_) | Value::Mapping(_) | Value::Tagged(_) => false,
+    };
+}
+
+/// Fetch a URL at build time and add it to the page, requires --allow-network
+///
+/// The response is added as raw text unless another format is given:
+/// - yaml: parsed and added like an included file
+/// - json: defined as a variable with the given name, for !FOREACH_VAR and accessor paths
+///
+/// A trailing mapping can give the seconds a stored response is reused for, instead of --fetch-ttl.
+/// With --offline only stored responses are used, whatever their age.
+///
+/// Usage:
+/// ```YAML
+/// !FETCH https://example.com/snippet.html
+/// !FETCH [https://example.com/nav.yaml, yaml]
+/// !FETCH [https://api.github.com/repos/me/app/releases, json, releases, {ttl: 3600}]
+/// ```
+pub fn fetch(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let (values, options) = match &tv.value {
+        Value::Sequence(s) => match s.split_last() {
+            Some((Value::Mapping(m), rest)) => (rest, Some(m)),
+            _ => (&s[..], None),
+        },
+        Value::Mapping(_) | Value::Null => (&[][..], None),
+        v => (std::slice::from_ref(v), None),
+    };
+    if values.len() < 1 || values.len() > 3 {
+        error!(
+            target.borrow().o,
+            r#"Invalid arguments to !FETCH directive: "{}""#,
+            value_tostring(&tv.value)
+        );
+        return;
+    }
+    let args: Vec<Box<str>> = values
+        .iter()
+        .map(|v| parse_value!(target, v, dir.clone()))
+        .collect();
+    let url = &args[0];
+    let format = args.get(1).map_or("raw", |f| &f[..]);
+    let o = target.borrow().o.clone();
+    if !o.allow_network && !o.offline {
+        error!(
+            o,
+            "Fetching {url} requires --allow-network, or --offline to use stored responses"
+        );
+        return;
+    }
+    if !matches!(format, "raw" | "yaml" | "json") || (format == "json") != (args.len() == 3) {
+        error!(
+            o,
+            "Invalid format for !FETCH of {url}: use raw, yaml, or json with the name of a variable"
+        );
+        return;
+    }
+    let mut ttl = o.fetch_ttl;
+    for (k, v) in options.into_iter().flatten() {
+        let k = parse_value!(target, k, dir.clone());
+        match &k[..] {
+            "ttl" => {
+                let v = parse_value!(target, v, dir.clone());
+                ttl = match v.parse() {
+                    Ok(t) => t,
+                    Err(_) => {
+                        error!(
+                            o,
+                            "!FETCH: ttl of {url} must be a number of seconds, not {v}"
+                        );
+                        return;
+                    }
+                }
+            }
+            _ => warn!(o, "!FETCH: unknown option {k}"),
+        }
+    }
+    info!(o, "Fetching {url}...");
+    let body = match o.fetch_cache.get(&o, url, ttl) {
+        Ok(b) => b,
+        Err(e) => {
+            error!(o, "{e}");
+            return;
+        }
+    };
+    // the response can change at any time, so incremental builds always rebuild the page
+    PageNode::add_dependency(target.clone(), PathBuf::from(&url[..]));
+
+    match format {
+        "json" => match serde_json::from_str::<Value>(&body) {
+            Ok(data) => {
+                target
+                    .borrow_mut()
+                    .set_var(args[2].clone(), Var::from_value(&data));
+            }
+            Err(e) => error!(o, "Response of {url} is not valid JSON | {e}"),
+        },
+        "yaml" => match yaml::parse(&body) {
+            Ok(docs) => {
+                let p = Arc::new(NodeCell::new(PageNode::new(o.clone())));
+                p.borrow_mut().set_parent(target.clone());
+                for k in docs.duplicates.iter() {
+                    warn!(o, "Duplicate key {k} in {url}, only the last one is used");
+                }
+                let docs = Arc::new(docs);
+                yaml::with_active(url.clone(), docs.clone(), || {
+                    for input in docs.values.iter() {
+                        Parser::add_value(p.clone(), input, dir.clone());
+                    }
+                });
+                target.borrow_mut().add_child(p);
+            }
+            Err(e) => error!(o, "Response of {url} is not valid YAML | {e}"),
+        },
+        _ => {
+            let p = Arc::new(NodeCell::new(PageNode::new(o.clone())));
+            p.borrow_mut().add_content_unparsed(body.to_string().into());
+            target.borrow_mut().add_child(p);
+        }
+    }
+}
+
 /// Get an absolute path to a file that resides (or should reside) in the output directory
 ///
 /// Does the following:
-/// - Create a PathBuf to specified file, respecting if it is relative or absolute
-/// - Ensure the file resides in the output directory
+/// - Create a PathBuf to specified file, relative paths being next to the output file of the page
+/// - Ensure the file resides in the output directory, paths going up with `..` are never allowed
 /// - Throw an error if one of the criteria cannot be satisfied
-fn resolve_output_path(
-    target: Arc<RefCell<PageNode>>,
-    path_str: &str,
-    dir: Option<PathBuf>,
-) -> Result<PathBuf, Box<str>> {
+fn resolve_output_path(target: Arc<NodeCell>, path_str: &str) -> Result<PathBuf, Box<str>> {
     if path_str.len() == 0 {
         return Err("Blank path provided!".into());
     }
+    // the joined path is not normalised, so `..` could leave the output directory without failing the check below
+    if Path::new(path_str)
+        .components()
+        .any(|c| c.as_os_str() == "..")
+    {
+        return Err(format!("File {path_str} does not reside in the output directory!").into());
+    }
 
-    let mut path = PathBuf::new();
-    if &path_str[..1] == "/" {
+    let path = if path_str.starts_with('/') {
         // absolute path (root is output directory)
-        path.push(target.borrow().o.output.clone());
-        path.push(&path_str[1..]);
+        paths::join_slash(&target.borrow().o.output, path_str)
     } else {
-        // relative path
-        path.push(match dir {
-            Some(d) => d.to_path_buf(),
-            None => target.borrow().o.input.clone(),
-        });
-        path.push(&path_str[..]);
-    }
+        // relative path, outside of a page there is only the output directory to be relative to
+        let o = target.borrow().o.clone();
+        let output = target
+            .borrow()
+            .lookup_var("page")
+            .and_then(|p| p.get_path(".output").cloned());
+        let page_dir = match output {
+            Some(Var::Str(out)) => paths::join_slash(&o.output, &out)
+                .parent()
+                .map_or(o.output.clone(), Path::to_path_buf),
+            _ => o.output.clone(),
+        };
+        paths::join_slash(&page_dir, path_str)
+    };
 
     // ensure target file is a subnode of the output directory
     if !path.as_path().starts_with(target.borrow().o.output.clone()) {
@@ -116,11 +410,12 @@ fn resolve_output_path(
 ///
 /// Does the following:
 /// - Create a PathBuf to specified file, respecting if it is relative or absolute
+/// - Paths starting with @name are relative to the template directory called name, see --template-dir
 /// - Ensure the path points to an actually existing file
-/// - Ensure the file resides in the input directory
+/// - Ensure the file resides in the input directory, or the template directory it was found through
 /// - Throw an error if one of the criteria cannot be satisfied
 fn resolve_input_path(
-    target: Arc<RefCell<PageNode>>,
+    target: Arc<NodeCell>,
     path_str: &str,
     dir: Option<PathBuf>,
 ) -> Result<PathBuf, Box<str>> {
@@ -128,34 +423,53 @@ fn resolve_input_path(
         return Err("Blank path provided!".into());
     }
 
-    let mut path = PathBuf::new();
-    debug!(target.borrow().o, "Resolving {}...", path_str);
-    if &path_str[..1] == "/" {
-        debug!(target.borrow().o, "...Absolute path!");
+    let o = target.borrow().o.clone();
+    debug!(o, "Resolving {}...", path_str);
+    let (root, path) = if let Some(templated) = path_str.strip_prefix('@') {
+        // template directory path (root is the template directory)
+        let (name, rest) = templated.split_once('/').unwrap_or((templated, ""));
+        let root = match o.template_dirs.iter().find(|(n, _)| &n[..] == name) {
+            Some((_, d)) => d.clone(),
+            None => {
+                return Err(format!(
+                    "No template directory called @{name}, add one with --template-dir"
+                )
+                .into())
+            }
+        };
+        debug!(
+            o,
+            "...Template path! Template directory is {}",
+            root.display()
+        );
+        let path = paths::join_slash(&root, rest);
+        (root, path)
+    } else if path_str.starts_with('/') {
+        debug!(o, "...Absolute path!");
         // absolute path (root is input directory)
-        path.push(target.borrow().o.input.clone());
-        path.push(&path_str[1..]);
+        (o.input.clone(), paths::join_slash(&o.input, path_str))
     } else {
         // relative path
-        path.push(match dir {
+        let d = match dir {
             Some(d) => {
-                debug!(
-                    target.borrow().o,
-                    "...Relative path! PWD is {}",
-                    d.display()
-                );
+                debug!(o, "...Relative path! PWD is {}", d.display());
                 d.to_path_buf()
             }
             None => {
-                debug!(target.borrow().o, "...Relative path but no PWD!");
-                target.borrow().o.input.clone()
+                debug!(o, "...Relative path but no PWD!");
+                o.input.clone()
             }
-        });
-        path.push(&path_str[..]);
-    }
+        };
+        // files included from a template directory stay inside of it
+        let root = match o.template_dirs.iter().find(|(_, t)| d.starts_with(t)) {
+            Some((_, t)) => t.clone(),
+            None => o.input.clone(),
+        };
+        (root, paths::join_slash(&d, path_str))
+    };
 
     // canonicalise file path
-    let file = match fs::canonicalize(&path) {
+    let file = match paths::canonicalize(&path) {
         Ok(p) => p,
         Err(e) => {
             return Err(format!(
@@ -166,11 +480,25 @@ fn resolve_input_path(
         }
     };
 
+    // canonicalisation follows links, so check for them beforehand
+    if o.symlinks != Symlinks::Follow && paths::has_symlink(&root, &path) {
+        return Err(format!(
+            "File {f} is a symbolic link, which --symlinks {s:?} does not allow",
+            f = path.display(),
+            s = o.symlinks,
+        )
+        .into());
+    }
+
     // ensure target file is a subnode of the input directory
-    if !file.as_path().starts_with(target.borrow().o.input.clone()) {
+    if !file.as_path().starts_with(&root) {
         return Err(format!(
-            "File {f} does not reside in the input directory!",
-            f = file.display()
+            "File {f} does not reside in {r}!",
+            f = file.display(),
+            r = match root == o.input {
+                true => "the input directory".into(),
+                false => format!("the template directory {}", root.display()),
+            }
         )
         .into());
     }
@@ -185,6 +513,8 @@ fn resolve_input_path(
 /// - Relative files are relative to the currently parsed file
 /// - Absolute files use the specified source directory as the root folder
 /// - Files outside of the source directory and its subdirectories should not be accessed
+/// - Files an asset hook matches are compiled by it instead, and get the extension of its output, see hooks
+///
 /// Usage:
 /// ```YAML
 /// !COPY "relative/file_to_copy"   # destination is relative to current file
@@ -192,7 +522,7 @@ fn resolve_input_path(
 /// !COPY_DIR "relative/dir_to_copy"
 /// !COPY_DIR "/absolute/dir_to_copy"
 /// ```
-pub fn copy(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBuf>) {
+pub fn copy(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
     'valid_copy: {
         let s = parse_value!(target, &tv.value, dir.clone());
         let is_copy_dir: bool = tv.tag == "!COPY_DIR";
@@ -204,35 +534,35 @@ pub fn copy(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBu
                 break 'valid_copy;
             }
         };
+        // template directories are only for including
+        if !source.starts_with(&target.borrow().o.input) {
+            error!(
+                target.borrow().o,
+                "Unable to copy {f}, only files in the input directory can be copied",
+                f = source.display()
+            );
+            return;
+        }
+        PageNode::add_dependency(target.clone(), source.clone());
 
         // do the recursion if this should be a dir
         if is_copy_dir {
-            let match_children = source.into_os_string().into_string().unwrap() + "/**/*";
-            for entry in glob_with(
-                match_children.as_str(),
-                MatchOptions {
-                    case_sensitive: false,
-                    require_literal_separator: false,
-                    require_literal_leading_dot: false,
-                },
-            )
-            .unwrap()
-            {
+            let (input, symlinks) = (target.borrow().o.input.clone(), target.borrow().o.symlinks);
+            for entry in paths::walk_files(&source, &input, symlinks) {
                 match entry {
                     Ok(path) => {
-                        if path.is_file() {
-                            debug!(target.clone().borrow().o, "Found file {}", path.display());
-                            let mut copy_tv: TaggedValue = tv.clone();
-                            copy_tv.tag = serde_yaml::value::Tag::new("!COPY");
-                            copy_tv.value = format!(
-                                "{}",
-                                path.strip_prefix(target.borrow().o.input.clone())
-                                    .unwrap()
-                                    .display()
+                        debug!(target.clone().borrow().o, "Found file {}", path.display());
+                        let mut copy_tv: TaggedValue = tv.clone();
+                        copy_tv.tag = serde_yaml::value::Tag::new("!COPY");
+                        // absolute, so it does not depend on the directory of the current file
+                        copy_tv.value = format!(
+                            "/{}",
+                            paths::to_slash(
+                                path.strip_prefix(target.borrow().o.input.clone()).unwrap()
                             )
-                            .into();
-                            copy(target.clone(), &copy_tv, dir.clone());
-                        }
+                        )
+                        .into();
+                        copy(target.clone(), &copy_tv, dir.clone());
                     }
                     Err(e) => error!(target.borrow().o, "Error finding file {}", e),
                 }
@@ -242,12 +572,19 @@ pub fn copy(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBu
 
         // copy the file
         let mut dest = target.borrow().o.output.clone();
-        dest.push(
-            match source.clone().strip_prefix(target.borrow().o.input.clone()) {
-                Ok(s) => s,
-                Err(e) => panic!("THIS SHOULDN'T EVER HAPPEN BUT IM TOO SCARED TO UNWRAP IT (strip_prefix of input from source failed: {e})"),
-            },
-        );
+        let relative = match source.clone().strip_prefix(target.borrow().o.input.clone()) {
+            Ok(s) => s.to_path_buf(),
+            Err(e) => panic!("THIS SHOULDN'T EVER HAPPEN BUT IM TOO SCARED TO UNWRAP IT (strip_prefix of input from source failed: {e})"),
+        };
+        dest.push(&relative);
+        // files an asset hook compiles get the extension of what it writes
+        let hook = hooks::asset_hook(&target.borrow().o, &relative).cloned();
+        match &hook {
+            Some(h) => {
+                dest.set_extension(&h.output[..]);
+            }
+            None => (),
+        }
 
         info!(
             target.borrow().o,
@@ -256,6 +593,11 @@ pub fn copy(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBu
             d = dest.display()
         );
 
+        PageNode::add_artifact(target.clone(), dest.clone(), Some(source.clone()));
+        if target.borrow().o.dry_run {
+            return;
+        }
+
         let mut containing_dir = dest.clone();
         containing_dir.pop();
         match fs::create_dir_all(containing_dir.clone()) {
@@ -266,8 +608,21 @@ pub fn copy(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBu
             }
         }
 
-        match fs::copy(source, dest) {
-            Ok(_) => (),
+        let copied = match hook {
+            Some(hook) => hooks::compile(&target.borrow().o, &hook, &source).and_then(|data| {
+                target
+                    .borrow()
+                    .o
+                    .writes
+                    .write(&dest, &data)
+                    .map_err(|e| e.to_string().into())
+            }),
+            None => fs::copy(source, dest)
+                .map(|_| ())
+                .map_err(|e| e.to_string().into()),
+        };
+        match copied {
+            Ok(()) => (),
             Err(e) => {
                 error!(target.borrow().o, "{e}");
                 return;
@@ -284,24 +639,34 @@ pub fn copy(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBu
     )
 }
 
+/// Raw includes bigger than this many bytes are copied into the page as it is written, see build::streamed
+const STREAM_SIZE: u64 = 16 * 1024 * 1024;
+
 /// Include another text or YAML file inside this page
 ///
-/// File name/extension does not matter, it is on the user to ensure it is a properly formatted YAML file (if not using !INCLUDE_RAW)
+/// File name/extension does not matter, it is on the user to ensure it is a properly formatted YAML file (if not using !INCLUDE_RAW or !INCLUDE_HTML)
+/// !INCLUDE_HTML removes every tag and attribute of the HTML file that is not on ammonia's allow-list, so untrusted snippets can be embedded
 /// - Relative files are relative to the currently parsed file
 /// - Absolute files use the specified source directory as the root folder
+/// - Files starting with @name use the template directory called name as the root folder
 /// - Files outside of the source directory and its subdirectories should not be accessed
+///
+/// !INCLUDE_RAW files over STREAM_SIZE that go straight into the page are only read as it is written, see build::streamed.
 /// Usage:
 /// ```YAML
 /// !INCLUDE relative/file_to_include.page
 /// !INCLUDE_RAW /absolute/file_to_include.page
+/// !INCLUDE_HTML comments/export.html
+/// !INCLUDE @lib/card.block
 /// ```
-pub fn include(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBuf>) {
+pub fn include(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
     let s = parse_value!(target, &tv.value, dir.clone());
-    let is_raw: bool = tv.tag == "!INCLUDE_RAW";
+    let is_raw: bool = tv.tag == "!INCLUDE_RAW" || tv.tag == "!INCLUDE_HTML";
     info!(target.borrow().o, "Including file {s}...");
+    let start = Instant::now();
 
     'valid_include: {
-        let p = Arc::new(RefCell::new(PageNode::new(target.borrow().o.clone())));
+        let p = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
         p.borrow_mut().set_parent(target.clone());
 
         let file = match resolve_input_path(target.clone(), &s, dir.clone()) {
@@ -311,43 +676,80 @@ pub fn include(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<Pat
                 break 'valid_include;
             }
         };
+        PageNode::add_dependency(target.clone(), file.clone());
+        if !PageNode::spend_budget(target.clone(), Budget::Includes) {
+            return;
+        }
 
         // read the file's YAML into a PageNode
-        match fs::read_to_string(file.clone()) {
-            Ok(data) => {
-                if is_raw {
-                    p.borrow_mut().add_content_unparsed(data.into());
-                } else {
-                    for doc in Deserializer::from_str(data.as_str()) {
-                        match Value::deserialize(doc) {
-                            Ok(input) => {
-                                // swap current file directory
-                                let mut new_dir = file.clone();
-                                new_dir.pop();
-                                debug!(
-                                    target.borrow().o,
-                                    r#"Changing directory to "{f}""#,
-                                    f = new_dir.display()
-                                );
-                                Parser::add_value(p.clone(), &input, Some(new_dir));
-                            }
-                            Err(e) => {
-                                panic!("Error while parsing YAML: {e} in {f}", f = file.display())
-                            }
-                        }
-                    }
+        let size = fs::metadata(&file).map_or(0, |m| m.len());
+        // transforms change the text of the page, which they cannot do to a file that is not read yet
+        if tv.tag == "!INCLUDE_RAW"
+            && size > STREAM_SIZE
+            && !target.borrow().o.dry_run
+            && target.borrow().o.transforms.is_empty()
+        {
+            debug!(
+                target.borrow().o,
+                "Streaming {f} into the page as it is written",
+                f = file.display()
+            );
+            p.borrow_mut()
+                .add_content_unparsed(build::streamed(&target.borrow().o, &file).into());
+        } else if is_raw {
+            match fs::read_to_string(file.clone()) {
+                Ok(data) if tv.tag == "!INCLUDE_HTML" => p
+                    .borrow_mut()
+                    .add_content_unparsed(ammonia::clean(&data).into()),
+                Ok(data) => p.borrow_mut().add_content_unparsed(data.into()),
+                Err(e) => {
+                    error!(
+                        target.borrow().o,
+                        r#"Error reading file "{f}" | {e}"#,
+                        f = file.display()
+                    );
+                    break 'valid_include;
                 }
             }
-            Err(e) => {
-                error!(
-                    target.borrow().o,
-                    r#"Error reading file "{f}" | {e}"#,
-                    f = file.display()
-                );
-                break 'valid_include;
+        } else {
+            // parsed once per build, no matter how many pages include it
+            let docs = target.borrow().o.include_cache.get(&file);
+            match docs {
+                Ok(docs) => {
+                    // swap current file directory
+                    let mut new_dir = file.clone();
+                    new_dir.pop();
+                    debug!(
+                        target.borrow().o,
+                        r#"Changing directory to "{f}""#,
+                        f = new_dir.display()
+                    );
+                    for k in docs.duplicates.iter() {
+                        warn!(
+                            target.borrow().o,
+                            "Duplicate key {k} in {f}, only the last one is used",
+                            f = file.display()
+                        );
+                    }
+                    let source = manifest::input_key(&target.borrow().o, &file);
+                    yaml::with_active(source, docs.clone(), || {
+                        for input in docs.values.iter() {
+                            Parser::add_value(p.clone(), input, Some(new_dir.clone()));
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!(target.borrow().o, "{e}");
+                    break 'valid_include;
+                }
             }
         }
         target.borrow_mut().add_child(p);
+        let o = target.borrow().o.clone();
+        match &o.timings {
+            Some(t) => t.include(&manifest::input_key(&o, &file), start.elapsed()),
+            None => (),
+        }
 
         return;
     }
@@ -355,577 +757,3953 @@ pub fn include(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<Pat
         target.borrow().o,
         r#"Invalid arguments to !INCLUDE directive: "{}""#,
         value_tostring(&tv.value)
-    )
+    );
+    // strict builds fail on files that cannot be included, see --strict
+    if target.borrow().o.strict {
+        target.borrow().o.diagnostics.fail();
+    }
 }
 
-/// Define a variable from YAML
+/// Inline a stylesheet from the input directory into a `<style>` element
 ///
-/// Define a variable in YAML into a target PageNode
+/// Small styles every page needs before it can be drawn are then part of the page, instead of another request.
+/// With minify, comments and whitespace are removed from the stylesheet first
 /// Usage:
 /// ```YAML
-/// !DEF: [key, val]
+/// !STYLE_INLINE /css/critical.css
+/// !STYLE_INLINE [critical.css, minify]
 /// ```
-pub fn def(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBuf>) {
-    if tv.value.is_sequence() {
-        let s = tv.value.as_sequence().unwrap();
-        if s.len() == 2 {
-            let kstr = parse_value!(target, &s[0], dir.clone());
-            let vstr = parse_value!(target, &s[1], dir);
-            target.borrow_mut().register_var(kstr, vstr);
+pub fn style_inline(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let o = target.borrow().o.clone();
+    let args: Vec<Box<str>> = match &tv.value {
+        Value::Sequence(s) => s
+            .iter()
+            .map(|v| parse_value!(target, v, dir.clone()))
+            .collect(),
+        v => vec![parse_value!(target, v, dir.clone())],
+    };
+    let minified = match args.get(1).map(|a| &a[..]) {
+        None => false,
+        Some("minify") if args.len() == 2 => true,
+        _ => {
+            error!(
+                o,
+                r#"Invalid arguments to !STYLE_INLINE directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
         }
-    } else {
-        error!(
-            target.borrow().o,
-            r#"Invalid arguments to !DEF directive: "{}""#,
-            value_tostring(&tv.value)
-        )
-    }
+    };
+    let transform = match minified {
+        true => minify::css,
+        false => |css: &str| css.trim().to_string(),
+    };
+    inline_file(target, &args[0], dir, "style", transform);
 }
 
-/// Execute an arbitrary string in the shell (dangerous)
+/// Inline a script or stylesheet from the input directory, after removing comments and unneeded whitespace from it
 ///
+/// Small per-page scripts can then be kept readable in the input directory while the page stays compact
 /// Usage:
 /// ```YAML
-/// !SHELL_CMD: ['echo', 'hi']
+/// !MINIFY_JS /js/menu.js
+/// !MINIFY_CSS card.css
 /// ```
-pub fn shell_command(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBuf>) {
-    // ensure this is allowed
-    if !target.borrow().o.allow_shell {
-        error!(
-            target.borrow().o,
-            r#"!SHELL_CMD used bet shell commands are not enabled! Run SSGen with the '--enable-shell' argument (danger!) to enable them."#
-        );
-        return;
+pub fn minify_inline(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let file = match &tv.value {
+        Value::String(_) => parse_value!(target, &tv.value, dir.clone()),
+        _ => {
+            error!(
+                target.borrow().o,
+                r#"Invalid arguments to {} directive: "{}""#,
+                tv.tag,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+    };
+    match tv.tag == "!MINIFY_JS" {
+        true => inline_file(target, &file, dir, "script", minify::js),
+        false => inline_file(target, &file, dir, "style", minify::css),
     }
+}
 
-    // build and run command
-    if tv.value.is_sequence() {
-        let seq = tv.value.as_sequence().unwrap();
-        let mut args_str = parse_value!(target, &seq[0], dir.clone()).to_string();
-        let args_os_str = OsStr::new(args_str.as_str());
-        let mut cmd = Command::new::<&OsStr>(args_os_str);
-
-        for p in seq.iter().skip(1) {
-            let arg_str = parse_value!(target, p, dir.clone()).to_string();
-            args_str = args_str + " " + arg_str.as_str();
-            let arg_os_str = OsStr::new(arg_str.as_str());
-            cmd.arg(arg_os_str);
+/// Add the contents of file to target inside of an element, after passing them through transform
+fn inline_file(
+    target: Arc<NodeCell>,
+    file: &str,
+    dir: Option<PathBuf>,
+    element: &str,
+    transform: fn(&str) -> String,
+) {
+    let o = target.borrow().o.clone();
+    let file = match resolve_input_path(target.clone(), file, dir) {
+        Ok(f) => f,
+        Err(e) => {
+            error!(o, "{e}");
+            return;
         }
+    };
+    PageNode::add_dependency(target.clone(), file.clone());
+    let text = match fs::read_to_string(&file) {
+        Ok(text) => text,
+        Err(e) => {
+            error!(o, r#"Error reading file "{f}" | {e}"#, f = file.display());
+            return;
+        }
+    };
+    // a closing tag inside the file would end the element early
+    let text = transform(&text).replace("</", r"<\/");
+    let p = Arc::new(NodeCell::new(PageNode::new(o)));
+    p.borrow_mut().set_parent(target.clone());
+    p.borrow_mut()
+        .add_content_unparsed(format!("<{element}>{text}</{element}>").into());
+    target.borrow_mut().add_child(p);
+}
 
-        info!(
-            target.borrow().o,
-            r#"Running shell command: "{}""#, args_str
-        );
-
-        // run and send unparsed output
-        let output = cmd.output().expect("Failed to run process!!");
-        target
-            .borrow_mut()
-            .add_content_unparsed(std::str::from_utf8(&output.stdout[..]).unwrap().into());
-
+/// Write an HTML comment into the page, or leave out everything inside of it
+///
+/// The text of !COMMENT is parsed like any other value, so it can use variables.
+/// Nothing inside of !IGNORE is parsed, so its directives have no effect at all
+/// Usage:
+/// ```YAML
+/// - !COMMENT 'Generated from {SOURCE}'
+/// - !IGNORE
+///   div: [p: not ready yet, !INCLUDE draft.block]
+/// ```
+pub fn comment(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    if tv.tag == "!IGNORE" {
+        debug!(target.borrow().o, "Ignoring {}", value_tostring(&tv.value));
         return;
     }
-
-    error!(
-        target.borrow().o,
-        r#"Invalid arguments to !SHELL_CMD directive: "{}""#,
-        value_tostring(&tv.value)
-    );
+    let text = parse_value!(target, &tv.value, dir);
+    // a comment cannot contain two dashes in a row, which could end it early
+    let mut text = text.to_string();
+    while text.contains("--") {
+        text = text.replace("--", "- -");
+    }
+    let p = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
+    p.borrow_mut().set_parent(target.clone());
+    p.borrow_mut()
+        .add_content_unparsed(format!("<!-- {} -->", text.trim()).into());
+    target.borrow_mut().add_child(p);
 }
 
-/// Take a substring of parsed YAML content
+/// Options of !HEAD_DEFAULTS and their defaults, `{title}` and `{language}` are filled in when it is used
+const HEAD_DEFAULTS: [(&str, &str); 4] = [
+    ("charset", "utf-8"),
+    ("viewport", "width=device-width, initial-scale=1"),
+    ("title", "{title}"),
+    ("lang", "{language}"),
+];
+
+/// Expand into the tags every head starts with, and give the enclosing html element a lang
 ///
+/// The title defaults to the `{title}` variable, or the title of the site if there is none,
+/// and lang defaults to the language of the site, see the `!SITE` block of META.yaml.
+/// A mapping overrides any of them, an empty value leaves it out
 /// Usage:
 /// ```YAML
-/// !SUBSTRING [
-///   0,            # Starting index
-///   5,            # Ending index
-///   '0123456789', # The YAML to parse then index
-/// ]
+/// html:
+///   - head:
+///       - !HEAD_DEFAULTS
+///       - !HEAD_DEFAULTS {title: '{TITLE} | Blog', lang: de, viewport: ''}
 /// ```
-pub fn substring(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBuf>) {
-    debug!(target.borrow().o, "Running substring...");
-    match &tv.value {
-        Value::Sequence(args) => 'invalid_substring: {
-            // ensure preconditions
-            if args.len() < 3 || !args[0].is_i64() || !args[0].is_i64() {
-                break 'invalid_substring;
-            };
-
-            // parse third arg then take substring
-            let vstr = parse_value!(target, &args[2], dir.clone());
-            let start: usize = max(0, args[0].as_i64().unwrap()).try_into().unwrap();
-            let end: usize = min(vstr.len() as i64, args[1].as_i64().unwrap())
-                .try_into()
-                .unwrap();
-            if start > end {
-                break 'invalid_substring;
-            }
-            Parser::add_value(target, &Value::String(vstr[start..end].into()), dir.clone());
-
+pub fn head_defaults(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let o = target.borrow().o.clone();
+    let given = match &tv.value {
+        Value::Null => None,
+        Value::Mapping(m) => Some(m),
+        _ => {
+            error!(
+                o,
+                r#"Invalid arguments to !HEAD_DEFAULTS directive: "{}""#,
+                value_tostring(&tv.value)
+            );
             return;
         }
-        _ => (),
+    };
+    for (k, _) in given.into_iter().flatten() {
+        let k = parse_value!(target, k, dir.clone());
+        if HEAD_DEFAULTS.iter().all(|(name, _)| *name != &k[..]) {
+            warn!(o, "!HEAD_DEFAULTS: unknown option {k}");
+        }
     }
-    let s = value_tostring(&tv.value);
-    // if fail
-    error!(
-        target.borrow().o,
-        r#"Invalid arguments to !SUBSTRING directive: "{}""#,
-        if s.len() > 100 {
-            format!("{}...", &s[..99])
-        } else {
-            s
+    let mut options = HashMap::<&str, Box<str>>::new();
+    for (name, default) in HEAD_DEFAULTS {
+        let val = match given.and_then(|m| m.get(name)) {
+            Some(v) => parse_value!(target, v, dir.clone()),
+            None => match default {
+                "{title}" => {
+                    let node = target.borrow();
+                    match node.lookup_var("title") {
+                        Some(v) => node.resolve_var("title", &v),
+                        None => o.title.clone(),
+                    }
+                }
+                "{language}" => o.language.clone(),
+                d => d.into(),
+            },
+        };
+        options.insert(name, val);
+    }
+
+    let mut html = String::new();
+    if options["charset"].len() != 0 {
+        html += &format!(
+            r#"<meta charset="{}">"#,
+            escape_attribute(&options["charset"])
+        );
+    }
+    if options["viewport"].len() != 0 {
+        html += &format!(
+            r#"<meta name="viewport" content="{}">"#,
+            escape_attribute(&options["viewport"])
+        );
+    }
+    if options["title"].len() != 0 {
+        html += &format!("<title>{}</title>", options["title"]);
+    }
+    if options["lang"].len() != 0 {
+        let lang = ("lang".into(), escape_attribute(&options["lang"]).into());
+        if !PageNode::add_enclosing_metadata(target.clone(), "html", lang) {
+            warn!(
+                o,
+                "!HEAD_DEFAULTS is not inside of an html element to give a lang"
+            );
         }
-    );
+    }
+    let p = Arc::new(NodeCell::new(PageNode::new(o)));
+    p.borrow_mut().set_parent(target.clone());
+    p.borrow_mut().add_content_unparsed(html.into());
+    target.borrow_mut().add_child(p);
 }
 
-/// Iterate over some data provided through YAML according to a template
+/// Number of hex digits of the hash in the name of a fingerprinted file
+const FINGERPRINT_LEN: usize = 10;
+
+/// Add a script or stylesheet from the input directory with subresource integrity
 ///
+/// The file is copied into the output directory with its hash in the name, like `app.3f2a1b9c0d.js`,
+/// so it can be cached forever and a changed file is never mixed up with the old one.
+/// The integrity attribute lets browsers refuse the file if it was tampered with on the way.
+/// Files an asset hook matches are compiled first, and the compiled file is what gets fingerprinted.
+/// A trailing mapping adds attributes to the tag, `true` adds an attribute without a value
 /// Usage:
 /// ```YAML
-/// !FOREACH [
-///   [x, y, ..., n],              # Variable names for use in template
-///   "{x} {y} (...) {n}",         # Template for values to be inserted into
-///   [xval, yval, ..., nval],     # One set of values to insert into the template
-///   [xval2, yval2, ..., zval2],  # Another set of values
-/// ]
+/// !SCRIPT /js/app.js
+/// !SCRIPT [/js/app.js, {type: module, defer: true}]
+/// !STYLESHEET [/css/main.css, {media: print}]
 /// ```
-pub fn foreach(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBuf>) {
-    info!(target.borrow().o, "Looping into !FOREACH directive...");
-    match &tv.value {
-        Value::Sequence(foreach) => 'invalid_foreach: {
-            // ensure preconditions
-            if foreach.len() < 3 || !foreach[0].is_sequence() {
-                break 'invalid_foreach;
-            };
-            let keys = foreach[0]
-                .as_sequence()
-                .unwrap()
-                .iter()
-                .map(|k| parse_value!(target, k, dir.clone()))
-                .collect::<Vec<Box<str>>>();
+pub fn asset_tag(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let o = target.borrow().o.clone();
+    let (path, attributes) = match &tv.value {
+        Value::Sequence(s) => match &s[..] {
+            [path] => (path, None),
+            [path, Value::Mapping(m)] => (path, Some(m)),
+            _ => (&Value::Null, None),
+        },
+        v => (v, None),
+    };
+    let path = parse_value!(target, path, dir.clone());
+    if path.len() == 0 {
+        error!(
+            o,
+            r#"Invalid arguments to {} directive: "{}""#,
+            tv.tag,
+            value_tostring(&tv.value)
+        );
+        return;
+    }
+    let source = match resolve_input_path(target.clone(), &path, dir.clone()) {
+        Ok(f) => f,
+        Err(e) => {
+            error!(o, "{e}");
+            return;
+        }
+    };
+    PageNode::add_dependency(target.clone(), source.clone());
 
-            // iterate over all subsequences in the rest of foreach
-            for values in foreach.iter().skip(2) {
-                match values {
-                    Value::Sequence(seq) => {
-                        if seq.len() != keys.len() {
-                            break 'invalid_foreach;
-                        }
-                        // create new child
-                        let child =
-                            Arc::new(RefCell::new(PageNode::new(target.borrow().o.clone())));
-                        child.borrow_mut().set_parent(target.clone());
-                        target.borrow_mut().add_child(child.clone());
-                        // register vars
-                        seq.iter().enumerate().for_each(|(i, v)| {
-                            let vstr = parse_value!(child, v, dir.clone());
-                            child
-                                .borrow_mut()
-                                .register_var(keys[i].clone().into(), vstr.into());
-                        });
-                        // apply template string
-                        Parser::add_value(child, &foreach[1], dir.clone());
-                    }
-                    _ => break 'invalid_foreach,
-                }
-            }
+    // fingerprinted files land next to where !COPY would put them
+    let mut relative = match source.strip_prefix(&o.input) {
+        Ok(r) => r.to_path_buf(),
+        Err(_) => PathBuf::from(source.file_name().unwrap_or_default()),
+    };
+    let data = match hooks::asset_hook(&o, &relative) {
+        Some(hook) => {
+            relative.set_extension(&hook.output[..]);
+            hooks::compile(&o, hook, &source)
+        }
+        None => fs::read(&source)
+            .map_err(|e| format!(r#"Error reading file "{f}" | {e}"#, f = source.display()).into()),
+    };
+    let data = match data {
+        Ok(d) => d,
+        Err(e) => {
+            error!(o, "{e}");
             return;
         }
-        _ => (),
-    }
-    let s = value_tostring(&tv.value);
-    // if fail
-    error!(
-        target.borrow().o,
-        r#"Invalid arguments to !FOREACH directive: "{}""#,
-        if s.len() > 100 {
-            format!("{}...", &s[..99])
-        } else {
-            s
+    };
+    let name = match (relative.file_stem(), relative.extension()) {
+        (Some(stem), Some(ext)) => format!(
+            "{}.{}.{}",
+            stem.to_string_lossy(),
+            &build::hash(&data)[..FINGERPRINT_LEN],
+            ext.to_string_lossy()
+        ),
+        _ => format!(
+            "{}.{}",
+            relative.to_string_lossy(),
+            &build::hash(&data)[..FINGERPRINT_LEN]
+        ),
+    };
+    let dest = paths::join_slash(&o.output, &relative.with_file_name(name).to_string_lossy());
+    PageNode::add_artifact(target.clone(), dest.clone(), Some(source.clone()));
+    // the name changes with the content, so an existing file is already up to date
+    if !o.dry_run && !dest.exists() {
+        debug!(o, "Writing fingerprinted file {}", dest.display());
+        match o.writes.write(&dest, &data) {
+            Ok(()) => (),
+            Err(e) => {
+                error!(o, "Error writing file {f} | {e}", f = dest.display());
+                return;
+            }
         }
+    }
+
+    let url = format!(
+        "{}/{}",
+        o.base_url.as_deref().unwrap_or("").trim_end_matches('/'),
+        manifest::output_key(&o, &dest)
     );
+    let integrity = format!("sha384-{}", BASE64_STANDARD.encode(Sha384::digest(&data)));
+    let mut extra = String::new();
+    for (k, v) in attributes.into_iter().flatten() {
+        let k = parse_value!(target, k, dir.clone());
+        match v {
+            Value::Bool(true) => extra += &format!(" {k}"),
+            Value::Bool(false) => (),
+            v => {
+                let v = parse_value!(target, v, dir.clone());
+                extra += &format!(r#" {k}="{}""#, escape_attribute(&v));
+            }
+        }
+    }
+    let html = match &tv.tag.to_string()[..] {
+        "!SCRIPT" => format!(
+            r#"<script src="{url}" integrity="{integrity}" crossorigin="anonymous"{extra}></script>"#,
+            url = escape_attribute(&url)
+        ),
+        _ => format!(
+            r#"<link rel="stylesheet" href="{url}" integrity="{integrity}" crossorigin="anonymous"{extra}>"#,
+            url = escape_attribute(&url)
+        ),
+    };
+    let p = Arc::new(NodeCell::new(PageNode::new(o)));
+    p.borrow_mut().set_parent(target.clone());
+    p.borrow_mut().add_content_unparsed(html.into());
+    target.borrow_mut().add_child(p);
 }
 
-/// Convert a serde_yaml::Value to a String
+/// Define a variable from YAML
 ///
-/// For use only in debugging or error output, do not include in places where formatting is super important!
-fn value_tostring(val: &Value) -> String {
-    return match val {
-        Value::Null => "NULL".to_string(),
-        Value::Bool(b) => b.to_string(),
-        Value::Number(n) => n.to_string(),
-        Value::String(s) => format!(r#""{}""#, s.to_string()),
-        Value::Sequence(seq) => {
-            format!(
-                "[{}]",
-                seq.iter()
-                    .map(|i| value_tostring(i) + ",")
-                    .collect::<String>()
-            )
+/// Define a variable in YAML into a target PageNode
+/// The variable is visible after it in the node it is defined in and everything inside of that node, never outside of it.
+/// Nested lists, elements, loop iterations, included files, components and !IF branches are each a node of their own, see !EXPORT for page-wide variables.
+/// Mappings are kept as map variables, whose values are accessed with `{key.name}`
+/// Usage:
+/// ```YAML
+/// !DEF: [key, val]
+/// !DEF: [author, {name: K, email: k@example.com}]
+/// ```
+pub fn def(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    if tv.value.is_sequence() {
+        let s = tv.value.as_sequence().unwrap();
+        if s.len() == 2 {
+            let kstr = parse_value!(target, &s[0], dir.clone());
+            if s[1].is_mapping() {
+                let map = value_to_var(target.clone(), &s[1], dir);
+                target.borrow_mut().set_var(kstr, map);
+            } else {
+                let vstr = parse_value!(target, &s[1], dir);
+                target.borrow_mut().register_var(kstr, vstr);
+            }
         }
-        Value::Mapping(map) => format!(
-            "{{{}}}",
+    } else {
+        error!(
+            target.borrow().o,
+            r#"Invalid arguments to !DEF directive: "{}""#,
+            value_tostring(&tv.value)
+        )
+    }
+}
+
+/// Define a list variable from YAML
+///
+/// Each element of the list is parsed now, nested sequences become nested lists.
+/// Elements are accessed with `{key[index]}`, and the list can be iterated using !FOREACH_VAR
+/// Usage:
+/// ```YAML
+/// !DEFLIST [key, [a, b, [c, d]]]
+/// ```
+pub fn def_list(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    match &tv.value {
+        Value::Sequence(s) if s.len() == 2 && s[1].is_sequence() => {
+            let kstr = parse_value!(target, &s[0], dir.clone());
+            let list = value_to_var(target.clone(), &s[1], dir);
+            target.borrow_mut().set_var(kstr, list);
+        }
+        _ => error!(
+            target.borrow().o,
+            r#"Invalid arguments to !DEFLIST directive: "{}""#,
+            value_tostring(&tv.value)
+        ),
+    }
+}
+
+/// Convert a Value into a Var, keeping the structure of sequences and mappings
+fn value_to_var(target: Arc<NodeCell>, val: &Value, dir: Option<PathBuf>) -> Var {
+    return match val {
+        Value::Sequence(seq) => Var::List(
+            seq.iter()
+                .map(|v| value_to_var(target.clone(), v, dir.clone()))
+                .collect(),
+        ),
+        Value::Mapping(map) => Var::Map(
             map.iter()
-                .map(|(k, v)| match v {
-                    Value::Sequence(_) | Value::Mapping(_) =>
-                        format!("{}:{},", value_tostring(k), value_tostring(v)),
-                    _ => format!("{}:{},", value_tostring(k), value_tostring(v)),
+                .map(|(k, v)| {
+                    (
+                        parse_value!(target, k, dir.clone()),
+                        value_to_var(target.clone(), v, dir.clone()),
+                    )
                 })
-                .collect::<String>()
+                .collect(),
         ),
-        Value::Tagged(t) => format!("{} {}", t.tag, value_tostring(&t.value).as_str()),
+        _ => Var::Str(parse_value!(target, val, dir)),
     };
 }
 
-/* TESTS */
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{Args, Parser};
-    use clap::Parser as ClapParser;
-    use serde_yaml::Number;
-    use std::{fs, fs::File, io::Write};
+/// Render YAML once and keep the HTML in a variable, to use it more than once on the page
+///
+/// Unlike !DEF, mappings are rendered as elements too, and the HTML is inserted as it is wherever the variable is used
+/// Usage:
+/// ```YAML
+/// !CAPTURE [heading, [!INCLUDE title.block, ' | ', '{site.name}']]
+/// head: {title: '{heading}'}
+/// h1: '{heading}'
+/// ```
+pub fn capture(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    match &tv.value {
+        Value::Sequence(s) if s.len() == 2 => {
+            let kstr = parse_value!(target, &s[0], dir.clone());
+            let html = parse_value!(target, &s[1], dir);
+            target.borrow_mut().set_var(kstr, Var::Str(html));
+        }
+        _ => error!(
+            target.borrow().o,
+            r#"Invalid arguments to !CAPTURE directive: "{}""#,
+            value_tostring(&tv.value)
+        ),
+    }
+}
 
-    /// Ensure that combining directives does not cause issues
-    #[test]
-    fn test_directives_combined() {
-        fs::create_dir_all("/tmp/ssgen_test_source_dir_combined").unwrap();
-        let o = Arc::new(
-            Args::parse_from([
-                "",
-                "-i",
-                "/tmp/ssgen_test_source_dir_combined",
-                "-o",
-                "/tmp/",
-                "-s",
-            ])
-            .build_options(),
-        );
+/// Define a variable from YAML that is resolved when it is used rather than now
+///
+/// The value must be text, and any variables inside of it are looked up in the scope the variable is used in.
+/// This allows variables to be defined in any order, and templates to be reused inside of loops
+/// Usage:
+/// ```YAML
+/// !DEFLAZY [key, '{some} {other} {vars}']
+/// ```
+pub fn def_lazy(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    match &tv.value {
+        Value::Sequence(s) if s.len() == 2 => {
+            let vstr: Box<str> = match &s[1] {
+                Value::String(v) => v.clone().into(),
+                Value::Number(n) => n.to_string().into(),
+                Value::Bool(b) => b.to_string().into(),
+                _ => {
+                    error!(
+                        target.borrow().o,
+                        r#"Value of !DEFLAZY must be text: "{}""#,
+                        value_tostring(&s[1])
+                    );
+                    return;
+                }
+            };
+            let kstr = parse_value!(target, &s[0], dir);
+            target.borrow_mut().register_var_lazy(kstr, vstr);
+        }
+        _ => error!(
+            target.borrow().o,
+            r#"Invalid arguments to !DEFLAZY directive: "{}""#,
+            value_tostring(&tv.value)
+        ),
+    }
+}
 
-        let mut p = Parser::new(o.clone());
-        let mut index = File::create("/tmp/ssgen_test_source_dir_combined/index.page").unwrap();
-        index
-            .write_all(
-                br#"
-- !DEF [
-    x,
-    !FOREACH [
-      [y],
-      "{y}",
-      ["a"],
-      ["b"]
-    ]
-  ]
-- p: "{x}"
-- !INCLUDE include.block
-"#,
-            )
-            .unwrap();
+/// Define a variable on the root of the page, making it visible everywhere after it
+///
+/// Useful for included files that need to set variables for the page including them
+/// Usage:
+/// ```YAML
+/// !EXPORT [key, val]
+/// ```
+pub fn export(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    match &tv.value {
+        Value::Sequence(s) if s.len() == 2 => {
+            let kstr = parse_value!(target, &s[0], dir.clone());
+            let vstr = parse_value!(target, &s[1], dir);
+            PageNode::root(target).borrow_mut().register_var(kstr, vstr);
+        }
+        _ => error!(
+            target.borrow().o,
+            r#"Invalid arguments to !EXPORT directive: "{}""#,
+            value_tostring(&tv.value)
+        ),
+    }
+}
 
-        let mut include =
-            File::create("/tmp/ssgen_test_source_dir_combined/include.block").unwrap();
-        include
-            .write_all(
-                br#"
-- p:
-    !IF ['{x}', '{x}', "nothing"]
-- '{x}': asdf
-- !DEF [var2, thisshouldntdoathing]
-"#,
-            )
+/// Define a named, reusable block of YAML with parameters
+///
+/// The body is only parsed when the component is used, with its parameters defined as variables
+/// Usage:
+/// ```YAML
+/// !COMPONENT [card, [title, body], {div: [h2: '{title}', p: '{body}']}]
+/// ```
+pub fn component(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    match &tv.value {
+        Value::Sequence(s) if s.len() == 3 && s[1].is_sequence() => {
+            let name = parse_value!(target, &s[0], dir.clone());
+            let params = s[1]
+                .as_sequence()
+                .unwrap()
+                .iter()
+                .map(|p| parse_value!(target, p, dir.clone()))
+                .collect();
+            target.borrow_mut().set_var(
+                name,
+                Var::Component(Arc::new(Component {
+                    params: params,
+                    body: s[2].clone(),
+                    dir: dir,
+                })),
+            );
+        }
+        _ => error!(
+            target.borrow().o,
+            r#"Invalid arguments to !COMPONENT directive: "{}""#,
+            value_tostring(&tv.value)
+        ),
+    }
+}
+
+/// Add an instance of a component defined with !COMPONENT
+///
+/// Arguments are parsed where the component is used, parameters without an argument are empty
+/// Usage:
+/// ```YAML
+/// !USE [card, {title: Hello, body: World}]
+/// !USE [footer]
+/// ```
+pub fn use_component(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let (name, args) = match &tv.value {
+        Value::Sequence(s) if s.len() == 1 => (parse_value!(target, &s[0], dir.clone()), None),
+        Value::Sequence(s) if s.len() == 2 && s[1].is_mapping() => {
+            (parse_value!(target, &s[0], dir.clone()), s[1].as_mapping())
+        }
+        _ => {
+            error!(
+                target.borrow().o,
+                r#"Invalid arguments to !USE directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+    };
+    let component = match target.borrow().lookup_var(&name) {
+        Some(Var::Component(c)) => c,
+        _ => {
+            error!(
+                target.borrow().o,
+                "No component called {name}, define it with !COMPONENT"
+            );
+            return;
+        }
+    };
+    let depth = COMPONENT_DEPTH.get();
+    if depth >= MAX_COMPONENT_DEPTH {
+        error!(
+            target.borrow().o,
+            "Component {name} is too deeply nested, does it use itself?"
+        );
+        return;
+    }
+
+    let child = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
+    child.borrow_mut().set_parent(target.clone());
+    target.borrow_mut().add_child(child.clone());
+    for param in component.params.iter() {
+        child.borrow_mut().register_var(param.clone(), "".into());
+    }
+    for (k, v) in args.into_iter().flatten() {
+        let key = parse_value!(target, k, dir.clone());
+        if !component.params.contains(&key) {
+            warn!(target.borrow().o, "Component {name} has no parameter {key}");
+        }
+        match v {
+            Value::Mapping(_) => {
+                let map = value_to_var(target.clone(), v, dir.clone());
+                child.borrow_mut().set_var(key, map);
+            }
+            _ => {
+                let val = parse_value!(target, v, dir.clone());
+                child.borrow_mut().register_var(key, val);
+            }
+        }
+    }
+    COMPONENT_DEPTH.set(depth + 1);
+    Parser::add_value(child, &component.body, component.dir.clone());
+    COMPONENT_DEPTH.set(depth);
+}
+
+/// Pages that share the most tags with the page being built, see build::page_tags
+///
+/// Pages sharing as many tags are ordered by title, pages without a shared tag are never listed.
+/// Lists links to the 5 most related pages by default, or adds body for each with the page bound to a variable
+/// Usage:
+/// ```YAML
+/// !RELATED
+/// !RELATED 3
+/// !RELATED [3, p, {li: '{p.title}: {p.excerpt}'}]
+/// ```
+pub fn related(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let args: Vec<&Value> = match &tv.value {
+        Value::Null => Vec::new(),
+        Value::Sequence(s) if s.len() == 1 || s.len() == 3 => s.iter().collect(),
+        Value::Sequence(_) | Value::Mapping(_) | Value::Tagged(_) => {
+            error!(
+                target.borrow().o,
+                r#"Invalid arguments to !RELATED directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+        v => vec![v],
+    };
+    let n = match args.first() {
+        Some(v) => {
+            let n = parse_value!(target, v, dir.clone());
+            match n.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => {
+                    error!(
+                        target.borrow().o,
+                        "!RELATED: number of pages must be a number, not {n}"
+                    );
+                    return;
+                }
+            }
+        }
+        None => 5,
+    };
+    let pages = match site_pages(&target, "!RELATED") {
+        Some(p) => p,
+        None => return,
+    };
+
+    let active = active_page();
+    let tags = match pages.iter().find(|p| field(p, "source") == active) {
+        Some(p) => build::page_tags(p),
+        None => Vec::new(),
+    };
+    let mut related: Vec<(usize, &Var)> = pages
+        .iter()
+        .filter(|p| field(p, "source") != active)
+        .map(|p| {
+            let shared = build::page_tags(p)
+                .iter()
+                .filter(|t| tags.contains(t))
+                .count();
+            (shared, p)
+        })
+        .filter(|(shared, _)| *shared != 0)
+        .collect();
+    related.sort_by(|(a_shared, a), (b_shared, b)| {
+        b_shared
+            .cmp(a_shared)
+            .then(field(a, "title").cmp(&field(b, "title")))
+    });
+    let related: Vec<Var> = related
+        .into_iter()
+        .take(n)
+        .map(|(_, p)| p.clone())
+        .collect();
+
+    if args.len() == 3 {
+        let name = parse_value!(target, args[1], dir.clone());
+        let length = related.len();
+        iterate(target, &related, args[2], dir, |child, index, page| {
+            child
+                .borrow_mut()
+                .set_var("loop".into(), loop_var(index, length));
+            child.borrow_mut().set_var(name.clone(), page.clone());
+        });
+        return;
+    }
+    if related.len() == 0 {
+        return;
+    }
+    let mut html = String::from("<ul>");
+    for page in related.iter() {
+        html.push_str(&format!(
+            r#"<li><a href="{url}">{title}</a></li>"#,
+            url = escape_attribute(&field(page, "url")),
+            title = escape_attribute(&field(page, "title"))
+        ));
+    }
+    html.push_str("</ul>");
+    let p = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
+    p.borrow_mut().set_parent(target.clone());
+    p.borrow_mut().add_content_unparsed(html.into());
+    target.borrow_mut().add_child(p);
+}
+
+/// Settings for ssgen itself, which are read before META.yaml is parsed, see settings::apply
+///
+/// Only has an effect at the top level of META.yaml
+/// Usage:
+/// ```YAML
+/// !SITE {title: SSGen, base_url: https://example.com}
+/// ```
+pub fn site(target: Arc<NodeCell>, _tv: &TaggedValue, _dir: Option<PathBuf>) {
+    let in_meta = yaml::active()
+        .last()
+        .is_some_and(|(file, _)| &file[..] == "META.yaml");
+    if !in_meta {
+        warn!(target.borrow().o, "!SITE is ignored outside of META.yaml");
+    }
+}
+
+/// Entries of site.pages, each of which becomes a dependency of the page since they are all listed
+///
+/// Errors if there is no site variable, which is the case outside of a site build
+fn site_pages(target: &Arc<NodeCell>, directive: &str) -> Option<Vec<Var>> {
+    let pages = match target.borrow().lookup_var("site") {
+        Some(site) => match site.get_path(".pages") {
+            Some(Var::List(pages)) => pages.clone(),
+            _ => Vec::new(),
+        },
+        None => {
+            error!(
+                target.borrow().o,
+                "{directive} needs the site variable, which only exists while building a site"
+            );
+            return None;
+        }
+    };
+    // a new, renamed or changed page can change what is listed
+    for page in pages.iter() {
+        let mut file = target.borrow().o.input.clone();
+        file.push(&field(page, "source")[..]);
+        PageNode::add_dependency(target.clone(), file);
+    }
+    return Some(pages);
+}
+
+/// Text of a field of an entry in site.pages, empty if it has none
+fn field(page: &Var, name: &str) -> Box<str> {
+    return match page.get_path(&format!(".{name}")) {
+        Some(Var::Str(s)) => s.clone(),
+        _ => "".into(),
+    };
+}
+
+/// Source of the page being built, which is the outermost file being added
+fn active_page() -> Box<str> {
+    return yaml::active()
+        .first()
+        .map(|(file, _)| file.clone())
+        .unwrap_or_default();
+}
+
+/// Page listed by !NAV, from its entry in site.pages
+struct NavPage {
+    title: Box<str>,
+    url: Box<str>,
+    /// Position among its siblings, from _nav_order
+    order: Option<f64>,
+    source: Box<str>,
+}
+
+/// Page or directory in the navigation tree built by !NAV
+#[derive(Default)]
+struct NavNode {
+    /// The page, or the index page of a directory
+    page: Option<NavPage>,
+    /// Pages and directories inside of this directory, by file name
+    children: BTreeMap<Box<str>, NavNode>,
+}
+
+impl NavNode {
+    /// Whether source is this page or any page below it
+    fn contains(&self, source: &str) -> bool {
+        return self.page.as_ref().is_some_and(|p| &p.source[..] == source)
+            || self.children.values().any(|c| c.contains(source));
+    }
+
+    /// Nested lists of the children of this node, marking the page built from active
+    fn render(&self, html: &mut String, active: &str) {
+        let mut children: Vec<(&Box<str>, &NavNode)> = self.children.iter().collect();
+        // pages with a _nav_order come first, then everything else by title
+        children.sort_by(|(a_name, a), (b_name, b)| {
+            let key = |name: &str, n: &NavNode| match &n.page {
+                Some(p) => (p.order.unwrap_or(f64::INFINITY), p.title.clone()),
+                None => (f64::INFINITY, name.into()),
+            };
+            let (a, b) = (key(a_name, a), key(b_name, b));
+            return a.0.total_cmp(&b.0).then(a.1.cmp(&b.1));
+        });
+        html.push_str("<ul>");
+        for (name, child) in children {
+            match &child.page {
+                Some(p) if &p.source[..] == active => html.push_str(r#"<li class="active">"#),
+                _ if child.contains(active) => html.push_str(r#"<li class="open">"#),
+                _ => html.push_str("<li>"),
+            }
+            match &child.page {
+                Some(p) => {
+                    let current = match &p.source[..] == active {
+                        true => r#" aria-current="page""#,
+                        false => "",
+                    };
+                    html.push_str(&format!(
+                        r#"<a href="{url}"{current}>{title}</a>"#,
+                        url = escape_attribute(&p.url),
+                        title = escape_attribute(&p.title)
+                    ));
+                }
+                None => html.push_str(&format!("<span>{}</span>", escape_attribute(name))),
+            }
+            if child.children.len() != 0 {
+                child.render(html, active);
+            }
+            html.push_str("</li>");
+        }
+        html.push_str("</ul>");
+    }
+}
+
+/// Nested lists linking to every page, following the directories of the input directory
+///
+/// Pages are titled and ordered by their `_title` and `_nav_order` metadata, see build::page_metadata.
+/// An index page stands for its directory, the page being built gets `class="active"`
+/// and the directories containing it `class="open"`.
+/// Optionally only lists the pages in a directory
+/// Usage:
+/// ```YAML
+/// nav: !NAV
+/// nav: !NAV docs
+/// ```
+pub fn nav(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let section = match &tv.value {
+        Value::Null => "".into(),
+        Value::Sequence(_) | Value::Mapping(_) | Value::Tagged(_) => {
+            error!(
+                target.borrow().o,
+                r#"Invalid arguments to !NAV directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+        v => parse_value!(target, v, dir.clone()),
+    };
+    let pages = match site_pages(&target, "!NAV") {
+        Some(p) => p,
+        None => return,
+    };
+
+    let mut tree = NavNode::default();
+    for page in pages.iter() {
+        let (source, output) = (field(page, "source"), field(page, "output"));
+        let mut parts: Vec<&str> = output.split('/').collect();
+        if parts.len() > 1 && parts.last() == Some(&"index.html") {
+            parts.pop();
+        }
+        let mut node = &mut tree;
+        for part in parts {
+            node = node.children.entry(part.into()).or_default();
+        }
+        node.page = Some(NavPage {
+            title: field(page, "title"),
+            url: field(page, "url"),
+            order: field(page, "nav_order").parse().ok(),
+            source: source,
+        });
+    }
+
+    let mut node = &tree;
+    for part in section.split('/').filter(|p| p.len() != 0) {
+        node = match node.children.get(part) {
+            Some(n) => n,
+            None => {
+                warn!(target.borrow().o, "!NAV: there are no pages in {section}");
+                return;
+            }
+        };
+    }
+    let mut html = String::new();
+    node.render(&mut html, &active_page());
+
+    let p = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
+    p.borrow_mut().set_parent(target.clone());
+    p.borrow_mut().add_content_unparsed(html.into());
+    target.borrow_mut().add_child(p);
+}
+
+/// Execute an arbitrary string in the shell (dangerous)
+///
+/// Usage:
+/// ```YAML
+/// !SHELL_CMD: ['echo', 'hi']
+/// ```
+pub fn shell_command(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    // ensure this is allowed
+    if !target.borrow().o.allow_shell {
+        error!(
+            target.borrow().o,
+            r#"!SHELL_CMD used bet shell commands are not enabled! Run SSGen with the '--enable-shell' argument (danger!) to enable them."#
+        );
+        return;
+    }
+
+    // build and run command
+    if tv.value.is_sequence() {
+        let seq = tv.value.as_sequence().unwrap();
+        let mut args_str = parse_value!(target, &seq[0], dir.clone()).to_string();
+        let args_os_str = OsStr::new(args_str.as_str());
+        let mut cmd = Command::new::<&OsStr>(args_os_str);
+
+        for p in seq.iter().skip(1) {
+            let arg_str = parse_value!(target, p, dir.clone()).to_string();
+            args_str = args_str + " " + arg_str.as_str();
+            let arg_os_str = OsStr::new(arg_str.as_str());
+            cmd.arg(arg_os_str);
+        }
+
+        info!(
+            target.borrow().o,
+            r#"Running shell command: "{}""#, args_str
+        );
+
+        // run and send unparsed output
+        let output = cmd.output().expect("Failed to run process!!");
+        target
+            .borrow_mut()
+            .add_content_unparsed(std::str::from_utf8(&output.stdout[..]).unwrap().into());
+
+        return;
+    }
+
+    error!(
+        target.borrow().o,
+        r#"Invalid arguments to !SHELL_CMD directive: "{}""#,
+        value_tostring(&tv.value)
+    );
+}
+
+/// Take a substring of parsed YAML content
+///
+/// Usage:
+/// ```YAML
+/// !SUBSTRING [
+///   0,            # Starting index
+///   5,            # Ending index
+///   '0123456789', # The YAML to parse then index
+/// ]
+/// ```
+pub fn substring(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    debug!(target.borrow().o, "Running substring...");
+    match &tv.value {
+        Value::Sequence(args) => 'invalid_substring: {
+            // ensure preconditions
+            if args.len() < 3 || !args[0].is_i64() || !args[0].is_i64() {
+                break 'invalid_substring;
+            };
+
+            // parse third arg then take substring
+            let vstr = parse_value!(target, &args[2], dir.clone());
+            let start: usize = max(0, args[0].as_i64().unwrap()).try_into().unwrap();
+            let end: usize = min(vstr.len() as i64, args[1].as_i64().unwrap())
+                .try_into()
+                .unwrap();
+            if start > end {
+                break 'invalid_substring;
+            }
+            // the substring is already parsed, so it is escaped to stay as it is
+            let sub = pagenode::escape(&vstr[start..end]);
+            Parser::add_value(target, &Value::String(sub), dir.clone());
+
+            return;
+        }
+        _ => (),
+    }
+    let s = value_tostring(&tv.value);
+    // if fail
+    error!(
+        target.borrow().o,
+        r#"Invalid arguments to !SUBSTRING directive: "{}""#,
+        if s.len() > 100 {
+            format!("{}...", &s[..99])
+        } else {
+            s
+        }
+    );
+}
+
+/// Change the case of, or trim the whitespace around, parsed YAML content
+///
+/// Case changes leave tags and character references like `&amp;` alone, so only the text is changed
+/// Usage:
+/// ```YAML
+/// !LOWERCASE '{title}'  # or !UPPERCASE, or !TRIM
+/// !UPPERCASE {p: text}   # <p>TEXT</p>
+/// ```
+pub fn transform(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    debug!(target.borrow().o, "Running {}...", tv.tag);
+    let vstr = parse_value!(target, &tv.value, dir);
+    let out = match tv.tag.to_string().as_str() {
+        "!LOWERCASE" => pagenode::map_text(&vstr, &[], &mut str::to_lowercase),
+        "!UPPERCASE" => pagenode::map_text(&vstr, &[], &mut str::to_uppercase),
+        _ => vstr.trim().to_string(),
+    };
+    let child = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
+    child.borrow_mut().set_parent(target.clone());
+    child.borrow_mut().add_content_unparsed(out.into());
+    target.borrow_mut().add_child(child);
+}
+
+/// Replace every match of a regular expression in parsed YAML content
+///
+/// The pattern is used as written, since `\` and `{}` would otherwise be taken as escapes and variables.
+/// The replacement can refer to groups with `$1` or `${name}`, unless the optional mode is `literal`,
+/// in which case both the pattern and replacement are plain text
+/// Usage:
+/// ```YAML
+/// !REPLACE [
+///   'https?://old\.example\.com/',  # Pattern to search for
+///   '/',                             # Replacement for every match
+///   !INCLUDE_RAW fragment.html,      # The YAML to parse then search
+///   regex,                           # Mode, regex or literal, optional
+/// ]
+/// ```
+pub fn replace(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    debug!(target.borrow().o, "Running replace...");
+    match &tv.value {
+        Value::Sequence(args) if args.len() == 3 || args.len() == 4 => 'invalid_replace: {
+            let pattern = pattern_arg(target.clone(), &args[0], dir.clone());
+            let literal = match args.get(3).map(|m| parse_value!(target, m, dir.clone())) {
+                None => false,
+                Some(m) if &m[..] == "regex" => false,
+                Some(m) if &m[..] == "literal" => true,
+                Some(_) => break 'invalid_replace,
+            };
+            let re = match Regex::new(&if literal {
+                regex::escape(&pattern)
+            } else {
+                pattern.to_string()
+            }) {
+                Ok(re) => re,
+                Err(e) => {
+                    error!(target.borrow().o, "!REPLACE: invalid pattern | {e}");
+                    return;
+                }
+            };
+            let replacement = parse_value!(target, &args[1], dir.clone());
+            let content = parse_value!(target, &args[2], dir.clone());
+            let out = if literal {
+                re.replace_all(&content, NoExpand(&replacement))
+            } else {
+                re.replace_all(&content, &replacement[..])
+            };
+
+            let child = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
+            child.borrow_mut().set_parent(target.clone());
+            child.borrow_mut().add_content_unparsed(out.into());
+            target.borrow_mut().add_child(child);
+            return;
+        }
+        _ => (),
+    }
+    let s = value_tostring(&tv.value);
+    // if fail
+    error!(
+        target.borrow().o,
+        r#"Invalid arguments to !REPLACE directive: "{}""#,
+        if s.len() > 100 {
+            format!("{}...", &s[..99])
+        } else {
+            s
+        }
+    );
+}
+
+/// Read the pattern argument of a directive, strings are taken as written instead of being parsed
+fn pattern_arg(target: Arc<NodeCell>, val: &Value, dir: Option<PathBuf>) -> Box<str> {
+    return match val {
+        Value::String(s) => s.clone().into(),
+        v => parse_value!(target, v, dir),
+    };
+}
+
+/// Replace every match of a regular expression in the finished page, after everything else has been rendered
+///
+/// Rules in META.yaml apply to every page, and rules in a page only to that page. They run in the order they were defined.
+/// The pattern and replacement work like those of !REPLACE
+/// Usage:
+/// ```YAML
+/// !REWRITE ['(/assets/[\w.]+\.(css|js))"', '$1?v={site.build.time}"'] # Cache busting
+/// !REWRITE ['https://staging\.example\.com', 'https://example.com']
+/// ```
+pub fn rewrite(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    debug!(target.borrow().o, "Registering rewrite rule...");
+    match &tv.value {
+        Value::Sequence(args) if args.len() == 2 => {
+            let pattern = pattern_arg(target.clone(), &args[0], dir.clone());
+            match Regex::new(&pattern) {
+                Ok(re) => {
+                    let replacement = parse_value!(target, &args[1], dir.clone());
+                    PageNode::add_rewrite(target, re, replacement);
+                }
+                Err(e) => error!(target.borrow().o, "!REWRITE: invalid pattern | {e}"),
+            }
+            return;
+        }
+        _ => (),
+    }
+    error!(
+        target.borrow().o,
+        r#"Invalid arguments to !REWRITE directive: "{}""#,
+        value_tostring(&tv.value)
+    );
+}
+
+/// Sorting and filtering of loop rows, given as a mapping in place of the variable names
+///
+/// Fields are variable names, optionally followed by an accessor like `post.date` or `row[1]`
+struct LoopOptions {
+    /// Variable names, as they would be given without options
+    vars: Value,
+    /// Field to sort rows by
+    sort: Option<Box<str>>,
+    /// Whether to sort from largest to smallest
+    descending: bool,
+    /// Fields that must not be empty for a row to be kept
+    filter: Vec<Box<str>>,
+}
+
+impl LoopOptions {
+    /// Read the variable names argument of a loop, or None if the options are invalid
+    fn parse(
+        target: Arc<NodeCell>,
+        val: &Value,
+        dir: Option<PathBuf>,
+        directive: &str,
+    ) -> Option<LoopOptions> {
+        let mut opts = LoopOptions {
+            vars: val.clone(),
+            sort: None,
+            descending: false,
+            filter: Vec::new(),
+        };
+        let map = match val {
+            Value::Mapping(m) => m,
+            _ => return Some(opts),
+        };
+        opts.vars = Value::Null;
+        for (k, v) in map {
+            let k = parse_value!(target, k, dir.clone());
+            match &k[..] {
+                "vars" => opts.vars = v.clone(),
+                "sort" => opts.sort = Some(parse_value!(target, v, dir.clone())),
+                "order" => {
+                    opts.descending = match &parse_value!(target, v, dir.clone())[..] {
+                        "asc" => false,
+                        "desc" => true,
+                        o => {
+                            error!(
+                                target.borrow().o,
+                                "{directive}: order must be asc or desc, not {o}"
+                            );
+                            return None;
+                        }
+                    }
+                }
+                "filter" => {
+                    opts.filter = match v {
+                        Value::Sequence(seq) => seq
+                            .iter()
+                            .map(|f| parse_value!(target, f, dir.clone()))
+                            .collect(),
+                        _ => vec![parse_value!(target, v, dir.clone())],
+                    }
+                }
+                _ => warn!(target.borrow().o, "{directive}: unknown option {k}"),
+            }
+        }
+        if opts.vars.is_null() {
+            error!(
+                target.borrow().o,
+                "{directive}: options must give the variable names as vars"
+            );
+            return None;
+        }
+        return Some(opts);
+    }
+
+    /// Whether every sort and filter field starts with one of names
+    fn check(&self, target: Arc<NodeCell>, names: &[Box<str>], directive: &str) -> bool {
+        for f in self.sort.iter().chain(self.filter.iter()) {
+            let name = &f[..f.find(['.', '[']).unwrap_or(f.len())];
+            if !names.iter().any(|n| **n == *name) {
+                error!(
+                    target.borrow().o,
+                    "{directive}: {f} is not one of the loop variables"
+                );
+                return false;
+            }
+        }
+        return true;
+    }
+
+    /// Filter and sort rows, bind gives the variables of a row as a mapping
+    ///
+    /// Fields that are numbers on both sides are compared as numbers, anything else as text
+    fn apply<T>(&self, rows: Vec<T>, bind: impl Fn(&T) -> Var) -> Vec<T> {
+        if self.sort.is_none() && self.filter.len() == 0 {
+            return rows;
+        }
+        let field = |vars: &Var, f: &str| -> Box<str> {
+            return match vars.get_path(&format!(".{f}")) {
+                Some(Var::Str(s)) | Some(Var::Lazy(s)) => s.clone(),
+                _ => "".into(),
+            };
+        };
+        let mut rows: Vec<(Var, T)> = rows
+            .into_iter()
+            .map(|r| (bind(&r), r))
+            .filter(|(vars, _)| self.filter.iter().all(|f| field(vars, f).len() > 0))
+            .collect();
+        match &self.sort {
+            Some(sort) => rows.sort_by(|(a, _), (b, _)| {
+                let (a, b) = (field(a, sort), field(b, sort));
+                let order = match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+                    (Ok(x), Ok(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+                    _ => a.cmp(&b),
+                };
+                return if self.descending {
+                    order.reverse()
+                } else {
+                    order
+                };
+            }),
+            None => (),
+        }
+        return rows.into_iter().map(|(_, r)| r).collect();
+    }
+}
+
+/// One iteration of !FOREACH
+enum Row<'a> {
+    /// Values bound to the variable names in order
+    Values(&'a [Value]),
+    /// Key and value of a mapping entry, bound to the two variable names
+    Entry(&'a Value, &'a Value),
+}
+
+/// Build the `loop` variable of an iteration, index counts from 0
+///
+/// `{loop.index}` counts from 1 and `{loop.index0}` from 0, `{loop.first}` and `{loop.last}` are empty unless true
+fn loop_var(index: usize, length: usize) -> Var {
+    let flag = |b: bool| Var::Str(if b { "true" } else { "" }.into());
+    return Var::Map(vec![
+        ("index".into(), Var::Str((index + 1).to_string().into())),
+        ("index0".into(), Var::Str(index.to_string().into())),
+        ("length".into(), Var::Str(length.to_string().into())),
+        ("first".into(), flag(index == 0)),
+        ("last".into(), flag(index + 1 == length)),
+    ]);
+}
+
+/// Loops with at least this many iterations are spread over several threads, see iterate
+const PARALLEL_ITERATIONS: usize = 256;
+
+/// Directives known to only affect the node they are in, a loop body using any other always runs in order
+///
+/// Others write files, export variables, or add YAML that is not known until they run, like !INCLUDE and !FETCH
+const UNORDERED_DIRECTIVES: [&str; 23] = [
+    "!CAPTURE",
+    "!COMMENT",
+    "!DATE",
+    "!DEF",
+    "!DEFLAZY",
+    "!DEFLIST",
+    "!FOREACH",
+    "!FOREACH_VAR",
+    "!IF",
+    "!IF_ENV",
+    "!IF_PROFILE",
+    "!IGNORE",
+    "!INCLUDE_HTML",
+    "!INCLUDE_RAW",
+    "!LOWERCASE",
+    "!MATH",
+    "!MATH_DISPLAY",
+    "!REPEAT",
+    "!REPLACE",
+    "!SUBSTRING",
+    "!TRIM",
+    "!TRY",
+    "!UPPERCASE",
+];
+
+/// Add one iteration of target for every item, bound by bind before body is added to it
+///
+/// The iterations are created in order, so the output keeps the order of items no matter which thread fills them.
+/// Big loops are filled on several threads, unless their body uses a directive that is not one of UNORDERED_DIRECTIVES.
+/// Stops creating iterations once the page runs out of its iteration budget
+fn iterate<T: Sync>(
+    target: Arc<NodeCell>,
+    items: &[T],
+    body: &Value,
+    dir: Option<PathBuf>,
+    bind: impl Fn(&Arc<NodeCell>, usize, &T) + Sync,
+) {
+    let mut children = Vec::with_capacity(items.len());
+    for _ in items {
+        if !PageNode::spend_budget(target.clone(), Budget::Iterations) {
+            break;
+        }
+        children.push(PageNode::new_iteration(target.clone()));
+    }
+    let fill = |index: usize, child: &Arc<NodeCell>| {
+        bind(child, index, &items[index]);
+        Parser::add_value(child.clone(), body, dir.clone());
+    };
+
+    let threads = loop_threads();
+    if children.len() < PARALLEL_ITERATIONS || threads == 1 || !is_unordered(body) {
+        children.iter().enumerate().for_each(|(i, c)| fill(i, c));
+        return;
+    }
+    debug!(
+        target.borrow().o,
+        "Filling {n} iterations on {threads} threads",
+        n = children.len()
+    );
+    // what this thread knows about where the directive is carries over to the others
+    let active = yaml::active();
+    let depth = COMPONENT_DEPTH.get();
+    let size = children.len().div_ceil(threads);
+    thread::scope(|scope| {
+        for (n, chunk) in children.chunks(size).enumerate() {
+            let (fill, active) = (&fill, active.clone());
+            scope.spawn(move || {
+                yaml::set_active(active);
+                COMPONENT_DEPTH.set(depth);
+                for (i, child) in chunk.iter().enumerate() {
+                    fill(n * size + i, child);
+                }
+            });
+        }
+    });
+}
+
+/// Number of threads a big loop is spread over
+fn loop_threads() -> usize {
+    let n = thread::available_parallelism().map_or(1, |n| n.get());
+    // tests run on machines of any size, but should always go through the threaded path
+    return if cfg!(test) { n.max(4) } else { n };
+}
+
+/// Whether v only contains directives from UNORDERED_DIRECTIVES, so its copies can be added in any order
+fn is_unordered(v: &Value) -> bool {
+    return match v {
+        Value::Tagged(t) => {
+            UNORDERED_DIRECTIVES.iter().any(|d| t.tag == *d) && is_unordered(&t.value)
+        }
+        Value::Sequence(seq) => seq.iter().all(is_unordered),
+        Value::Mapping(map) => map.iter().all(|(k, v)| is_unordered(k) && is_unordered(v)),
+        _ => true,
+    };
+}
+
+/// Iterate over some data provided through YAML according to a template
+///
+/// Every iteration can use the `loop` variable, see loop_var.
+/// The variable names can be replaced with a mapping to sort and filter the values, see LoopOptions.
+/// Nested loops see the variables of the loops around them, `{outer.x}` is x of the enclosing loop even if the inner one also binds x
+/// Usage:
+/// ```YAML
+/// !FOREACH [
+///   [x, y, ..., n],              # Variable names for use in template
+///   "{x} {y} (...) {n}",         # Template for values to be inserted into
+///   [xval, yval, ..., nval],     # One set of values to insert into the template
+///   [xval2, yval2, ..., zval2],  # Another set of values
+/// ]
+/// !FOREACH [
+///   [key, value],                # Two names to bind each entry of a mapping to
+///   "<a href={value}>{key}</a>",
+///   {Home: /, About: /about},    # One set of values per entry
+/// ]
+/// !FOREACH [
+///   {vars: [title, date], sort: date, order: desc, filter: title}, # Newest first, skipping rows without a title
+///   "<li>{date}: {title}</li>",
+///   [First post, 2024-01-02],
+///   ['', 2024-03-04],
+/// ]
+/// ```
+pub fn foreach(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    info!(target.borrow().o, "Looping into !FOREACH directive...");
+    match &tv.value {
+        Value::Sequence(foreach) => 'invalid_foreach: {
+            // ensure preconditions
+            if foreach.len() < 3 {
+                break 'invalid_foreach;
+            };
+            let opts =
+                match LoopOptions::parse(target.clone(), &foreach[0], dir.clone(), "!FOREACH") {
+                    Some(o) => o,
+                    None => return,
+                };
+            let keys = match &opts.vars {
+                Value::Sequence(seq) => seq
+                    .iter()
+                    .map(|k| parse_value!(target, k, dir.clone()))
+                    .collect::<Vec<Box<str>>>(),
+                _ => break 'invalid_foreach,
+            };
+            if !opts.check(target.clone(), &keys, "!FOREACH") {
+                return;
+            }
+
+            // mappings run once per entry
+            let mut rows = Vec::new();
+            for values in foreach.iter().skip(2) {
+                match values {
+                    Value::Sequence(seq) if seq.len() == keys.len() => rows.push(Row::Values(seq)),
+                    Value::Mapping(map) if keys.len() == 2 => {
+                        rows.extend(map.iter().map(|(k, v)| Row::Entry(k, v)))
+                    }
+                    _ => break 'invalid_foreach,
+                }
+            }
+            let rows = opts.apply(rows, |row| match row {
+                Row::Values(seq) => Var::Map(
+                    keys.iter()
+                        .zip(seq.iter())
+                        .map(|(k, v)| (k.clone(), value_to_var(target.clone(), v, dir.clone())))
+                        .collect(),
+                ),
+                Row::Entry(k, v) => Var::Map(vec![
+                    (
+                        keys[0].clone(),
+                        Var::Str(parse_value!(target, k, dir.clone())),
+                    ),
+                    (
+                        keys[1].clone(),
+                        value_to_var(target.clone(), v, dir.clone()),
+                    ),
+                ]),
+            });
+
+            let length = rows.len();
+            iterate(
+                target,
+                &rows,
+                &foreach[1],
+                dir.clone(),
+                |child, index, row| {
+                    child
+                        .borrow_mut()
+                        .set_var("loop".into(), loop_var(index, length));
+                    match row {
+                        Row::Values(seq) => seq.iter().enumerate().for_each(|(i, v)| {
+                            let vstr = parse_value!(child, v, dir.clone());
+                            child.borrow_mut().register_var(keys[i].clone(), vstr);
+                        }),
+                        Row::Entry(k, v) => {
+                            let kstr = parse_value!(child, k, dir.clone());
+                            child.borrow_mut().register_var(keys[0].clone(), kstr);
+                            if v.is_mapping() {
+                                let map = value_to_var(child.clone(), v, dir.clone());
+                                child.borrow_mut().set_var(keys[1].clone(), map);
+                            } else {
+                                let vstr = parse_value!(child, v, dir.clone());
+                                child.borrow_mut().register_var(keys[1].clone(), vstr);
+                            }
+                        }
+                    }
+                },
+            );
+            return;
+        }
+        _ => (),
+    }
+    let s = value_tostring(&tv.value);
+    // if fail
+    error!(
+        target.borrow().o,
+        r#"Invalid arguments to !FOREACH directive: "{}""#,
+        if s.len() > 100 {
+            format!("{}...", &s[..99])
+        } else {
+            s
+        }
+    );
+}
+
+/// Iterate over a list variable according to a template
+///
+/// Each element is bound to the given name, or if a list of names is given, each element must be a list of the same length which gets bound to the names in order
+/// Every iteration can use the `loop` variable, see loop_var, and the names can be given as options like in !FOREACH
+/// Usage:
+/// ```YAML
+/// !FOREACH_VAR [
+///   items,                # Name of the list variable to iterate over
+///   x,                    # Name to bind each element to, or [x, y, ..., n]
+///   "<li>{x}</li>",       # Template for values to be inserted into
+/// ]
+/// !FOREACH_VAR [posts, {vars: post, sort: post.date, order: desc}, "<li>{post.title}</li>"]
+/// ```
+pub fn foreach_var(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    info!(target.borrow().o, "Looping into !FOREACH_VAR directive...");
+    match &tv.value {
+        Value::Sequence(args) if args.len() == 3 => 'invalid_foreach: {
+            let name = parse_value!(target, &args[0], dir.clone());
+            let items = match target.borrow().lookup_var(&name) {
+                Some(Var::List(l)) => l,
+                _ => {
+                    error!(
+                        target.borrow().o,
+                        "!FOREACH_VAR: {name} is not a list variable"
+                    );
+                    return;
+                }
+            };
+            let opts =
+                match LoopOptions::parse(target.clone(), &args[1], dir.clone(), "!FOREACH_VAR") {
+                    Some(o) => o,
+                    None => return,
+                };
+            let keys: Option<Vec<Box<str>>> = match &opts.vars {
+                Value::Sequence(seq) => Some(
+                    seq.iter()
+                        .map(|k| parse_value!(target, k, dir.clone()))
+                        .collect(),
+                ),
+                _ => None,
+            };
+            let key = parse_value!(target, &opts.vars, dir.clone());
+            let names = keys.clone().unwrap_or(vec![key.clone()]);
+            if !opts.check(target.clone(), &names, "!FOREACH_VAR") {
+                return;
+            }
+            let items = opts.apply(items, |item| match (&keys, item) {
+                (Some(keys), Var::List(values)) => {
+                    Var::Map(keys.iter().cloned().zip(values.iter().cloned()).collect())
+                }
+                (Some(_), _) => Var::Map(Vec::new()),
+                (None, v) => Var::Map(vec![(key.clone(), v.clone())]),
+            });
+
+            // every item is checked before any output is added
+            let items = match &keys {
+                Some(keys) => {
+                    let mut rows = Vec::with_capacity(items.len());
+                    for item in items {
+                        match item {
+                            Var::List(values) if values.len() == keys.len() => rows.push(values),
+                            _ => break 'invalid_foreach,
+                        }
+                    }
+                    rows
+                }
+                None => items.into_iter().map(|v| vec![v]).collect(),
+            };
+            let names = keys.unwrap_or(vec![key]);
+            let length = items.len();
+            iterate(
+                target,
+                &items,
+                &args[2],
+                dir.clone(),
+                |child, index, values| {
+                    child
+                        .borrow_mut()
+                        .set_var("loop".into(), loop_var(index, length));
+                    names.iter().zip(values).for_each(|(k, v)| {
+                        child.borrow_mut().set_var(k.clone(), v.clone());
+                    });
+                },
+            );
+            return;
+        }
+        _ => (),
+    }
+    let s = value_tostring(&tv.value);
+    // if fail
+    error!(
+        target.borrow().o,
+        r#"Invalid arguments to !FOREACH_VAR directive: "{}""#,
+        if s.len() > 100 {
+            format!("{}...", &s[..99])
+        } else {
+            s
+        }
+    );
+}
+
+/// Iterate over a range of numbers according to a template
+///
+/// Both ends of the range are included, and it counts down if end is smaller than start
+/// Every iteration can use the `loop` variable, see loop_var
+/// Usage:
+/// ```YAML
+/// !REPEAT [
+///   i,                    # Name to bind each number to
+///   1,                    # First number
+///   12,                   # Last number
+///   2,                    # Step between numbers, optional
+///   "<li>{i}</li>",       # Template for values to be inserted into
+/// ]
+/// ```
+pub fn repeat(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    info!(target.borrow().o, "Looping into !REPEAT directive...");
+    match &tv.value {
+        Value::Sequence(args) if args.len() == 4 || args.len() == 5 => 'invalid_repeat: {
+            let name = parse_value!(target, &args[0], dir.clone());
+            let number = |v: &Value| parse_value!(target, v, dir.clone()).trim().parse::<i64>();
+            let (start, end) = match (number(&args[1]), number(&args[2])) {
+                (Ok(s), Ok(e)) => (s, e),
+                _ => break 'invalid_repeat,
+            };
+            let step = match args.len() {
+                5 => match number(&args[3]) {
+                    Ok(s) if s != 0 => s.abs(),
+                    _ => break 'invalid_repeat,
+                },
+                _ => 1,
+            };
+            let step = if end < start { -step } else { step };
+
+            let length = ((end as i128 - start as i128) / step as i128 + 1) as usize;
+            // iterate stops once the budget runs out, numbers past that would only take up memory
+            let limit = match target.borrow().o.max_iterations {
+                0 => usize::MAX,
+                n => n + 1,
+            };
+            let mut numbers = Vec::new();
+            let mut i = start;
+            while numbers.len() < limit && ((step > 0 && i <= end) || (step < 0 && i >= end)) {
+                numbers.push(i);
+                i = match i.checked_add(step) {
+                    Some(next) => next,
+                    None => break,
+                };
+            }
+            iterate(
+                target,
+                &numbers,
+                args.last().unwrap(),
+                dir.clone(),
+                |child, index, i| {
+                    child
+                        .borrow_mut()
+                        .set_var("loop".into(), loop_var(index, length));
+                    child
+                        .borrow_mut()
+                        .set_var(name.clone(), Var::Str(i.to_string().into()));
+                },
+            );
+            return;
+        }
+        _ => (),
+    }
+    error!(
+        target.borrow().o,
+        r#"Invalid arguments to !REPEAT directive: "{}""#,
+        value_tostring(&tv.value)
+    );
+}
+
+/// Fields of !SEO, in the order their tags are written
+const SEO_FIELDS: [&str; 7] = [
+    "title",
+    "description",
+    "image",
+    "url",
+    "type",
+    "site_name",
+    "twitter",
+];
+
+/// Generate Open Graph, Twitter card and JSON-LD metadata for the head of a page
+///
+/// Fields that are not given are taken from the variable of the same name, if it is defined.
+/// Only the fields that have a value produce tags
+/// Usage:
+/// ```YAML
+/// head:
+///   - !SEO {title: My Page, description: About me, image: /me.png, url: 'https://example.com/', type: website, site_name: Me, twitter: '@me'}
+///   - !SEO # uses {title}, {description}, {image}, ...
+/// ```
+pub fn seo(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let given = match &tv.value {
+        Value::Null => None,
+        Value::Mapping(m) => Some(m),
+        _ => {
+            error!(
+                target.borrow().o,
+                r#"Invalid arguments to !SEO directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+    };
+    let mut fields = HashMap::<&str, Box<str>>::new();
+    for name in SEO_FIELDS {
+        let val = match given.and_then(|m| m.get(name)) {
+            Some(v) => Some(parse_value!(target, v, dir.clone())),
+            None => {
+                let node = target.borrow();
+                node.lookup_var(name).map(|v| node.resolve_var(name, &v))
+            }
+        };
+        match val {
+            Some(v) if v.len() != 0 => {
+                fields.insert(name, v);
+            }
+            _ => (),
+        }
+    }
+
+    let mut html = String::new();
+    let mut tag = |attr: &str, key: &str, field: &str| match fields.get(field) {
+        Some(v) => {
+            html += &format!(
+                r#"<meta {attr}="{key}" content="{v}">"#,
+                v = escape_attribute(v)
+            )
+        }
+        None => (),
+    };
+    tag("name", "description", "description");
+    tag("property", "og:title", "title");
+    tag("property", "og:description", "description");
+    tag("property", "og:image", "image");
+    tag("property", "og:url", "url");
+    tag("property", "og:type", "type");
+    tag("property", "og:site_name", "site_name");
+    tag("name", "twitter:title", "title");
+    tag("name", "twitter:description", "description");
+    tag("name", "twitter:image", "image");
+    tag("name", "twitter:site", "twitter");
+    html += &format!(
+        r#"<meta name="twitter:card" content="{}">"#,
+        if fields.contains_key("image") {
+            "summary_large_image"
+        } else {
+            "summary"
+        }
+    );
+
+    // JSON-LD
+    let mut ld = serde_json::Map::new();
+    ld.insert("@context".into(), "https://schema.org".into());
+    ld.insert(
+        "@type".into(),
+        match fields.get("type").map(|t| &t[..]) {
+            Some("article") => "Article",
+            _ => "WebPage",
+        }
+        .into(),
+    );
+    for (field, key) in [
+        ("title", "name"),
+        ("description", "description"),
+        ("image", "image"),
+        ("url", "url"),
+    ] {
+        match fields.get(field) {
+            Some(v) => {
+                ld.insert(key.into(), v.to_string().into());
+            }
+            None => (),
+        }
+    }
+    // a closing tag inside of the JSON would end the script early
+    html += &format!(
+        r#"<script type="application/ld+json">{}</script>"#,
+        serde_json::Value::Object(ld)
+            .to_string()
+            .replace("</", r"<\/")
+    );
+
+    let p = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
+    p.borrow_mut().set_parent(target.clone());
+    p.borrow_mut().add_content_unparsed(html.into());
+    target.borrow_mut().add_child(p);
+}
+
+/// Escape text for use inside of a double quoted HTML attribute
+fn escape_attribute(s: &str) -> String {
+    return s
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+}
+
+/// Insert the date of the build, formatted with a strftime format string (default `%Y-%m-%d`)
+///
+/// The date is in UTC and taken from SOURCE_DATE_EPOCH if it is set, so reproducible builds stay reproducible
+/// Usage:
+/// ```YAML
+/// !DATE
+/// !DATE '%B %-d, %Y'
+/// ```
+pub fn date(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let format: Box<str> = match &tv.value {
+        Value::Null => "%Y-%m-%d".into(),
+        v => parse_value!(target, v, dir),
+    };
+    let o = target.borrow().o.clone();
+    let items = match StrftimeItems::new(&format).parse() {
+        Ok(items) => items,
+        Err(e) => {
+            error!(o, r#"Invalid format "{format}" for !DATE directive | {e}"#);
+            return;
+        }
+    };
+    match DateTime::from_timestamp(o.build_time, 0) {
+        Some(t) => {
+            let p = Arc::new(NodeCell::new(PageNode::new(o.clone())));
+            p.borrow_mut().set_parent(target.clone());
+            p.borrow_mut()
+                .add_content_unparsed(t.format_with_items(items.iter()).to_string().into());
+            target.borrow_mut().add_child(p);
+        }
+        None => error!(o, "Build time {} is out of range", o.build_time),
+    }
+}
+
+/// List the files in a directory as a table of links with their size and modification date, like for a downloads page
+///
+/// The directory is in the input directory, and its files are linked where !COPY_DIR puts them.
+/// With output, the directory is in the output directory instead, which only lists what was written before the page was built.
+/// Files starting with a dot and subdirectories are left out.
+/// Reproducible builds date files by their last commit, or by the build time if there is none, instead of when they were modified on disk
+/// Usage:
+/// ```YAML
+/// !TABLEOFFILES /downloads
+/// !TABLEOFFILES [releases, output]
+/// ```
+pub fn table_of_files(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let o = target.borrow().o.clone();
+    let args: Vec<Box<str>> = match &tv.value {
+        Value::Sequence(s) => s
+            .iter()
+            .map(|v| parse_value!(target, v, dir.clone()))
+            .collect(),
+        v => vec![parse_value!(target, v, dir.clone())],
+    };
+    let (listed, in_output) = match &args[..] {
+        [d] if d.len() != 0 => (d, false),
+        [d, place] if &place[..] == "output" => (d, true),
+        _ => {
+            error!(
+                o,
+                r#"Invalid arguments to !TABLEOFFILES directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+    };
+    let (path, root) = match in_output {
+        true if listed.split('/').any(|c| c == "..") => {
+            error!(
+                o,
+                "Unable to list {listed}, it is outside of the output directory"
+            );
+            return;
+        }
+        true => (paths::join_slash(&o.output, listed), o.output.clone()),
+        false => match resolve_input_path(target.clone(), listed, dir.clone()) {
+            Ok(p) => (p, o.input.clone()),
+            Err(e) => {
+                error!(o, "{e}");
+                return;
+            }
+        },
+    };
+    let mut files: Vec<(PathBuf, fs::Metadata)> = match fs::read_dir(&path) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+            .filter_map(|e| Some((e.path(), e.metadata().ok()?)))
+            .filter(|(_, m)| m.is_file())
+            .collect(),
+        Err(e) => {
+            error!(o, r#"Unable to list "{f}" | {e}"#, f = path.display());
+            return;
+        }
+    };
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let base = o.base_url.as_deref().unwrap_or("").trim_end_matches('/');
+    let mut rows = String::new();
+    for (file, meta) in files.iter() {
+        if !in_output {
+            PageNode::add_dependency(target.clone(), file.clone());
+        }
+        let url = match file.strip_prefix(&root) {
+            Ok(r) => format!("{base}/{}", paths::to_slash(r)),
+            Err(_) => continue,
+        };
+        let modified = match o.reproducible {
+            true => {
+                let commit = match in_output {
+                    true => None,
+                    false => o.git_history.commits(&o, file).first().map(|c| c.time),
+                };
+                DateTime::from_timestamp(commit.unwrap_or(o.build_time), 0)
+            }
+            false => meta.modified().ok().map(DateTime::<chrono::Utc>::from),
+        };
+        let modified = modified.map_or("".into(), |t| {
+            format!(
+                r#"<time datetime="{}">{}</time>"#,
+                t.format("%Y-%m-%dT%H:%M:%SZ"),
+                t.format("%Y-%m-%d")
+            )
+        });
+        rows += &format!(
+            r#"<tr><td><a href="{url}">{name}</a></td><td>{size}</td><td>{modified}</td></tr>"#,
+            url = escape_attribute(&url),
+            name = escape_attribute(&file.file_name().unwrap_or_default().to_string_lossy()),
+            size = human_size(meta.len())
+        );
+    }
+    let p = Arc::new(NodeCell::new(PageNode::new(o)));
+    p.borrow_mut().set_parent(target.clone());
+    p.borrow_mut().add_content_unparsed(
+        format!(
+            r#"<table class="table-of-files"><thead><tr><th>Name</th><th>Size</th><th>Modified</th></tr></thead><tbody>{rows}</tbody></table>"#
+        )
+        .into(),
+    );
+    target.borrow_mut().add_child(p);
+}
+
+/// List the people who changed a file in its git history, with the number of commits each made
+///
+/// Without a file it lists those of the page being built. Whoever made the most commits comes first,
+/// and files outside of a repository or without commits list nobody
+/// Usage:
+/// ```YAML
+/// footer: [h3: Contributors, !CONTRIBUTORS]
+/// aside: !CONTRIBUTORS /docs/install.page
+/// ```
+pub fn contributors(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let o = target.borrow().o.clone();
+    let path = parse_value!(target, &tv.value, dir.clone());
+    let file = match &path[..] {
+        "" => match target
+            .borrow()
+            .lookup_var("page")
+            .as_ref()
+            .map(|p| p.get_path(".source"))
+        {
+            Some(Some(Var::Str(source))) => paths::join_slash(&o.input, source),
+            _ => {
+                error!(o, "!CONTRIBUTORS without a file can only be used in a page");
+                return;
+            }
+        },
+        path => match resolve_input_path(target.clone(), path, dir) {
+            Ok(f) => {
+                PageNode::add_dependency(target.clone(), f.clone());
+                f
+            }
+            Err(e) => {
+                error!(o, "{e}");
+                return;
+            }
+        },
+    };
+    let people = git::contributors(&o.git_history.commits(&o, &file));
+    if people.len() == 0 {
+        debug!(o, "No contributors to {}", file.display());
+        return;
+    }
+    let items: String = people
+        .iter()
+        .map(|p| {
+            format!(
+                r#"<li><span class="name">{name}</span> <span class="commits">{n} {commits}</span></li>"#,
+                name = escape_attribute(&p.name),
+                n = p.commits,
+                commits = if p.commits == 1 { "commit" } else { "commits" }
+            )
+        })
+        .collect();
+    let p = Arc::new(NodeCell::new(PageNode::new(o)));
+    p.borrow_mut().set_parent(target.clone());
+    p.borrow_mut()
+        .add_content_unparsed(format!(r#"<ul class="contributors">{items}</ul>"#).into());
+    target.borrow_mut().add_child(p);
+}
+
+/// Insert a property of a file in the input directory, computed when the page is built
+///
+/// Properties:
+/// - `sha256`: hex encoded SHA-256 checksum
+/// - `size`: size in the biggest unit it has at least one of, like `1.5 MiB`
+/// - `bytes`: exact size in bytes
+/// - `mtime`: date it was last modified, in UTC
+///
+/// Usage:
+/// ```YAML
+/// p: [Checksum, code: !FILEINFO [/downloads/app.tar.gz, sha256]]
+/// p: [!FILEINFO [/downloads/app.tar.gz, size], ', ', !FILEINFO [/downloads/app.tar.gz, mtime]]
+/// ```
+pub fn file_info(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let o = target.borrow().o.clone();
+    let args: Vec<Box<str>> = match &tv.value {
+        Value::Sequence(s) => s
+            .iter()
+            .map(|v| parse_value!(target, v, dir.clone()))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let (path, property) = match &args[..] {
+        [path, property] if matches!(&property[..], "sha256" | "size" | "bytes" | "mtime") => {
+            (path, property)
+        }
+        _ => {
+            error!(
+                o,
+                r#"Invalid arguments to !FILEINFO directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+    };
+    let file = match resolve_input_path(target.clone(), path, dir.clone()) {
+        Ok(f) => f,
+        Err(e) => {
+            error!(o, "{e}");
+            return;
+        }
+    };
+    PageNode::add_dependency(target.clone(), file.clone());
+    let info = fs::metadata(&file).and_then(|meta| {
+        return Ok(match &property[..] {
+            // read in pieces, release files can be far bigger than pages
+            "sha256" => build::hash_reader(fs::File::open(&file)?)?.to_string(),
+            "size" => human_size(meta.len()),
+            "bytes" => meta.len().to_string(),
+            _ => DateTime::<chrono::Utc>::from(meta.modified()?)
+                .format("%Y-%m-%d")
+                .to_string(),
+        });
+    });
+    match info {
+        Ok(info) => {
+            let p = Arc::new(NodeCell::new(PageNode::new(o)));
+            p.borrow_mut().set_parent(target.clone());
+            p.borrow_mut().add_content_unparsed(info.into());
+            target.borrow_mut().add_child(p);
+        }
+        Err(e) => error!(o, r#"Error reading file "{f}" | {e}"#, f = file.display()),
+    }
+}
+
+/// Generate favicons in every common size from one image, and insert the link elements for them
+///
+/// Writes PNG icons, an apple-touch-icon, favicon.ico and a web manifest to the root of the output directory,
+/// or to the directory given after the image. Put it in the head of every page, the icons are only generated once
+/// Usage:
+/// ```YAML
+/// head: [!FAVICONS /assets/logo.png]
+/// head: [!FAVICONS [/assets/logo.png, /icons]]
+/// ```
+pub fn favicons(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let o = target.borrow().o.clone();
+    let (source, dest) = match &tv.value {
+        Value::Sequence(s) if s.len() == 2 => (
+            parse_value!(target, &s[0], dir.clone()),
+            parse_value!(target, &s[1], dir.clone()),
+        ),
+        Value::Sequence(_) | Value::Mapping(_) => {
+            error!(
+                o,
+                r#"Invalid arguments to !FAVICONS directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+        v => (parse_value!(target, v, dir.clone()), "/".into()),
+    };
+    let source = match resolve_input_path(target.clone(), &source, dir.clone()) {
+        Ok(f) => f,
+        Err(e) => {
+            error!(o, "{e}");
+            return;
+        }
+    };
+    let dest = match dest.trim_matches('/') {
+        "" => o.output.clone(),
+        _ => match resolve_output_path(target.clone(), &dest) {
+            Ok(d) => d,
+            Err(e) => {
+                error!(o, "{e}");
+                return;
+            }
+        },
+    };
+    PageNode::add_dependency(target.clone(), source.clone());
+    match o.favicon_cache.get(&o, &source, &dest) {
+        Ok(set) => {
+            for file in &set.files {
+                PageNode::add_artifact(target.clone(), file.clone(), Some(source.clone()));
+            }
+            let p = Arc::new(NodeCell::new(PageNode::new(o.clone())));
+            p.borrow_mut().set_parent(target.clone());
+            p.borrow_mut().add_content_unparsed(set.links.clone());
+            target.borrow_mut().add_child(p);
+        }
+        Err(e) => error!(o, "{e}"),
+    }
+}
+
+/// Insert LaTeX math converted to MathML, so formulas are displayed without any scripts
+///
+/// !MATH is part of the surrounding text, and !MATH_DISPLAY is centered on its own line.
+/// The source is taken as written, so braces are LaTeX groups instead of variables
+/// Usage:
+/// ```YAML
+/// p: [The roots are, !MATH 'x = \frac{-b \pm \sqrt{b^2 - 4ac}}{2a}']
+/// !MATH_DISPLAY |
+///   \sum_{i=1}^n i = \frac{n(n+1)}{2}
+/// ```
+pub fn math(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let latex = pattern_arg(target.clone(), &tv.value, dir);
+    match math::to_mathml(&latex, tv.tag == "!MATH_DISPLAY") {
+        Ok(ml) => {
+            let p = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
+            p.borrow_mut().set_parent(target.clone());
+            p.borrow_mut().add_content_unparsed(ml.into());
+            target.borrow_mut().add_child(p);
+        }
+        Err(e) => error!(target.borrow().o, r#"{e}: "{latex}""#),
+    }
+}
+
+/// Insert a diagram rendered to SVG by the renderer configured for its language in `diagrams` of !SITE
+///
+/// The source is taken as written, so braces are part of the diagram instead of variables.
+/// Renderings are cached by their source, see hooks::render_diagram
+/// Usage:
+/// ```YAML
+/// !DIAGRAM [dot, 'digraph { parse -> render -> write }']
+/// !DIAGRAM
+///   - mermaid
+///   - |
+///     graph LR
+///       A[Page] --> B[HTML]
+/// ```
+pub fn diagram(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let o = target.borrow().o.clone();
+    let (language, source) = match &tv.value {
+        Value::Sequence(s) if s.len() == 2 => (
+            parse_value!(target, &s[0], dir.clone()),
+            pattern_arg(target.clone(), &s[1], dir.clone()),
+        ),
+        _ => {
+            error!(
+                o,
+                r#"Invalid arguments to !DIAGRAM directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+    };
+    match hooks::render_diagram(&o, &language, &source) {
+        Ok(svg) => {
+            let p = Arc::new(NodeCell::new(PageNode::new(o)));
+            p.borrow_mut().set_parent(target.clone());
+            p.borrow_mut().add_content_unparsed(svg.into());
+            target.borrow_mut().add_child(p);
+        }
+        Err(e) => error!(o, "{e}"),
+    }
+}
+
+/// Insert a QR code for a URL or any other text
+///
+/// On its own the code is inlined as an SVG element.
+/// With an output path ending in `.png` or `.svg`, the code is written to that file and linked with an image instead
+/// Usage:
+/// ```YAML
+/// !QR https://example.com/event
+/// !QR ['{page_url}', /img/qr/{name}.png]
+/// ```
+pub fn qr(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let o = target.borrow().o.clone();
+    let (text, path) = match &tv.value {
+        Value::Sequence(s) if s.len() == 2 => (
+            parse_value!(target, &s[0], dir.clone()),
+            Some(parse_value!(target, &s[1], dir.clone())),
+        ),
+        Value::Sequence(_) | Value::Mapping(_) => {
+            error!(
+                o,
+                r#"Invalid arguments to !QR directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+        v => (parse_value!(target, v, dir.clone()), None),
+    };
+    let code = match qr::encode(&text) {
+        Ok(c) => c,
+        Err(e) => {
+            error!(o, r#"{e}: "{text}""#);
+            return;
+        }
+    };
+
+    let html = match path {
+        None => qr::svg(&code),
+        Some(path) => {
+            let dest = match resolve_output_path(target.clone(), &path) {
+                Ok(d) => d,
+                Err(e) => {
+                    error!(o, "{e}");
+                    return;
+                }
+            };
+            let data = match dest.extension().and_then(OsStr::to_str) {
+                Some("png") => qr::png(&code),
+                Some("svg") => qr::svg(&code).into_bytes(),
+                _ => {
+                    error!(
+                        o,
+                        "QR code {f} must be written to a .png or .svg file",
+                        f = dest.display()
+                    );
+                    return;
+                }
+            };
+            PageNode::add_artifact(target.clone(), dest.clone(), None);
+            if !o.dry_run && fs::read(&dest).ok().as_ref() != Some(&data) {
+                debug!(o, "Writing QR code {}", dest.display());
+                match o.writes.write(&dest, &data) {
+                    Ok(()) => (),
+                    Err(e) => {
+                        error!(o, "Error writing file {f} | {e}", f = dest.display());
+                        return;
+                    }
+                }
+            }
+            format!(
+                r#"<img class="qr" src="{}/{}" alt="{}">"#,
+                o.base_url.as_deref().unwrap_or("").trim_end_matches('/'),
+                manifest::output_key(&o, &dest),
+                escape_attribute(&text)
+            )
+        }
+    };
+    let p = Arc::new(NodeCell::new(PageNode::new(o)));
+    p.borrow_mut().set_parent(target.clone());
+    p.borrow_mut().add_content_unparsed(html.into());
+    target.borrow_mut().add_child(p);
+}
+
+/// Write a file into the output directory next to the page, like a JSON data file or an .htaccess
+///
+/// The content is rendered like the rest of the page and nothing is inserted where the directive is.
+/// Paths are resolved like those of !QR, relative ones next to the output file of the page, and the file cannot end up outside of the output directory.
+/// Braces that are not variables have to be escaped as `\{`
+/// Usage:
+/// ```YAML
+/// !WRITE_FILE [data.json, '\{"title": "{title}"\}']
+/// !WRITE_FILE [/data/{name}.json, '\{"title": "{title}"\}']
+/// !WRITE_FILE [/.htaccess, !INCLUDE htaccess.block]
+/// ```
+pub fn write_file(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    let o = target.borrow().o.clone();
+    let (path, content) = match &tv.value {
+        Value::Sequence(s) if s.len() == 2 => (
+            parse_value!(target, &s[0], dir.clone()),
+            parse_value!(target, &s[1], dir.clone()),
+        ),
+        _ => {
+            error!(
+                o,
+                r#"Invalid arguments to !WRITE_FILE directive: "{}""#,
+                value_tostring(&tv.value)
+            );
+            return;
+        }
+    };
+    let dest = match resolve_output_path(target.clone(), &path) {
+        Ok(d) => d,
+        Err(e) => {
+            error!(o, "{e}");
+            return;
+        }
+    };
+    PageNode::add_artifact(target, dest.clone(), None);
+    if !o.dry_run && fs::read(&dest).ok().as_deref() != Some(content.as_bytes()) {
+        debug!(o, "Writing {}", dest.display());
+        match o.writes.write(&dest, content.as_bytes()) {
+            Ok(()) => (),
+            Err(e) => error!(o, "Error writing file {f} | {e}", f = dest.display()),
+        }
+    }
+}
+
+/// Size of a file in bytes in the biggest unit it has at least one of, like `1.5 MiB`
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    return format!("{size:.1} {}", UNITS[unit]);
+}
+
+/// Convert a serde_yaml::Value to a String
+///
+/// For use only in debugging or error output, do not include in places where formatting is super important!
+pub fn value_tostring(val: &Value) -> String {
+    return match val {
+        Value::Null => "NULL".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => format!(r#""{}""#, s),
+        Value::Sequence(seq) => {
+            format!(
+                "[{}]",
+                seq.iter()
+                    .map(|i| value_tostring(i) + ",")
+                    .collect::<String>()
+            )
+        }
+        Value::Mapping(map) => format!(
+            "{{{}}}",
+            map.iter()
+                .map(|(k, v)| match v {
+                    Value::Sequence(_) | Value::Mapping(_) =>
+                        format!("{}:{},", value_tostring(k), value_tostring(v)),
+                    _ => format!("{}:{},", value_tostring(k), value_tostring(v)),
+                })
+                .collect::<String>()
+        ),
+        Value::Tagged(t) => format!("{} {}", t.tag, value_tostring(&t.value).as_str()),
+    };
+}
+
+/* TESTS */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, Parser};
+    use clap::Parser as ClapParser;
+    use serde_yaml::Number;
+    use std::{fs, fs::File, io::Write};
+
+    /// Ensure that combining directives does not cause issues
+    #[test]
+    fn test_directives_combined() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_combined").unwrap();
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                "/tmp/ssgen_test_source_dir_combined",
+                "-o",
+                "/tmp/",
+                "-s",
+            ])
+            .build_options(),
+        );
+
+        let mut p = Parser::new(o.clone());
+        let mut index = File::create("/tmp/ssgen_test_source_dir_combined/index.page").unwrap();
+        index
+            .write_all(
+                br#"
+- !DEF [
+    x,
+    !FOREACH [
+      [y],
+      "{y}",
+      ["a"],
+      ["b"]
+    ]
+  ]
+- p: "{x}"
+- !INCLUDE include.block
+"#,
+            )
+            .unwrap();
+
+        let mut include =
+            File::create("/tmp/ssgen_test_source_dir_combined/include.block").unwrap();
+        include
+            .write_all(
+                br#"
+- p:
+    !IF ['{x}', '{x}', "nothing"]
+- '{x}': asdf
+- !DEF [var2, thisshouldntdoathing]
+"#,
+            )
+            .unwrap();
+
+        p.parse_yaml(
+            r#"
+!INCLUDE /index.page
+"#,
+        );
+
+        assert_eq!(format!("{}", p), "<p>ab</p><p>ab</p><ab>asdf</ab>");
+    }
+
+    /// Ensure Parser can handle !FOREACH and follow its directives
+    #[test]
+    fn test_substring() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+!SUBSTRING [
+  0, 6,
+  "<div>asht</div>",
+]
+"#,
+        );
+        assert_eq!(format!("{}", p), "<div>a");
+
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+!SUBSTRING [
+  0, 626,
+  "<div>asht</div>",
+]
+"#,
+        );
+        assert_eq!(format!("{}", p), "<div>asht</div>");
+    }
+
+    /// Ensure Parser can handle !FOREACH and follow its directives
+    #[test]
+    fn test_foreach() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+!FOREACH [
+  [x],
+  "<div>{x}</div>",
+  [text1],
+  [text2],
+  [text3],
+]
+"#,
+        );
+        assert_eq!(
+            format!("{}", p),
+            "<div>text1</div><div>text2</div><div>text3</div>"
+        );
+
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+!FOREACH [
+  [x, y, z],
+  div: '{x}{y}{z}',
+  [text1, abc, 123],
+  [text2, def, 456],
+  [text3, ghi, 789],
+]
+---
+- !FOREACH [[invalid,],]
+- !FOREACH [[nonamatching, keys, length,], '', [a,],]
+- !FOREACH not a sequence
+- !FOREACH [[x], '', not a sequence,]
+"#,
+        );
+        assert_eq!(
+            format!("{}", p),
+            "<div>text1abc123</div><div>text2def456</div><div>text3ghi789</div>"
+        );
+    }
+
+    /// Ensure loops stop once a page runs out of iterations
+    #[test]
+    fn test_iteration_budget() {
+        let o = Arc::new(
+            Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s", "--max-iterations", "3"])
+                .build_options(),
+        );
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !FOREACH [[x], '{x}', [a], [b]]
+- !DEFLIST [l, [c, d, e]]
+- !FOREACH_VAR [l, x, '{x}']
+- !FOREACH [[x], '{x}', [f]]
+"#,
+        );
+        assert_eq!(format!("{}", p), "abc");
+
+        // ranges far past the limit stop at it instead of being counted out first
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml("!REPEAT [i, 1, 9000000000000, '{i}/{loop.length} ']");
+        assert_eq!(
+            format!("{}", p),
+            "1/9000000000000 2/9000000000000 3/9000000000000 "
+        );
+
+        // no limit
+        let o = Arc::new(
+            Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s", "--max-iterations", "0"])
+                .build_options(),
+        );
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml("!FOREACH [[x], '{x}', [a], [b], [c], [d]]");
+        assert_eq!(format!("{}", p), "abcd");
+    }
+
+    /// Ensure !FOREACH binds the key and value of every entry of a mapping
+    #[test]
+    fn test_foreach_mapping() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !FOREACH [
+    [label, url],
+    li: '<a href="{url}">{label}</a>',
+    {Home: /, About: /about},
+    [Blog, /blog],
+  ]
+- !FOREACH [[k, v], '{k}={v.a};', {x: {a: 1}}]
+- !FOREACH [[k], '{k}', {x: y}]
+"#,
+        );
+        assert_eq!(
+            format!("{}", p),
+            r#"<li><a href="/">Home</a></li><li><a href="/about">About</a></li><li><a href="/blog">Blog</a></li>x=1;"#
+        );
+    }
+
+    /// Ensure nested loops see outer bindings, and reach shadowed ones through outer
+    #[test]
+    fn test_nested_loops() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !FOREACH [[x], [!FOREACH [[y], '{x}{y}{outer.loop.index},', [a], ['{x}b']]], [1], [2]]
+- ';'
+- !FOREACH [[x], [!FOREACH [[x], '{x}{outer.x}', [i]]], [o]]
+- ';'
+- !FOREACH [[x], [!FOREACH [[x], [!REPEAT [x, 1, 2, '{x}{outer.x}{outer.outer.x}']], [m]]], [o]]
+- ';'
+- !DEFLIST [l, [a, b]]
+- !FOREACH [[x, y], [!FOREACH_VAR [l, y, [!FOREACH [[z], '{x}{y}{z}{outer.y}{outer.outer.y}', [z]]]]], [1, c]]
+- ';'
+- !DEF [outer, {x: 5}]
+- '{outer.x}'
+"#,
+        );
+        assert_eq!(
+            format!("{}", p),
+            "1a1,11b1,2a2,22b2,;io;1mo2mo;1azac1bzbc;5"
+        );
+    }
+
+    /// Ensure big loops fill their iterations on several threads without changing the output
+    #[test]
+    fn test_parallel_loops() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !DEF [x, a]
+- !REPEAT [i, 1, 1000, [!IF ['{loop.last}', '{i}{x}.', '{i},'], !REPEAT [j, 1, 2, '{j}']]]
+- !REPEAT [i, 1, 300, [!EXPORT [last, '{i}']]]
+- '{last}'
+"#,
+        );
+        let expected = (1..=1000)
+            .map(|i| {
+                if i == 1000 {
+                    format!("{i}a.12")
+                } else {
+                    format!("{i},12")
+                }
+            })
+            .collect::<String>();
+        assert_eq!(format!("{}", p), expected + "300");
+
+        assert_eq!(
+            is_unordered(&serde_yaml::from_str("p: [!IF [a, !DATE x]]").unwrap()),
+            true
+        );
+        assert_eq!(
+            is_unordered(&serde_yaml::from_str("p: [!IF [a, !EXPORT [a, b]]]").unwrap()),
+            false
+        );
+        assert_eq!(
+            is_unordered(&serde_yaml::from_str("p: [!INCLUDE '{i}.yaml']").unwrap()),
+            false
+        );
+        assert_eq!(
+            is_unordered(&serde_yaml::from_str("p: [!USE [card, '{i}']]").unwrap()),
+            false
+        );
+        assert_eq!(
+            is_unordered(&serde_yaml::from_str("p: [!INCLUDE_RAW '{i}.txt']").unwrap()),
+            true
+        );
+        for body in [
+            "!FETCH 'https://example.com/{i}.yaml'",
+            "!COMPONENT [card, [x], p: '{x}']",
+            "!QR ['{i}', '/qr/{i}.svg']",
+            "!FAVICONS icon.png",
+        ] {
+            assert_eq!(is_unordered(&serde_yaml::from_str(body).unwrap()), false);
+        }
+    }
+
+    /// Ensure loops can sort and filter their rows
+    #[test]
+    fn test_loop_options() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !FOREACH [{vars: [t, d], sort: d, order: desc, filter: t}, '{t}{loop.index}', [a, 2024-01-02], ['', 2024-05-06], [b, 2024-03-04], [c, 2023-12-31]]
+- ';'
+- !FOREACH [{vars: [k, v], sort: v}, '{k}', {a: 10, b: 9, c: 100}]
+- ';'
+- !DEFLIST [posts, [{title: x, n: 2}, {title: '', n: 1}, {title: y, n: 3}]]
+- !FOREACH_VAR [posts, {vars: p, sort: p.n, filter: p.title}, '{p.title}']
+- ';'
+- !FOREACH [{vars: [x], sort: y}, '{x}', [a]]
+- !FOREACH [{vars: [x], order: sideways}, '{x}', [a]]
+- !FOREACH [{sort: x}, '{x}', [a]]
+"#,
+        );
+        assert_eq!(format!("{}", p), "b1a2c3;bac;xy;");
+    }
+
+    /// Ensure case changes only touch text and trimming only the ends
+    #[test]
+    fn test_transform() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !DEF [title, '  My Post  ']
+- !LOWERCASE '{title}'
+- ';'
+- !UPPERCASE {p: [{_class: Intro}, 'a &amp; b & c&d']}
+- ';'
+- !TRIM '{title}'
+- ';'
+- !UPPERCASE '\{x}'
+"#,
+        );
+        assert_eq!(
+            format!("{}", p),
+            "  my post  ;<p class=\"Intro\">A &amp; B & C&D</p>;My Post;{X}"
+        );
+    }
+
+    /// Ensure patterns are used as written, and literal mode matches plain text
+    #[test]
+    fn test_replace() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !DEF [new, /docs/]
+- !REPLACE ['https?://old\.example\.com/(\w+)', '{new}$1', {a: [{_href: 'http://old.example.com/faq'}, faq]}]
+- ';'
+- !REPLACE ['[?&]utm_\w+=[^&"]*', '', '<a href="/a?utm_source=x&utm_medium=y">a</a>']
+- ';'
+- !REPLACE ['a.c', '$0', 'abc a.c', literal]
+- ';'
+- !REPLACE ['\d{2}', '#', 'a123b4']
+- !REPLACE ['(', '', 'a']
+- !REPLACE ['a', 'b', 'a', sideways]
+"#,
+        );
+        assert_eq!(
+            format!("{}", p),
+            "<a href=\"/docs/faq\">faq</a>;<a href=\"/a\">a</a>;abc $0;a#3b4"
+        );
+    }
+
+    /// Ensure every kind of loop can use the loop variable
+    #[test]
+    fn test_loop_var() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !FOREACH [[x], '{x}{loop.index}{loop.index0}/{loop.length}!IF', [a], [b]]
+- ';'
+- !FOREACH [[x], [!IF ['{loop.first}', '', ', '], !IF ['{loop.last}', 'and '], '{x}'], [a], [b], [c]]
+- ';'
+- !FOREACH [[k, v], '{k}{loop.index}', {a: 1, b: 2}, [c, 3]]
+- ';'
+- !DEFLIST [l, [a, b]]
+- !FOREACH_VAR [l, x, [!IF ['{loop.first}', '', '|'], '{x}']]
+- !FOREACH_VAR [l, x, '{loop.index}/{loop.length}']
+- ';'
+- !REPEAT [i, 10, 0, 5, '{loop.index}{loop.last}']
+"#,
+        );
+        assert_eq!(
+            format!("{}", p),
+            "a10/2!IFb21/2!IF;a, b, and c;a1b2c3;a|b1/22/2;123true"
+        );
+    }
+
+    /// Ensure !REPEAT counts through ranges in both directions
+    #[test]
+    fn test_repeat() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !DEF [last, 3]
+- !REPEAT [i, 1, '{last}', '{i}']
+- ','
+- !REPEAT [i, 10, 0, 5, '{i} ']
+- ','
+- !REPEAT [i, 2, 2, li: '{i}']
+- !REPEAT [i, 1, 3, 0, '{i}']
+- !REPEAT [i, a, 3, '{i}']
+"#,
+        );
+        assert_eq!(format!("{}", p), "123,10 5 0 ,<li>2</li>");
+    }
+
+    /// Ensure Parser can handle !IF and follow its directives
+    #[test]
+    fn test_if() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !DEF [x, y]
+- !IF ['{x}', z]
+- !IF ['{y}', x, q]
+- !IF [
+    '',
+    [se, qu, en, ce],
+    {p: text,},
+  ]
+- !IF [a, b, c, d, e, f, g]
+- !IF not a sequence
+"#,
+        );
+
+        assert_eq!(format!("{}", p), "zq<p>text</p>");
+    }
+
+    /// Ensure !TRY keeps what worked, falls back on errors without logging them, and still fails on its own arguments
+    #[test]
+    fn test_try() {
+        let src = "/tmp/ssgen_test_source_dir_try";
+        fs::create_dir_all(src).unwrap();
+        fs::write(format!("{src}/a.block"), "p: a").unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", src, "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.set_root_dir(PathBuf::from(src));
+        p.parse_yaml(
+            r#"
+- !TRY [!INCLUDE a.block, b]
+- !TRY [!INCLUDE missing.block, c]
+- !TRY [[d, !INCLUDE missing.block]]
+- !TRY [!DEF [e, f], '{e}']
+"#,
+        );
+        assert_eq!(format!("{}", p), "<p>a</p>c");
+        assert_eq!(o.diagnostics.counts(), (0, 0));
+
+        p.parse_yaml("!TRY [a, b, c]");
+        assert_eq!(o.diagnostics.counts(), (0, 1));
+        fs::remove_dir_all(src).unwrap();
+    }
+
+    /// Ensure conditionals on the profile and environment pick the right branch
+    #[test]
+    fn test_if_profile_env() {
+        let o = Arc::new(
+            Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s", "--profile", "staging"])
+                .build_options(),
+        );
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(&format!(
+            r#"
+- !DEF [p, staging]
+- !IF_PROFILE [production, a, b]
+- !IF_PROFILE [[preview, '{{p}}'], c]
+- !IF_ENV [PATH, d]
+- !IF_ENV [SSGEN_TEST_UNSET_VARIABLE, e, f]
+- !IF_ENV ['PATH={path}', g, h]
+- !IF_ENV [PATH=wrong, i]
+- !IF_ENV not a sequence
+"#,
+            path = env::var("PATH").unwrap()
+        ));
+        assert_eq!(format!("{}", p), "bcdfg");
+        let env_vars = p.get_env_vars();
+        assert_eq!(env_vars.len(), 2);
+        assert_eq!(env_vars["SSGEN_TEST_UNSET_VARIABLE"], None);
+        assert_eq!(
+            env_vars["PATH"].as_deref(),
+            env::var("PATH").ok().as_deref()
+        );
+
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml("[!IF_PROFILE [production, a, b], !IF_PROFILE ['', c]]");
+        assert_eq!(format!("{}", p), "b");
+    }
+
+    /// Ensure !STYLE_INLINE wraps the stylesheet in a style element, minified if asked for
+    #[test]
+    fn test_style_inline() {
+        let src = "/tmp/ssgen_test_source_dir_style_inline";
+        fs::create_dir_all(src).unwrap();
+        fs::write(
+            format!("{src}/critical.css"),
+            "/* above the fold */\nbody {\n  margin: 0;\n}\n",
+        )
+        .unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", src, "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            "[!STYLE_INLINE /critical.css, !STYLE_INLINE [critical.css, minify], !STYLE_INLINE [critical.css, x]]",
+        );
+        assert_eq!(
+            format!("{}", p),
+            "<style>/* above the fold */\nbody {\n  margin: 0;\n}</style><style>body{margin:0}</style>"
+        );
+        fs::remove_dir_all(src).unwrap();
+    }
+
+    /// Ensure !MINIFY_JS and !MINIFY_CSS inline the minified file in a script or style element
+    #[test]
+    fn test_minify_inline() {
+        let src = "/tmp/ssgen_test_source_dir_minify_inline";
+        fs::create_dir_all(src).unwrap();
+        fs::write(
+            format!("{src}/menu.js"),
+            "// toggle the menu\nmenu.onclick = () => {\n  nav.hidden = !nav.hidden;\n};\nlog('</script>')\n",
+        )
+        .unwrap();
+        fs::write(format!("{src}/card.css"), ".card {\n  padding: 1em;\n}\n").unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", src, "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml("[!MINIFY_JS menu.js, !MINIFY_CSS /card.css, !MINIFY_JS [menu.js]]");
+        assert_eq!(
+            format!("{}", p),
+            r"<script>menu.onclick=()=>{nav.hidden=!nav.hidden;};log('<\/script>')</script><style>.card{padding:1em}</style>"
+        );
+        fs::remove_dir_all(src).unwrap();
+    }
+
+    /// Ensure files matching an asset hook are compiled by it when copied or fingerprinted
+    #[test]
+    #[cfg(unix)]
+    fn test_asset_hooks() {
+        let src = "/tmp/ssgen_test_source_dir_asset_hooks";
+        let dest = "/tmp/ssgen_test_dest_dir_asset_hooks";
+        fs::create_dir_all(format!("{src}/ts")).unwrap();
+        fs::create_dir_all(dest).unwrap();
+        fs::write(format!("{src}/ts/app.ts"), "let a: number = 1;").unwrap();
+        let mut o = Args::parse_from(["", "-i", src, "-o", dest, "-s", "-e"]).build_options();
+        o.asset_hooks.push(hooks::AssetHook {
+            pattern: "*.ts".into(),
+            output: "js".into(),
+            // a stand-in for a compiler like esbuild
+            command: [
+                "sh",
+                "-c",
+                r#"sed 's/: number//' "$0" > "$1""#,
+                "{input}",
+                "{output}",
+            ]
+            .map(|a| a.into())
+            .to_vec(),
+        });
+        let mut p = Parser::new(Arc::new(o));
+        p.parse_yaml("[!COPY_DIR ts, !SCRIPT ts/app.ts]");
+        let compiled = b"let a = 1;";
+        assert_eq!(fs::read(format!("{dest}/ts/app.js")).unwrap(), compiled);
+        assert_eq!(PathBuf::from(format!("{dest}/ts/app.ts")).exists(), false);
+        let fingerprint = &build::hash(compiled)[..FINGERPRINT_LEN];
+        assert_eq!(
+            format!("{}", p).contains(&format!(r#"src="/ts/app.{fingerprint}.js""#)),
+            true
+        );
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    /// Ensure !SCRIPT and !STYLESHEET copy the file under a fingerprinted name and point to it with its integrity
+    #[test]
+    fn test_asset_tag() {
+        let src = "/tmp/ssgen_test_source_dir_asset_tag";
+        let dest = "/tmp/ssgen_test_dest_dir_asset_tag";
+        fs::create_dir_all(format!("{src}/js")).unwrap();
+        fs::create_dir_all(dest).unwrap();
+        fs::write(format!("{src}/js/app.js"), "alert(1);").unwrap();
+        fs::write(format!("{src}/main.css"), "p{}").unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", src, "-o", dest, "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            "[!SCRIPT [/js/app.js, {type: module, defer: true, async: false}], !STYLESHEET main.css]",
+        );
+        let app = &build::hash(b"alert(1);")[..FINGERPRINT_LEN];
+        let main = &build::hash(b"p{}")[..FINGERPRINT_LEN];
+        assert_eq!(
+            format!("{}", p),
+            format!(
+                r#"<script src="/js/app.{app}.js" integrity="sha384-{}" crossorigin="anonymous" type="module" defer></script><link rel="stylesheet" href="/main.{main}.css" integrity="sha384-{}" crossorigin="anonymous">"#,
+                BASE64_STANDARD.encode(Sha384::digest(b"alert(1);")),
+                BASE64_STANDARD.encode(Sha384::digest(b"p{}")),
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(format!("{dest}/js/app.{app}.js")).unwrap(),
+            "alert(1);"
+        );
+        assert_eq!(p.get_artifacts().len(), 2);
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    /// Ensure !COMMENT writes a comment that cannot be ended early, and !IGNORE leaves out everything inside of it
+    #[test]
+    fn test_comment() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            "[!DEF [x, y], !COMMENT 'made by {x} --> <b>', !IGNORE [p: gone, !DEF [x, z]], p: '{x}']",
+        );
+        assert_eq!(format!("{}", p), "<!-- made by y - -> <b> --><p>y</p>");
+    }
+
+    /// Ensure !HEAD_DEFAULTS writes the usual head tags, fills in the title and lang, and leaves out empty options
+    #[test]
+    fn test_head_defaults() {
+        let mut o = Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options();
+        o.title = "Site".into();
+        o.language = "en".into();
+        let o = Arc::new(o);
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml("html: [head: [!HEAD_DEFAULTS ], body: x]");
+        assert_eq!(
+            format!("{}", p),
+            r#"<html lang="en"><head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1"><title>Site</title></head><body>x</body></html>"#
+        );
+
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            "[!DEF [title, Page], html: [_lang: fr, head: [!HEAD_DEFAULTS {viewport: '', title: '{title} | Blog', lang: de}]]]",
+        );
+        assert_eq!(
+            format!("{}", p),
+            r#"<html lang="fr"><head><meta charset="utf-8"><title>Page | Blog</title></head></html>"#
+        );
+    }
+
+    /// Ensure !NAV nests pages by directory, orders them and marks the page being built
+    #[test]
+    fn test_nav() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let page = |source: &str, title: &str, order: &str| {
+            let output = source.replace(".page", ".html");
+            let mut entry: Vec<(Box<str>, Var)> = vec![
+                ("source".into(), Var::Str(source.into())),
+                ("url".into(), Var::Str(format!("/{output}").into())),
+                ("output".into(), Var::Str(output.into())),
+                ("title".into(), Var::Str(title.into())),
+            ];
+            if order.len() != 0 {
+                entry.push(("nav_order".into(), Var::Str(order.into())));
+            }
+            return Var::Map(entry);
+        };
+        let site = Var::Map(vec![(
+            "pages".into(),
+            Var::List(vec![
+                page("docs/a.page", "A", "1"),
+                page("docs/b.page", "B & C", ""),
+                page("docs/index.page", "Docs", ""),
+                page("index.page", "Home", "1"),
+                page("other/x.page", "x", ""),
+            ]),
+        )]);
+        let mut p =
+            Parser::new_with_vars(o.clone(), HashMap::from([("site".into(), site.clone())]));
+        p.set_source("docs/b.page");
+        p.parse_yaml("[nav: !NAV, !NAV docs, !NAV missing]");
+        let docs = r#"<ul><li><a href="/docs/a.html">A</a></li><li class="active"><a href="/docs/b.html" aria-current="page">B &amp; C</a></li></ul>"#;
+        assert_eq!(
+            format!("{}", p),
+            format!(
+                r#"<nav><ul><li><a href="/index.html">Home</a></li><li class="open"><a href="/docs/index.html">Docs</a>{docs}</li><li><span>other</span><ul><li><a href="/other/x.html">x</a></li></ul></li></ul></nav>{docs}"#
+            )
+        );
+        assert_eq!(p.get_dependencies().len(), 5);
+
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml("!NAV");
+        assert_eq!(format!("{}", p), "");
+    }
+
+    /// Ensure !RELATED orders pages by shared tags and leaves out unrelated ones
+    #[test]
+    fn test_related() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let page = |name: &str, tags: &[&str]| {
+            Var::Map(vec![
+                ("source".into(), Var::Str(format!("{name}.page").into())),
+                ("url".into(), Var::Str(format!("/{name}.html").into())),
+                ("title".into(), Var::Str(name.into())),
+                (
+                    "tags".into(),
+                    Var::List(tags.iter().map(|t| Var::Str((*t).into())).collect()),
+                ),
+            ])
+        };
+        let site = Var::Map(vec![(
+            "pages".into(),
+            Var::List(vec![
+                page("self", &["rust", "web", "yaml"]),
+                page("one", &["web"]),
+                page("both", &["rust", "yaml", "go"]),
+                page("another", &["yaml"]),
+                page("none", &["go"]),
+            ]),
+        )]);
+        let mut p = Parser::new_with_vars(o.clone(), HashMap::from([("site".into(), site)]));
+        p.set_source("self.page");
+        p.parse_yaml("[!RELATED 2, !RELATED [5, r, '{loop.index}{r.title};'], !RELATED [x]]");
+        assert_eq!(
+            format!("{}", p),
+            r#"<ul><li><a href="/both.html">both</a></li><li><a href="/another.html">another</a></li></ul>1both;2another;3one;"#
+        );
+    }
+
+    /// Ensure Parser can handle !COPY or !COPY_DIR and follow its directives
+    #[test]
+    fn test_copy() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_copy/somedir").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_copy/somedir2").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_dest_dir_copy").unwrap();
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                "/tmp/ssgen_test_source_dir_copy",
+                "-o",
+                "/tmp/ssgen_test_dest_dir_copy",
+                "-s",
+            ])
+            .build_options(),
+        );
+
+        // copy a file that does not exist
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+!COPY "/somefilethatdoesnotexist"
+"#,
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/somefilethatdoesnotexist")
+                .try_exists()
+                .unwrap(),
+            false
+        );
+
+        // copy a file that should not be accessed
+        let mut p = Parser::new(o.clone());
+        let mut out = File::create("/tmp/inaccessible_file.copy").unwrap();
+        out.write_all(b"text").unwrap();
+
+        p.parse_yaml(
+            r#"
+!COPY "//etc/shadow"
+"#,
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/somefilethatdoesnotexist")
+                .try_exists()
+                .unwrap(),
+            false
+        );
+
+        // copy a file that is valid
+        let mut p = Parser::new(o.clone());
+        let mut out = File::create("/tmp/ssgen_test_source_dir_copy/valid.file").unwrap();
+        out.write_all(b"text").unwrap();
+        let mut out2 = File::create("/tmp/ssgen_test_source_dir_copy/somedir/valid2.file").unwrap();
+        out2.write_all(b"moretext").unwrap();
+        let mut out3 = File::create("/tmp/ssgen_test_source_dir_copy/somedir2/a.file").unwrap();
+        out3.write_all(b"moretext").unwrap();
+        let mut out4 = File::create("/tmp/ssgen_test_source_dir_copy/somedir2/b.file").unwrap();
+        out4.write_all(b"moretext").unwrap();
+        p.parse_yaml(
+            r#"
+- !COPY "/valid.file"
+- !COPY "somedir/valid2.file"
+- !COPY_DIR "somedir2"
+"#,
+        );
+
+        assert_eq!(
+            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/valid.file")
+                .try_exists()
+                .unwrap(),
+            true
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/somedir/valid2.file")
+                .try_exists()
+                .unwrap(),
+            true
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/somedir2/b.file")
+                .try_exists()
+                .unwrap(),
+            true
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/somedir2/b.file")
+                .try_exists()
+                .unwrap(),
+            true
+        );
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_copy").unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_copy").unwrap();
+    }
+
+    /// Ensure Parser can handle !INCLUDE and follow its directives
+    #[test]
+    fn test_include() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_include").unwrap();
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                "/tmp/ssgen_test_source_dir_include",
+                "-o",
+                "/tmp/",
+                "-s",
+            ])
+            .build_options(),
+        );
+
+        // include a file that does not exist
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+!INCLUDE /nonexistent_file.page
+"#,
+        );
+        assert_eq!(format!("{}", p), "");
+
+        // include a file that should not be accessed
+        let mut p = Parser::new(o.clone());
+        let mut out = File::create("/tmp/inaccessible_file.page").unwrap();
+        out.write_all(b"p: content").unwrap();
+
+        p.parse_yaml(
+            r#"
+!INCLUDE /../inaccessible_file.page
+"#,
+        );
+        assert_eq!(format!("{}", p), "");
+
+        // include a file that is valid
+        let mut p = Parser::new(o.clone());
+        let mut out = File::create("/tmp/ssgen_test_source_dir_include/valid_file.page").unwrap();
+        out.write_all(b"p: content").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_include/inc").unwrap();
+        let mut out2 =
+            File::create("/tmp/ssgen_test_source_dir_include/inc/another_valid_file.page").unwrap();
+        out2.write_all(b"- !INCLUDE /valid_file.page\n- !INCLUDE ../valid_file.page")
+            .unwrap();
+
+        p.parse_yaml(
+            r#"
+- !INCLUDE
+- !INCLUDE /valid_file.page
+- sep
+- !INCLUDE valid_file.page
+- !INCLUDE inc/another_valid_file.page
+- !INCLUDE_RAW valid_file.page
+"#,
+        );
+
+        assert_eq!(
+            format!("{}", p),
+            "<p>content</p>sep<p>content</p><p>content</p><p>content</p>p: content"
+        );
+
+        // include a fragment that cannot be trusted
+        let mut p = Parser::new(o.clone());
+        fs::write(
+            "/tmp/ssgen_test_source_dir_include/comment.html",
+            r#"<p onclick="steal()">Nice {post}!<script>steal()</script> <a href="javascript:steal()">me</a></p>"#,
+        )
+        .unwrap();
+        p.parse_yaml("div: !INCLUDE_HTML comment.html");
+        assert_eq!(
+            format!("{}", p),
+            r#"<div><p>Nice {post}! <a rel="noopener noreferrer">me</a></p></div>"#
+        );
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_include").unwrap();
+    }
+
+    /// Ensure fetched responses are added in every format, and only with --allow-network
+    #[test]
+    fn test_fetch() {
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                "./",
+                "-o",
+                "/tmp/",
+                "-s",
+                "--allow-network",
+                "--fetch-cache",
+                "/tmp/ssgen_test_fetch_directive",
+            ])
+            .build_options(),
+        );
+        let raw = crate::fetch::tests::serve("<b>{raw}</b>", 1);
+        let yaml = crate::fetch::tests::serve("p: '{x}'", 1);
+        let json = crate::fetch::tests::serve(r#"[{"tag": "v2"}, {"tag": "v1"}]"#, 1);
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(&format!(
+            r#"
+- !DEF [x, a]
+- div: [!FETCH '{raw}', '|']
+- !FETCH ['{yaml}', yaml]
+- !FETCH ['{json}', json, releases]
+- !FOREACH_VAR [releases, r, {{li: '{{r.tag}}'}}]
+- !FETCH ['{raw}', json]
+"#
+        ));
+        assert_eq!(
+            format!("{}", p),
+            "<div><b>{raw}</b>|</div><p>a</p><li>v2</li><li>v1</li>"
+        );
+
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(&format!("!FETCH '{raw}'"));
+        assert_eq!(format!("{}", p), "");
+
+        // stored responses are still used offline, the server is gone by now
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                "./",
+                "-o",
+                "/tmp/",
+                "-s",
+                "--offline",
+                "--fetch-cache",
+                "/tmp/ssgen_test_fetch_directive",
+            ])
+            .build_options(),
+        );
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(&format!(
+            "[!FETCH ['{raw}', raw, {{ttl: 60}}], !FETCH ['{raw}', {{ttl: soon}}]]"
+        ));
+        assert_eq!(format!("{}", p), "<b>{raw}</b>");
+        let _ = fs::remove_dir_all("/tmp/ssgen_test_fetch_directive");
+    }
+
+    /// Ensure files in template directories can be included, and stay inside of them
+    #[test]
+    fn test_template_dir() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_template").unwrap();
+        fs::create_dir_all("/tmp/ssgen_test_template_dir/cards").unwrap();
+        fs::write(
+            "/tmp/ssgen_test_template_dir/cards/card.block",
+            "div: [!INCLUDE ../title.block, '{body}']",
+        )
+        .unwrap();
+        fs::write("/tmp/ssgen_test_template_dir/title.block", "h2: '{title}'").unwrap();
+        fs::write("/tmp/ssgen_test_template_dir_secret.block", "p: secret").unwrap();
+        let o = Arc::new(
+            Args::parse_from([
+                "",
+                "-i",
+                "/tmp/ssgen_test_source_dir_template",
+                "-o",
+                "/tmp/",
+                "-s",
+                "--template-dir",
+                "lib=/tmp/ssgen_test_template_dir",
+            ])
+            .build_options(),
+        );
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !DEF [title, Card]
+- !DEF [body, text]
+- !INCLUDE '@lib/cards/card.block'
+- !INCLUDE '@lib/../ssgen_test_template_dir_secret.block'
+- !INCLUDE '@nonexistent/card.block'
+- !COPY '@lib/title.block'
+"#,
+        );
+        assert_eq!(format!("{}", p), "<div><h2>Card</h2>text</div>");
+        assert_eq!(std::path::Path::new("/tmp/title.block").exists(), false);
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_template").unwrap();
+        fs::remove_dir_all("/tmp/ssgen_test_template_dir").unwrap();
+        fs::remove_file("/tmp/ssgen_test_template_dir_secret.block").unwrap();
+    }
+
+    /// Ensure symbolic links are only included when --symlinks allows it
+    #[test]
+    #[cfg(unix)]
+    fn test_symlinks() {
+        let _ = fs::remove_dir_all("/tmp/ssgen_test_source_dir_symlinks");
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_symlinks").unwrap();
+        fs::write("/tmp/ssgen_test_source_dir_symlinks/a.block", "p: content").unwrap();
+        fs::write("/tmp/ssgen_test_outside_symlinks.block", "p: secret").unwrap();
+        std::os::unix::fs::symlink(
+            "/tmp/ssgen_test_source_dir_symlinks/a.block",
+            "/tmp/ssgen_test_source_dir_symlinks/link.block",
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            "/tmp/ssgen_test_outside_symlinks.block",
+            "/tmp/ssgen_test_source_dir_symlinks/escape.block",
+        )
+        .unwrap();
+
+        for (policy, expected) in [("follow", "<p>content</p>"), ("error", "")] {
+            let o = Arc::new(
+                Args::parse_from([
+                    "",
+                    "-i",
+                    "/tmp/ssgen_test_source_dir_symlinks",
+                    "-o",
+                    "/tmp/",
+                    "-s",
+                    "--symlinks",
+                    policy,
+                ])
+                .build_options(),
+            );
+            let mut p = Parser::new(o.clone());
+            p.parse_yaml("[!INCLUDE /link.block, !INCLUDE /escape.block]");
+            assert_eq!(format!("{}", p), expected, "{policy}");
+        }
+
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_symlinks").unwrap();
+        fs::remove_file("/tmp/ssgen_test_outside_symlinks.block").unwrap();
+    }
+
+    /// Ensure components can be defined once and used with different arguments
+    #[test]
+    fn test_component() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !DEF [site, S]
+- !COMPONENT [card, [title, body], {div: [h2: '{title}', p: '{body}']}]
+- !COMPONENT [footer, [], {footer: '{site}'}]
+- !COMPONENT [recursive, [], !USE [recursive]]
+- !USE [card, {title: A, body: '{site}'}]
+- !USE [card, {title: B}]
+- !USE [footer]
+- !USE [recursive]
+- !USE [nonexistent]
+- '{card}'
+"#,
+        );
+        assert_eq!(
+            format!("{}", p),
+            "<div><h2>A</h2><p>S</p></div><div><h2>B</h2><p/></div><footer>S</footer>"
+        );
+    }
+
+    /// Ensure Parser can handle !DEF and follow its directives
+    #[test]
+    fn test_def() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !DEF [x, y]
+- '{x}'
+- a:
+    - !DEF [x, z]
+    - '{x}'
+- [!DEF [x, w], '{x}']
+- '{x}'
+- !DEF [incorrect, size, arguments, aaaaaaa,]
+- !DEF this is not a sequence
+"#,
+        );
+
+        assert_eq!(format!("{}", p), "y<a>z</a>wy");
+    }
+
+    /// Ensure Parser can handle !DEFLIST and !FOREACH_VAR and follow their directives
+    #[test]
+    fn test_def_list() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !DEF [y, b]
+- !DEFLIST [items, [a, '{y}', [c, d]]]
+- '{items[0]}{items[1]}{items[2][1]}{items[-1][0]};'
+- !DEF [i, 1]
+- '{items[{i}]};'
+- '{items};'
+- !FOREACH_VAR [items, x, "<{x}>"]
+- ;
+- !DEFLIST [rows, [[1, one], [2, two]]]
+- !FOREACH_VAR [rows, [n, name], li: '{n}={name}']
+- !DEFLIST [not a list, a]
+- !FOREACH_VAR [y, x, '{x}']
+- !FOREACH_VAR [items, [a, b], '{a}']
+"#,
+        );
+        assert_eq!(
+            format!("{}", p),
+            "abdc;b;abcd;<a><b><cd>;<li>1=one</li><li>2=two</li>"
+        );
+    }
+
+    /// Ensure Parser can handle !DATE and uses the build time
+    #[test]
+    fn test_date() {
+        let mut o = Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options();
+        o.build_time = 86400 * 365;
+        let mut p = Parser::new(Arc::new(o));
+        p.parse_yaml(
+            r#"
+- !DATE
+- ;
+- !DATE '%B %-d, %Y'
+- !DATE '%Q invalid'
+"#,
+        );
+        assert_eq!(format!("{}", p), "1971-01-01;January 1, 1971");
+    }
+
+    /// Ensure !TABLEOFFILES lists the files of a directory in the input or output directory
+    #[test]
+    fn test_table_of_files() {
+        let src = "/tmp/ssgen_test_source_dir_table_of_files";
+        let dest = "/tmp/ssgen_test_dest_dir_table_of_files";
+        fs::create_dir_all(format!("{src}/dl/nested")).unwrap();
+        fs::create_dir_all(format!("{dest}/out")).unwrap();
+        let day = std::time::UNIX_EPOCH + std::time::Duration::from_secs(86400);
+        for (file, size) in [("dl/b.zip", 2048), ("dl/a<1>.txt", 10), ("dl/.hidden", 1)] {
+            let f = fs::File::create(format!("{src}/{file}")).unwrap();
+            f.set_len(size).unwrap();
+            f.set_modified(day).unwrap();
+        }
+        fs::File::create(format!("{dest}/out/c.txt"))
+            .unwrap()
+            .set_modified(day)
             .unwrap();
-
+        let mut o = Args::parse_from(["", "-i", src, "-o", dest, "-s"]).build_options();
+        o.base_url = Some("https://example.com/".into());
+        let mut p = Parser::new(Arc::new(o));
         p.parse_yaml(
-            r#"
-!INCLUDE /index.page
-"#,
+            "[!TABLEOFFILES /dl, !TABLEOFFILES [out, output], !TABLEOFFILES [../x, output]]",
+        );
+        let time = r#"<time datetime="1970-01-02T00:00:00Z">1970-01-02</time>"#;
+        let head = "<table class=\"table-of-files\"><thead><tr><th>Name</th><th>Size</th><th>Modified</th></tr></thead><tbody>";
+        assert_eq!(
+            format!("{}", p).split("</table>").collect::<Vec<_>>()[..2],
+            [
+                format!(
+                    r#"{head}<tr><td><a href="https://example.com/dl/a&lt;1&gt;.txt">a&lt;1&gt;.txt</a></td><td>10 B</td><td>{time}</td></tr><tr><td><a href="https://example.com/dl/b.zip">b.zip</a></td><td>2.0 KiB</td><td>{time}</td></tr></tbody>"#
+                ),
+                format!(
+                    r#"{head}<tr><td><a href="https://example.com/out/c.txt">c.txt</a></td><td>0 B</td><td>{time}</td></tr></tbody>"#
+                )
+            ]
         );
+        assert_eq!(human_size(5 * 1024 * 1024 + 1024 * 512), "5.5 MiB");
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
 
-        assert_eq!(format!("{}", p), "<p>ab</p><p>ab</p><ab>asdf</ab>");
+    /// Ensure reproducible builds date listed files by the build when they have no history, not by the filesystem
+    #[test]
+    fn test_table_of_files_reproducible() {
+        let src = "/tmp/ssgen_test_source_dir_table_of_files_reproducible";
+        let dest = "/tmp/ssgen_test_dest_dir_table_of_files_reproducible";
+        fs::create_dir_all(format!("{src}/dl")).unwrap();
+        fs::create_dir_all(format!("{dest}/out")).unwrap();
+        let day = std::time::UNIX_EPOCH + std::time::Duration::from_secs(86400);
+        for file in [format!("{src}/dl/a.txt"), format!("{dest}/out/b.txt")] {
+            fs::File::create(file).unwrap().set_modified(day).unwrap();
+        }
+        let mut o = Args::parse_from(["", "-i", src, "-o", dest, "-s"]).build_options();
+        o.reproducible = true;
+        o.build_time = 86400 * 2;
+        let mut p = Parser::new(Arc::new(o));
+        p.parse_yaml("[!TABLEOFFILES /dl, !TABLEOFFILES [out, output]]");
+        let html = format!("{}", p);
+        assert_eq!(html.contains("1970-01-02"), false);
+        assert_eq!(html.matches("1970-01-03</time>").count(), 2);
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
     }
 
-    /// Ensure Parser can handle !FOREACH and follow its directives
+    /// Ensure !MATH converts LaTeX taken as written, and leaves out LaTeX it cannot convert
     #[test]
-    fn test_substring() {
-        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
-        let mut p = Parser::new(o.clone());
+    fn test_math() {
+        let o = Arc::new(Args::parse_from(["", "-i", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o);
         p.parse_yaml(
             r#"
-!SUBSTRING [
-  0, 6,
-  "<div>asht</div>",
-]
+- !DEF [n, 2]
+- !MATH '{n}^2'
+- !MATH_DISPLAY \alpha
+- !MATH \unknown
 "#,
         );
-        assert_eq!(format!("{}", p), "<div>a");
+        assert_eq!(
+            format!("{}", p),
+            format!(
+                "{}{}",
+                math::to_mathml("{n}^2", false).unwrap(),
+                math::to_mathml("\\alpha", true).unwrap()
+            )
+        );
+    }
 
-        let mut p = Parser::new(o.clone());
+    /// Ensure !DIAGRAM inserts what the renderer wrote, with the source taken as written
+    #[test]
+    #[cfg(unix)]
+    fn test_diagram() {
+        let cache = "/tmp/ssgen_test_cache_dir_diagram";
+        let mut o =
+            Args::parse_from(["", "-i", "/tmp/", "-s", "--fetch-cache", cache]).build_options();
+        o.diagram_renderers.insert("svg".into(), vec!["cat".into()]);
+        let mut p = Parser::new(Arc::new(o));
         p.parse_yaml(
             r#"
-!SUBSTRING [
-  0, 626,
-  "<div>asht</div>",
-]
+- !DIAGRAM [svg, '<svg>{x}</svg>']
+- !DIAGRAM [dot, 'a -> b']
+- !DIAGRAM svg
 "#,
         );
-        assert_eq!(format!("{}", p), "<div>asht</div>");
+        assert_eq!(format!("{}", p), "<svg>{x}</svg>");
+        fs::remove_dir_all(cache).unwrap();
     }
 
-    /// Ensure Parser can handle !FOREACH and follow its directives
+    /// Ensure !QR inlines SVG codes, and writes image files into the output directory only
     #[test]
-    fn test_foreach() {
-        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
-        let mut p = Parser::new(o.clone());
+    fn test_qr() {
+        let dest = "/tmp/ssgen_test_dest_dir_qr";
+        fs::create_dir_all(dest).unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", "/tmp/", "-o", dest, "-s"]).build_options());
+        let mut p = Parser::new(o);
         p.parse_yaml(
             r#"
-!FOREACH [
-  [x],
-  "<div>{x}</div>",
-  [text1],
-  [text2],
-  [text3],
-]
+- !QR https://example.com
+- !QR [https://example.com/?a&b, /img/qr.png]
+- !QR [https://example.com, ../qr.png]
+- !QR [https://example.com, /qr.gif]
 "#,
         );
+        let code = qr::encode("https://example.com").unwrap();
         assert_eq!(
             format!("{}", p),
-            "<div>text1</div><div>text2</div><div>text3</div>"
+            format!(
+                r#"{}<img class="qr" src="/img/qr.png" alt="https://example.com/?a&amp;b">"#,
+                qr::svg(&code)
+            )
         );
+        assert_eq!(
+            fs::read(format!("{dest}/img/qr.png")).unwrap(),
+            qr::png(&qr::encode("https://example.com/?a&b").unwrap())
+        );
+        assert_eq!(fs::read_dir(dest).unwrap().count(), 1);
+        fs::remove_dir_all(dest).unwrap();
+    }
 
-        let mut p = Parser::new(o.clone());
+    /// Ensure !WRITE_FILE writes rendered content inside the output directory only, and leaves the page alone
+    #[test]
+    fn test_write_file() {
+        let dest = "/tmp/ssgen_test_dest_dir_write_file";
+        fs::create_dir_all(dest).unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", "/tmp/", "-o", dest, "-s"]).build_options());
+        let mut p = Parser::new(o);
         p.parse_yaml(
             r#"
-!FOREACH [
-  [x, y, z],
-  div: '{x}{y}{z}',
-  [text1, abc, 123],
-  [text2, def, 456],
-  [text3, ghi, 789],
-]
----
-- !FOREACH [[invalid,],]
-- !FOREACH [[nonamatching, keys, length,], '', [a,],]
-- !FOREACH not a sequence
-- !FOREACH [[x], '', not a sequence,]
+- !DEF [title, Hello]
+- a
+- !WRITE_FILE [/data/post.json, '\{"title": "{title}"\}']
+- !WRITE_FILE [/.htaccess, [Options -Indexes, !DEF [x, y]]]
+- !WRITE_FILE [../escape.txt, x]
+- !WRITE_FILE [/../ssgen_test_write_file_escaped.txt, x]
+- !WRITE_FILE [/data/../../ssgen_test_write_file_escaped.txt, x]
+- b
 "#,
         );
+        assert!(!Path::new("/tmp/ssgen_test_write_file_escaped.txt").exists());
+        assert_eq!(format!("{}", p), "ab");
         assert_eq!(
-            format!("{}", p),
-            "<div>text1abc123</div><div>text2def456</div><div>text3ghi789</div>"
+            fs::read_to_string(format!("{dest}/data/post.json")).unwrap(),
+            r#"{"title": "Hello"}"#
+        );
+        assert_eq!(
+            fs::read_to_string(format!("{dest}/.htaccess")).unwrap(),
+            "Options -Indexes"
         );
+        assert_eq!(fs::read_dir(dest).unwrap().count(), 2);
+        fs::remove_dir_all(dest).unwrap();
     }
 
-    /// Ensure Parser can handle !IF and follow its directives
+    /// Ensure relative paths of !WRITE_FILE are next to the output file of the page
     #[test]
-    fn test_if() {
-        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+    fn test_write_file_relative() {
+        let src = "/tmp/ssgen_test_source_dir_write_file_relative";
+        let dest = "/tmp/ssgen_test_dest_dir_write_file_relative";
+        fs::create_dir_all(format!("{src}/sub")).unwrap();
+        fs::create_dir_all(dest).unwrap();
+        fs::write(
+            format!("{src}/sub/index.page"),
+            "[!WRITE_FILE [data.json, hi], !WRITE_FILE [more/data.json, there]]",
+        )
+        .unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", src, "-o", dest, "-s"]).build_options());
+        let page = o.input.join("sub/index.page");
+        let (_, entry) = build::render_page(o.clone(), build::Meta::default(), &page, None);
+        assert_eq!(
+            fs::read_to_string(format!("{dest}/sub/data.json")).unwrap(),
+            "hi"
+        );
+        assert_eq!(
+            fs::read_to_string(format!("{dest}/sub/more/data.json")).unwrap(),
+            "there"
+        );
+        assert_eq!(entry.artifacts.contains_key("sub/data.json"), true);
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    /// Ensure !FAVICONS writes the icons once for every page using them, and inserts their links
+    #[test]
+    fn test_favicons() {
+        let src = "/tmp/ssgen_test_source_dir_favicons_directive";
+        let dest = "/tmp/ssgen_test_dest_dir_favicons_directive";
+        fs::create_dir_all(src).unwrap();
+        fs::create_dir_all(dest).unwrap();
+        fs::write(
+            format!("{src}/logo.png"),
+            qr::png(&qr::encode("x").unwrap()),
+        )
+        .unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", src, "-o", dest, "-s"]).build_options());
         let mut p = Parser::new(o.clone());
         p.parse_yaml(
             r#"
-- !DEF [x, y]
-- !IF ['{x}', z]
-- !IF ['{y}', x, q]
-- !IF [
-    '',
-    [se, qu, en, ce],
-    {p: text,},
-  ]
-- !IF [a, b, c, d, e, f, g]
-- !IF not a sequence
+- !FAVICONS logo.png
+- !FAVICONS [/logo.png, /icons]
+- !FAVICONS missing.png
 "#,
         );
+        let page = format!("{}", p);
+        assert!(page.starts_with(r#"<link rel="icon" href="/favicon.ico" sizes="48x48">"#));
+        assert!(page.contains(r#"<link rel="manifest" href="/icons/site.webmanifest">"#));
+        assert!(fs::metadata(format!("{dest}/icons/android-chrome-192x192.png")).is_ok());
+        assert_eq!(fs::read_dir(dest).unwrap().count(), 8);
 
-        assert_eq!(format!("{}", p), "zq<p>text</p>");
+        // a second page reuses the set generated for the first
+        let mut p = Parser::new(o);
+        p.parse_yaml("!FAVICONS logo.png");
+        assert!(format!("{}", p).ends_with(r#"<link rel="manifest" href="/site.webmanifest">"#));
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
     }
 
-    /// Ensure Parser can handle !COPY or !COPY_DIR and follow its directives
+    /// Ensure !CONTRIBUTORS lists the authors of the page or of the file given, with their commits
     #[test]
-    fn test_copy() {
-        fs::create_dir_all("/tmp/ssgen_test_source_dir_copy/somedir").unwrap();
-        fs::create_dir_all("/tmp/ssgen_test_source_dir_copy/somedir2").unwrap();
-        fs::create_dir_all("/tmp/ssgen_test_dest_dir_copy").unwrap();
-        let o = Arc::new(
-            Args::parse_from([
-                "",
-                "-i",
-                "/tmp/ssgen_test_source_dir_copy",
-                "-o",
-                "/tmp/ssgen_test_dest_dir_copy",
-                "-s",
-            ])
-            .build_options(),
+    fn test_contributors() {
+        let src = "/tmp/ssgen_test_source_dir_contributors";
+        fs::create_dir_all(src).unwrap();
+        let repo = git2::Repository::init(src).unwrap();
+        let mut parent = None;
+        for (author, text) in [("Ada", "1"), ("Bob & Co", "2"), ("Ada", "3")] {
+            fs::write(format!("{src}/a.page"), text).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(std::path::Path::new("a.page")).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let signature = git2::Signature::now(author, &format!("{}@x", &author[..1])).unwrap();
+            let parents: Vec<&git2::Commit> = parent.iter().collect();
+            let id = repo
+                .commit(Some("HEAD"), &signature, &signature, "x", &tree, &parents)
+                .unwrap();
+            parent = Some(repo.find_commit(id).unwrap());
+        }
+        fs::write(format!("{src}/new.page"), "").unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", src, "-o", "/tmp/", "-s"]).build_options());
+        let page = Var::Map(vec![("source".into(), Var::Str("a.page".into()))]);
+        let mut p = Parser::new_with_vars(o.clone(), HashMap::from([("page".into(), page)]));
+        p.parse_yaml("[!CONTRIBUTORS, !CONTRIBUTORS /new.page]");
+        assert_eq!(
+            format!("{}", p),
+            concat!(
+                r#"<ul class="contributors"><li><span class="name">Ada</span> <span class="commits">2 commits</span></li>"#,
+                r#"<li><span class="name">Bob &amp; Co</span> <span class="commits">1 commit</span></li></ul>"#
+            )
         );
 
-        // copy a file that does not exist
-        let mut p = Parser::new(o.clone());
+        let mut p = Parser::new(o);
+        p.parse_yaml("!CONTRIBUTORS");
+        assert_eq!(format!("{}", p), "");
+        fs::remove_dir_all(src).unwrap();
+    }
+
+    /// Ensure !FILEINFO gives the checksum, size and modification date of a file
+    #[test]
+    fn test_file_info() {
+        let src = "/tmp/ssgen_test_source_dir_file_info";
+        fs::create_dir_all(src).unwrap();
+        let data = "x".repeat(3000);
+        fs::write(format!("{src}/app.tar.gz"), &data).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(format!("{src}/app.tar.gz"))
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH)
+            .unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", src, "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o);
         p.parse_yaml(
             r#"
-!COPY "/somefilethatdoesnotexist"
+- !FILEINFO [app.tar.gz, sha256]
+- ;
+- !FILEINFO [/app.tar.gz, size]
+- ;
+- !FILEINFO [app.tar.gz, bytes]
+- ;
+- !FILEINFO [app.tar.gz, mtime]
+- !FILEINFO [app.tar.gz, md5]
+- !FILEINFO app.tar.gz
 "#,
         );
         assert_eq!(
-            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/somefilethatdoesnotexist")
-                .try_exists()
-                .unwrap(),
-            false
+            format!("{}", p),
+            format!("{};2.9 KiB;3000;1970-01-01", build::hash(data.as_bytes()))
         );
+        fs::remove_dir_all(src).unwrap();
+    }
 
-        // copy a file that should not be accessed
+    /// Ensure Parser can handle !SEO and generate metadata from arguments and variables
+    #[test]
+    fn test_seo() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
         let mut p = Parser::new(o.clone());
-        let mut out = File::create("/tmp/inaccessible_file.copy").unwrap();
-        out.write_all(b"text").unwrap();
-
         p.parse_yaml(
             r#"
-!COPY "//etc/shadow"
+- !DEF [title, 'A "quoted" </script> title']
+- !SEO {description: '{title}!', type: article}
+- ;
+- !SEO
+- !SEO not a mapping
 "#,
         );
         assert_eq!(
-            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/somefilethatdoesnotexist")
-                .try_exists()
-                .unwrap(),
-            false
+            format!("{}", p),
+            concat!(
+                r#"<meta name="description" content="A &quot;quoted&quot; &lt;/script&gt; title!">"#,
+                r#"<meta property="og:title" content="A &quot;quoted&quot; &lt;/script&gt; title">"#,
+                r#"<meta property="og:description" content="A &quot;quoted&quot; &lt;/script&gt; title!">"#,
+                r#"<meta property="og:type" content="article">"#,
+                r#"<meta name="twitter:title" content="A &quot;quoted&quot; &lt;/script&gt; title">"#,
+                r#"<meta name="twitter:description" content="A &quot;quoted&quot; &lt;/script&gt; title!">"#,
+                r#"<meta name="twitter:card" content="summary">"#,
+                r#"<script type="application/ld+json">{"@context":"https://schema.org","@type":"Article","description":"A \"quoted\" <\/script> title!","name":"A \"quoted\" <\/script> title"}</script>"#,
+                ";",
+                r#"<meta property="og:title" content="A &quot;quoted&quot; &lt;/script&gt; title">"#,
+                r#"<meta name="twitter:title" content="A &quot;quoted&quot; &lt;/script&gt; title">"#,
+                r#"<meta name="twitter:card" content="summary">"#,
+                r#"<script type="application/ld+json">{"@context":"https://schema.org","@type":"WebPage","name":"A \"quoted\" <\/script> title"}</script>"#,
+            )
         );
+    }
 
-        // copy a file that is valid
+    /// Ensure Parser can handle !DEF with a mapping and access it with dots
+    #[test]
+    fn test_def_map() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
         let mut p = Parser::new(o.clone());
-        let mut out = File::create("/tmp/ssgen_test_source_dir_copy/valid.file").unwrap();
-        out.write_all(b"text").unwrap();
-        let mut out2 = File::create("/tmp/ssgen_test_source_dir_copy/somedir/valid2.file").unwrap();
-        out2.write_all(b"moretext").unwrap();
-        let mut out3 = File::create("/tmp/ssgen_test_source_dir_copy/somedir2/a.file").unwrap();
-        out3.write_all(b"moretext").unwrap();
-        let mut out4 = File::create("/tmp/ssgen_test_source_dir_copy/somedir2/b.file").unwrap();
-        out4.write_all(b"moretext").unwrap();
         p.parse_yaml(
             r#"
-- !COPY "/valid.file"
-- !COPY "somedir/valid2.file"
-- !COPY_DIR "somedir2"
+- !DEF [n, K]
+- !DEF [author, {name: '{n}', email: k@x, links: [a, {url: u}]}]
+- '{author.name} {author.email} {author.links[1].url};'
+- !DEF [site.title, exact]
+- '{site.title};'
+- !DEFLIST [people, [{name: A}, {name: B}]]
+- !FOREACH_VAR [people, p, '{p.name}']
+- ;
+- !DEF [html, {b: bold}]
+- '{html}'
 "#,
         );
-
-        assert_eq!(
-            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/valid.file")
-                .try_exists()
-                .unwrap(),
-            true
-        );
-        assert_eq!(
-            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/somedir/valid2.file")
-                .try_exists()
-                .unwrap(),
-            true
-        );
-        assert_eq!(
-            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/somedir2/b.file")
-                .try_exists()
-                .unwrap(),
-            true
-        );
-        assert_eq!(
-            PathBuf::from("/tmp/ssgen_test_dest_dir_copy/somedir2/b.file")
-                .try_exists()
-                .unwrap(),
-            true
-        );
-
-        fs::remove_dir_all("/tmp/ssgen_test_source_dir_copy").unwrap();
-        fs::remove_dir_all("/tmp/ssgen_test_dest_dir_copy").unwrap();
+        assert_eq!(format!("{}", p), "K k@x u;exact;AB;<b>bold</b>");
     }
 
-    /// Ensure Parser can handle !INCLUDE and follow its directives
+    /// Ensure Parser can handle !DEFLAZY and resolves it where it is used
     #[test]
-    fn test_include() {
-        fs::create_dir_all("/tmp/ssgen_test_source_dir_include").unwrap();
-        let o = Arc::new(
-            Args::parse_from([
-                "",
-                "-i",
-                "/tmp/ssgen_test_source_dir_include",
-                "-o",
-                "/tmp/",
-                "-s",
-            ])
-            .build_options(),
+    fn test_def_lazy() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- !DEFLAZY [full, '{first} {last}']
+- !DEF [first, A]
+- !DEF [last, B]
+- '{full};'
+- !FOREACH [[first], '{full};', [X], [Y]]
+- !DEFLAZY [self, '{self}']
+- '{self}'
+- !DEFLAZY [not text, [a, b]]
+- !DEFLAZY not a sequence
+"#,
         );
+        assert_eq!(format!("{}", p), "A B;X B;Y B;");
+    }
 
-        // include a file that does not exist
+    /// Ensure !CAPTURE renders its value once, and the HTML is reused as it is
+    #[test]
+    fn test_capture() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
         let mut p = Parser::new(o.clone());
         p.parse_yaml(
             r#"
-!INCLUDE /nonexistent_file.page
+- !DEF [name, A]
+- !CAPTURE [title, [{b: '{name}'}, ' \{x\}']]
+- !DEF [name, B]
+- h1: '{title}'
+- '{title}'
+- !CAPTURE not a sequence
 "#,
         );
-        assert_eq!(format!("{}", p), "");
+        assert_eq!(format!("{}", p), "<h1><b>A</b> {x}</h1><b>A</b> {x}");
+    }
 
-        // include a file that should not be accessed
+    /// Ensure !DEF is local to its node, including !IF branches, and !EXPORT escapes any scope
+    #[test]
+    fn test_scoping() {
+        let src = "/tmp/ssgen_test_source_dir_scoping";
+        fs::create_dir_all(src).unwrap();
+        fs::write(format!("{src}/a.block"), "[!DEF [x, include], '{x}']").unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", src, "-o", "/tmp/", "-s"]).build_options());
         let mut p = Parser::new(o.clone());
-        let mut out = File::create("/tmp/inaccessible_file.page").unwrap();
-        out.write_all(b"p: content").unwrap();
-
         p.parse_yaml(
             r#"
-!INCLUDE /../inaccessible_file.page
+- !DEF [x, outer]
+- [!DEF [x, nested], '{x}']
+- div: [!DEF [x, div], '{x}']
+- !REPEAT [i, 1, 2, [!DEF [x, '{i}'], '{x}']]
+- !FOREACH [[y], !DEF [x, '{y}'], [a]]
+- !INCLUDE a.block
+- !COMPONENT [card, [], [!DEF [x, card], '{x}']]
+- !USE [card]
+- '{x}'
 "#,
         );
-        assert_eq!(format!("{}", p), "");
+        assert_eq!(format!("{}", p), "nested<div>div</div>12includecardouter");
+        fs::remove_dir_all(src).unwrap();
 
-        // include a file that is valid
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
         let mut p = Parser::new(o.clone());
-        let mut out = File::create("/tmp/ssgen_test_source_dir_include/valid_file.page").unwrap();
-        out.write_all(b"p: content").unwrap();
-        fs::create_dir_all("/tmp/ssgen_test_source_dir_include/inc").unwrap();
-        let mut out2 =
-            File::create("/tmp/ssgen_test_source_dir_include/inc/another_valid_file.page").unwrap();
-        out2.write_all(b"- !INCLUDE /valid_file.page\n- !INCLUDE ../valid_file.page")
-            .unwrap();
-
         p.parse_yaml(
             r#"
-- !INCLUDE
-- !INCLUDE /valid_file.page
-- sep
-- !INCLUDE valid_file.page
-- !INCLUDE inc/another_valid_file.page
-- !INCLUDE_RAW valid_file.page
+- !DEF [x, outer]
+- !IF [yes, [!DEF [x, inner], '{x}']]
+- '{x}'
+- div:
+    - !IF [yes, {_class: c}]
+    - !EXPORT [y, exported]
+- '{y}'
+- !EXPORT not a sequence
 "#,
         );
+        assert_eq!(format!("{}", p), r#"innerouter<div class="c"/>exported"#);
 
-        assert_eq!(
-            format!("{}", p),
-            "<p>content</p>sep<p>content</p><p>content</p><p>content</p>p: content"
+        let o = Arc::new(
+            Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s", "--legacy-scoping"])
+                .build_options(),
         );
-
-        fs::remove_dir_all("/tmp/ssgen_test_source_dir_include").unwrap();
-    }
-
-    /// Ensure Parser can handle !DEF and follow its directives
-    #[test]
-    fn test_def() {
-        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
         let mut p = Parser::new(o.clone());
         p.parse_yaml(
             r#"
-- !DEF [x, y]
-- '{x}'
-- a:
-    - !DEF [x, z]
-    - '{x}'
-- [!DEF [x, w], '{x}']
+- !DEF [x, outer]
+- !IF [yes, [!DEF [x, inner], '{x}']]
 - '{x}'
-- !DEF [incorrect, size, arguments, aaaaaaa,]
-- !DEF this is not a sequence
 "#,
         );
-
-        assert_eq!(format!("{}", p), "y<a>z</a>wy");
+        assert_eq!(format!("{}", p), "innerinner");
     }
 
     /// Ensure Parser can handle !SHELL_CMD and follow its directives
     #[test]
+    #[cfg(unix)]
     fn test_shell_cmd() {
         let o = Arc::new(
             Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s", "--enable-shell"])
@@ -969,13 +4747,9 @@ mod tests {
         assert_eq!(value_tostring(&t), r#"!TAG "value""#);
 
         // sequence
-        let mut v: Vec<Value> = Vec::new();
-        v.push(Value::Null);
-        v.push(123.into());
-        v.push("abc".into());
-        v.push(true.into());
+        let mut v: Vec<Value> = vec![Value::Null, 123.into(), "abc".into(), true.into()];
         v.push(v.clone().into());
-        v.push(m.into());
+        v.push(m);
         v.push(t);
         assert_eq!(
             value_tostring(&Value::Sequence(v)),
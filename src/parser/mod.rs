@@ -6,27 +6,337 @@
 //! ```
 /* IMPORTS */
 use indicatif::ProgressBar;
-use serde::Deserialize;
-use serde_yaml::{value::TaggedValue, Deserializer, Mapping, Sequence, Value};
-use std::{cell::RefCell, collections::HashMap, fmt, path::PathBuf, sync::Arc};
+use regex::Regex;
+use serde_yaml::{value::TaggedValue, Mapping, Sequence, Value};
+use std::{
+    cmp::max,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
+};
 
 /* LOCAL IMPORTS */
-use crate::{debug, error, info, parse_value, warn, Options, PageNode};
+use crate::{debug, error, info, parse_value, transforms, warn, NodeCell, Options, PageNode, Var};
+mod cache;
+pub use cache::IncludeCache;
 mod directives;
+pub(crate) use directives::value_tostring;
+pub mod schema;
+use schema::{Form, Kind, Schema};
+pub(crate) mod yaml;
 
 /* PARSER */
+/// Function following a directive, given the node it is in, the tagged value and the directory of the file it was written in
+pub type Directive = fn(Arc<NodeCell>, &TaggedValue, Option<PathBuf>);
+
+/// Every directive by its tag, with the arguments it accepts
+pub const DIRECTIVES: &[(&str, Directive, Schema)] = &[
+    ("!COMMENT", directives::comment, &[Form::Any]),
+    ("!IGNORE", directives::comment, &[Form::Any]),
+    (
+        "!COMPONENT",
+        directives::component,
+        &[Form::List(&[
+            ("name", Kind::Text),
+            ("parameters", Kind::List),
+            ("body", Kind::Any),
+        ])],
+    ),
+    (
+        "!CONTRIBUTORS",
+        directives::contributors,
+        &[Form::Nothing, Form::Text("file")],
+    ),
+    (
+        "!DATE",
+        directives::date,
+        &[Form::Nothing, Form::Text("format")],
+    ),
+    (
+        "!DEF",
+        directives::def,
+        &[Form::List(&[("key", Kind::Text), ("value", Kind::Any)])],
+    ),
+    (
+        "!DEFLAZY",
+        directives::def_lazy,
+        &[Form::List(&[("key", Kind::Text), ("value", Kind::Text)])],
+    ),
+    (
+        "!DEFLIST",
+        directives::def_list,
+        &[Form::List(&[("key", Kind::Text), ("list", Kind::List)])],
+    ),
+    (
+        "!CAPTURE",
+        directives::capture,
+        &[Form::List(&[("key", Kind::Text), ("value", Kind::Any)])],
+    ),
+    (
+        "!DIAGRAM",
+        directives::diagram,
+        &[Form::List(&[
+            ("language", Kind::Text),
+            ("source", Kind::Text),
+        ])],
+    ),
+    (
+        "!EXPORT",
+        directives::export,
+        &[Form::List(&[("key", Kind::Text), ("value", Kind::Any)])],
+    ),
+    (
+        "!FAVICONS",
+        directives::favicons,
+        &[
+            Form::Text("image"),
+            Form::List(&[("image", Kind::Text), ("directory", Kind::Text)]),
+        ],
+    ),
+    (
+        "!FETCH",
+        directives::fetch,
+        &[
+            Form::Text("url"),
+            Form::List(&[
+                ("url", Kind::Text),
+                ("?format", Kind::Any),
+                ("?name", Kind::Any),
+                ("?options", Kind::Any),
+            ]),
+        ],
+    ),
+    (
+        "!FILEINFO",
+        directives::file_info,
+        &[Form::List(&[
+            ("file", Kind::Text),
+            ("property", Kind::Text),
+        ])],
+    ),
+    (
+        "!FOREACH",
+        directives::foreach,
+        &[Form::List(&[
+            ("names", Kind::Any),
+            ("template", Kind::Any),
+            ("values...", Kind::Any),
+        ])],
+    ),
+    (
+        "!FOREACH_VAR",
+        directives::foreach_var,
+        &[Form::List(&[
+            ("list", Kind::Text),
+            ("names", Kind::Any),
+            ("template", Kind::Any),
+        ])],
+    ),
+    ("!INCLUDE", directives::include, &[Form::Text("file")]),
+    ("!INCLUDE_RAW", directives::include, &[Form::Text("file")]),
+    ("!INCLUDE_HTML", directives::include, &[Form::Text("file")]),
+    ("!LOWERCASE", directives::transform, &[Form::Any]),
+    ("!UPPERCASE", directives::transform, &[Form::Any]),
+    ("!TRIM", directives::transform, &[Form::Any]),
+    ("!MATH", directives::math, &[Form::Text("latex")]),
+    ("!MATH_DISPLAY", directives::math, &[Form::Text("latex")]),
+    (
+        "!MINIFY_JS",
+        directives::minify_inline,
+        &[Form::Text("file")],
+    ),
+    (
+        "!MINIFY_CSS",
+        directives::minify_inline,
+        &[Form::Text("file")],
+    ),
+    (
+        "!NAV",
+        directives::nav,
+        &[Form::Nothing, Form::Text("directory")],
+    ),
+    (
+        "!HEAD_DEFAULTS",
+        directives::head_defaults,
+        &[Form::Nothing, Form::Map("options")],
+    ),
+    (
+        "!IF",
+        directives::if_else,
+        &[Form::List(&[
+            ("condition", Kind::Any),
+            ("then", Kind::Any),
+            ("?else", Kind::Any),
+        ])],
+    ),
+    (
+        "!IF_ENV",
+        directives::if_env,
+        &[Form::List(&[
+            ("condition", Kind::Any),
+            ("then", Kind::Any),
+            ("?else", Kind::Any),
+        ])],
+    ),
+    (
+        "!IF_PROFILE",
+        directives::if_profile,
+        &[Form::List(&[
+            ("condition", Kind::Any),
+            ("then", Kind::Any),
+            ("?else", Kind::Any),
+        ])],
+    ),
+    ("!COPY", directives::copy, &[Form::Text("path")]),
+    ("!COPY_DIR", directives::copy, &[Form::Text("path")]),
+    (
+        "!QR",
+        directives::qr,
+        &[
+            Form::Text("text"),
+            Form::List(&[("text", Kind::Text), ("file", Kind::Text)]),
+        ],
+    ),
+    (
+        "!RELATED",
+        directives::related,
+        &[
+            Form::Nothing,
+            Form::Text("count"),
+            Form::List(&[
+                ("count", Kind::Text),
+                ("?name", Kind::Text),
+                ("?body", Kind::Any),
+            ]),
+        ],
+    ),
+    (
+        "!REPEAT",
+        directives::repeat,
+        &[Form::List(&[
+            ("name", Kind::Text),
+            ("start", Kind::Text),
+            ("end", Kind::Text),
+            ("?step", Kind::Any),
+            ("template", Kind::Any),
+        ])],
+    ),
+    (
+        "!REPLACE",
+        directives::replace,
+        &[Form::List(&[
+            ("pattern", Kind::Text),
+            ("replacement", Kind::Text),
+            ("content", Kind::Any),
+            ("?mode", Kind::Text),
+        ])],
+    ),
+    (
+        "!REWRITE",
+        directives::rewrite,
+        &[Form::List(&[
+            ("pattern", Kind::Text),
+            ("replacement", Kind::Text),
+        ])],
+    ),
+    (
+        "!SCRIPT",
+        directives::asset_tag,
+        &[
+            Form::Text("file"),
+            Form::List(&[("file", Kind::Text), ("?attributes", Kind::Map)]),
+        ],
+    ),
+    (
+        "!STYLESHEET",
+        directives::asset_tag,
+        &[
+            Form::Text("file"),
+            Form::List(&[("file", Kind::Text), ("?attributes", Kind::Map)]),
+        ],
+    ),
+    (
+        "!SEO",
+        directives::seo,
+        &[Form::Nothing, Form::Map("fields")],
+    ),
+    (
+        "!SHELL_CMD",
+        directives::shell_command,
+        &[Form::List(&[
+            ("command", Kind::Text),
+            ("?arguments...", Kind::Text),
+        ])],
+    ),
+    ("!SITE", directives::site, &[Form::Map("settings")]),
+    (
+        "!STYLE_INLINE",
+        directives::style_inline,
+        &[
+            Form::Text("file"),
+            Form::List(&[("file", Kind::Text), ("?minify", Kind::Text)]),
+        ],
+    ),
+    (
+        "!SUBSTRING",
+        directives::substring,
+        &[Form::List(&[
+            ("start", Kind::Text),
+            ("end", Kind::Text),
+            ("content", Kind::Any),
+        ])],
+    ),
+    (
+        "!TABLEOFFILES",
+        directives::table_of_files,
+        &[
+            Form::Text("directory"),
+            Form::List(&[("directory", Kind::Text), ("?output", Kind::Text)]),
+        ],
+    ),
+    (
+        "!TRY",
+        directives::try_else,
+        &[Form::List(&[
+            ("attempt", Kind::Any),
+            ("?fallback", Kind::Any),
+        ])],
+    ),
+    (
+        "!USE",
+        directives::use_component,
+        &[Form::List(&[
+            ("component", Kind::Text),
+            ("?arguments", Kind::Map),
+        ])],
+    ),
+    (
+        "!WRITE_FILE",
+        directives::write_file,
+        &[Form::List(&[("file", Kind::Text), ("content", Kind::Any)])],
+    ),
+];
+
 pub struct Parser {
     /// Global Options struct
     o: Arc<Options>,
 
     /// The root node of the PageNode tree representing the entire HTML document
-    root_node: Arc<RefCell<PageNode>>,
+    root_node: Arc<NodeCell>,
 
     /// indicatif ProgressBar that gets incremented once parsing is completed
     progressbar: Option<Arc<ProgressBar>>,
 
     /// Path of initially parsed file
     root_dir: Option<PathBuf>,
+
+    /// Name of the parsed file in messages about it
+    source: Box<str>,
+
+    /// File the page is written to, if the parsed file is a whole page
+    output: Option<PathBuf>,
 }
 
 impl Parser {
@@ -34,15 +344,17 @@ impl Parser {
     pub fn new(o: Arc<Options>) -> Self {
         debug!(o, "Creating new Parser...");
         return Parser {
-            root_node: Arc::new(RefCell::new(PageNode::new(o.clone()))),
+            root_node: Arc::new(NodeCell::new(PageNode::new(o.clone()))),
             progressbar: None,
             o: o,
             root_dir: None,
+            source: "".into(),
+            output: None,
         };
     }
 
     /// Create a new Parser with variables set
-    pub fn new_with_vars(o: Arc<Options>, vars: HashMap<Box<str>, Box<str>>) -> Self {
+    pub fn new_with_vars(o: Arc<Options>, vars: HashMap<Box<str>, Var>) -> Self {
         let p = Parser::new(o);
         p.root_node.borrow_mut().override_vars(vars);
         return p;
@@ -50,15 +362,28 @@ impl Parser {
 
     /// Parse a string into the PageNode
     pub fn parse_yaml(&mut self, yaml: &str) {
+        match self.try_parse_yaml(yaml) {
+            Ok(()) => (),
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Parse a string into the PageNode, returning an error instead of panicking if it is not valid YAML
+    pub fn try_parse_yaml(&mut self, yaml: &str) -> Result<(), Box<str>> {
         debug!(self.o, "Parsing YAML...");
-        for doc in Deserializer::from_str(yaml) {
-            match Value::deserialize(doc) {
-                Ok(input) => {
-                    Parser::add_value(self.root_node.clone(), &input, self.root_dir.clone())
-                }
-                Err(e) => panic!("Error while parsing YAML: {}", e),
-            }
+        let docs = match yaml::parse(yaml) {
+            Ok(docs) => docs,
+            Err(e) => return Err(format!("Error while parsing YAML: {}", e).into()),
+        };
+        for k in docs.duplicates.iter() {
+            warn!(self.o, "Duplicate key {k}, only the last one is used");
         }
+        let docs = Arc::new(docs);
+        yaml::with_active(self.source.clone(), docs.clone(), || {
+            for input in docs.values.iter() {
+                Parser::add_value(self.root_node.clone(), input, self.root_dir.clone())
+            }
+        });
         // increment progressbar after completion
         match &self.progressbar {
             Some(pb) => {
@@ -67,6 +392,22 @@ impl Parser {
             }
             None => (),
         }
+        return Ok(());
+    }
+
+    /// Give every heading parsed so far an id, see PageNode::add_heading_ids
+    pub fn add_heading_ids(&mut self) {
+        self.root_node.borrow_mut().add_heading_ids();
+    }
+
+    /// Run the transforms chosen in the options over everything parsed so far, see transforms::apply
+    pub fn apply_transforms(&mut self) {
+        let dir = self.root_dir.clone().unwrap_or(self.o.input.clone());
+        transforms::apply(
+            &mut self.root_node.borrow_mut(),
+            &dir,
+            self.output.as_deref(),
+        );
     }
 
     /// Consume the Parser object and return its root_node
@@ -77,6 +418,26 @@ impl Parser {
         }
     }
 
+    /// Get every file read while parsing, excluding the initially parsed file
+    pub fn get_dependencies(&self) -> BTreeSet<PathBuf> {
+        return self.root_node.borrow().get_dependencies().clone();
+    }
+
+    /// Get every environment variable read while parsing, along with its value
+    pub fn get_env_vars(&self) -> BTreeMap<Box<str>, Option<Box<str>>> {
+        return self.root_node.borrow().get_env_vars().clone();
+    }
+
+    /// Get every file written into the output directory while parsing, excluding the page itself
+    pub fn get_artifacts(&self) -> BTreeMap<PathBuf, Option<PathBuf>> {
+        return self.root_node.borrow().get_artifacts().clone();
+    }
+
+    /// Get every rewrite rule registered while parsing, in order
+    pub fn get_rewrites(&self) -> Vec<(Regex, Box<str>)> {
+        return self.root_node.borrow().get_rewrites().clone();
+    }
+
     /// Add a progressbar to the struct
     pub fn add_progressbar(&mut self, pb: Arc<ProgressBar>) {
         self.progressbar = Some(pb);
@@ -88,6 +449,16 @@ impl Parser {
         self.root_dir = Some(f);
     }
 
+    /// Name the parsed file, so that messages about its directives say which file they are in
+    pub fn set_source(&mut self, name: &str) {
+        self.source = name.into();
+    }
+
+    /// Set the file the page is written to, for transforms that need its URL
+    pub fn set_output(&mut self, f: PathBuf) {
+        self.output = Some(f);
+    }
+
     /// Add a ```serde_yaml::Value``` into self
     ///
     /// Primitive `Value`s will just be converted to strings
@@ -95,8 +466,9 @@ impl Parser {
     /// - `Sequence`: Create a Pagenode for each element (except for metadata)
     /// - `Mapping`: Convert Mapping into PageNode
     /// - `TaggedValue`: Follow the !TAG directive
+    ///
     /// TODO cleanup the function
-    fn add_value(target: Arc<RefCell<PageNode>>, val: &Value, dir: Option<PathBuf>) {
+    fn add_value(target: Arc<NodeCell>, val: &Value, dir: Option<PathBuf>) {
         match val {
             // primitives just get read as strings
             Value::Null => (),
@@ -119,7 +491,7 @@ impl Parser {
     /// Create a PageNode for each element and add it as a nameless child
     /// If an element in the sequence would be metadata, instead add it to the parent's metadata
     /// This is achieved by just forwarding mappings to parse_map
-    fn parse_seq(target: Arc<RefCell<PageNode>>, seq: &Sequence, dir: Option<PathBuf>) {
+    fn parse_seq(target: Arc<NodeCell>, seq: &Sequence, dir: Option<PathBuf>) {
         for i in seq.iter() {
             let mut skip = false;
             match i {
@@ -129,13 +501,15 @@ impl Parser {
                 }
                 Value::Mapping(map) => {
                     map.iter().for_each(|(k, v)| {
+                        // invalid keys are reported once the mapping is parsed
+                        if !Parser::is_key(k) {
+                            return;
+                        }
                         let kstr = parse_value!(target, k, dir.clone());
 
                         if kstr.len() > 0 && &kstr[..1] == "_" {
                             let vstr = parse_value!(target, v, dir.clone());
-                            target
-                                .borrow_mut()
-                                .add_metadata((kstr[1..].into(), vstr.into()));
+                            target.borrow_mut().add_metadata((kstr[1..].into(), vstr));
                             skip = true;
                         }
                     });
@@ -143,7 +517,7 @@ impl Parser {
                 _ => (),
             };
             if !skip {
-                let child = Arc::new(RefCell::new(PageNode::new(target.borrow().o.clone())));
+                let child = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
                 child.borrow_mut().set_parent(target.clone());
                 target.borrow_mut().add_child(child.clone());
                 Parser::add_value(child.clone(), i, dir.clone());
@@ -152,39 +526,84 @@ impl Parser {
     }
 
     /// Create a PageNode for Mapping element and add it to target
-    fn parse_map(target: Arc<RefCell<PageNode>>, map: &Mapping, dir: Option<PathBuf>) {
+    fn parse_map(target: Arc<NodeCell>, map: &Mapping, dir: Option<PathBuf>) {
         map.iter().for_each(|(k, v)| {
+            if !Parser::is_key(k) {
+                error!(
+                    target.borrow().o,
+                    "Mapping keys must be text, numbers or booleans, skipping {}",
+                    directives::value_tostring(k)
+                );
+                return;
+            }
             let kstr = parse_value!(target, k, dir.clone());
             if kstr.len() > 0 && &kstr[..1] == "_" {
                 // leading underscore for key indicates metadata
                 let vstr = parse_value!(target, v, dir.clone());
-                target
-                    .borrow_mut()
-                    .add_metadata((kstr[1..].into(), vstr.into()));
+                target.borrow_mut().add_metadata((kstr[1..].into(), vstr));
             } else {
                 // no leading unnderscore means parse as normal data
-                let child = Arc::new(RefCell::new(PageNode::new(target.borrow().o.clone())));
+                let child = Arc::new(NodeCell::new(PageNode::new(target.borrow().o.clone())));
                 child.borrow_mut().set_parent(target.clone());
-                child.borrow_mut().set_name(kstr.into());
+                child.borrow_mut().set_name(kstr);
                 Parser::add_value(child.clone(), v, dir.clone());
                 target.borrow_mut().add_child(child.clone());
             }
         });
     }
 
+    /// Whether a value can be a mapping key
+    ///
+    /// Numbers and booleans are written as they are in YAML, and directives can produce a key.
+    /// Lists, mappings and nulls have no sensible name, so they can not be keys
+    fn is_key(k: &Value) -> bool {
+        return !matches!(k, Value::Sequence(_) | Value::Mapping(_) | Value::Null);
+    }
+
     /// Parse a TaggedValue and follow its directive
-    fn parse_tagged(target: Arc<RefCell<PageNode>>, tv: &TaggedValue, dir: Option<PathBuf>) {
+    fn parse_tagged(target: Arc<NodeCell>, tv: &TaggedValue, dir: Option<PathBuf>) {
         let tag: String = tv.tag.to_string();
-        match tag.as_str() {
-            "!DEF" => directives::def(target, tv, dir),
-            "!FOREACH" => directives::foreach(target, tv, dir),
-            "!INCLUDE" | "!INCLUDE_RAW" => directives::include(target, tv, dir),
-            "!IF" => directives::if_else(target, tv, dir),
-            "!COPY" | "!COPY_DIR" => directives::copy(target, tv, dir),
-            "!SHELL_CMD" => directives::shell_command(target, tv, dir),
-            "!SUBSTRING" => directives::substring(target, tv, dir),
+        // anything logged while following the directive, including by nodes it creates, says where it was written
+        let outer = match yaml::locate(tv) {
+            Some(source) => {
+                let o = target.borrow().o.clone();
+                target.borrow_mut().o = Arc::new(o.for_source(&source));
+                Some((target.clone(), o))
+            }
+            None => None,
+        };
+        let timings = target.borrow().o.timings.clone();
+        let start = Instant::now();
+        match DIRECTIVES.iter().find(|(t, _, _)| *t == tag) {
+            Some((_, directive, schema)) => match schema::check(&tag, schema, &tv.value) {
+                Ok(()) => directive(target, tv, dir),
+                Err(e) => error!(target.borrow().o, "{e}"),
+            },
             // no matching directive
-            _ => warn!(target.borrow().o, "No matching directive for {tag}"),
+            None => {
+                let o = target.borrow().o.clone();
+                let msg = match suggest_directive(&tag) {
+                    Some(known) => {
+                        format!("No matching directive for {tag}, did you mean {known}?")
+                    }
+                    None => format!("No matching directive for {tag}"),
+                };
+                match o.strict {
+                    true => {
+                        error!(o, "{msg}");
+                        o.diagnostics.fail();
+                    }
+                    false => warn!(o, "{msg}"),
+                }
+            }
+        }
+        match timings {
+            Some(t) => t.directive(&tag, start.elapsed()),
+            None => (),
+        }
+        match outer {
+            Some((node, o)) => node.borrow_mut().o = o,
+            None => (),
         }
     }
 }
@@ -196,6 +615,42 @@ impl fmt::Display for Parser {
     }
 }
 
+/// Known directive closest to tag by edit distance, ignoring case, if it is close enough to be a typo
+///
+/// Up to a third of the letters of the tag may differ, but at least two, so short tags still get a suggestion
+fn suggest_directive(tag: &str) -> Option<&'static str> {
+    let tag = tag.to_ascii_uppercase();
+    let limit = max(2, tag.chars().count() / 3);
+    return DIRECTIVES
+        .iter()
+        .map(|(known, _, _)| (edit_distance(&tag, known), *known))
+        .filter(|(d, _)| *d <= limit)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, known)| known);
+}
+
+/// Number of characters that have to be inserted, deleted, replaced or swapped with their neighbour to turn a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // rows of the table for the previous two characters of a, and the current one
+    let mut before: Vec<usize> = Vec::new();
+    let mut last: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            row[j] = (last[j] + 1).min(row[j - 1] + 1).min(last[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        before = last;
+        last = row;
+    }
+    return last[b.len()];
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -302,6 +757,15 @@ error: a: b: c: d: e
         );
     }
 
+    /// Ensure duplicate keys keep the last value instead of losing the page
+    #[test]
+    fn test_duplicate_keys() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml("div: {p: a, span: b, p: c}");
+        assert_eq!(format!("{}", p), "<div><p>c</p><span>b</span></div>");
+    }
+
     /// Ensure miscelanous tests work
     #[test]
     fn test_misc() {
@@ -317,6 +781,25 @@ error: a: b: c: d: e
         assert_eq!(format!("{}", p), "");
     }
 
+    /// Ensure numbers and booleans are keys as written, and other non-text keys are skipped
+    #[test]
+    fn test_key_types() {
+        let o = Arc::new(Args::parse_from(["", "-i", "./", "-o", "/tmp/", "-s"]).build_options());
+        let mut p = Parser::new(o.clone());
+        p.parse_yaml(
+            r#"
+- {h1: a, 2: b, 1.5: c, true: d}
+- p: [{_1: e}, f]
+- {[x, y]: g, {x: y}: h, ~: i, !LOWERCASE K: j}
+- [{[x]: k}, l]
+"#,
+        );
+        assert_eq!(
+            format!("{}", p),
+            r#"<h1>a</h1><2>b</2><1.5>c</1.5><true>d</true><p 1="e">f</p><k>j</k>l"#
+        );
+    }
+
     /// Ensure Parser can handle `Value::Mapping`
     #[test]
     fn test_map() {
@@ -379,4 +862,16 @@ html:
             r#"<html><head><meta charset="UTF-8"/></head><body><p>test</p></body></html>"#
         );
     }
+
+    /// Ensure typos of a directive suggest it, and tags that are nothing like one suggest nothing
+    #[test]
+    fn test_suggest_directive() {
+        assert_eq!(edit_distance("!FOREAHC", "!FOREACH"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(suggest_directive("!FOREAHC"), Some("!FOREACH"));
+        assert_eq!(suggest_directive("!include"), Some("!INCLUDE"));
+        assert_eq!(suggest_directive("!IF_PROFLE"), Some("!IF_PROFILE"));
+        assert_eq!(suggest_directive("!BANANA"), None);
+    }
 }
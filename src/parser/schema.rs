@@ -0,0 +1,228 @@
+//! Shapes of the arguments every directive accepts, checked before the directive runs
+//!
+//! A directive that is given something else is not run, and the error says what it expected instead of dumping its arguments.
+//! Names of list elements starting with `?` may be left out, and a name ending in `...` takes the rest of the elements
+//! ```
+//! const DEF: Schema = &[Form::List(&[("key", Kind::Text), ("value", Kind::Any)])];
+//! assert_eq!(
+//!     check("!DEF", DEF, &serde_yaml::from_str("[a, b, c, d]")?),
+//!     Err("!DEF expects [key, value], got 4 elements".into())
+//! );
+//! ```
+
+/* IMPORTS */
+use serde_yaml::Value;
+
+/* SCHEMA */
+/// Every form of arguments a directive accepts
+pub type Schema = &'static [Form];
+
+/// Element of a list of arguments, with the name it has in messages
+pub type Param = (&'static str, Kind);
+
+/// One way of giving a directive its arguments
+#[derive(Clone, Copy, Debug)]
+pub enum Form {
+    /// Anything, the directive makes sense of it itself
+    Any,
+    /// No arguments at all
+    Nothing,
+    /// A single value that is not a list or mapping, like text, a number or another directive
+    Text(&'static str),
+    /// A mapping of options
+    Map(&'static str),
+    /// A list of the given elements
+    List(&'static [Param]),
+}
+
+/// What an element of a list of arguments can be
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// Anything, like YAML that is rendered
+    Any,
+    /// Anything but a list or mapping
+    Text,
+    /// A list
+    List,
+    /// A mapping
+    Map,
+}
+
+/// Check value against the forms of schema, or say what tag expects instead
+pub fn check(tag: &str, schema: Schema, value: &Value) -> Result<(), Box<str>> {
+    if schema.iter().any(|f| matches(f, value)) {
+        return Ok(());
+    }
+    let expected = schema
+        .iter()
+        .map(describe)
+        .collect::<Vec<String>>()
+        .join(" or ");
+    // a list of the right length only fails on an element of the wrong kind, which is what to point out
+    match value {
+        Value::Sequence(s) => {
+            for form in schema {
+                match form {
+                    Form::List(params) if arity_fits(params, s.len()) => {
+                        for (i, v) in s.iter().enumerate() {
+                            let (name, kind) = param_at(params, i);
+                            if !kind_matches(kind, v) {
+                                return Err(format!(
+                                    "{tag} expects {expected}, but {name} is {got}",
+                                    name = name.trim_start_matches('?').trim_end_matches("..."),
+                                    got = element(v)
+                                )
+                                .into());
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        _ => (),
+    }
+    return Err(format!("{tag} expects {expected}, got {got}", got = given(value)).into());
+}
+
+/// Whether value is given in form
+fn matches(form: &Form, value: &Value) -> bool {
+    return match (form, value) {
+        (Form::Any, _) => true,
+        (Form::Nothing, Value::Null) => true,
+        (Form::Text(_), v) => !matches!(v, Value::Null) && kind_matches(Kind::Text, v),
+        (Form::Map(_), Value::Mapping(_)) => true,
+        (Form::List(params), Value::Sequence(s)) => {
+            arity_fits(params, s.len())
+                && s.iter()
+                    .enumerate()
+                    .all(|(i, v)| kind_matches(param_at(params, i).1, v))
+        }
+        _ => false,
+    };
+}
+
+/// Whether a list of len elements has an element for every param that has to be given, and none too many
+fn arity_fits(params: &[Param], len: usize) -> bool {
+    let required = params
+        .iter()
+        .filter(|(name, _)| !name.starts_with('?'))
+        .count();
+    let repeats = params.last().is_some_and(|(name, _)| name.ends_with("..."));
+    return len >= required && (repeats || len <= params.len());
+}
+
+/// Param the element at index i of a list stands for, the last one repeats if it takes any number of elements
+fn param_at(params: &[Param], i: usize) -> Param {
+    return params[i.min(params.len() - 1)];
+}
+
+/// Whether v is of kind
+fn kind_matches(kind: Kind, v: &Value) -> bool {
+    return match kind {
+        Kind::Any => true,
+        Kind::Text => !matches!(v, Value::Sequence(_) | Value::Mapping(_)),
+        Kind::List => v.is_sequence(),
+        Kind::Map => v.is_mapping(),
+    };
+}
+
+/// Form as it is written, like `[key, value]`
+fn describe(form: &Form) -> String {
+    return match form {
+        Form::Any => "anything".into(),
+        Form::Nothing => "nothing".into(),
+        Form::Text(name) => name.to_string(),
+        Form::Map(name) => format!("{{{name}}}"),
+        Form::List(params) => format!(
+            "[{}]",
+            params
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ),
+    };
+}
+
+/// What was given as the arguments of a directive
+fn given(v: &Value) -> String {
+    return match v {
+        Value::Null => "nothing".into(),
+        Value::Sequence(s) if s.len() == 1 => "1 element".into(),
+        Value::Sequence(s) => format!("{} elements", s.len()),
+        Value::Mapping(_) => "a mapping".into(),
+        _ => "a single value".into(),
+    };
+}
+
+/// What was given as an element of a list of arguments
+fn element(v: &Value) -> &'static str {
+    return match v {
+        Value::Sequence(_) => "a list",
+        Value::Mapping(_) => "a mapping",
+        _ => "a single value",
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure every form is told apart, and mismatches say what was expected and what was wrong
+    #[test]
+    fn test_check() {
+        let yaml = |s: &str| serde_yaml::from_str::<Value>(s).unwrap();
+        const DEF: Schema = &[Form::List(&[("key", Kind::Text), ("value", Kind::Any)])];
+        const IF: Schema = &[Form::List(&[
+            ("condition", Kind::Any),
+            ("then", Kind::Any),
+            ("?else", Kind::Any),
+        ])];
+        const COMPONENT: Schema = &[Form::List(&[
+            ("name", Kind::Text),
+            ("parameters", Kind::List),
+            ("body", Kind::Any),
+        ])];
+        const NAV: Schema = &[Form::Nothing, Form::Text("directory")];
+        const SHELL: Schema = &[Form::List(&[
+            ("command", Kind::Text),
+            ("?arguments...", Kind::Text),
+        ])];
+
+        assert_eq!(check("!DEF", DEF, &yaml("[a, {b: c}]")), Ok(()));
+        assert_eq!(
+            check("!DEF", DEF, &yaml("[a, b, c, d]")),
+            Err("!DEF expects [key, value], got 4 elements".into())
+        );
+        assert_eq!(
+            check("!DEF", DEF, &yaml("a")),
+            Err("!DEF expects [key, value], got a single value".into())
+        );
+        assert_eq!(check("!IF", IF, &yaml("[a, b]")), Ok(()));
+        assert_eq!(check("!IF", IF, &yaml("[a, b, c]")), Ok(()));
+        assert_eq!(
+            check("!IF", IF, &yaml("[a]")),
+            Err("!IF expects [condition, then, ?else], got 1 element".into())
+        );
+        assert_eq!(
+            check("!COMPONENT", COMPONENT, &yaml("[card, title, {p: x}]")),
+            Err(
+                "!COMPONENT expects [name, parameters, body], but parameters is a single value"
+                    .into()
+            )
+        );
+        assert_eq!(check("!NAV", NAV, &Value::Null), Ok(()));
+        assert_eq!(check("!NAV", NAV, &yaml("docs")), Ok(()));
+        assert_eq!(
+            check("!NAV", NAV, &yaml("{a: b}")),
+            Err("!NAV expects nothing or directory, got a mapping".into())
+        );
+        assert_eq!(check("!SHELL_CMD", SHELL, &yaml("[ls, -l, -a]")), Ok(()));
+        assert_eq!(check("!SHELL_CMD", SHELL, &yaml("[ls]")), Ok(()));
+        assert_eq!(
+            check("!SHELL_CMD", SHELL, &yaml("[ls, [a]]")),
+            Err("!SHELL_CMD expects [command, ?arguments...], but arguments is a list".into())
+        );
+    }
+}
@@ -0,0 +1,343 @@
+//! Parse YAML into Values, carrying on when a mapping has the same key twice
+//!
+//! serde_yaml refuses duplicate keys outright, which would lose the whole file over one typo.
+//! Here the last value is used, and every duplicated key is handed back so the caller can warn about it.
+//! Where each directive was written is kept as well, so that messages about it can point there
+//! ```
+//! let docs = Arc::new(yaml::parse("p: a\np: b")?);
+//! assert_eq!(docs.values.len(), 1);
+//! assert_eq!(docs.duplicates, vec!["p".into()]);
+//! yaml::with_active("index.page".into(), docs.clone(), || {
+//!     // yaml::locate(tagged) is Some("index.page:3:5") for any directive in docs
+//! });
+//! ```
+
+/* IMPORTS */
+use serde::de::{self, DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde_yaml::{
+    value::{Tag, TaggedValue},
+    Deserializer, Mapping, Sequence, Value,
+};
+use std::{cell::RefCell, collections::HashMap, fmt, sync::Arc};
+use yaml_rust2::parser::{Event, Parser};
+
+/* YAML */
+thread_local! {
+    /// Documents whose values are being added on this thread and the name of their file, innermost last
+    static ACTIVE: RefCell<Vec<(Box<str>, Arc<Documents>)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Every document of a YAML file
+pub struct Documents {
+    /// The documents, in order
+    pub values: Vec<Value>,
+
+    /// Keys that appeared more than once in the same mapping, in the order they were found
+    pub duplicates: Vec<Box<str>>,
+
+    /// Line and column of every directive in values, by the address of its TaggedValue
+    locations: HashMap<usize, (usize, usize)>,
+}
+
+/// Parse every document in yaml
+pub fn parse(yaml: &str) -> Result<Documents, serde_yaml::Error> {
+    let state = State::default();
+    let mut values = Vec::new();
+    for doc in Deserializer::from_str(yaml) {
+        values.push(ValueSeed(&state).deserialize(doc)?);
+    }
+
+    // serde_yaml does not say where anything was written, but its directives come in the same order as the tags in the source
+    let mut locations = HashMap::new();
+    let tagged = state.tagged.into_inner();
+    for ((tag, address), (source_tag, line, col)) in tagged.iter().zip(tag_positions(yaml)) {
+        // aliases are expanded by serde_yaml, after which the order no longer matches
+        if tag.trim_start_matches('!') != source_tag.trim_start_matches('!') {
+            break;
+        }
+        match address {
+            Some(a) => {
+                locations.insert(*a, (line, col));
+            }
+            None => (),
+        }
+    }
+    return Ok(Documents {
+        values: values,
+        duplicates: state.duplicates.into_inner(),
+        locations: locations,
+    });
+}
+
+/// Every tag in yaml with its line and column, in order
+fn tag_positions(yaml: &str) -> Vec<(String, usize, usize)> {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(yaml.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let mut chars = yaml.char_indices().enumerate();
+    // byte just after the previous tag, the next one cannot start before it
+    let mut searched = 0;
+    let mut positions = Vec::new();
+    let mut parser = Parser::new_from_str(yaml);
+    loop {
+        let (t, m) = match parser.next_token() {
+            Ok((Event::StreamEnd, _)) | Err(_) => break,
+            Ok((Event::Scalar(_, _, _, Some(t)), m))
+            | Ok((Event::SequenceStart(_, Some(t)), m))
+            | Ok((Event::MappingStart(_, Some(t)), m)) => (t, m),
+            Ok(_) => continue,
+        };
+        let tag = t.handle + &t.suffix;
+        // events are marked where their contents start, the tag is somewhere in front of that
+        let end = match chars.find(|(c, _)| *c == m.index()) {
+            Some((_, (b, _))) => b,
+            None => yaml.len(),
+        };
+        let (line, col) = match yaml[searched..end].rfind(tag.as_str()) {
+            Some(i) => {
+                let at = searched + i;
+                searched = at + tag.len();
+                let line = line_starts.partition_point(|s| *s <= at);
+                (line, yaml[line_starts[line - 1]..at].chars().count() + 1)
+            }
+            None => (m.line(), m.col() + 1),
+        };
+        positions.push((tag, line, col));
+    }
+    return positions;
+}
+
+/// Run f while docs are being added, so that locate can find their directives
+pub fn with_active<R>(file: Box<str>, docs: Arc<Documents>, f: impl FnOnce() -> R) -> R {
+    ACTIVE.with_borrow_mut(|a| a.push((file, docs)));
+    let r = f();
+    ACTIVE.with_borrow_mut(|a| a.pop());
+    return r;
+}
+
+/// Every file being added on this thread, to carry over to a thread helping with it, see set_active
+pub fn active() -> Vec<(Box<str>, Arc<Documents>)> {
+    return ACTIVE.with_borrow(|a| a.clone());
+}
+
+/// Continue adding the files from active on this thread
+pub fn set_active(active: Vec<(Box<str>, Arc<Documents>)>) {
+    ACTIVE.set(active);
+}
+
+/// Where tv was written as `file:line:column`, if it is part of a file being added on this thread
+///
+/// Files without a name give just `line:column`
+pub fn locate(tv: &TaggedValue) -> Option<Box<str>> {
+    let address = tv as *const TaggedValue as usize;
+    return ACTIVE.with_borrow(|a| {
+        a.iter().rev().find_map(|(file, docs)| {
+            docs.locations
+                .get(&address)
+                .map(|(line, col)| match file.is_empty() {
+                    true => format!("{line}:{col}").into(),
+                    false => format!("{file}:{line}:{col}").into(),
+                })
+        })
+    });
+}
+
+/// What was found while parsing
+#[derive(Default)]
+struct State {
+    /// Keys that appeared more than once in the same mapping
+    duplicates: RefCell<Vec<Box<str>>>,
+    /// Tag of every directive in the order they were started, and the address of its TaggedValue unless it was dropped
+    tagged: RefCell<Vec<(String, Option<usize>)>>,
+}
+
+impl State {
+    /// Stop tracking the directives in a value that was dropped, since its addresses can be reused
+    fn forget(&self, v: &Value) {
+        match v {
+            Value::Tagged(t) => {
+                let address = &**t as *const TaggedValue as usize;
+                self.tagged
+                    .borrow_mut()
+                    .iter_mut()
+                    .filter(|(_, a)| *a == Some(address))
+                    .for_each(|(_, a)| *a = None);
+                self.forget(&t.value);
+            }
+            Value::Sequence(seq) => seq.iter().for_each(|i| self.forget(i)),
+            Value::Mapping(map) => map.iter().for_each(|(k, i)| {
+                self.forget(k);
+                self.forget(i);
+            }),
+            _ => (),
+        }
+    }
+}
+
+/// Builds a Value the same way serde_yaml does, recording duplicate keys instead of failing on them
+#[derive(Clone, Copy)]
+struct ValueSeed<'a>(&'a State);
+
+impl<'de> DeserializeSeed<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        return deserializer.deserialize_any(self);
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed<'_> {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        return formatter.write_str("any YAML value");
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Value, E> {
+        return Ok(Value::Bool(b));
+    }
+
+    fn visit_i64<E: de::Error>(self, i: i64) -> Result<Value, E> {
+        return Ok(Value::Number(i.into()));
+    }
+
+    fn visit_u64<E: de::Error>(self, u: u64) -> Result<Value, E> {
+        return Ok(Value::Number(u.into()));
+    }
+
+    fn visit_f64<E: de::Error>(self, f: f64) -> Result<Value, E> {
+        return Ok(Value::Number(f.into()));
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+        return Ok(Value::String(s.to_owned()));
+    }
+
+    fn visit_string<E: de::Error>(self, s: String) -> Result<Value, E> {
+        return Ok(Value::String(s));
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        return Ok(Value::Null);
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        return Ok(Value::Null);
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        return self.deserialize(deserializer);
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut data: A) -> Result<Value, A::Error> {
+        let mut sequence = Sequence::new();
+        while let Some(v) = data.next_element_seed(self)? {
+            sequence.push(v);
+        }
+        return Ok(Value::Sequence(sequence));
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut data: A) -> Result<Value, A::Error> {
+        let mut mapping = Mapping::new();
+        while let Some(k) = data.next_key_seed(self)? {
+            let v = data.next_value_seed(self)?;
+            // the entry keeps its place and its first key, only the value is replaced
+            if mapping.contains_key(&k) {
+                self.0.duplicates.borrow_mut().push(key_name(&k));
+                self.0.forget(&k);
+            }
+            match mapping.insert(k, v) {
+                Some(old) => self.0.forget(&old),
+                None => (),
+            }
+        }
+        return Ok(Value::Mapping(mapping));
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Value, A::Error> {
+        let (tag, contents): (String, _) = data.variant()?;
+        if tag.is_empty() {
+            return Err(de::Error::custom("empty YAML tag is not allowed"));
+        }
+        // numbered before its contents, which is the order tags appear in the source
+        let index = {
+            let mut tagged = self.0.tagged.borrow_mut();
+            tagged.push((tag.clone(), None));
+            tagged.len() - 1
+        };
+        let value = contents.newtype_variant_seed(self)?;
+        let tagged = Box::new(TaggedValue {
+            tag: Tag::new(tag),
+            value: value,
+        });
+        self.0.tagged.borrow_mut()[index].1 = Some(&*tagged as *const TaggedValue as usize);
+        return Ok(Value::Tagged(tagged));
+    }
+}
+
+/// Short description of a key for warnings
+fn key_name(k: &Value) -> Box<str> {
+    return match k {
+        Value::String(s) => s.clone().into(),
+        _ => serde_yaml::to_string(k)
+            .map(|s| s.trim_end().to_string())
+            .unwrap_or_default()
+            .into(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure YAML parses like serde_yaml, apart from keeping the last of duplicate keys
+    #[test]
+    fn test_parse() {
+        let yaml = "a: [1, -2, 3.5, true, ~, !IF [x, y]]\nb: {c: d}\n---\ne";
+        let docs = parse(yaml).unwrap();
+        let expected: Vec<Value> = Deserializer::from_str(yaml)
+            .map(|d| serde::Deserialize::deserialize(d).unwrap())
+            .collect();
+        assert_eq!(docs.values, expected);
+        assert_eq!(docs.duplicates.len(), 0);
+
+        let docs = parse("p: a\ndiv: {p: b, 1: c, 1: d}\np: e").unwrap();
+        let expected: Value = serde_yaml::from_str("{p: e, div: {p: b, 1: d}}").unwrap();
+        assert_eq!(docs.values, vec![expected]);
+        assert_eq!(docs.duplicates, vec!["1".into(), "p".into()]);
+
+        assert_eq!(parse("p: [").is_err(), true);
+    }
+
+    /// Ensure directives are located where their tag was written, also in later documents
+    #[test]
+    fn test_locate() {
+        let yaml = "p: !IF [x, !DATE y]\n---\n- a\n-   !UPPERCASE {p: !TRIM z}\n- {q: !IF [a], q: !IF [b]}\n";
+        let docs = Arc::new(parse(yaml).unwrap());
+        fn tagged(v: &Value) -> &TaggedValue {
+            return match v {
+                Value::Tagged(t) => t,
+                _ => panic!("not tagged"),
+            };
+        }
+        let if_ = tagged(&docs.values[0]["p"]);
+        let date = tagged(&if_.value[1]);
+        let upper = tagged(&docs.values[1][1]);
+        let trim = tagged(&upper.value["p"]);
+        let q = tagged(&docs.values[1][2]["q"]);
+
+        assert_eq!(locate(if_), None);
+        with_active("a.page".into(), docs.clone(), || {
+            assert_eq!(locate(if_).as_deref(), Some("a.page:1:4"));
+            assert_eq!(locate(date).as_deref(), Some("a.page:1:12"));
+            assert_eq!(locate(upper).as_deref(), Some("a.page:4:5"));
+            assert_eq!(locate(trim).as_deref(), Some("a.page:4:20"));
+            // the duplicate key keeps its place but takes the last value
+            assert_eq!(locate(q).as_deref(), Some("a.page:5:19"));
+        });
+        assert_eq!(locate(if_), None);
+        with_active("".into(), docs.clone(), || {
+            assert_eq!(locate(if_).as_deref(), Some("1:4"));
+        });
+    }
+}
@@ -0,0 +1,305 @@
+//! Cross-platform path handling
+//!
+//! Paths written in pages, manifest keys and URLs always use `/` as a separator, on every platform.
+//! These helpers convert between them and native paths, which use `\` on Windows.
+//! ```
+//! let page = paths::join_slash(&o.input, "blog/post.page"); // C:\site\blog\post.page on Windows
+//! assert_eq!(&paths::to_slash(Path::new("blog").join("post.page"))[..], "blog/post.page");
+//! ```
+
+/* IMPORTS */
+use clap::ValueEnum;
+use regex::Regex;
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/* PATHS */
+/// Convert a relative path into a string with `/` separators
+pub fn to_slash(path: &Path) -> Box<str> {
+    return path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+        .into();
+}
+
+/// Join a path with `/` separators onto base, one component at a time
+///
+/// Pushing the whole string at once would keep the `/` on Windows, which verbatim paths do not allow
+pub fn join_slash(base: &Path, path: &str) -> PathBuf {
+    let mut joined = base.to_path_buf();
+    path.split('/')
+        .filter(|c| c.len() > 0)
+        .for_each(|c| joined.push(c));
+    return joined;
+}
+
+/// Whether path, with `/` separators, matches the glob pattern
+///
+/// `*` and `?` stay within one component and `**` crosses them. Patterns without a `/` are matched against the file name only
+pub fn matches_glob(pattern: &str, path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    let subject = match pattern.contains('/') {
+        true => path,
+        false => path.rsplit('/').next().unwrap_or(path),
+    };
+    let pattern = regex::escape(pattern.trim_start_matches('/'))
+        .replace(r"\*\*", ".*")
+        .replace(r"\*", "[^/]*")
+        .replace(r"\?", "[^/]");
+    return Regex::new(&format!("^{pattern}$")).is_ok_and(|r| r.is_match(subject));
+}
+
+/// Canonicalize path, leaving out the `\\?\` prefix Windows adds wherever the path works without it
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    let path = fs::canonicalize(path)?;
+    if cfg!(windows) {
+        match strip_verbatim(&path.to_string_lossy()) {
+            Some(p) => return Ok(PathBuf::from(p)),
+            None => (),
+        }
+    }
+    return Ok(path);
+}
+
+/// Turn a verbatim drive (`\\?\C:\site`) or UNC (`\\?\UNC\server\share`) path into a regular one
+fn strip_verbatim(path: &str) -> Option<String> {
+    if let Some(unc) = path.strip_prefix(r"\\?\UNC\") {
+        return Some(format!(r"\\{unc}"));
+    }
+    let rest = path.strip_prefix(r"\\?\")?;
+    let bytes = rest.as_bytes();
+    // only drive paths, anything else really needs the prefix
+    if bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && &bytes[1..3] == br":\" {
+        return Some(rest.to_string());
+    }
+    return None;
+}
+
+/// What to do with symbolic links in the input directory
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Symlinks {
+    /// Treat them like the file they point to, as long as it is in the input directory
+    Follow,
+    /// Leave them out of directory walks, using them directly is an error
+    Skip,
+    /// Any symbolic link is an error
+    Error,
+}
+
+/// Every file below dir in sorted order, or the errors reading its directories
+///
+/// Symbolic links are handled according to symlinks, and followed links never lead outside of root
+pub fn walk_files(dir: &Path, root: &Path, symlinks: Symlinks) -> Vec<io::Result<PathBuf>> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    match fs::canonicalize(dir) {
+        Ok(d) => {
+            visited.insert(d);
+        }
+        Err(e) => return vec![Err(e)],
+    }
+    walk(dir, root, symlinks, &mut visited, &mut files);
+    return files;
+}
+
+/// Recursive part of walk_files, visited holds the canonical path of every directory above the current one
+fn walk(
+    dir: &Path,
+    root: &Path,
+    symlinks: Symlinks,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<io::Result<PathBuf>>,
+) {
+    let mut entries = match fs::read_dir(dir).and_then(|d| {
+        d.map(|e| e.map(|e| e.path()))
+            .collect::<io::Result<Vec<PathBuf>>>()
+    }) {
+        Ok(entries) => entries,
+        Err(e) => return files.push(Err(e)),
+    };
+    entries.sort();
+    for path in entries {
+        let is_link = fs::symlink_metadata(&path)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false);
+        if is_link {
+            match (symlinks, symlink_target(&path, root)) {
+                (Symlinks::Skip, _) => continue,
+                (Symlinks::Error, _) => {
+                    files.push(Err(io::Error::other(format!(
+                        "{} is a symbolic link",
+                        path.display()
+                    ))));
+                    continue;
+                }
+                (Symlinks::Follow, Err(e)) => {
+                    files.push(Err(e));
+                    continue;
+                }
+                (Symlinks::Follow, Ok(_)) => (),
+            }
+        }
+        if path.is_dir() {
+            // a link back up the tree would be walked forever
+            match fs::canonicalize(&path) {
+                Ok(real) if visited.insert(real.clone()) => {
+                    walk(&path, root, symlinks, visited, files);
+                    visited.remove(&real);
+                }
+                Ok(_) => files.push(Err(io::Error::other(format!(
+                    "{} is a symbolic link loop",
+                    path.display()
+                )))),
+                Err(e) => files.push(Err(e)),
+            }
+        } else {
+            files.push(Ok(path));
+        }
+    }
+}
+
+/// Canonical path of what the link at path points to, which has to be inside root
+fn symlink_target(path: &Path, root: &Path) -> io::Result<PathBuf> {
+    let real = fs::canonicalize(path)?;
+    if !real.starts_with(fs::canonicalize(root)?) {
+        return Err(io::Error::other(format!(
+            "{} is a symbolic link to {}, outside of the input directory",
+            path.display(),
+            real.display()
+        )));
+    }
+    return Ok(real);
+}
+
+/// Whether any part of path below root is a symbolic link
+pub fn has_symlink(root: &Path, path: &Path) -> bool {
+    let relative = match path.strip_prefix(root) {
+        Ok(r) => r,
+        Err(_) => return false,
+    };
+    let mut current = root.to_path_buf();
+    for c in relative.components() {
+        current.push(c);
+        match fs::symlink_metadata(&current) {
+            Ok(m) if m.file_type().is_symlink() => return true,
+            _ => (),
+        }
+    }
+    return false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// Ensure paths with / separators are converted the same way on every platform
+    #[test]
+    fn test_slash() {
+        let native = Path::new("blog").join("2024").join("post.page");
+        assert_eq!(&to_slash(&native)[..], "blog/2024/post.page");
+        assert_eq!(
+            join_slash(Path::new("site"), "blog/2024/post.page"),
+            Path::new("site").join(&native)
+        );
+        assert_eq!(
+            join_slash(Path::new("site"), "/blog//2024/post.page/"),
+            Path::new("site").join(&native)
+        );
+    }
+
+    /// Ensure Windows drive and UNC paths lose their verbatim prefix
+    #[test]
+    fn test_strip_verbatim() {
+        assert_eq!(
+            strip_verbatim(r"\\?\C:\site\blog"),
+            Some(r"C:\site\blog".into())
+        );
+        assert_eq!(
+            strip_verbatim(r"\\?\UNC\server\share\site"),
+            Some(r"\\server\share\site".into())
+        );
+        assert_eq!(strip_verbatim(r"\\?\Volume{1234}\site"), None);
+        assert_eq!(strip_verbatim("/home/site"), None);
+    }
+
+    /// Ensure every file below a directory is found
+    #[test]
+    fn test_walk_files() {
+        let dir = env::temp_dir().join("ssgen_test_walk_files");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("b").join("c")).unwrap();
+        fs::write(dir.join("a.page"), "").unwrap();
+        fs::write(dir.join("b").join("c").join("d.page"), "").unwrap();
+
+        let files: Vec<PathBuf> = walk_files(&dir, &dir, Symlinks::Follow)
+            .into_iter()
+            .map(|f| f.unwrap())
+            .collect();
+        assert_eq!(
+            files,
+            vec![dir.join("a.page"), dir.join("b").join("c").join("d.page")]
+        );
+        assert_eq!(
+            walk_files(&dir.join("nonexistent"), &dir, Symlinks::Follow)[0].is_err(),
+            true
+        );
+        assert_eq!(canonicalize(&dir).unwrap().is_absolute(), true);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Ensure every symlink policy is respected, and links never escape the walked directory
+    #[test]
+    #[cfg(unix)]
+    fn test_walk_symlinks() {
+        use std::os::unix::fs::symlink;
+        let dir = env::temp_dir().join("ssgen_test_walk_symlinks");
+        let outside = env::temp_dir().join("ssgen_test_walk_symlinks_outside");
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&outside);
+        fs::create_dir_all(dir.join("real")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(dir.join("real").join("a.page"), "").unwrap();
+        fs::write(outside.join("secret.page"), "").unwrap();
+        symlink(dir.join("real"), dir.join("link")).unwrap();
+        symlink(&outside, dir.join("escape")).unwrap();
+        symlink(&dir, dir.join("real").join("loop")).unwrap();
+
+        let walk = |symlinks| {
+            let (ok, err): (Vec<_>, Vec<_>) = walk_files(&dir, &dir, symlinks)
+                .into_iter()
+                .partition(|f| f.is_ok());
+            let ok: Vec<PathBuf> = ok.into_iter().map(|f| f.unwrap()).collect();
+            (ok, err.len())
+        };
+        assert_eq!(
+            walk(Symlinks::Follow),
+            (
+                vec![
+                    dir.join("link").join("a.page"),
+                    dir.join("real").join("a.page")
+                ],
+                3
+            )
+        );
+        assert_eq!(
+            walk(Symlinks::Skip),
+            (vec![dir.join("real").join("a.page")], 0)
+        );
+        assert_eq!(
+            walk(Symlinks::Error),
+            (vec![dir.join("real").join("a.page")], 3)
+        );
+        assert_eq!(has_symlink(&dir, &dir.join("link").join("a.page")), true);
+        assert_eq!(has_symlink(&dir, &dir.join("real").join("a.page")), false);
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+//! Web app manifest and service worker, which let the site be installed and used offline
+//!
+//! Both are written into the root of the output directory once every page is built,
+//! from the `webmanifest` and `service_worker` settings in `!SITE`.
+//! Icons generated into the root by !FAVICONS are listed in the web manifest unless it has `icons`,
+//! and it replaces the one that comes with them. Pages still have to link both:
+//! ```YAML
+//! head:
+//!   - link: {_rel: manifest, _href: /site.webmanifest}
+//!   - script: "navigator.serviceWorker?.register('/sw.js')"
+//! ```
+//! ```
+//! pwa::write(&o, &mut manifest);
+//! ```
+
+/* IMPORTS */
+use serde_json::{Map, Value};
+
+/* LOCAL IMPORTS */
+use crate::{build, debug, error, favicons, paths, Manifest, Options};
+
+/* PWA */
+/// Name of the service worker inside the output directory
+pub const SERVICE_WORKER: &str = "sw.js";
+
+/// Prefix of the caches the service worker fills, older ones are deleted once a new version takes over
+const CACHE_PREFIX: &str = "ssgen-";
+
+/// Web app manifest described by the `webmanifest` setting, with defaults for the members it leaves out
+pub fn webmanifest(o: &Options, members: &Map<String, Value>) -> Value {
+    let mut members = members.clone();
+    let mut defaults = vec![
+        (
+            "start_url",
+            Value::from(format!(
+                "{}/",
+                o.base_url.as_deref().unwrap_or("").trim_end_matches('/')
+            )),
+        ),
+        ("display", Value::from("standalone")),
+    ];
+    if o.title.len() != 0 {
+        defaults.push(("name", Value::from(&o.title[..])));
+    }
+    if o.language.len() != 0 {
+        defaults.push(("lang", Value::from(&o.language[..])));
+    }
+    let icons: Vec<Value> = favicons::manifest_icons(o, &o.output)
+        .into_iter()
+        .filter(|(file, _)| file.exists())
+        .map(|(_, icon)| icon)
+        .collect();
+    if icons.len() != 0 {
+        defaults.push(("icons", Value::from(icons)));
+    }
+    for (name, value) in defaults {
+        members.entry(name).or_insert(value);
+    }
+    return Value::Object(members);
+}
+
+/// Service worker that precaches every file in manifest matching one of patterns
+///
+/// Its cache is named after the hashes of those files, so any change to them replaces the whole cache
+pub fn service_worker(o: &Options, manifest: &Manifest, patterns: &[Box<str>]) -> String {
+    let base = o.base_url.as_deref().unwrap_or("").trim_end_matches('/');
+    let mut urls = Vec::new();
+    let mut revisions = String::new();
+    for (key, file) in manifest.files.iter() {
+        if !patterns.iter().any(|p| paths::matches_glob(p, key)) {
+            continue;
+        }
+        revisions += &format!("{key} {}\n", file.hash);
+        // directories are served by their index.html, and linked either way
+        match key.strip_suffix("index.html") {
+            Some(dir) if dir.len() == 0 || dir.ends_with('/') => urls.push(format!("{base}/{dir}")),
+            _ => (),
+        }
+        urls.push(format!("{base}/{key}"));
+    }
+    let version = &build::hash(revisions.as_bytes())[..16];
+    let urls = serde_json::to_string(&urls).unwrap();
+    return format!(
+        r#"// Generated by SSGen, do not edit
+const CACHE = "{CACHE_PREFIX}{version}";
+const PRECACHE = {urls};
+
+self.addEventListener("install", (event) => {{
+  event.waitUntil(caches.open(CACHE).then((cache) => cache.addAll(PRECACHE)).then(() => self.skipWaiting()));
+}});
+
+self.addEventListener("activate", (event) => {{
+  event.waitUntil(
+    caches.keys()
+      .then((keys) => Promise.all(keys.filter((k) => k.startsWith("{CACHE_PREFIX}") && k !== CACHE).map((k) => caches.delete(k))))
+      .then(() => self.clients.claim())
+  );
+}});
+
+self.addEventListener("fetch", (event) => {{
+  if (event.request.method !== "GET") return;
+  event.respondWith(caches.match(event.request, {{ ignoreSearch: true }}).then((hit) => hit || fetch(event.request)));
+}});
+"#
+    );
+}
+
+/// Write the web manifest and service worker that are enabled, recording them as artifacts of every page
+///
+/// The service worker lists what the manifest holds so far, files written after this are not precached
+pub fn write(o: &Options, manifest: &mut Manifest) {
+    match &o.webmanifest {
+        Some(members) => write_file(
+            o,
+            manifest,
+            favicons::WEBMANIFEST,
+            &webmanifest(o, members).to_string(),
+        ),
+        None => (),
+    }
+    if o.service_worker.len() != 0 {
+        // the web manifest is indexed too, so it is precached along with everything else
+        manifest.index_files(o);
+        let sw = service_worker(o, manifest, &o.service_worker);
+        write_file(o, manifest, SERVICE_WORKER, &sw);
+    }
+}
+
+/// Write data to file in the root of the output directory, as an artifact of every page
+fn write_file(o: &Options, manifest: &mut Manifest, file: &str, data: &str) {
+    let path = paths::join_slash(&o.output, file);
+    debug!(o, "Writing {}", path.display());
+    match o.writes.write(&path, data.as_bytes()) {
+        Ok(()) => (),
+        Err(e) => {
+            error!(o, "Error writing file {f} | {e}", f = path.display());
+            return;
+        }
+    }
+    for entry in manifest.pages.values_mut() {
+        entry.artifacts.insert(file.into(), "META.yaml".into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, PageEntry};
+    use clap::Parser;
+    use std::fs;
+
+    /// Ensure the web manifest fills in what it leaves out, and the service worker precaches what matches
+    #[test]
+    fn test_write() {
+        let dest = "/tmp/ssgen_test_dest_dir_pwa";
+        fs::create_dir_all(format!("{dest}/blog")).unwrap();
+        let mut o = Args::parse_from(["", "-i", "/tmp/", "-o", dest, "-s"]).build_options();
+        o.title = "Example".into();
+        o.base_url = Some("https://example.com/".into());
+        o.webmanifest = match serde_json::json!({"short_name": "Ex", "display": "browser"}) {
+            Value::Object(m) => Some(m),
+            _ => None,
+        };
+        o.service_worker = vec!["*.html".into(), "*.webmanifest".into()];
+
+        let mut manifest = Manifest::default();
+        for (source, output) in [
+            ("index.page", "index.html"),
+            ("blog/index.page", "blog/index.html"),
+        ] {
+            fs::write(format!("{dest}/{output}"), output).unwrap();
+            manifest.pages.insert(
+                source.into(),
+                PageEntry {
+                    output: output.into(),
+                    artifacts: [("blog/a.css".into(), "a.css".into())].into(),
+                    ..Default::default()
+                },
+            );
+        }
+        fs::write(format!("{dest}/blog/a.css"), "").unwrap();
+        write(&o, &mut manifest);
+
+        let webmanifest: Value =
+            serde_json::from_str(&fs::read_to_string(format!("{dest}/site.webmanifest")).unwrap())
+                .unwrap();
+        assert_eq!(
+            webmanifest,
+            serde_json::json!({
+                "name": "Example",
+                "short_name": "Ex",
+                "start_url": "https://example.com/",
+                "display": "browser",
+            })
+        );
+        let sw = fs::read_to_string(format!("{dest}/{SERVICE_WORKER}")).unwrap();
+        assert!(sw.contains(
+            r#"const PRECACHE = ["https://example.com/blog/","https://example.com/blog/index.html","https://example.com/","https://example.com/index.html","https://example.com/site.webmanifest"];"#
+        ));
+        assert!(manifest.outputs().contains(SERVICE_WORKER));
+
+        // the cache is only renamed when a precached file changes
+        let version = |sw: &str| sw.lines().nth(1).unwrap().to_string();
+        fs::write(format!("{dest}/blog/a.css"), "changed").unwrap();
+        manifest.index_files(&o);
+        assert_eq!(
+            version(&service_worker(&o, &manifest, &o.service_worker)),
+            version(&sw)
+        );
+        manifest.pages.get_mut("index.page").unwrap().hash = "changed".into();
+        manifest.index_files(&o);
+        assert_ne!(
+            version(&service_worker(&o, &manifest, &o.service_worker)),
+            version(&sw)
+        );
+        fs::remove_dir_all(dest).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+//! QR codes for the !QR directive, drawn as SVG or PNG
+//!
+//! Both are drawn with a quiet zone of 4 modules, which scanners need to find the code
+//! ```
+//! let code = qr::encode("https://example.com")?;
+//! let inline = qr::svg(&code);
+//! fs::write("public/qr.png", qr::png(&code))?;
+//! ```
+
+/* IMPORTS */
+use flate2::{write::ZlibEncoder, Compression, Crc};
+use qrcodegen::{QrCode, QrCodeEcc};
+use std::io::Write;
+
+/* QR */
+/// Empty modules around the code
+const QUIET_ZONE: i32 = 4;
+
+/// Pixels per module in PNG images, enough to scan a code printed from the image
+const PNG_SCALE: i32 = 8;
+
+/// Encode text as a QR code, with enough error correction to survive being printed
+pub fn encode(text: &str) -> Result<QrCode, Box<str>> {
+    return QrCode::encode_text(text, QrCodeEcc::Medium)
+        .map_err(|e| format!("Cannot encode QR code | {e}").into());
+}
+
+/// Draw code as an SVG element, one path covering every dark module
+pub fn svg(code: &QrCode) -> String {
+    let size = code.size() + QUIET_ZONE * 2;
+    let mut path = String::new();
+    for y in 0..code.size() {
+        for x in 0..code.size() {
+            if code.get_module(x, y) {
+                path += &format!("M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE);
+            }
+        }
+    }
+    return format!(
+        r##"<svg class="qr" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {size} {size}" shape-rendering="crispEdges"><rect width="100%" height="100%" fill="#fff"/><path d="{path}" fill="#000"/></svg>"##
+    );
+}
+
+/// Draw code as a greyscale PNG image
+pub fn png(code: &QrCode) -> Vec<u8> {
+    let size = (code.size() + QUIET_ZONE * 2) * PNG_SCALE;
+
+    // every row starts with filter type 0, then one byte per pixel
+    let mut pixels = Vec::with_capacity(((size + 1) * size) as usize);
+    for py in 0..size {
+        pixels.push(0);
+        for px in 0..size {
+            let dark = code.get_module(px / PNG_SCALE - QUIET_ZONE, py / PNG_SCALE - QUIET_ZONE);
+            pixels.push(if dark { 0x00 } else { 0xff });
+        }
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&pixels).unwrap();
+    let data = encoder.finish().unwrap();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((size as u32).to_be_bytes());
+    header.extend((size as u32).to_be_bytes());
+    // bit depth 8, greyscale, deflate, adaptive filtering, no interlacing
+    header.extend([8, 0, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &data);
+    chunk(&mut png, b"IEND", &[]);
+    return png;
+}
+
+/// Append a PNG chunk to png
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    png.extend(crc.sum().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure codes are drawn with their quiet zone, and PNG images are well formed
+    #[test]
+    fn test_draw() {
+        let code = encode("https://example.com").unwrap();
+        assert_eq!(code.size(), 25);
+        let svg = svg(&code);
+        assert!(svg.starts_with(
+            r#"<svg class="qr" xmlns="http://www.w3.org/2000/svg" viewBox="0 0 33 33""#
+        ));
+        // the top left finder pattern starts just inside the quiet zone
+        assert!(svg.contains(r#"<path d="M4,4h1v1h-1zM5,4h1v1h-1z"#));
+
+        let png = png(&code);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..20], &264u32.to_be_bytes());
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+
+        assert!(encode(&"x".repeat(5000)).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! Redirects from the old URLs of pages, given as `_redirect_from` metadata
+//!
+//! Every old URL gets a small page that sends browsers on to the new one, which works on any host.
+//! Servers that can redirect by themselves also get them in their configuration, see the server module
+//! ```YAML
+//! - _redirect_from: [/old/path.html, /blog/]
+//! - p: Moved here!
+//! ```
+//! ```
+//! let redirects = redirects::write(&o, redirects::collect(&o, &meta), &mut manifest);
+//! ```
+
+/* IMPORTS */
+use std::path::{Component, Path, PathBuf};
+
+/* LOCAL IMPORTS */
+use crate::{build, error, info, manifest, paths, warn, Manifest, Options, Var};
+
+/* REDIRECTS */
+/// An old URL of a page, or a redirect declared in the `!SITE` block of META.yaml
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redirect {
+    /// Old path, starting with a slash
+    pub from: Box<str>,
+    /// Path of the page in the output directory, starting with a slash
+    pub path: Box<str>,
+    /// Full URL of the page, see site.pages
+    pub url: Box<str>,
+    /// Page the redirect belongs to, as a manifest key
+    pub source: Box<str>,
+    /// HTTP status code servers redirect with
+    pub status: u16,
+}
+
+/// Every redirect in the `_redirect_from` of the pages in site.pages
+pub fn collect(o: &Options, meta: &build::Meta) -> Vec<Redirect> {
+    let pages = match meta.vars.get("site").and_then(|s| s.get_path(".pages")) {
+        Some(Var::List(pages)) => pages.clone(),
+        _ => return Vec::new(),
+    };
+    let field = |page: &Var, name: &str| match page.get_path(&format!(".{name}")) {
+        Some(Var::Str(s)) => s.clone(),
+        _ => "".into(),
+    };
+    let mut redirects = Vec::new();
+    for page in pages.iter() {
+        let from = match page.get_path(".redirect_from") {
+            Some(Var::List(l)) => l.clone(),
+            Some(Var::Str(s)) if s.len() != 0 => vec![Var::Str(s.clone())],
+            _ => continue,
+        };
+        for f in from {
+            let f = match f {
+                Var::Str(f) => f,
+                _ => {
+                    warn!(
+                        o,
+                        "_redirect_from of {} must be a list of paths",
+                        field(page, "source")
+                    );
+                    continue;
+                }
+            };
+            redirects.push(Redirect {
+                from: format!("/{}", f.trim_start_matches('/')).into(),
+                path: format!("/{}", field(page, "output")).into(),
+                url: field(page, "url"),
+                source: field(page, "source"),
+                status: 301,
+            });
+        }
+    }
+    return redirects;
+}
+
+/// File in the output directory that is served for the old path from
+///
+/// Paths ending with a slash or without an extension are directories, served by their index.html.
+/// None if the path would leave the output directory
+pub fn stub_path(o: &Options, from: &str) -> Option<PathBuf> {
+    if Path::new(from)
+        .components()
+        .any(|c| matches!(c, Component::ParentDir))
+    {
+        return None;
+    }
+    let mut path = paths::join_slash(&o.output, from);
+    let is_dir = from.ends_with('/') || Path::new(from).extension().is_none();
+    if is_dir {
+        path.push("index.html");
+    }
+    return Some(path);
+}
+
+/// Page that sends browsers on to url
+pub fn stub(url: &str) -> String {
+    let url = url
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    return format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"/><title>Redirecting…</title><link rel=\"canonical\" href=\"{url}\"/><meta http-equiv=\"refresh\" content=\"0; url={url}\"/></head><body><a href=\"{url}\">{url}</a></body></html>"
+    );
+}
+
+/// Write a stub for every redirect, recording them as artifacts of their pages
+///
+/// A redirect never replaces a file the build produced, the redirects that were written are returned
+pub fn write(o: &Options, redirects: Vec<Redirect>, manifest: &mut Manifest) -> Vec<Redirect> {
+    let mut written = Vec::new();
+    for r in redirects {
+        let path = match stub_path(o, &r.from) {
+            Some(p) => p,
+            None => {
+                error!(o, "Redirect from {} leaves the output directory", r.from);
+                continue;
+            }
+        };
+        let key = manifest::output_key(o, &path);
+        if manifest.outputs().contains(&key) {
+            warn!(
+                o,
+                "Not redirecting {} to {}, the build already writes {key}", r.from, r.path
+            );
+            continue;
+        }
+        info!(o, "Redirecting {} to {}", r.from, r.path);
+        build::write_page(o, &path, &stub(&r.url));
+        match manifest.pages.get_mut(&r.source) {
+            Some(entry) => {
+                entry.artifacts.insert(key, r.source.clone());
+            }
+            None => (),
+        }
+        written.push(r);
+    }
+    return written;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, PageEntry};
+    use clap::Parser;
+    use std::{fs, sync::Arc};
+
+    /// Ensure stubs are written for every old path, without replacing pages
+    #[test]
+    fn test_redirects() {
+        let src = "/tmp/ssgen_test_source_dir_redirects";
+        let dest = "/tmp/ssgen_test_dest_dir_redirects";
+        fs::create_dir_all(src).unwrap();
+        fs::create_dir_all(dest).unwrap();
+        let o = Arc::new(Args::parse_from(["", "-i", src, "-o", dest, "-s"]).build_options());
+        fs::write(
+            format!("{src}/new.page"),
+            "[_redirect_from: [old.html, /blog/, /a.html, ../escape.html], p: new]",
+        )
+        .unwrap();
+        fs::write(format!("{src}/a.page"), "").unwrap();
+
+        let pages = build::find_pages(&o);
+        let meta = build::parse_meta(o.clone(), &pages);
+        let redirects = collect(&o, &meta);
+        assert_eq!(redirects.len(), 4);
+        assert_eq!(
+            redirects[0],
+            Redirect {
+                from: "/old.html".into(),
+                path: "/new.html".into(),
+                url: "/new.html".into(),
+                source: "new.page".into(),
+                status: 301,
+            }
+        );
+
+        let mut manifest = Manifest::default();
+        for (source, output) in [("new.page", "new.html"), ("a.page", "a.html")] {
+            manifest.pages.insert(
+                source.into(),
+                PageEntry {
+                    output: output.into(),
+                    ..Default::default()
+                },
+            );
+        }
+        let written = write(&o, redirects, &mut manifest);
+        assert_eq!(written.len(), 2);
+        assert_eq!(
+            fs::read_to_string(format!("{dest}/old.html")).unwrap(),
+            stub("/new.html")
+        );
+        assert_eq!(Path::new(&format!("{dest}/blog/index.html")).exists(), true);
+        assert_eq!(Path::new(&format!("{dest}/a.html")).exists(), false);
+        assert_eq!(
+            manifest.pages["new.page"]
+                .artifacts
+                .keys()
+                .map(|k| &k[..])
+                .collect::<Vec<_>>(),
+            ["blog/index.html", "old.html"]
+        );
+
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+}
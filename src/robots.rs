@@ -0,0 +1,164 @@
+//! robots.txt written from the `robots` setting in `!SITE`, so its rules and sitemaps follow the site
+//!
+//! A robots.txt the build already writes, like one copied with !COPY, is kept instead
+//! ```YAML
+//! - !SITE
+//!   robots:
+//!     '*': {disallow: /assets/, allow: /assets/public/}
+//!     GPTBot: {disallow: /}
+//!     sitemap: /sitemap.xml
+//! ```
+//! ```
+//! robots::write(&o, &mut manifest);
+//! ```
+
+/* LOCAL IMPORTS */
+use crate::{debug, error, paths, warn, Manifest, Options};
+
+/* ROBOTS */
+/// Name of the file inside the output directory
+pub const ROBOTS_FILE: &str = "robots.txt";
+
+/// Rule of a group, `Allow` or `Disallow` and a path
+pub type Rule = (Box<str>, Box<str>);
+
+/// Rules for crawlers, see the `robots` setting
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Robots {
+    /// User agents with their rules
+    pub groups: Vec<(Box<str>, Vec<Rule>)>,
+    /// Sitemaps, as paths in the output directory or full URLs
+    pub sitemaps: Vec<Box<str>>,
+}
+
+impl Robots {
+    /// Text of robots.txt, sitemaps given as paths are made full URLs with the base URL
+    pub fn text(&self, o: &Options) -> String {
+        let mut text = String::new();
+        for (agent, rules) in self.groups.iter() {
+            text += &format!("User-agent: {agent}\n");
+            // a group needs at least one rule, and an empty Disallow allows everything
+            if rules.len() == 0 {
+                text += "Disallow:\n";
+            }
+            for (kind, path) in rules {
+                text += &format!("{kind}: {path}\n");
+            }
+            text += "\n";
+        }
+        for sitemap in self.sitemaps.iter() {
+            match sitemap.contains("://") {
+                true => text += &format!("Sitemap: {sitemap}\n"),
+                false => {
+                    text += &format!(
+                        "Sitemap: {}/{}\n",
+                        o.base_url.as_deref().unwrap_or("").trim_end_matches('/'),
+                        sitemap.trim_start_matches('/')
+                    )
+                }
+            }
+        }
+        return text.trim_end().to_string() + "\n";
+    }
+}
+
+/// Write robots.txt if the `robots` setting is given, recording it as an artifact of every page
+pub fn write(o: &Options, manifest: &mut Manifest) {
+    let robots = match &o.robots {
+        Some(r) => r,
+        None => return,
+    };
+    // pages kept from a previous build still list the robots.txt written from the setting
+    let written_by_build = manifest.pages.values().any(|e| {
+        &e.output[..] == ROBOTS_FILE
+            || e.artifacts
+                .get(ROBOTS_FILE)
+                .is_some_and(|s| &s[..] != "META.yaml")
+    });
+    if written_by_build {
+        warn!(
+            o,
+            "Not writing {ROBOTS_FILE} from the robots setting, the build already writes it"
+        );
+        return;
+    }
+    let path = paths::join_slash(&o.output, ROBOTS_FILE);
+    debug!(o, "Writing {}", path.display());
+    match o.writes.write(&path, robots.text(o).as_bytes()) {
+        Ok(()) => (),
+        Err(e) => {
+            error!(o, "Error writing file {f} | {e}", f = path.display());
+            return;
+        }
+    }
+    for entry in manifest.pages.values_mut() {
+        entry
+            .artifacts
+            .insert(ROBOTS_FILE.into(), "META.yaml".into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, PageEntry};
+    use clap::Parser;
+    use std::fs;
+
+    /// Ensure robots.txt lists every group and sitemap, and never replaces one the build writes
+    #[test]
+    fn test_write() {
+        let dest = "/tmp/ssgen_test_dest_dir_robots";
+        fs::create_dir_all(dest).unwrap();
+        let mut o = Args::parse_from(["", "-i", "/tmp/", "-o", dest, "-s"]).build_options();
+        o.base_url = Some("https://example.com/".into());
+        o.robots = Some(Robots {
+            groups: vec![
+                (
+                    "*".into(),
+                    vec![
+                        ("Disallow".into(), "/assets/".into()),
+                        ("Allow".into(), "/assets/public/".into()),
+                    ],
+                ),
+                ("Bot".into(), Vec::new()),
+            ],
+            sitemaps: vec![
+                "/sitemap.xml".into(),
+                "https://cdn.example.com/map.xml".into(),
+            ],
+        });
+        let mut manifest = Manifest::default();
+        manifest.pages.insert(
+            "index.page".into(),
+            PageEntry {
+                output: "index.html".into(),
+                ..Default::default()
+            },
+        );
+        write(&o, &mut manifest);
+        assert_eq!(
+            fs::read_to_string(format!("{dest}/{ROBOTS_FILE}")).unwrap(),
+            "User-agent: *\nDisallow: /assets/\nAllow: /assets/public/\n\nUser-agent: Bot\nDisallow:\n\nSitemap: https://example.com/sitemap.xml\nSitemap: https://cdn.example.com/map.xml\n"
+        );
+        assert_eq!(
+            manifest.pages["index.page"].artifacts[ROBOTS_FILE],
+            "META.yaml".into()
+        );
+
+        // a copied robots.txt wins
+        fs::write(format!("{dest}/{ROBOTS_FILE}"), "copied").unwrap();
+        manifest
+            .pages
+            .get_mut("index.page")
+            .unwrap()
+            .artifacts
+            .insert(ROBOTS_FILE.into(), ROBOTS_FILE.into());
+        write(&o, &mut manifest);
+        assert_eq!(
+            fs::read_to_string(format!("{dest}/{ROBOTS_FILE}")).unwrap(),
+            "copied"
+        );
+        fs::remove_dir_all(dest).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+//! Configuration files for the server that hosts the output directory
+//!
+//! Redirects and headers are declared in the `!SITE` block of META.yaml and checked when the site is built,
+//! then written in the format of the server chosen with --server-config or the `server` setting.
+//! Redirects from `_redirect_from` metadata are written too, before the declared ones
+//! ```YAML
+//! - !SITE
+//!   server: netlify
+//!   redirects:
+//!     - {from: /docs/*, to: /manual/:splat, status: 302}
+//!   headers:
+//!     /*: {X-Frame-Options: DENY}
+//! ```
+//! ```
+//! server::write(&o, &redirects, &mut manifest);
+//! ```
+
+/* IMPORTS */
+use clap::ValueEnum;
+
+/* LOCAL IMPORTS */
+use crate::{debug, error, paths, redirects::Redirect, Manifest, Options};
+
+/* SERVER */
+/// Status codes a declared redirect can have
+pub const REDIRECT_STATUSES: [u16; 5] = [301, 302, 303, 307, 308];
+
+/// Formats of server configuration
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ServerConfig {
+    /// `_redirects` and `_headers` files, also understood by Cloudflare Pages
+    Netlify,
+    /// `server.nginx.conf` file of location blocks, for an `include` in the server block
+    Nginx,
+}
+
+/// Headers to send with every file matching a path
+#[derive(Clone, Debug, PartialEq)]
+pub struct Headers {
+    /// Path of the files, ending with `*` to match every path starting with it
+    pub path: Box<str>,
+    /// Names and values of the headers
+    pub headers: Vec<(Box<str>, Box<str>)>,
+}
+
+/// Check a path of a declared redirect or header, ending with `*` to match every path starting with it
+pub fn check_path(path: &str) -> Result<(), String> {
+    if !path.starts_with('/') {
+        return Err(format!("{path} must start with /"));
+    }
+    if path.contains(char::is_whitespace) {
+        return Err(format!("{path} must not contain whitespace"));
+    }
+    if path.trim_end_matches('*').contains('*') || path.ends_with("**") {
+        return Err(format!("{path} can only end with a single *"));
+    }
+    return Ok(());
+}
+
+/// Check the name and value of a declared header
+pub fn check_header(name: &str, value: &str) -> Result<(), String> {
+    if name.len() == 0 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!(
+            "Header name {name} must only contain letters, digits and -"
+        ));
+    }
+    if value.contains(char::is_control) {
+        return Err(format!("Header {name} must be a single line"));
+    }
+    return Ok(());
+}
+
+/// Write the configuration files for the redirects and headers, recording them as artifacts of every page
+pub fn write(o: &Options, redirects: &[Redirect], manifest: &mut Manifest) {
+    let format = match o.server_config {
+        Some(f) => f,
+        None => return,
+    };
+    let redirects: Vec<&Redirect> = redirects.iter().chain(o.redirects.iter()).collect();
+    let files = match format {
+        ServerConfig::Netlify => vec![
+            (
+                "_redirects",
+                redirects.iter().map(|r| netlify_redirect(r)).collect(),
+            ),
+            ("_headers", o.headers.iter().map(netlify_headers).collect()),
+        ],
+        ServerConfig::Nginx => vec![(
+            "server.nginx.conf",
+            redirects
+                .iter()
+                .map(|r| nginx_redirect(r))
+                .chain(o.headers.iter().map(nginx_headers))
+                .collect::<Vec<String>>(),
+        )],
+    };
+
+    for (file, lines) in files {
+        if lines.len() == 0 {
+            continue;
+        }
+        let path = paths::join_slash(&o.output, file);
+        debug!(o, "Writing server configuration {}", path.display());
+        match o.writes.write(&path, lines.concat().as_bytes()) {
+            Ok(()) => (),
+            Err(e) => {
+                error!(o, "Error writing file {f} | {e}", f = path.display());
+                continue;
+            }
+        }
+        for entry in manifest.pages.values_mut() {
+            entry.artifacts.insert(file.into(), "META.yaml".into());
+        }
+    }
+}
+
+/// Line of a Netlify `_redirects` file
+fn netlify_redirect(r: &Redirect) -> String {
+    return format!("{} {} {}\n", r.from, r.path, r.status);
+}
+
+/// Block of a Netlify `_headers` file
+fn netlify_headers(h: &Headers) -> String {
+    let mut block = format!("{}\n", h.path);
+    for (name, value) in h.headers.iter() {
+        block += &format!("  {name}: {value}\n");
+    }
+    return block;
+}
+
+/// Location block of an nginx configuration that redirects
+///
+/// Paths ending with `*` become regular expressions, with `:splat` in the target standing for the rest of the path
+fn nginx_redirect(r: &Redirect) -> String {
+    return match r.from.strip_suffix('*') {
+        Some(prefix) => format!(
+            "location ~ ^{}(.*)$ {{ return {} {}; }}\n",
+            regex::escape(prefix),
+            r.status,
+            r.path.replace(":splat", "$1")
+        ),
+        None => format!(
+            "location = {} {{ return {} {}; }}\n",
+            r.from, r.status, r.path
+        ),
+    };
+}
+
+/// Location block of an nginx configuration that adds headers
+///
+/// A location with add_header drops those of the server block, so every header for a path has to be declared with it
+fn nginx_headers(h: &Headers) -> String {
+    let mut block = match h.path.strip_suffix('*') {
+        Some(prefix) => format!("location {prefix} {{\n"),
+        None => format!("location = {} {{\n", h.path),
+    };
+    for (name, value) in h.headers.iter() {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        block += &format!("    add_header {name} \"{value}\" always;\n");
+    }
+    return block + "}\n";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, PageEntry};
+    use clap::Parser;
+    use std::fs;
+
+    /// Ensure paths and headers are checked before they end up in a configuration file
+    #[test]
+    fn test_checks() {
+        assert_eq!(check_path("/docs/*"), Ok(()));
+        assert_eq!(check_path("docs").is_err(), true);
+        assert_eq!(check_path("/a b").is_err(), true);
+        assert_eq!(check_path("/*/x").is_err(), true);
+        assert_eq!(check_header("Cache-Control", "no-cache"), Ok(()));
+        assert_eq!(check_header("Bad:Name", "x").is_err(), true);
+        assert_eq!(check_header("X-Split", "a\nb").is_err(), true);
+    }
+
+    /// Ensure redirects and headers are written in the chosen format, and recorded in the manifest
+    #[test]
+    fn test_write() {
+        let dest = "/tmp/ssgen_test_dest_dir_server";
+        fs::create_dir_all(dest).unwrap();
+        let redirect = |from: &str, to: &str, status| Redirect {
+            from: from.into(),
+            path: to.into(),
+            url: to.into(),
+            source: "META.yaml".into(),
+            status: status,
+        };
+        let mut manifest = Manifest::default();
+        manifest
+            .pages
+            .insert("index.page".into(), PageEntry::default());
+        let pages = [redirect("/old.html", "/new.html", 301)];
+        for (format, files) in [
+            (
+                "netlify",
+                vec![
+                    (
+                        "_redirects",
+                        "/old.html /new.html 301\n/docs/* /manual/:splat 302\n",
+                    ),
+                    ("_headers", "/*\n  X-Frame-Options: DENY\n"),
+                ],
+            ),
+            (
+                "nginx",
+                vec![(
+                    "server.nginx.conf",
+                    "location = /old.html { return 301 /new.html; }\n\
+                     location ~ ^/docs/(.*)$ { return 302 /manual/$1; }\n\
+                     location / {\n    add_header X-Frame-Options \"DENY\" always;\n}\n",
+                )],
+            ),
+        ] {
+            let mut o = Args::parse_from(["", "-i", "./", "-o", dest, "-s"]).build_options();
+            o.server_config = ServerConfig::from_str(format, false).ok();
+            o.redirects = vec![redirect("/docs/*", "/manual/:splat", 302)];
+            o.headers = vec![Headers {
+                path: "/*".into(),
+                headers: vec![("X-Frame-Options".into(), "DENY".into())],
+            }];
+            write(&o, &pages, &mut manifest);
+            for (file, contents) in files {
+                assert_eq!(
+                    fs::read_to_string(format!("{dest}/{file}")).unwrap(),
+                    contents
+                );
+                assert_eq!(
+                    manifest.pages["index.page"].artifacts[file],
+                    "META.yaml".into()
+                );
+            }
+        }
+        fs::remove_dir_all(dest).unwrap();
+    }
+}
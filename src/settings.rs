@@ -0,0 +1,928 @@
+//! Site settings given in the `!SITE` block of META.yaml
+//!
+//! Settings are read before anything is built, so they can configure ssgen itself and not only templates.
+//! Values are taken as written, variables and directives are not available yet.
+//! Command-line arguments win over settings, and switches are on if either turns them on.
+//! Profiles bundle settings for one environment, so that a single META.yaml covers all of them
+//! ```YAML
+//! - !SITE
+//!   title: SSGen
+//!   base_url: https://staging.example.com
+//!   language: en
+//!   heading_ids: true
+//!   transforms: [emoji, external-links]
+//!   stamp: [meta, json]
+//!   external_links: {target: _blank, rel: noopener, icon: ' ↗'}
+//!   vars: {analytics_id: ''}
+//!   server: netlify
+//!   redirects:
+//!     - {from: /blog/*, to: /news/:splat}
+//!   headers:
+//!     /assets/*: {Cache-Control: 'public, max-age=31536000'}
+//!   asset_hooks:
+//!     - {pattern: '*.ts', output: js, command: [esbuild, '{input}', '--outfile={output}']}
+//!   pre_build: [make data]
+//!   post_build: [{command: ./notify.sh, on_failure: warn}]
+//!   html_filter: {command: tidy -q, jobs: 4}
+//!   diagrams: {dot: dot -Tsvg}
+//!   webmanifest: {short_name: SSGen, theme_color: '#222'}
+//!   service_worker: ['*.html', 'assets/**']
+//!   robots:
+//!     '*': {disallow: /drafts/}
+//!     sitemap: /sitemap.xml
+//!   profiles:
+//!     production:
+//!       base_url: https://example.com
+//!       vars: {analytics_id: UA-1234}
+//! ```
+//! ```
+//! let o = Arc::new(settings::apply(&Args::parse().build_options()));
+//! ```
+
+/* IMPORTS */
+use clap::ValueEnum;
+use serde_yaml::{Mapping, Value};
+use std::{fs, path::PathBuf, sync::Arc};
+
+/* LOCAL IMPORTS */
+use crate::{
+    buildinfo::Stamp,
+    error,
+    hooks::{AssetHook, BuildHook, HtmlFilter},
+    manifest,
+    parser::{value_tostring, yaml},
+    redirects::Redirect,
+    robots::Robots,
+    server::{self, Headers, ServerConfig, REDIRECT_STATUSES},
+    transforms::{ExternalLinks, Transform},
+    warn, Options,
+};
+
+/* SETTINGS */
+
+/// Applies a setting to the options, logging problems with it to the first options
+type AddSetting = fn(&Options, &mut Options, &Value);
+
+/// Settings that are more than a single value, each with what applies it to the options
+///
+/// The other settings are applied by apply itself
+const STRUCTURED_SETTINGS: [(&str, AddSetting); 14] = [
+    ("vars", add_vars),
+    ("redirects", add_redirects),
+    ("headers", add_headers),
+    ("asset_hooks", add_asset_hooks),
+    ("pre_build", |log, o, v| {
+        o.pre_build.extend(build_hooks(log, "pre_build", v))
+    }),
+    ("post_build", |log, o, v| {
+        o.post_build.extend(build_hooks(log, "post_build", v))
+    }),
+    ("html_filter", |log, o, v| {
+        o.html_filter = html_filter(log, v).map(Arc::new)
+    }),
+    ("diagrams", add_diagram_renderers),
+    ("transforms", add_transforms),
+    ("stamp", add_stamps),
+    ("external_links", external_links),
+    ("webmanifest", webmanifest),
+    ("service_worker", service_worker),
+    ("robots", robots),
+];
+
+/// Copy of o with the settings of the `!SITE` blocks in META.yaml applied
+///
+/// Settings:
+/// - `base_url`: URL the output directory is served from, like --base-url
+/// - `title`: name of the site, for templates as `{site.title}`
+/// - `language`: language of the site, for templates as `{site.language}`
+/// - `heading_ids`: true to give headings ids, like --heading-ids
+/// - `transforms`: list of passes over the text of every page, added to those of --transform
+/// - `stamp`: list of places to stamp the build into, added to those of --stamp
+/// - `external_links`: attributes for the external-links transform to give links to other sites, and an `icon` to put in them
+/// - `clean`: true to remove stale files from the output directory, like --clean
+/// - `vars`: variables to define before META.yaml is parsed, as a mapping of names to values
+/// - `server`: format of the server configuration to write, like --server-config
+/// - `redirects`: list of redirects for the server, each with `from`, `to` and optionally `status`
+/// - `headers`: headers for the server, as a mapping of paths to mappings of header names to values
+/// - `asset_hooks`: commands that compile copied files, each with a `pattern`, `output` extension and `command`, see hooks
+/// - `pre_build` and `post_build`: commands to run before and after the build,
+///   each a command or a `command` with `on_failure: abort` or `warn`
+/// - `html_filter`: command every page is passed through, or a `command` with `on_failure` and a number of `jobs` at once
+/// - `diagrams`: commands that render !DIAGRAM sources to SVG, as a mapping of diagram languages to commands
+/// - `webmanifest`: members of a web app manifest to write as site.webmanifest, see pwa
+/// - `robots`: rules of the robots.txt to write, as a mapping of user agents to their `allow` and `disallow` paths,
+///   and the `sitemap` paths or URLs
+/// - `service_worker`: true to write a service worker that precaches every file, or a list of globs of the files to precache
+/// - `profiles`: settings by profile name, those of the profile chosen with --profile override the others
+pub fn apply(o: &Options) -> Options {
+    let mut o = o.clone();
+    let mut meta_file: PathBuf = o.input.clone();
+    meta_file.push("META.yaml");
+    // META.yaml is only missing or unreadable here if parse_meta reports it
+    let docs = match fs::read_to_string(&meta_file).map(|s| yaml::parse(&s)) {
+        Ok(Ok(d)) => d,
+        _ => return o,
+    };
+    let log = o.for_page(&manifest::input_key(&o, &meta_file));
+
+    // settings of the active profile come last, so they win
+    let mut settings = Vec::<(&Value, &Value)>::new();
+    let mut profile: Option<Vec<(&Value, &Value)>> = None;
+    for (k, v) in docs.values.iter().flat_map(site_blocks).flatten() {
+        if k.as_str() != Some("profiles") {
+            settings.push((k, v));
+            continue;
+        }
+        for (name, p) in v.as_mapping().into_iter().flatten() {
+            if scalar(name) != o.profile {
+                continue;
+            }
+            match p {
+                Value::Mapping(m) => profile = Some(m.iter().collect()),
+                Value::Null => profile = Some(Vec::new()),
+                _ => error!(
+                    log,
+                    "Profile {} must be a mapping of settings",
+                    value_tostring(name)
+                ),
+            }
+        }
+        if !v.is_mapping() {
+            error!(
+                log,
+                "Site setting profiles must be a mapping of profile names to settings"
+            );
+        }
+    }
+    match (&o.profile, &profile) {
+        (Some(p), None) => warn!(log, "Profile {p} is not defined in the profiles of !SITE"),
+        _ => (),
+    }
+
+    let (mut base_url, mut heading_ids, mut clean, mut server) = (None, false, false, None);
+    for (k, v) in settings.into_iter().chain(profile.into_iter().flatten()) {
+        match STRUCTURED_SETTINGS
+            .iter()
+            .find(|(name, _)| k.as_str() == Some(*name))
+        {
+            Some((_, add)) => {
+                add(&log, &mut o, v);
+                continue;
+            }
+            None => (),
+        }
+        let (key, val) = match (scalar(k), scalar(v)) {
+            (Some(key), Some(val)) => (key, val),
+            _ => {
+                error!(
+                    log,
+                    "Site setting {} must be a single value",
+                    value_tostring(k)
+                );
+                continue;
+            }
+        };
+        match &key[..] {
+            "base_url" => base_url = Some(val),
+            "title" => o.title = val,
+            "language" => o.language = val,
+            "server" => match ServerConfig::from_str(&val, true) {
+                Ok(s) => server = Some(s),
+                Err(_) => error!(
+                    log,
+                    "Site setting server must be netlify or nginx, not {val}"
+                ),
+            },
+            "heading_ids" | "clean" => {
+                let on = match &val[..] {
+                    "true" => true,
+                    "false" => false,
+                    _ => {
+                        error!(log, "Site setting {key} must be true or false, not {val}");
+                        continue;
+                    }
+                };
+                match &key[..] {
+                    "heading_ids" => heading_ids = on,
+                    _ => clean = on,
+                }
+            }
+            _ => warn!(log, "Unknown site setting {key}"),
+        }
+    }
+    if o.base_url.is_none() {
+        o.base_url = base_url;
+    }
+    if o.server_config.is_none() {
+        o.server_config = server;
+    }
+    o.heading_ids |= heading_ids;
+    o.clean |= clean;
+
+    if o.clean && o.input.starts_with(&o.output) {
+        error!(
+            log,
+            "Refusing to clean the output directory because it contains the input directory!"
+        );
+        o.clean = false;
+    }
+    if o.server_config.is_none() && (o.redirects.len() != 0 || o.headers.len() != 0) {
+        warn!(
+            log,
+            "Redirects and headers are only written for a server, choose one with --server-config or server in !SITE"
+        );
+    }
+    let hooks =
+        o.asset_hooks.len() + o.pre_build.len() + o.post_build.len() + o.diagram_renderers.len();
+    if (hooks != 0 || o.html_filter.is_some()) && !o.allow_shell {
+        error!(
+            log,
+            "Hooks run commands, which are not enabled! Run SSGen with the '--enable-shell' argument (danger!) to enable them."
+        );
+        o.asset_hooks.clear();
+        o.pre_build.clear();
+        o.post_build.clear();
+        o.html_filter = None;
+        o.diagram_renderers.clear();
+        o.diagnostics.fail();
+    }
+    // without it the URLs are only paths, which search engines may not accept
+    if o.base_url.is_none() {
+        let sitemap_paths = o
+            .robots
+            .as_ref()
+            .is_some_and(|r| r.sitemaps.iter().any(|s| !s.contains("://")));
+        if sitemap_paths || o.transforms.contains(&Transform::Canonical) {
+            warn!(
+                log,
+                "Sitemaps in robots.txt and canonical links need the URL of the site, set it with --base-url or base_url in !SITE"
+            );
+        }
+    }
+    if o.purge_cdn.is_some() && o.base_url.is_none() {
+        error!(
+            log,
+            "Purging a CDN requires the URL of the site, set it with --base-url or base_url in !SITE"
+        );
+        o.diagnostics.fail();
+    }
+    return o;
+}
+
+/// Define the variables of a `vars` setting in o
+fn add_vars(log: &Options, o: &mut Options, vars: &Value) {
+    let vars = match vars {
+        Value::Mapping(m) => m,
+        _ => {
+            error!(
+                log,
+                "Site setting vars must be a mapping of names to values"
+            );
+            return;
+        }
+    };
+    for (name, val) in vars {
+        match (scalar(name), scalar(val)) {
+            (Some(name), Some(val)) => {
+                o.site_vars.insert(name, val);
+            }
+            _ => error!(
+                log,
+                "Site variable {} must be a single value",
+                value_tostring(name)
+            ),
+        }
+    }
+}
+
+/// Add the redirects of a `redirects` setting to o, leaving out those that are not valid
+fn add_redirects(log: &Options, o: &mut Options, redirects: &Value) {
+    let redirects = match redirects {
+        Value::Sequence(s) => s,
+        _ => {
+            error!(log, "Site setting redirects must be a list of redirects");
+            return;
+        }
+    };
+    for r in redirects {
+        let get = |key: &str| r.get(key).and_then(scalar);
+        let (from, to) = match (get("from"), get("to")) {
+            (Some(from), Some(to)) => (from, to),
+            _ => {
+                error!(log, "Redirect {} needs a from and a to", value_tostring(r));
+                continue;
+            }
+        };
+        let status = match get("status").map(|s| s.parse::<u16>()) {
+            None => 301,
+            Some(Ok(s)) if REDIRECT_STATUSES.contains(&s) => s,
+            Some(_) => {
+                error!(
+                    log,
+                    "Redirect from {from} must have one of the statuses {REDIRECT_STATUSES:?}"
+                );
+                continue;
+            }
+        };
+        let external = to.starts_with("https://") || to.starts_with("http://");
+        let checked = server::check_path(&from).and_then(|_| match external {
+            true => Ok(()),
+            false => server::check_path(to.trim_end_matches('*')),
+        });
+        match checked {
+            Ok(()) if to.contains(":splat") && !from.ends_with('*') => {
+                error!(
+                    log,
+                    "Redirect from {from} uses :splat but does not end with *"
+                )
+            }
+            Ok(()) => o.redirects.push(Redirect {
+                from: from,
+                path: to.clone(),
+                url: to,
+                source: "META.yaml".into(),
+                status: status,
+            }),
+            Err(e) => error!(log, "Redirect {e}"),
+        }
+    }
+}
+
+/// Add the headers of a `headers` setting to o, leaving out those that are not valid
+fn add_headers(log: &Options, o: &mut Options, headers: &Value) {
+    let headers = match headers {
+        Value::Mapping(m) => m,
+        _ => {
+            error!(
+                log,
+                "Site setting headers must be a mapping of paths to headers"
+            );
+            return;
+        }
+    };
+    for (path, values) in headers {
+        let path = match scalar(path).map(|p| server::check_path(&p).map(|_| p)) {
+            Some(Ok(p)) => p,
+            Some(Err(e)) => {
+                error!(log, "Headers for {e}");
+                continue;
+            }
+            None => {
+                error!(log, "Headers for {} need a path", value_tostring(path));
+                continue;
+            }
+        };
+        let mut h = Headers {
+            path: path,
+            headers: Vec::new(),
+        };
+        for (name, value) in values.as_mapping().into_iter().flatten() {
+            match (scalar(name), scalar(value)) {
+                (Some(name), Some(value)) => match server::check_header(&name, &value) {
+                    Ok(()) => h.headers.push((name, value)),
+                    Err(e) => error!(log, "{e}"),
+                },
+                _ => error!(
+                    log,
+                    "Header {} must be a single value",
+                    value_tostring(name)
+                ),
+            }
+        }
+        if !values.is_mapping() {
+            error!(
+                log,
+                "Headers for {} must be a mapping of names to values", h.path
+            );
+        }
+        o.headers.push(h);
+    }
+}
+
+/// Add the hooks of an `asset_hooks` setting to o, leaving out those that are not valid
+fn add_asset_hooks(log: &Options, o: &mut Options, hooks: &Value) {
+    let hooks = match hooks {
+        Value::Sequence(s) => s,
+        _ => {
+            error!(log, "Site setting asset_hooks must be a list of hooks");
+            return;
+        }
+    };
+    for h in hooks {
+        let get = |key: &str| h.get(key).and_then(scalar);
+        let command = h.get("command").map(command).unwrap_or_default();
+        match (get("pattern"), get("output"), command.len()) {
+            (Some(pattern), Some(output), 1..) => o.asset_hooks.push(AssetHook {
+                pattern: pattern,
+                output: output.trim_start_matches('.').into(),
+                command: command,
+            }),
+            _ => error!(
+                log,
+                "Asset hook {} needs a pattern, an output extension and a command",
+                value_tostring(h)
+            ),
+        }
+    }
+}
+
+/// Hooks of a `pre_build` or `post_build` setting called name, leaving out those that are not valid
+fn build_hooks(log: &Options, name: &str, hooks: &Value) -> Vec<BuildHook> {
+    let hooks = match hooks {
+        Value::Sequence(s) => s,
+        _ => {
+            error!(log, "Site setting {name} must be a list of commands");
+            return Vec::new();
+        }
+    };
+    let mut valid = Vec::new();
+    for h in hooks {
+        let (cmd, on_failure) = match h {
+            Value::Mapping(_) => (
+                h.get("command").map(command).unwrap_or_default(),
+                h.get("on_failure").and_then(scalar),
+            ),
+            v => (command(v), None),
+        };
+        let abort = match aborts(log, on_failure) {
+            Some(a) => a,
+            None => continue,
+        };
+        match cmd.len() {
+            0 => error!(log, "Hook {} needs a command", value_tostring(h)),
+            _ => valid.push(BuildHook {
+                command: cmd,
+                abort: abort,
+            }),
+        }
+    }
+    return valid;
+}
+
+/// Filter of an `html_filter` setting, None if it is not valid
+fn html_filter(log: &Options, filter: &Value) -> Option<HtmlFilter> {
+    let (cmd, jobs, on_failure) = match filter {
+        Value::Mapping(_) => (
+            filter.get("command").map(command).unwrap_or_default(),
+            filter.get("jobs").and_then(scalar),
+            filter.get("on_failure").and_then(scalar),
+        ),
+        v => (command(v), None, None),
+    };
+    let jobs = match jobs.map(|j| j.parse::<usize>()) {
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        Some(Ok(j)) if j > 0 => j,
+        Some(_) => {
+            error!(log, "HTML filter jobs must be a number above 0");
+            return None;
+        }
+    };
+    if cmd.len() == 0 {
+        error!(
+            log,
+            "HTML filter {} needs a command",
+            value_tostring(filter)
+        );
+        return None;
+    }
+    return Some(HtmlFilter::new(cmd, jobs, aborts(log, on_failure)?));
+}
+
+/// Add the passes of a `transforms` setting to o that it does not run yet, leaving out those that are not valid
+fn add_transforms(log: &Options, o: &mut Options, transforms: &Value) {
+    let transforms = match transforms {
+        Value::Sequence(s) => s,
+        _ => {
+            error!(log, "Site setting transforms must be a list of transforms");
+            return;
+        }
+    };
+    for t in transforms {
+        match scalar(t).map(|name| Transform::from_str(&name, true)) {
+            Some(Ok(t)) if !o.transforms.contains(&t) => o.transforms.push(t),
+            Some(Ok(_)) => (),
+            _ => error!(log, "Unknown transform {}", value_tostring(t)),
+        }
+    }
+}
+
+/// Add the places of a `stamp` setting to those the build is stamped into
+fn add_stamps(log: &Options, o: &mut Options, stamps: &Value) {
+    let stamps = match stamps {
+        Value::Sequence(s) => s,
+        _ => {
+            error!(log, "Site setting stamp must be a list of places to stamp");
+            return;
+        }
+    };
+    for s in stamps {
+        match scalar(s).map(|name| Stamp::from_str(&name, true)) {
+            Some(Ok(s)) if !o.stamp.contains(&s) => o.stamp.push(s),
+            Some(Ok(_)) => (),
+            _ => error!(
+                log,
+                "Unknown stamp {}, must be comment, meta or json",
+                value_tostring(s)
+            ),
+        }
+    }
+}
+
+/// Replace what external links get with an `external_links` setting, leaving out values that are not valid
+fn external_links(log: &Options, o: &mut Options, links: &Value) {
+    let links = match links {
+        Value::Mapping(m) => m,
+        _ => {
+            error!(
+                log,
+                "Site setting external_links must be a mapping of attributes to values"
+            );
+            return;
+        }
+    };
+    let mut annotations = ExternalLinks {
+        attributes: Vec::new(),
+        icon: "".into(),
+    };
+    for (k, v) in links {
+        match (scalar(k), scalar(v)) {
+            (Some(k), Some(v)) if &k[..] == "icon" => annotations.icon = v,
+            (Some(k), Some(v)) => annotations.attributes.push((k, v)),
+            _ => error!(
+                log,
+                "External link attribute {} must be a single value",
+                value_tostring(k)
+            ),
+        }
+    }
+    o.external_links = annotations;
+}
+
+/// Replace the members of the web app manifest with a `webmanifest` setting
+fn webmanifest(log: &Options, o: &mut Options, members: &Value) {
+    match serde_json::to_value(members) {
+        Ok(serde_json::Value::Object(m)) if members.is_mapping() => o.webmanifest = Some(m),
+        _ => error!(
+            log,
+            "Site setting webmanifest must be a mapping of manifest members to values"
+        ),
+    }
+}
+
+/// Replace the files the service worker precaches with a `service_worker` setting
+fn service_worker(log: &Options, o: &mut Options, patterns: &Value) {
+    o.service_worker = match patterns {
+        Value::Bool(true) => vec!["**".into()],
+        Value::Bool(false) => Vec::new(),
+        Value::Sequence(s) if s.iter().all(|p| p.is_string()) => {
+            s.iter().filter_map(scalar).collect()
+        }
+        _ => {
+            error!(
+                log,
+                "Site setting service_worker must be true, false or a list of globs"
+            );
+            return;
+        }
+    };
+}
+
+/// Replace the rules of robots.txt with a `robots` setting, leaving out those that are not valid
+fn robots(log: &Options, o: &mut Options, robots: &Value) {
+    let groups = match robots {
+        Value::Mapping(m) => m,
+        _ => {
+            error!(
+                log,
+                "Site setting robots must be a mapping of user agents to rules"
+            );
+            return;
+        }
+    };
+    let mut r = Robots::default();
+    for (agent, rules) in groups {
+        let agent = match scalar(agent) {
+            Some(a) => a,
+            None => {
+                error!(log, "User agent {} must be a name", value_tostring(agent));
+                continue;
+            }
+        };
+        if &agent[..] == "sitemap" {
+            match rules {
+                Value::Sequence(s) => r.sitemaps.extend(s.iter().filter_map(scalar)),
+                v => r.sitemaps.extend(scalar(v)),
+            }
+            continue;
+        }
+        let rules = match rules {
+            Value::Mapping(m) => m.iter().collect(),
+            Value::Null => Vec::new(),
+            _ => {
+                error!(
+                    log,
+                    "Robots rules for {agent} must be a mapping of allow and disallow to paths"
+                );
+                continue;
+            }
+        };
+        let mut group = Vec::new();
+        for (kind, paths) in rules {
+            let kind = match scalar(kind).as_deref() {
+                Some("allow") => "Allow",
+                Some("disallow") => "Disallow",
+                _ => {
+                    error!(
+                        log,
+                        "Robots rule {} must be allow or disallow",
+                        value_tostring(kind)
+                    );
+                    continue;
+                }
+            };
+            let paths = match paths {
+                Value::Sequence(s) => s.iter().collect(),
+                p => vec![p],
+            };
+            for path in paths {
+                match scalar(path) {
+                    Some(p) => group.push((kind.into(), p)),
+                    None => error!(
+                        log,
+                        "Robots {kind} path {} must be a path",
+                        value_tostring(path)
+                    ),
+                }
+            }
+        }
+        r.groups.push((agent, group));
+    }
+    o.robots = Some(r);
+}
+
+/// Add the renderers of a `diagrams` setting to o, leaving out those that are not valid
+fn add_diagram_renderers(log: &Options, o: &mut Options, renderers: &Value) {
+    let renderers = match renderers {
+        Value::Mapping(m) => m,
+        _ => {
+            error!(
+                log,
+                "Site setting diagrams must be a mapping of diagram languages to commands"
+            );
+            return;
+        }
+    };
+    for (language, cmd) in renderers {
+        match (scalar(language), command(cmd)) {
+            (Some(language), cmd) if cmd.len() != 0 => {
+                o.diagram_renderers.insert(language, cmd);
+            }
+            _ => error!(
+                log,
+                "Diagram renderer {} needs a command",
+                value_tostring(language)
+            ),
+        }
+    }
+}
+
+/// Whether a hook with an `on_failure` setting aborts the build when it fails, None if it is not valid
+fn aborts(log: &Options, on_failure: Option<Box<str>>) -> Option<bool> {
+    return match on_failure.as_deref() {
+        None | Some("abort") => Some(true),
+        Some("warn") => Some(false),
+        Some(f) => {
+            error!(log, "Hook on_failure must be abort or warn, not {f}");
+            None
+        }
+    };
+}
+
+/// Program and arguments of a command given as a list, or as a string split at whitespace
+fn command(v: &Value) -> Vec<Box<str>> {
+    return match v {
+        Value::Sequence(s) => s.iter().filter_map(scalar).collect(),
+        v => scalar(v)
+            .map(|c| c.split_whitespace().map(|a| a.into()).collect())
+            .unwrap_or_default(),
+    };
+}
+
+/// Mappings of the `!SITE` blocks at the top level of a META.yaml document
+fn site_blocks(doc: &Value) -> Vec<&Mapping> {
+    let values = match doc {
+        Value::Sequence(s) => s.iter().collect(),
+        v => vec![v],
+    };
+    return values
+        .into_iter()
+        .filter_map(|v| match v {
+            Value::Tagged(tv) if tv.tag == "!SITE" => tv.value.as_mapping(),
+            _ => None,
+        })
+        .collect();
+}
+
+/// Text of a single value, None if it is a sequence, mapping or directive
+fn scalar(v: &Value) -> Option<Box<str>> {
+    return match v {
+        Value::String(s) => Some(s.as_str().into()),
+        Value::Bool(b) => Some(b.to_string().into()),
+        Value::Number(n) => Some(n.to_string().into()),
+        Value::Null => Some("".into()),
+        Value::Sequence(_) | Value::Mapping(_) | Value::Tagged(_) => None,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+    use std::collections::BTreeMap;
+
+    /// Ensure settings are read from every !SITE block, without overriding the command line, and invalid ones are left out
+    #[test]
+    fn test_apply() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_settings").unwrap();
+        fs::write(
+            "/tmp/ssgen_test_source_dir_settings/META.yaml",
+            r#"
+- !DEF [x, y]
+- !SITE
+  title: Example
+  base_url: https://example.com
+  heading_ids: true
+  transforms: [emoji, emoji, shouting]
+  stamp: [json, meta, json, html]
+  external_links: {rel: nofollow, icon: ↗, data: [x]}
+  vars: {mode: dev}
+- !SITE {language: en, clean: false, unknown: 1, title: Final}
+- !SITE
+  server: nginx
+  redirects:
+    - {from: /blog/*, to: /news/:splat, status: 302}
+    - {from: /bad, to: /x, status: 200}
+    - {from: /old, to: /new/:splat}
+  headers:
+    /assets/*: {Cache-Control: 'max-age=60', 'Bad Name': x}
+    relative: {X-A: b}
+  asset_hooks:
+    - {pattern: '*.ts', output: .js, command: 'esbuild {input} --outfile={output}'}
+    - {pattern: '*.scss'}
+  pre_build: [make data, {command: [./a.sh, x y], on_failure: warn}, {on_failure: warn}]
+  post_build: [{command: ./notify.sh, on_failure: maybe}]
+  html_filter: {command: tidy -q, jobs: 3, on_failure: warn}
+  diagrams: {dot: dot -Tsvg, mermaid: []}
+  webmanifest: {short_name: Ex, icons: [{src: /a.png}]}
+  service_worker: true
+  robots: {'*': {disallow: [/a/, /b/], allow: /a/c}, Bot: ~, X: [x], sitemap: /sitemap.xml}
+- !SITE {webmanifest: [x], service_worker: '*.html'}
+"#,
+        )
+        .unwrap();
+        let args = [
+            "",
+            "-i",
+            "/tmp/ssgen_test_source_dir_settings",
+            "-o",
+            "/tmp/",
+            "-s",
+        ];
+        let o = apply(&Args::parse_from(args).build_options());
+        assert_eq!(o.title, "Final".into());
+        assert_eq!(o.language, "en".into());
+        assert_eq!(o.base_url, Some("https://example.com".into()));
+        assert_eq!(o.heading_ids, true);
+        assert_eq!(o.transforms, [Transform::Emoji]);
+        assert_eq!(o.stamp, [Stamp::Json, Stamp::Meta]);
+        assert_eq!(
+            o.external_links,
+            ExternalLinks {
+                attributes: vec![("rel".into(), "nofollow".into())],
+                icon: "↗".into(),
+            }
+        );
+        assert_eq!(o.clean, false);
+        assert_eq!(o.site_vars["mode"], "dev".into());
+        assert_eq!(o.server_config, Some(ServerConfig::Nginx));
+        assert_eq!(
+            o.redirects
+                .iter()
+                .map(|r| (&r.from[..], &r.path[..], r.status))
+                .collect::<Vec<_>>(),
+            [("/blog/*", "/news/:splat", 302)]
+        );
+        assert_eq!(
+            o.headers,
+            [Headers {
+                path: "/assets/*".into(),
+                headers: vec![("Cache-Control".into(), "max-age=60".into())],
+            }]
+        );
+        assert_eq!(
+            serde_json::Value::Object(o.webmanifest.clone().unwrap()),
+            serde_json::json!({"short_name": "Ex", "icons": [{"src": "/a.png"}]})
+        );
+        assert_eq!(o.service_worker, ["**".into()]);
+        assert_eq!(
+            o.robots,
+            Some(Robots {
+                groups: vec![
+                    (
+                        "*".into(),
+                        vec![
+                            ("Disallow".into(), "/a/".into()),
+                            ("Disallow".into(), "/b/".into()),
+                            ("Allow".into(), "/a/c".into())
+                        ]
+                    ),
+                    ("Bot".into(), Vec::new())
+                ],
+                sitemaps: vec!["/sitemap.xml".into()],
+            })
+        );
+        // hooks need --enable-shell
+        assert_eq!(o.asset_hooks.len(), 0);
+
+        let o = apply(
+            &Args::parse_from(
+                [&args[..], &["--base-url", "https://cli.example.com", "-e"]].concat(),
+            )
+            .build_options(),
+        );
+        assert_eq!(o.base_url, Some("https://cli.example.com".into()));
+        assert_eq!(o.asset_hooks.len(), 1);
+        assert_eq!(&o.asset_hooks[0].output[..], "js");
+        assert_eq!(o.asset_hooks[0].command.len(), 3);
+        assert_eq!(
+            o.pre_build,
+            [
+                BuildHook {
+                    command: vec!["make".into(), "data".into()],
+                    abort: true,
+                },
+                BuildHook {
+                    command: vec!["./a.sh".into(), "x y".into()],
+                    abort: false,
+                }
+            ]
+        );
+        assert_eq!(o.post_build.len(), 0);
+        let filter = o.html_filter.unwrap();
+        assert_eq!(filter.command, ["tidy".into(), "-q".into()]);
+        assert_eq!((filter.jobs, filter.abort), (3, false));
+        assert_eq!(
+            o.diagram_renderers,
+            BTreeMap::from([("dot".into(), vec!["dot".into(), "-Tsvg".into()])])
+        );
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_settings").unwrap();
+    }
+
+    /// Ensure the settings of the chosen profile override the others, and only those
+    #[test]
+    fn test_profiles() {
+        fs::create_dir_all("/tmp/ssgen_test_source_dir_profiles").unwrap();
+        fs::write(
+            "/tmp/ssgen_test_source_dir_profiles/META.yaml",
+            r#"
+!SITE
+title: Example
+base_url: https://staging.example.com
+vars: {mode: dev, analytics: off}
+profiles:
+  production:
+    base_url: https://example.com
+    vars: {mode: prod}
+  staging:
+    title: Staging
+"#,
+        )
+        .unwrap();
+        let args = [
+            "",
+            "-i",
+            "/tmp/ssgen_test_source_dir_profiles",
+            "-o",
+            "/tmp/",
+            "-s",
+        ];
+        let o = apply(&Args::parse_from(args).build_options());
+        assert_eq!(o.base_url, Some("https://staging.example.com".into()));
+        assert_eq!(o.site_vars["mode"], "dev".into());
+
+        let o = apply(
+            &Args::parse_from([&args[..], &["--profile", "production"]].concat()).build_options(),
+        );
+        assert_eq!(o.title, "Example".into());
+        assert_eq!(o.base_url, Some("https://example.com".into()));
+        assert_eq!(o.site_vars["mode"], "prod".into());
+        assert_eq!(o.site_vars["analytics"], "off".into());
+
+        let o = apply(
+            &Args::parse_from([&args[..], &["--profile", "missing"]].concat()).build_options(),
+        );
+        assert_eq!(o.base_url, Some("https://staging.example.com".into()));
+        fs::remove_dir_all("/tmp/ssgen_test_source_dir_profiles").unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+//! Statistics printed at the end of a build with --summary
+//!
+//! Phases are timed by marking where each one ends, the counts come from the manifest and the diagnostics
+//! ```
+//! let mut stats = Stats::new(Instant::now());
+//! let pages = build::find_pages(&o);
+//! stats.phase("discover");
+//! // ...
+//! for line in stats.lines(&o, &manifest) {
+//!     eprintln!("{line}");
+//! }
+//! ```
+
+/* IMPORTS */
+use std::time::{Duration, Instant};
+
+/* LOCAL IMPORTS */
+use crate::{Manifest, Options};
+
+/* STATS */
+/// Time spent in each phase of a build
+pub struct Stats {
+    /// When the build started
+    start: Instant,
+    /// When the previous phase ended
+    last: Instant,
+    /// Name and duration of every phase so far, in order
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Stats {
+    /// Start timing a build that started at start
+    pub fn new(start: Instant) -> Stats {
+        return Stats {
+            start: start,
+            last: start,
+            phases: Vec::new(),
+        };
+    }
+
+    /// Mark the end of a phase, which started where the previous one ended
+    pub fn phase(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.phases.push((name, now - self.last));
+        self.last = now;
+    }
+
+    /// Lines of the statistics block for a build that produced manifest
+    ///
+    /// Assets are every file in the output directory that is not a page or the manifest itself
+    pub fn lines(&self, o: &Options, manifest: &Manifest) -> Vec<String> {
+        let pages = manifest.pages.len();
+        let assets = manifest.files.len().saturating_sub(pages);
+        let bytes: u64 = manifest.files.values().map(|f| f.size).sum();
+        let (warnings, errors) = o.diagnostics.counts();
+        let phases: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(name, d)| format!("{name} {:.2}s", d.as_secs_f32()))
+            .collect();
+        return vec![
+            format!("Pages:    {pages}"),
+            format!("Assets:   {assets}"),
+            format!("Warnings: {warnings}"),
+            format!("Errors:   {errors}"),
+            format!("Bytes:    {bytes}"),
+            format!(
+                "Time:     {:.2}s ({})",
+                self.start.elapsed().as_secs_f32(),
+                phases.join(", ")
+            ),
+        ];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        manifest::{FileEntry, PageEntry},
+        Args,
+    };
+    use clap::Parser;
+
+    /// Ensure pages, assets and bytes are counted from the manifest, and every phase is listed
+    #[test]
+    fn test_lines() {
+        let o = Args::parse_from(["", "-i", "./", "-o", "/tmp/", "--summary"]).build_options();
+        let mut manifest = Manifest::default();
+        manifest
+            .pages
+            .insert("index.page".into(), PageEntry::default());
+        for (file, size) in [("index.html", 100), ("style.css", 20), ("logo.png", 3)] {
+            manifest.files.insert(
+                file.into(),
+                FileEntry {
+                    source: "index.page".into(),
+                    hash: "".into(),
+                    size: size,
+                },
+            );
+        }
+        let mut stats = Stats::new(Instant::now());
+        stats.phase("discover");
+        stats.phase("pages");
+
+        let lines = stats.lines(&o, &manifest);
+        assert_eq!(
+            lines[..5],
+            [
+                "Pages:    1",
+                "Assets:   2",
+                "Warnings: 0",
+                "Errors:   0",
+                "Bytes:    123"
+            ]
+        );
+        assert_eq!(
+            lines[5].starts_with("Time:     0.00s (discover 0.00s, pages"),
+            true
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Time spent in directives and included files, recorded with --profile-templates
+//!
+//! Times include everything nested inside, so an !INCLUDE also counts the directives of the file it includes.
+//! They are added up over every page and thread, so together they can be more than the build took
+//! ```
+//! let timings = Timings::default();
+//! timings.directive("!INCLUDE", start.elapsed());
+//! timings.include("nav.block", start.elapsed());
+//! for line in timings.report(10) {
+//!     eprintln!("{line}"); // nav.block included 400×, 12.30s total
+//! }
+//! ```
+
+/* IMPORTS */
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+/* TIMINGS */
+/// Number of times something ran and the time it took altogether
+type Entry = (usize, Duration);
+
+/// Time spent per directive and per included file over a whole build
+#[derive(Default)]
+pub struct Timings {
+    /// By tag, like `!INCLUDE`
+    directives: Mutex<HashMap<Box<str>, Entry>>,
+    /// By path relative to the input directory
+    includes: Mutex<HashMap<Box<str>, Entry>>,
+}
+
+impl Timings {
+    /// Record one run of the directive tag that took time
+    pub fn directive(&self, tag: &str, time: Duration) {
+        add(&self.directives, tag, time);
+    }
+
+    /// Record one inclusion of file that took time
+    pub fn include(&self, file: &str, time: Duration) {
+        add(&self.includes, file, time);
+    }
+
+    /// The limit directives and included files that took the longest altogether, slowest first
+    pub fn report(&self, limit: usize) -> Vec<String> {
+        let mut lines = vec!["Slowest directives:".to_string()];
+        for (tag, (count, time)) in slowest(&self.directives, limit) {
+            lines.push(format!(
+                "  {tag} used {count}×, {:.2}s total",
+                time.as_secs_f32()
+            ));
+        }
+        lines.push("Slowest included files:".to_string());
+        for (file, (count, time)) in slowest(&self.includes, limit) {
+            lines.push(format!(
+                "  {file} included {count}×, {:.2}s total",
+                time.as_secs_f32()
+            ));
+        }
+        return lines;
+    }
+}
+
+/// Count one more run of key that took time
+fn add(entries: &Mutex<HashMap<Box<str>, Entry>>, key: &str, time: Duration) {
+    let mut entries = entries.lock().unwrap();
+    let entry = entries.entry(key.into()).or_default();
+    entry.0 += 1;
+    entry.1 += time;
+}
+
+/// The limit entries with the most time, ties in order of their key
+fn slowest(entries: &Mutex<HashMap<Box<str>, Entry>>, limit: usize) -> Vec<(Box<str>, Entry)> {
+    let mut entries: Vec<(Box<str>, Entry)> = entries
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(k, e)| (k.clone(), *e))
+        .collect();
+    entries.sort_by(|a, b| b.1 .1.cmp(&a.1 .1).then(a.0.cmp(&b.0)));
+    entries.truncate(limit);
+    return entries;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure runs are counted and added up, and only the slowest are reported
+    #[test]
+    fn test_report() {
+        let timings = Timings::default();
+        for _ in 0..400 {
+            timings.include("nav.block", Duration::from_millis(30));
+        }
+        timings.include("footer.block", Duration::from_millis(10));
+        timings.directive("!FOREACH", Duration::from_millis(500));
+        timings.directive("!INCLUDE", Duration::from_secs(12));
+        timings.directive("!DEF", Duration::from_millis(1));
+        assert_eq!(
+            timings.report(2),
+            [
+                "Slowest directives:",
+                "  !INCLUDE used 1×, 12.00s total",
+                "  !FOREACH used 1×, 0.50s total",
+                "Slowest included files:",
+                "  nav.block included 400×, 12.00s total",
+                "  footer.block included 1×, 0.01s total",
+            ]
+        );
+    }
+}
@@ -0,0 +1,512 @@
+//! Passes over the tree of a parsed page that rewrite its text or elements, chosen with --transform or transforms in !SITE
+//!
+//! Passes run in the order they were chosen, after heading ids and before rewrites.
+//! Text in elements that are shown as written, like code and pre, is never changed
+//! ```YAML
+//! - !SITE
+//!   transforms: [emoji, smartypants]
+//! ```
+//! ```
+//! parser.parse_yaml(&yaml);
+//! parser.apply_transforms(); // transforms::apply on the root node
+//! ```
+
+/* IMPORTS */
+use clap::ValueEnum;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/* LOCAL IMPORTS */
+use crate::{debug, manifest, paths, NodeCell, Options, PageNode};
+
+/* TRANSFORMS */
+/// Elements whose text is left as written by every pass
+const VERBATIM: [&str; 7] = ["code", "pre", "kbd", "samp", "script", "style", "textarea"];
+
+/// Pass over a parsed page
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Transform {
+    /// GitHub-style shortcodes like `:tada:` become the emoji they name
+    Emoji,
+    /// Straight quotes become curly ones, `--` and `---` en and em dashes, and `...` an ellipsis
+    Smartypants,
+    /// Links to other sites get the attributes of `external_links` in !SITE, by default opening in a new tab
+    ExternalLinks,
+    /// Images load lazily, and get the width and height of their file if they have neither
+    LazyImages,
+    /// Pages get a canonical link to their URL under the base URL in their head, unless they have one
+    Canonical,
+}
+
+/// What external links get, see the `external_links` setting
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExternalLinks {
+    /// Attributes to give the links, `class` and `rel` are added to the values the links already have
+    pub attributes: Vec<(Box<str>, Box<str>)>,
+    /// HTML put at the end of the links, like an icon
+    pub icon: Box<str>,
+}
+
+impl Default for ExternalLinks {
+    fn default() -> Self {
+        return ExternalLinks {
+            attributes: vec![
+                ("target".into(), "_blank".into()),
+                ("rel".into(), "noopener noreferrer".into()),
+                ("class".into(), "external".into()),
+            ],
+            icon: "".into(),
+        };
+    }
+}
+
+/// Run every pass chosen in the options of root over it and its children
+///
+/// dir is the directory of the page, and output the file it is written to if it is a whole page
+pub fn apply(root: &mut PageNode, dir: &Path, output: Option<&Path>) {
+    for transform in root.o.transforms.clone() {
+        match transform {
+            Transform::Emoji => root.map_text(&VERBATIM, &mut emoji),
+            Transform::Smartypants => {
+                // quotes depend on the text before them, which may be in another element
+                let mut prev = ' ';
+                root.map_text(&VERBATIM, &mut |text| smartypants(text, &mut prev));
+            }
+            Transform::ExternalLinks => annotate_links(root),
+            Transform::LazyImages => lazy_images(root, dir),
+            Transform::Canonical => match output {
+                Some(output) => {
+                    let key = manifest::output_key(&root.o, output);
+                    // directories are served by their index.html, and that is the URL they are linked by
+                    let path = match key.strip_suffix("index.html") {
+                        Some(dir) if dir.len() == 0 || dir.ends_with('/') => dir,
+                        _ => &key,
+                    };
+                    let base_url = root.o.base_url.clone().unwrap_or_default();
+                    canonical(root, &format!("{}/{path}", base_url.trim_end_matches('/')));
+                }
+                None => (),
+            },
+        }
+    }
+}
+
+/// Replace the emoji shortcodes in text with their emoji
+///
+/// Unknown shortcodes stay as they are, and a backslash in front of a known one keeps it as written without the backslash.
+/// Since a single backslash is an escape in YAML content, that is `\\:tada:` in a page
+pub fn emoji(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        let after = &rest[start + 1..];
+        let len = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-')))
+            .unwrap_or(after.len());
+        let code = &after[..len];
+        if len > 0 && after[len..].starts_with(':') {
+            match emojis::get_by_shortcode(code) {
+                Some(_) if rest[..start].ends_with('\\') => {
+                    out += &rest[..start - 1];
+                    out += &rest[start..start + len + 2];
+                    rest = &after[len + 1..];
+                    continue;
+                }
+                Some(e) => {
+                    out += &rest[..start];
+                    out += e.as_str();
+                    rest = &after[len + 1..];
+                    continue;
+                }
+                None => (),
+            }
+        }
+        // not a shortcode, but its closing colon may open the next one
+        out += &rest[..=start];
+        rest = after;
+    }
+    out += rest;
+    return out;
+}
+
+/// Replace straight quotes, dashes and ellipses in text with their typographic forms
+///
+/// prev is the character before text, and is set to the last character of it.
+/// A quote opens after whitespace or an opening bracket or quote, and before a word if it does not follow one
+pub fn smartypants(text: &str, prev: &mut char) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        let opens = || {
+            prev.is_whitespace()
+                || matches!(*prev, '(' | '[' | '{' | '“' | '‘' | '—' | '–' | '-')
+                || (!prev.is_alphanumeric() && next.is_some_and(|n| n.is_alphanumeric()))
+        };
+        let typographic = match c {
+            '"' if opens() => '“',
+            '"' => '”',
+            // apostrophes in words like don't close
+            '\'' if !prev.is_alphanumeric() && opens() => '‘',
+            '\'' => '’',
+            '-' if next == Some('-') => {
+                chars.next();
+                match chars.peek() {
+                    Some('-') => {
+                        chars.next();
+                        '—'
+                    }
+                    _ => '–',
+                }
+            }
+            '.' if next == Some('.') => {
+                let mut ahead = chars.clone();
+                ahead.next();
+                match ahead.next() {
+                    Some('.') => {
+                        chars = ahead;
+                        '…'
+                    }
+                    _ => '.',
+                }
+            }
+            c => c,
+        };
+        out.push(typographic);
+        *prev = typographic;
+    }
+    return out;
+}
+
+/// Give every link in root that leads to another site the attributes and icon of the external_links options
+pub fn annotate_links(root: &mut PageNode) {
+    let o = root.o.clone();
+    let own_host = o.base_url.as_deref().and_then(host);
+    root.visit(&mut |node| {
+        if !node.get_name().eq_ignore_ascii_case("a") {
+            return;
+        }
+        match node.get_attribute("href").and_then(host) {
+            Some(h) if Some(&h) != own_host.as_ref() => (),
+            _ => return,
+        }
+        for (key, value) in &o.external_links.attributes {
+            let merged = match (&key[..], node.get_attribute(key)) {
+                (_, None) => value.clone(),
+                ("class" | "rel", Some(old)) => {
+                    let mut tokens: Vec<&str> = old.split_whitespace().collect();
+                    for token in value.split_whitespace() {
+                        if !tokens.contains(&token) {
+                            tokens.push(token);
+                        }
+                    }
+                    tokens.join(" ").into()
+                }
+                // attributes written on the link win
+                _ => continue,
+            };
+            node.set_attribute(key, merged);
+        }
+        if o.external_links.icon.len() != 0 {
+            let icon = Arc::new(NodeCell::new(PageNode::new(o.clone())));
+            icon.borrow_mut()
+                .add_content_unparsed(o.external_links.icon.clone());
+            node.add_child(icon);
+        }
+    });
+}
+
+/// Give the head of root a canonical link to url, unless there already is one
+///
+/// Search engines then count copies of the page, like those reached with a query string or another host, as this one
+pub fn canonical(root: &mut PageNode, url: &str) {
+    let o = root.o.clone();
+    let mut found = false;
+    root.visit(&mut |node| {
+        found |= node.get_name().eq_ignore_ascii_case("link")
+            && node.get_attribute("rel").is_some_and(|rel| {
+                rel.split_whitespace()
+                    .any(|r| r.eq_ignore_ascii_case("canonical"))
+            });
+    });
+    if found {
+        return;
+    }
+    let href: Box<str> = url.replace('&', "&amp;").replace('"', "&quot;").into();
+    let mut added = false;
+    root.visit(&mut |node| {
+        if added || !node.get_name().eq_ignore_ascii_case("head") {
+            return;
+        }
+        let mut link = PageNode::new(o.clone());
+        link.set_name("link".into());
+        link.set_attribute("rel", "canonical".into());
+        link.set_attribute("href", href.clone());
+        node.add_child(Arc::new(NodeCell::new(link)));
+        added = true;
+    });
+    if !added {
+        debug!(o, "Not adding a canonical link, the page has no head");
+    }
+}
+
+/// Make every image in root load lazily, and give those without a size the size of their file
+///
+/// Knowing the size before the image loads keeps the page from shifting around as it does.
+/// Files are looked for in the input directory, then in the output directory for images written by the build.
+/// Images on other sites or that are not found only load lazily
+pub fn lazy_images(root: &mut PageNode, dir: &Path) {
+    let o = root.o.clone();
+    let mut read = Vec::new();
+    root.visit(&mut |node| {
+        if !node.get_name().eq_ignore_ascii_case("img") {
+            return;
+        }
+        if node.get_attribute("loading").is_none() {
+            node.set_attribute("loading", "lazy".into());
+        }
+        if node.get_attribute("width").is_some() || node.get_attribute("height").is_some() {
+            return;
+        }
+        let file = match node
+            .get_attribute("src")
+            .and_then(|s| image_file(&o, dir, s))
+        {
+            Some(f) => f,
+            None => return,
+        };
+        match imagesize::size(&file) {
+            Ok(size) => {
+                node.set_attribute("width", size.width.to_string().into());
+                node.set_attribute("height", size.height.to_string().into());
+                if file.starts_with(&o.input) {
+                    read.push(file);
+                }
+            }
+            Err(e) => debug!(
+                o,
+                "Unable to read the size of image {} | {e}",
+                file.display()
+            ),
+        }
+    });
+    // the size is part of the page, so it is rebuilt when the image changes
+    for file in read {
+        root.record_dependency(file);
+    }
+}
+
+/// File an image src refers to, in the input directory or else in the output directory
+fn image_file(o: &Options, dir: &Path, src: &str) -> Option<PathBuf> {
+    let path = src.split(['?', '#']).next()?;
+    // other sites and data: URLs
+    if path.len() == 0 || host(path).is_some() || path.contains(':') {
+        return None;
+    }
+    let candidates = match path.strip_prefix('/') {
+        Some(p) => [
+            paths::join_slash(&o.input, p),
+            paths::join_slash(&o.output, p),
+        ],
+        None => [
+            paths::join_slash(dir, path),
+            paths::join_slash(
+                &o.output
+                    .join(dir.strip_prefix(&o.input).unwrap_or(Path::new(""))),
+                path,
+            ),
+        ],
+    };
+    return candidates.into_iter().find_map(|c| {
+        paths::canonicalize(&c)
+            .ok()
+            .filter(|c| c.is_file() && (c.starts_with(&o.input) || c.starts_with(&o.output)))
+    });
+}
+
+/// Lowercase host of an absolute URL, None for links within the site
+fn host(url: &str) -> Option<String> {
+    let rest = ["https://", "http://", "//"].iter().find_map(|scheme| {
+        url.get(..scheme.len())
+            .filter(|s| s.eq_ignore_ascii_case(scheme))
+            .map(|_| &url[scheme.len()..])
+    })?;
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    return match host.len() {
+        0 => None,
+        _ => Some(host.to_ascii_lowercase()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{qr, Args, Parser};
+    use clap::Parser as _;
+    use std::fs;
+
+    /// Ensure chosen passes change the text of the page, but not attributes or verbatim elements
+    #[test]
+    fn test_apply() {
+        let o = Args::parse_from(["", "-i", "/tmp/", "-s", "--transform", "emoji,smartypants"])
+            .build_options();
+        let mut p = Parser::new(Arc::new(o));
+        p.parse_yaml(
+            r#"
+- p: [':tada:', {code: ':tada:'}, ' "', {em: it's}, '"']
+- a: [{_title: ':tada:'}, ':+1:']
+- div: '<pre>:tada:</pre>:tada:'
+"#,
+        );
+        p.apply_transforms();
+        assert_eq!(
+            format!("{}", p),
+            r#"<p>🎉<code>:tada:</code> “<em>it’s</em>”</p><a title=":tada:">👍</a><div><pre>:tada:</pre>🎉</div>"#
+        );
+    }
+
+    /// Ensure links to other sites get the attributes and icon, merged with the ones they have
+    #[test]
+    fn test_annotate_links() {
+        let mut o = Args::parse_from([
+            "",
+            "-i",
+            "/tmp/",
+            "-s",
+            "--base-url",
+            "https://Example.com/",
+        ])
+        .build_options();
+        o.external_links.icon = "<span>↗</span>".into();
+        o.transforms = vec![Transform::ExternalLinks];
+        let mut p = Parser::new(Arc::new(o));
+        p.parse_yaml(
+            r#"
+- a: [{_href: 'https://rust-lang.org/learn'}, Rust]
+- a: [{_href: '//cdn.example.net', _class: btn, _rel: noopener, _target: _self}, CDN]
+- a: [{_href: 'https://example.com/about'}, About]
+- a: [{_href: /blog}, Blog]
+- link: {_href: 'https://rust-lang.org'}
+"#,
+        );
+        p.apply_transforms();
+        assert_eq!(
+            format!("{}", p),
+            concat!(
+                r#"<a href="https://rust-lang.org/learn" target="_blank" rel="noopener noreferrer" class="external">Rust<span>↗</span></a>"#,
+                r#"<a href="//cdn.example.net" class="btn external" rel="noopener noreferrer" target="_self">CDN<span>↗</span></a>"#,
+                r#"<a href="https://example.com/about">About</a><a href="/blog">Blog</a>"#,
+                r#"<link href="https://rust-lang.org"/>"#
+            )
+        );
+    }
+
+    /// Ensure pages get a canonical link to their URL in their head, unless they already link one
+    #[test]
+    fn test_canonical() {
+        let mut o = Args::parse_from(["", "-i", "/tmp/", "-o", "/tmp/out", "-s"]).build_options();
+        o.base_url = Some("https://example.com/".into());
+        o.transforms = vec![Transform::Canonical];
+        let o = Arc::new(o);
+        let page = |yaml: &str, output: &str| {
+            let mut p = Parser::new(o.clone());
+            p.set_output(PathBuf::from(output));
+            p.parse_yaml(yaml);
+            p.apply_transforms();
+            return format!("{}", p);
+        };
+        assert_eq!(
+            page(
+                "html: [head: [title: a], body: b]",
+                "/tmp/out/blog/index.html"
+            ),
+            r#"<html><head><title>a</title><link rel="canonical" href="https://example.com/blog/"/></head><body>b</body></html>"#
+        );
+        assert_eq!(
+            page("head: [title: a]", "/tmp/out/a&b.html"),
+            r#"<head><title>a</title><link rel="canonical" href="https://example.com/a&amp;b.html"/></head>"#
+        );
+        assert_eq!(
+            page(
+                "head: [link: {_rel: Canonical, _href: /x}]",
+                "/tmp/out/index.html"
+            ),
+            r#"<head><link rel="Canonical" href="/x"/></head>"#
+        );
+        assert_eq!(page("p: a", "/tmp/out/index.html"), "<p>a</p>");
+    }
+
+    /// Ensure images load lazily, and get the size of files from the input or output directory
+    #[test]
+    fn test_lazy_images() {
+        let src = "/tmp/ssgen_test_source_dir_lazy_images";
+        let dest = "/tmp/ssgen_test_dest_dir_lazy_images";
+        fs::create_dir_all(format!("{src}/blog")).unwrap();
+        fs::create_dir_all(format!("{dest}/img")).unwrap();
+        let code = qr::encode("x").unwrap();
+        fs::write(format!("{src}/blog/a.png"), qr::png(&code)).unwrap();
+        fs::write(format!("{dest}/img/b.svg"), qr::svg(&code)).unwrap();
+        fs::write(format!("{dest}/img/c.png"), qr::png(&code)).unwrap();
+        let mut o = Args::parse_from(["", "-i", src, "-o", dest, "-s"]).build_options();
+        o.transforms = vec![Transform::LazyImages];
+        let mut p = Parser::new(Arc::new(o));
+        p.set_root_dir(PathBuf::from(format!("{src}/blog")));
+        p.parse_yaml(
+            r#"
+- img: {_src: a.png}
+- img: {_src: '/img/c.png?v=2', _loading: eager}
+- img: {_src: /img/b.svg, _width: 10}
+- img: {_src: 'https://example.com/a.png'}
+- img: {_src: ../../etc/passwd}
+"#,
+        );
+        p.apply_transforms();
+        assert_eq!(
+            format!("{}", p),
+            concat!(
+                r#"<img src="a.png" loading="lazy" width="232" height="232"/>"#,
+                r#"<img src="/img/c.png?v=2" loading="eager" width="232" height="232"/>"#,
+                r#"<img src="/img/b.svg" width="10" loading="lazy"/>"#,
+                r#"<img src="https://example.com/a.png" loading="lazy"/>"#,
+                r#"<img src="../../etc/passwd" loading="lazy"/>"#
+            )
+        );
+        assert_eq!(
+            p.get_dependencies().into_iter().collect::<Vec<_>>(),
+            [PathBuf::from(format!("{src}/blog/a.png"))]
+        );
+        fs::remove_dir_all(src).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    /// Ensure quotes open and close by their context, and dashes and ellipses are replaced
+    #[test]
+    fn test_smartypants() {
+        let convert = |text: &str| smartypants(text, &mut ' ');
+        assert_eq!(
+            convert(r#"She said "it's done" -- 'finally'..."#),
+            "She said “it’s done” – ‘finally’…"
+        );
+        assert_eq!(
+            convert(r#"("quoted") 1990--2000---ok. x.."#),
+            "(“quoted”) 1990–2000—ok. x.."
+        );
+        assert_eq!(convert(r#"a."b""#), "a.“b”");
+
+        let mut prev = ' ';
+        assert_eq!(smartypants(r#"He said ""#, &mut prev), "He said “");
+        assert_eq!(smartypants("hi", &mut prev), "hi");
+        assert_eq!(smartypants(r#"""#, &mut prev), "”");
+    }
+
+    /// Ensure known shortcodes are replaced, unless they are escaped
+    #[test]
+    fn test_emoji() {
+        assert_eq!(emoji("Released :tada: at 10:30"), "Released 🎉 at 10:30");
+        assert_eq!(emoji("12:30:tada::+1:"), "12:30🎉👍");
+        assert_eq!(emoji(":not_an_emoji: :tada"), ":not_an_emoji: :tada");
+        assert_eq!(emoji(r"type \:tada: for 🎉"), "type :tada: for 🎉");
+    }
+}
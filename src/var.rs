@@ -0,0 +1,278 @@
+//! Values that variables can hold
+//!
+//! Every variable registered in a PageNode is stored as a Var
+//! ```
+//! let mut node = PageNode::new(o.clone());
+//! node.register_var("x".into(), "value".into());
+//! node.register_var_lazy("y".into(), "{x}".into());
+//! node.set_var("z".into(), Var::List(vec![Var::Str("a".into())]));
+//! node.set_var("m".into(), Var::Map(vec![("k".into(), Var::Str("b".into()))]));
+//! node.add_content("{z[0]}{m.k}".into());
+//! ```
+
+/* IMPORTS */
+use clap::ValueEnum;
+use serde_yaml::Value;
+use std::{
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+/* VAR */
+/// Value of a variable
+#[derive(Clone)]
+pub enum Var {
+    /// Text that was resolved when the variable was registered
+    Str(Box<str>),
+
+    /// Text whose variables are resolved every time the variable is used, in the scope it is used in
+    Lazy(Box<str>),
+
+    /// Ordered list of values, accessed with `{name[index]}`
+    List(Vec<Var>),
+
+    /// Named values in the order they were defined, accessed with `{name.key}`
+    Map(Vec<(Box<str>, Var)>),
+
+    /// Reusable block of YAML, instantiated with `!USE`
+    Component(Arc<Component>),
+
+    /// Value that is only worked out the first time it is used, such as the git history of a page
+    Deferred(Arc<Deferred>),
+}
+
+/// What using a variable that is not defined turns into
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum UndefinedVars {
+    /// Nothing
+    Empty,
+    /// The variable as it was written, i.e. {name}
+    Keep,
+    /// The text given with --undefined-placeholder
+    Placeholder,
+    /// Nothing, and the build fails once it is done
+    Error,
+}
+
+/// Block of YAML defined with `!COMPONENT`, kept unparsed until it is used
+pub struct Component {
+    /// Names of the variables every instance is given
+    pub params: Vec<Box<str>>,
+
+    /// YAML that is parsed for every instance
+    pub body: Value,
+
+    /// Directory of the file the component was defined in, relative paths in body are relative to it
+    pub dir: Option<PathBuf>,
+}
+
+/// Value of a Var::Deferred, kept by every copy of the variable once it is worked out
+pub struct Deferred {
+    /// Stands for the value when hashing, so that hashing does not work it out
+    pub key: Box<str>,
+    value: OnceLock<Var>,
+    init: Box<dyn Fn() -> Var + Send + Sync>,
+}
+
+impl Deferred {
+    /// The value, worked out now if this is the first time it is used
+    pub fn get(&self) -> &Var {
+        return self.value.get_or_init(|| (self.init)());
+    }
+}
+
+impl Var {
+    /// Create a variable whose value is init's result, calling it at most once
+    ///
+    /// key has to change whenever the value would, see Deferred::key
+    pub fn deferred(key: &str, init: impl Fn() -> Var + Send + Sync + 'static) -> Var {
+        return Var::Deferred(Arc::new(Deferred {
+            key: key.into(),
+            value: OnceLock::new(),
+            init: Box::new(init),
+        }));
+    }
+
+    /// Convert data, such as JSON that was fetched, into a variable
+    ///
+    /// Unlike values in a page, text is taken as it is rather than parsed for variables or directives.
+    /// null becomes empty text, so that it counts as false in !IF
+    pub fn from_value(v: &Value) -> Var {
+        return match v {
+            Value::Null => Var::Str("".into()),
+            Value::Bool(b) => Var::Str(b.to_string().into()),
+            Value::Number(n) => Var::Str(n.to_string().into()),
+            Value::String(s) => Var::Str(s.clone().into()),
+            Value::Sequence(seq) => Var::List(seq.iter().map(Var::from_value).collect()),
+            Value::Mapping(map) => Var::Map(
+                map.iter()
+                    .map(|(k, v)| match Var::from_value(k) {
+                        Var::Str(k) => (k, Var::from_value(v)),
+                        _ => ("".into(), Var::from_value(v)),
+                    })
+                    .collect(),
+            ),
+            Value::Tagged(t) => Var::from_value(&t.value),
+        };
+    }
+
+    /// Follow an accessor path such as `[0][2]` or `.author.name` into this variable
+    ///
+    /// Negative indices count from the end of a list, and deferred variables on the way are worked out
+    pub fn get_path(&self, path: &str) -> Option<&Var> {
+        let mut var = self;
+        let mut rest = path;
+        while rest.len() != 0 {
+            match var {
+                Var::Deferred(d) => var = d.get(),
+                _ => (),
+            }
+            match rest.strip_prefix('.') {
+                Some(inner) => {
+                    let end = inner.find(['.', '[']).unwrap_or(inner.len());
+                    var = match var {
+                        Var::Map(m) => &m.iter().find(|(k, _)| **k == inner[..end])?.1,
+                        _ => return None,
+                    };
+                    rest = &inner[end..];
+                }
+                None => {
+                    let inner = rest.strip_prefix('[')?;
+                    let end = inner.find(']')?;
+                    let index: i64 = inner[..end].trim().parse().ok()?;
+                    var = match var {
+                        Var::List(l) => {
+                            let i = if index < 0 {
+                                l.len() as i64 + index
+                            } else {
+                                index
+                            };
+                            l.get(usize::try_from(i).ok()?)?
+                        }
+                        _ => return None,
+                    };
+                    rest = &inner[end + 1..];
+                }
+            }
+        }
+        return match var {
+            Var::Deferred(d) => Some(d.get()),
+            _ => Some(var),
+        };
+    }
+}
+
+impl From<Box<str>> for Var {
+    fn from(s: Box<str>) -> Self {
+        return Var::Str(s);
+    }
+}
+
+/// Split a variable name into its name and accessor path, i.e. `items[0].name` into `items` and `[0].name`
+pub fn split_path(k: &str) -> (&str, &str) {
+    return match k.find(['[', '.']) {
+        Some(i) => (&k[..i], &k[i..]),
+        None => (k, ""),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ensure data keeps its order and text is not touched
+    #[test]
+    fn test_from_value() {
+        let v: Value = serde_json::from_str(
+            r#"[{"tag": "v2", "name": "{x}", "draft": false, "id": 3, "body": null}]"#,
+        )
+        .unwrap();
+        let var = Var::from_value(&v);
+        let release = var.get_path("[0]").unwrap();
+        match release {
+            Var::Map(m) => assert_eq!(
+                m.iter().map(|(k, _)| &k[..]).collect::<Vec<_>>(),
+                vec!["tag", "name", "draft", "id", "body"]
+            ),
+            _ => panic!("not a map"),
+        }
+        let text = |path: &str| match var.get_path(path) {
+            Some(Var::Str(s)) => s.clone(),
+            _ => panic!("not text"),
+        };
+        assert_eq!(&*text("[0].name"), "{x}");
+        assert_eq!(&*text("[0].draft"), "false");
+        assert_eq!(&*text("[0].id"), "3");
+        assert_eq!(&*text("[0].body"), "");
+    }
+
+    /// Ensure accessor paths index into lists correctly
+    #[test]
+    fn test_get_path() {
+        let v = Var::List(vec![
+            Var::Str("a".into()),
+            Var::List(vec![Var::Str("b".into()), Var::Str("c".into())]),
+        ]);
+        let text = |v: Option<&Var>| match v {
+            Some(Var::Str(s)) => s.to_string(),
+            _ => "NONE".to_string(),
+        };
+        assert_eq!(text(v.get_path("[0]")), "a");
+        assert_eq!(text(v.get_path("[1][1]")), "c");
+        assert_eq!(text(v.get_path("[-1][0]")), "b");
+        assert_eq!(text(v.get_path("[ 0 ]")), "a");
+        assert_eq!(text(v.get_path("[2]")), "NONE");
+        assert_eq!(text(v.get_path("[-3]")), "NONE");
+        assert_eq!(text(v.get_path("[0][0]")), "NONE");
+        assert_eq!(text(v.get_path("[x]")), "NONE");
+        assert_eq!(text(v.get_path("[0")), "NONE");
+
+        assert_eq!(split_path("items[0][1]"), ("items", "[0][1]"));
+        assert_eq!(split_path("items"), ("items", ""));
+        assert_eq!(split_path("author.name"), ("author", ".name"));
+    }
+
+    /// Ensure accessor paths look up keys of maps correctly
+    #[test]
+    fn test_get_path_map() {
+        let v = Var::Map(vec![
+            ("name".into(), Var::Str("K".into())),
+            (
+                "links".into(),
+                Var::List(vec![Var::Map(vec![("url".into(), Var::Str("u".into()))])]),
+            ),
+        ]);
+        let text = |v: Option<&Var>| match v {
+            Some(Var::Str(s)) => s.to_string(),
+            _ => "NONE".to_string(),
+        };
+        assert_eq!(text(v.get_path(".name")), "K");
+        assert_eq!(text(v.get_path(".links[0].url")), "u");
+        assert_eq!(text(v.get_path(".email")), "NONE");
+        assert_eq!(text(v.get_path(".name.first")), "NONE");
+        assert_eq!(text(v.get_path("[0]")), "NONE");
+    }
+
+    /// Ensure deferred values are worked out once, on first use, and shared by every copy
+    ///
+    /// Accessor paths go through them
+    #[test]
+    fn test_deferred() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let v = Var::deferred("author", move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            return Var::Map(vec![("author".into(), Var::Str("Ada".into()))]);
+        });
+        let copy = v.clone();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        let text = |v: &Var| match v.get_path(".author") {
+            Some(Var::Str(s)) => s.to_string(),
+            _ => "NONE".to_string(),
+        };
+        assert_eq!(text(&v), "Ada");
+        assert_eq!(text(&copy), "Ada");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}